@@ -3,12 +3,13 @@ use silk_proto::Response;
 
 struct ContractSimple;
 
+#[async_trait::async_trait]
 impl Contract for ContractSimple {
-    fn init<'a>(&self, _stub: &'a mut ContractStub) -> Response {
+    async fn init(&self, _stub: &mut ContractStub) -> Response {
         unimplemented!()
     }
 
-    fn invoke<'a>(&self, _stub: &'a mut ContractStub) -> Response {
+    async fn invoke(&self, _stub: &mut ContractStub) -> Response {
         Response {
             status: 200,
             message: "ok".to_string(),
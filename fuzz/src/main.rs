@@ -10,6 +10,7 @@ async fn commit_transaction(client: &mut EndorserClient<Channel>) -> Result<(),
     let payload = ContractProposalPayload {
         contract_id: Some(ContractId {
             name: "contract-simple".to_string(),
+            ..Default::default()
         }),
         input: Some(ContractInput {
             args: vec![],
@@ -0,0 +1,346 @@
+// The tests every block store must pass. block_store_tests! runs them against the stores of a
+// provider, built by a function returning the provider with a guard to keep alive meanwhile
+use crate::{BlockStore, BlockStoreProvider};
+use error::*;
+use silk_proto::*;
+
+macro_rules! block_store_tests {
+    ($provider:ident) => {
+        #[test]
+        fn test_provider() {
+            let (_guard, provider) = $provider();
+            crate::conformance::test_provider(&provider);
+        }
+
+        #[test]
+        fn test_get_blockchain_info() {
+            let (_guard, provider) = $provider();
+            crate::conformance::test_get_blockchain_info(&provider);
+        }
+
+        #[test]
+        fn test_retrieve_block_by_hash() {
+            let (_guard, provider) = $provider();
+            crate::conformance::test_retrieve_block_by_hash(&provider);
+        }
+
+        #[test]
+        fn test_retrieve_block_by_number() {
+            let (_guard, provider) = $provider();
+            crate::conformance::test_retrieve_block_by_number(&provider);
+        }
+
+        #[test]
+        fn test_retrieve_tx_by_id() {
+            let (_guard, provider) = $provider();
+            crate::conformance::test_retrieve_tx_by_id(&provider);
+        }
+
+        #[test]
+        fn test_retrieve_tx_by_blocknum_txnum() {
+            let (_guard, provider) = $provider();
+            crate::conformance::test_retrieve_tx_by_blocknum_txnum(&provider);
+        }
+
+        #[test]
+        fn test_retrieve_block_by_txid() {
+            let (_guard, provider) = $provider();
+            crate::conformance::test_retrieve_block_by_txid(&provider);
+        }
+
+        #[test]
+        fn test_rollback() {
+            let (_guard, provider) = $provider();
+            crate::conformance::test_rollback(&provider);
+        }
+
+        #[test]
+        fn test_rebuild_tx_index() {
+            let (_guard, provider) = $provider();
+            crate::conformance::test_rebuild_tx_index(&provider);
+        }
+
+        #[test]
+        fn test_retrieve_tx_validation_code_by_txid() {
+            let (_guard, provider) = $provider();
+            crate::conformance::test_retrieve_tx_validation_code_by_txid(&provider);
+        }
+    };
+}
+
+// init creates the store of a ledger holding the blocks 0 to 100, one transaction each
+fn init<P: BlockStoreProvider>(provider: &P) -> Result<P::S> {
+    let mut store = provider.create_block_store("chain_id")?;
+
+    store.add_block(&create_block(
+        0,
+        vec![],
+        vec![create_tx("tx_0".to_string())?],
+    ))?;
+
+    for i in 1..=100 {
+        let info = store.get_blockchain_info()?;
+        let block = create_block(
+            i as u64,
+            info.current_block_hash,
+            vec![create_tx(format!("tx_{:}", i))?],
+        );
+        store.add_block(&block)?;
+    }
+    Ok(store)
+}
+
+fn create_block(num: u64, prev_hash: Vec<u8>, txs: Vec<Transaction>) -> Block {
+    let data: Vec<Vec<u8>> = txs
+        .iter()
+        .map(|t| utils::proto::marshal(t).unwrap())
+        .collect();
+
+    Block {
+        header: Some(BlockHeader {
+            number: num,
+            previous_hash: prev_hash,
+            data_hash: utils::hash::compute_vec_sha256(&data).to_vec(),
+        }),
+        data: Some(BlockData { data }),
+        metadata: None,
+    }
+}
+
+fn create_tx(txid: String) -> Result<Transaction> {
+    let payload = ContractProposalPayload {
+        contract_id: None,
+        input: None,
+        transient_map: Default::default(),
+        timeout: 0,
+    };
+
+    let proposal = Proposal {
+        header: Some(Header {
+            header_type: HeaderType::Invoke as i32,
+            version: 0,
+            timestamp: Some(utils::time::timestamp()),
+            channel_id: "chain_id".to_string(),
+            tx_id: txid,
+            tls_cert_hash: vec![],
+            creator: vec![],
+            nonce: utils::random::get_random_nonce(),
+        }),
+        payload: utils::proto::marshal(&payload)?,
+    };
+    let sp = SignedProposal {
+        proposal_bytes: utils::proto::marshal(&proposal)?,
+        signature: vec![],
+    };
+
+    let payload = ProposalResponsePayload {
+        results: vec![],
+        events: vec![],
+    };
+    let proposal_response = ProposalResponse {
+        version: 0,
+        timestamp: None,
+        response: None,
+        payload: utils::proto::marshal(&payload)?,
+        endorsement: None,
+    };
+
+    let tx = Transaction {
+        signed_proposal: Some(sp),
+        response: vec![proposal_response],
+    };
+    Ok(tx)
+}
+
+pub fn test_provider<P: BlockStoreProvider>(provider: &P) {
+    assert!(!provider.exists("ch1").unwrap());
+    assert!(provider.open_block_store("ch1").is_err());
+    assert!(provider.list().unwrap().is_empty());
+
+    let mut store = provider.create_block_store("ch2").unwrap();
+    store
+        .add_block(&create_block(
+            0,
+            vec![],
+            vec![create_tx("tx_0".to_string()).unwrap()],
+        ))
+        .unwrap();
+    provider.create_block_store("ch1").unwrap();
+    assert!(provider.create_block_store("ch2").is_err());
+    assert!(provider.exists("ch1").unwrap());
+    assert_eq!(provider.list().unwrap(), vec!["ch1", "ch2"]);
+
+    // the blocks are found again by the stores opened later
+    drop(store);
+    let store = provider.open_block_store("ch2").unwrap();
+    assert!(store.retrieve_block_by_number(0).unwrap().is_some());
+    assert!(store.retrieve_tx_by_id("tx_0").unwrap().is_some());
+}
+
+pub fn test_get_blockchain_info<P: BlockStoreProvider>(provider: &P) {
+    let store = provider.create_block_store("empty").unwrap();
+    let info = store.get_blockchain_info().unwrap();
+    assert_eq!(info.height, 0);
+    assert!(info.current_block_hash.is_empty());
+
+    let store = init(provider).unwrap();
+    let info = store.get_blockchain_info().unwrap();
+    assert_eq!(info.height, 100);
+    let blk99 = store.retrieve_block_by_number(99).unwrap().unwrap();
+    assert_eq!(
+        info.previous_block_hash,
+        utils::hash::compute_sha256(&utils::proto::marshal(&blk99.header.unwrap()).unwrap())
+            .to_vec()
+    );
+}
+
+pub fn test_retrieve_block_by_hash<P: BlockStoreProvider>(provider: &P) {
+    let store = init(provider).unwrap();
+    let info = store.get_blockchain_info().unwrap();
+
+    let blk = store
+        .retrieve_block_by_hash(&info.current_block_hash)
+        .unwrap()
+        .unwrap();
+    let mut num = blk.header.as_ref().unwrap().number;
+    assert_eq!(num, 100);
+    let mut hash = blk.header.unwrap().previous_hash;
+    while !hash.is_empty() {
+        let blk = store.retrieve_block_by_hash(&hash).unwrap().unwrap();
+        let header = blk.header.unwrap();
+        assert_eq!(header.number, num - 1);
+        num = header.number;
+        hash = header.previous_hash;
+    }
+    assert_eq!(num, 0);
+    assert!(store.retrieve_block_by_hash(b"unknown").unwrap().is_none());
+}
+
+pub fn test_retrieve_block_by_number<P: BlockStoreProvider>(provider: &P) {
+    let store = init(provider).unwrap();
+
+    for i in 0..=100 {
+        let blk = store.retrieve_block_by_number(i).unwrap().unwrap();
+        assert_eq!(i, blk.header.unwrap().number)
+    }
+
+    let last = store.retrieve_block_by_number(u64::MAX).unwrap();
+    assert_eq!(last.unwrap().header.unwrap().number, 100);
+    let blk1000 = store.retrieve_block_by_number(1000).unwrap();
+    assert!(blk1000.is_none())
+}
+
+pub fn test_retrieve_tx_by_id<P: BlockStoreProvider>(provider: &P) {
+    let store = init(provider).unwrap();
+
+    let tx = store.retrieve_tx_by_id("tx1").unwrap();
+    assert!(tx.is_none());
+
+    let tx = store.retrieve_tx_by_id("tx_12").unwrap().unwrap();
+    let signed_proposal = tx.signed_proposal.unwrap();
+    let proposal = utils::proto::unmarshal::<Proposal>(&signed_proposal.proposal_bytes).unwrap();
+    assert_eq!(proposal.header.unwrap().tx_id, "tx_12");
+}
+
+pub fn test_retrieve_tx_by_blocknum_txnum<P: BlockStoreProvider>(provider: &P) {
+    let store = init(provider).unwrap();
+
+    let tx = store.retrieve_tx_by_blocknum_txnum(99, 2).unwrap();
+    assert!(tx.is_none());
+
+    let tx = store.retrieve_tx_by_blocknum_txnum(101, 0).unwrap();
+    assert!(tx.is_none());
+
+    let tx = store.retrieve_tx_by_blocknum_txnum(10, 0).unwrap().unwrap();
+    let (_, header) =
+        utils::utils::get_tx_header_from_data(&utils::proto::marshal(&tx).unwrap()).unwrap();
+    assert_eq!(header.tx_id, "tx_10");
+}
+
+pub fn test_retrieve_block_by_txid<P: BlockStoreProvider>(provider: &P) {
+    let store = init(provider).unwrap();
+
+    let blk = store.retrieve_block_by_txid("tx1").unwrap();
+    assert!(blk.is_none());
+
+    let blk = store.retrieve_block_by_txid("tx_99").unwrap().unwrap();
+    assert_eq!(blk.header.unwrap().number, 99);
+}
+
+pub fn test_rollback<P: BlockStoreProvider>(provider: &P) {
+    let mut store = init(provider).unwrap();
+
+    assert!(store.rollback(101).is_err());
+    store.rollback(50).unwrap();
+
+    let info = store.get_blockchain_info().unwrap();
+    assert_eq!(info.height, 50);
+    let blk50 = store.retrieve_block_by_number(50).unwrap().unwrap();
+    assert_eq!(
+        info.current_block_hash,
+        utils::hash::compute_sha256(&utils::proto::marshal(&blk50.header.unwrap()).unwrap())
+            .to_vec()
+    );
+    assert!(store.retrieve_block_by_number(51).unwrap().is_none());
+    assert!(store.retrieve_tx_by_id("tx_51").unwrap().is_none());
+    assert!(store.retrieve_tx_by_id("tx_50").unwrap().is_some());
+
+    let block = create_block(
+        51,
+        info.current_block_hash,
+        vec![create_tx("tx_51".to_string()).unwrap()],
+    );
+    store.add_block(&block).unwrap();
+    assert_eq!(store.get_blockchain_info().unwrap().height, 51);
+    assert!(store.retrieve_tx_by_id("tx_51").unwrap().is_some());
+
+    let mut empty = provider.create_block_store("empty").unwrap();
+    assert!(empty.rollback(0).is_err());
+}
+
+pub fn test_rebuild_tx_index<P: BlockStoreProvider>(provider: &P) {
+    let mut store = init(provider).unwrap();
+
+    let info = store.get_blockchain_info().unwrap();
+    let mut block = create_block(
+        101,
+        info.current_block_hash,
+        vec![create_tx("tx_101".to_string()).unwrap()],
+    );
+    utils::utils::set_tx_validation_flags(&mut block, &[TxValidationCode::MvccReadConflict]);
+    store.add_block(&block).unwrap();
+
+    store.rebuild_tx_index().unwrap();
+    for i in 0..=100 {
+        let code = store
+            .retrieve_tx_validation_code_by_txid(&format!("tx_{:}", i))
+            .unwrap();
+        assert_eq!(code, TxValidationCode::Valid)
+    }
+    let code = store.retrieve_tx_validation_code_by_txid("tx_101").unwrap();
+    assert_eq!(code, TxValidationCode::MvccReadConflict);
+}
+
+pub fn test_retrieve_tx_validation_code_by_txid<P: BlockStoreProvider>(provider: &P) {
+    let mut store = init(provider).unwrap();
+
+    let code = store.retrieve_tx_validation_code_by_txid("tx1").unwrap();
+    assert_eq!(code, TxValidationCode::NilEnvelope);
+
+    for i in 0..=100 {
+        let code = store
+            .retrieve_tx_validation_code_by_txid(&format!("tx_{:}", i))
+            .unwrap();
+        assert_eq!(code, TxValidationCode::Valid)
+    }
+
+    // a block already stored or following a gap is not added
+    let block = create_block(100, vec![], vec![create_tx("tx_dup".to_string()).unwrap()]);
+    store.add_block(&block).unwrap();
+    assert_eq!(
+        store.retrieve_tx_validation_code_by_txid("tx_dup").unwrap(),
+        TxValidationCode::NilEnvelope
+    );
+    let block = create_block(102, vec![], vec![create_tx("tx_gap".to_string()).unwrap()]);
+    assert!(store.add_block(&block).is_err());
+}
@@ -107,6 +107,16 @@ impl crate::BlockStore for BlockStore {
     fn retrieve_tx_validation_code_by_txid(&self, _tx_id: &str) -> Result<TxValidationCode> {
         unimplemented!()
     }
+
+    fn rollback(&mut self, _block_num: u64) -> Result<()> {
+        Err(from_str("the file block store does not support rollback"))
+    }
+
+    fn rebuild_tx_index(&mut self) -> Result<()> {
+        Err(from_str(
+            "the file block store does not support rebuilding the tx index",
+        ))
+    }
 }
 
 #[cfg(test)]
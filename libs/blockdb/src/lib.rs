@@ -1,20 +1,25 @@
 #[macro_use]
 extern crate log;
 
+#[cfg(test)]
+#[macro_use]
+mod conformance;
 mod fs;
 mod keys;
+pub mod mem;
 pub mod provider;
-mod store;
+pub mod store;
 use error::*;
 use silk_proto::*;
 
 pub trait BlockStoreProvider {
     type S: BlockStore;
-    fn create_block_store(ledger_id: &str) -> Result<Self::S>;
-    fn open_block_store(ledger_id: &str) -> Result<Self::S>;
-    fn exists(ledger_id: &str) -> Result<bool>;
-    fn list() -> Result<Vec<String>>;
-    fn close();
+    fn create_block_store(&self, ledger_id: &str) -> Result<Self::S>;
+    fn open_block_store(&self, ledger_id: &str) -> Result<Self::S>;
+    fn exists(&self, ledger_id: &str) -> Result<bool>;
+    // list returns the ids of the ledgers having a block store, sorted
+    fn list(&self) -> Result<Vec<String>>;
+    fn close(&self);
 }
 
 // BlockStore - an interface for persisting and retrieving blocks
@@ -37,6 +42,10 @@ pub trait BlockStore {
     ) -> Result<Option<Transaction>>;
     fn retrieve_block_by_txid(&self, tx_id: &str) -> Result<Option<Block>>;
     fn retrieve_tx_validation_code_by_txid(&self, tx_id: &str) -> Result<TxValidationCode>;
+    // rollback removes the blocks above `block_num` together with their indexes
+    fn rollback(&mut self, block_num: u64) -> Result<()>;
+    // rebuild_tx_index drops the transaction index and rebuilds it from the stored blocks
+    fn rebuild_tx_index(&mut self) -> Result<()>;
     fn shutdown() {}
 }
 
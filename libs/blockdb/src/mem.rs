@@ -0,0 +1,330 @@
+use crate::keys::CheckPoint;
+use crate::BlockStore;
+use error::*;
+use silk_proto::{
+    tx_validation_code_from, Block, BlockchainInfo, Transaction, TxIdIndexValProto,
+    TxValidationCode,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// MemBlockStoreProvider keeps the block stores in memory, they are lost with the provider. It
+// behaves as the LevelDBBlockStoreProvider and is meant for the tests
+#[derive(Default)]
+pub struct MemBlockStoreProvider {
+    stores: Mutex<BTreeMap<String, MemStore>>,
+}
+
+impl MemBlockStoreProvider {
+    pub fn new() -> Self {
+        MemBlockStoreProvider::default()
+    }
+
+    fn stores(&self) -> Result<MutexGuard<'_, BTreeMap<String, MemStore>>> {
+        self.stores.lock().map_err(|e| from_str(&e.to_string()))
+    }
+}
+
+impl crate::BlockStoreProvider for MemBlockStoreProvider {
+    type S = MemStore;
+
+    fn create_block_store(&self, ledger_id: &str) -> Result<Self::S> {
+        let mut stores = self.stores()?;
+        if stores.contains_key(ledger_id) {
+            return Err(from_str(&format!(
+                "block store of ledger {:} exists",
+                ledger_id
+            )));
+        }
+        let store = MemStore::default();
+        stores.insert(ledger_id.to_string(), store.clone());
+        Ok(store)
+    }
+
+    fn open_block_store(&self, ledger_id: &str) -> Result<Self::S> {
+        self.stores()?.get(ledger_id).cloned().ok_or_else(|| {
+            from_str(&format!(
+                "block store of ledger {:} does not exist",
+                ledger_id
+            ))
+        })
+    }
+
+    fn exists(&self, ledger_id: &str) -> Result<bool> {
+        Ok(self.stores()?.contains_key(ledger_id))
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        Ok(self.stores()?.keys().cloned().collect())
+    }
+
+    fn close(&self) {}
+}
+
+#[derive(Default)]
+struct MemState {
+    check_point: Option<CheckPoint>,
+    // block hash -> block
+    blocks: HashMap<Vec<u8>, Block>,
+    // block num -> block hash
+    numbers: BTreeMap<u64, Vec<u8>>,
+    // tx id -> block hash and validation code
+    txs: HashMap<String, TxIdIndexValProto>,
+}
+
+// MemStore is a block store held in memory, the stores opened for the same ledger share the blocks
+#[derive(Clone, Default)]
+pub struct MemStore {
+    state: Arc<Mutex<MemState>>,
+}
+
+impl MemStore {
+    fn state(&self) -> Result<MutexGuard<'_, MemState>> {
+        self.state.lock().map_err(|e| from_str(&e.to_string()))
+    }
+}
+
+impl MemState {
+    fn block_by_number(&self, block_num: u64) -> Option<Block> {
+        let num = if block_num == u64::MAX {
+            self.check_point
+                .as_ref()
+                .map(|cp| cp.block_num)
+                .unwrap_or(0)
+        } else {
+            block_num
+        };
+        self.numbers
+            .get(&num)
+            .and_then(|hash| self.blocks.get(hash))
+            .cloned()
+    }
+
+    fn tx_index_val(&self, tx_id: &str) -> TxIdIndexValProto {
+        self.txs
+            .get(tx_id)
+            .cloned()
+            .unwrap_or_else(|| TxIdIndexValProto {
+                block_hash: vec![],
+                tx_validation_code: TxValidationCode::NilEnvelope as i32,
+            })
+    }
+
+    // index_txs maps the id of each transaction of a block to the block hash and the
+    // validation code of the transaction
+    fn index_txs(&mut self, block_hash: &[u8], block: &Block) -> Result<()> {
+        let data = match &block.data {
+            Some(data) => data,
+            None => return Ok(()),
+        };
+        let flags = utils::utils::get_tx_validation_flags(block);
+        for (index, evn) in data.data.iter().enumerate() {
+            let (_, tx_header) = utils::utils::get_tx_header_from_data(evn)?;
+            let code = flags.get(index).copied().unwrap_or(TxValidationCode::Valid);
+            self.txs.insert(
+                tx_header.tx_id,
+                TxIdIndexValProto {
+                    block_hash: block_hash.to_vec(),
+                    tx_validation_code: code as i32,
+                },
+            );
+        }
+        Ok(())
+    }
+}
+
+impl BlockStore for MemStore {
+    fn add_block(&mut self, block: &Block) -> Result<()> {
+        let mut state = self.state()?;
+        let (header, data) = match (&block.header, &block.data) {
+            (Some(header), Some(data)) => (header.clone(), data),
+            _ => return Err(from_str("block header or data is null")),
+        };
+
+        let mut check_point = match state.check_point.take() {
+            Some(cp) => {
+                // the block has been saved
+                if cp.block_num >= header.number {
+                    state.check_point = Some(cp);
+                    return Ok(());
+                }
+
+                // lose blocks
+                if cp.block_num + 1 < header.number {
+                    state.check_point = Some(cp);
+                    return Err(from_str("block number > checkpoint number + 1"));
+                }
+                cp
+            }
+            None => CheckPoint {
+                suffix: 0,
+                offset: 0,
+                block_num: 0,
+                block_hash: vec![],
+                previous_block_hash: vec![],
+                tx_total_count: 0,
+            },
+        };
+        let hash = utils::hash::compute_sha256(&utils::proto::marshal(&header)?).to_vec();
+
+        // the transactions are indexed first, a block that can not be indexed is not stored
+        let txs = state.txs.clone();
+        if let Err(e) = state.index_txs(&hash, block) {
+            state.txs = txs;
+            return Err(e);
+        }
+
+        check_point.block_num = header.number;
+        check_point.block_hash = hash.clone();
+        check_point.previous_block_hash = header.previous_hash;
+        check_point.tx_total_count += data.data.len() as u128;
+        state.check_point = Some(check_point);
+        state.numbers.insert(header.number, hash.clone());
+        state.blocks.insert(hash, block.clone());
+        Ok(())
+    }
+
+    fn get_blockchain_info(&self) -> Result<BlockchainInfo> {
+        let state = self.state()?;
+        match &state.check_point {
+            Some(cp) => Ok(BlockchainInfo {
+                height: cp.block_num,
+                current_block_hash: cp.block_hash.clone(),
+                previous_block_hash: cp.previous_block_hash.clone(),
+            }),
+            None => Ok(BlockchainInfo {
+                height: 0,
+                current_block_hash: vec![],
+                previous_block_hash: vec![],
+            }),
+        }
+    }
+
+    fn retrieve_blocks(
+        &self,
+        start_num: u64,
+    ) -> Result<Box<dyn Iterator<Item = Result<Option<Block>>>>> {
+        let state = self.state()?;
+        let blocks = state
+            .numbers
+            .range(start_num..)
+            .map(|(_, hash)| Ok(state.blocks.get(hash).cloned()))
+            .collect::<Vec<_>>();
+        Ok(Box::new(blocks.into_iter()))
+    }
+
+    fn retrieve_block_by_hash(&self, block_hash: &[u8]) -> Result<Option<Block>> {
+        Ok(self.state()?.blocks.get(block_hash).cloned())
+    }
+
+    fn retrieve_block_by_number(&self, block_num: u64) -> Result<Option<Block>> {
+        Ok(self.state()?.block_by_number(block_num))
+    }
+
+    fn retrieve_tx_by_id(&self, tx_id: &str) -> Result<Option<Transaction>> {
+        let blk = match self.retrieve_block_by_txid(tx_id)? {
+            Some(blk) => blk,
+            None => return Ok(None),
+        };
+        for env in blk.data.unwrap_or_default().data {
+            let (tx, header) = utils::utils::get_tx_header_from_data(&env)?;
+            if header.tx_id == tx_id {
+                return Ok(Some(tx));
+            }
+        }
+        Ok(None)
+    }
+
+    fn retrieve_tx_by_blocknum_txnum(
+        &self,
+        block_num: u64,
+        tx_num: u64,
+    ) -> Result<Option<Transaction>> {
+        let data = match self.retrieve_block_by_number(block_num)? {
+            Some(blk) => blk.data.unwrap_or_default(),
+            None => return Ok(None),
+        };
+        match data.data.get(tx_num as usize) {
+            Some(env) => Ok(Some(utils::utils::get_tx_header_from_data(env)?.0)),
+            None => Ok(None),
+        }
+    }
+
+    fn retrieve_block_by_txid(&self, tx_id: &str) -> Result<Option<Block>> {
+        let state = self.state()?;
+        let index_val = state.tx_index_val(tx_id);
+        Ok(state.blocks.get(&index_val.block_hash).cloned())
+    }
+
+    fn retrieve_tx_validation_code_by_txid(&self, tx_id: &str) -> Result<TxValidationCode> {
+        let index_val = self.state()?.tx_index_val(tx_id);
+        Ok(tx_validation_code_from(index_val.tx_validation_code))
+    }
+
+    fn rollback(&mut self, block_num: u64) -> Result<()> {
+        let mut state = self.state()?;
+        let last = match &state.check_point {
+            Some(cp) => cp.block_num,
+            None => return Err(from_str("block store is empty")),
+        };
+        if block_num > last {
+            return Err(from_str(&format!(
+                "can not roll back to block {:}, the last block is {:}",
+                block_num, last
+            )));
+        }
+        let target = state
+            .block_by_number(block_num)
+            .and_then(|b| b.header)
+            .ok_or_else(|| from_str(&format!("block {:} not found", block_num)))?;
+
+        let removed = state
+            .numbers
+            .range(block_num + 1..)
+            .map(|(_, hash)| hash.clone())
+            .collect::<Vec<_>>();
+        let mut tx_count = 0;
+        for hash in &removed {
+            if let Some(data) = state.blocks.remove(hash).and_then(|b| b.data) {
+                // the entry of a duplicated tx id indexed by a remaining block is kept
+                state.txs.retain(|_, v| &v.block_hash != hash);
+                tx_count += data.data.len() as u128;
+            }
+        }
+        state.numbers.split_off(&(block_num + 1));
+
+        if let Some(cp) = state.check_point.as_mut() {
+            cp.block_num = block_num;
+            cp.block_hash = utils::hash::compute_sha256(&utils::proto::marshal(&target)?).to_vec();
+            cp.previous_block_hash = target.previous_hash;
+            cp.tx_total_count -= tx_count;
+        }
+        Ok(())
+    }
+
+    fn rebuild_tx_index(&mut self) -> Result<()> {
+        let mut state = self.state()?;
+        state.txs.clear();
+        let blocks = state
+            .numbers
+            .values()
+            .map(|hash| (hash.clone(), state.blocks.get(hash).cloned()))
+            .collect::<Vec<_>>();
+        for (hash, block) in blocks {
+            let block = block.ok_or_else(|| from_str("block not found"))?;
+            state.index_txs(&hash, &block)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemBlockStoreProvider;
+
+    fn provider() -> ((), MemBlockStoreProvider) {
+        ((), MemBlockStoreProvider::new())
+    }
+
+    block_store_tests!(provider);
+}
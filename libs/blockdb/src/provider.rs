@@ -1,35 +1,77 @@
 use crate::store::Store;
 
 use error::*;
+use std::fs;
+use std::path::PathBuf;
 
-pub struct LevelDBBlockStoreProvider {}
+// LevelDBBlockStoreProvider keeps the block store of every ledger in its own directory
+pub struct LevelDBBlockStoreProvider {
+    path: PathBuf,
+}
 
 impl LevelDBBlockStoreProvider {
-    pub fn new() -> Self {
-        unimplemented!()
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        LevelDBBlockStoreProvider {
+            path: path.into().join("chains"),
+        }
     }
 }
 
 impl crate::BlockStoreProvider for LevelDBBlockStoreProvider {
     type S = Store;
 
-    fn create_block_store(_ledger_id: &str) -> Result<Self::S> {
-        unimplemented!()
+    fn create_block_store(&self, ledger_id: &str) -> Result<Self::S> {
+        if self.exists(ledger_id)? {
+            return Err(from_str(&format!(
+                "block store of ledger {:} exists",
+                ledger_id
+            )));
+        }
+        Store::open(self.path.join(ledger_id))
     }
 
-    fn open_block_store(_ledger_id: &str) -> Result<Self::S> {
-        unimplemented!()
+    fn open_block_store(&self, ledger_id: &str) -> Result<Self::S> {
+        if !self.exists(ledger_id)? {
+            return Err(from_str(&format!(
+                "block store of ledger {:} does not exist",
+                ledger_id
+            )));
+        }
+        Store::open(self.path.join(ledger_id))
     }
 
-    fn exists(_ledger_id: &str) -> Result<bool> {
-        unimplemented!()
+    fn exists(&self, ledger_id: &str) -> Result<bool> {
+        Ok(self.path.join(ledger_id).is_dir())
     }
 
-    fn list() -> Result<Vec<String>> {
-        unimplemented!()
+    fn list(&self) -> Result<Vec<String>> {
+        if !self.path.is_dir() {
+            return Ok(vec![]);
+        }
+        let mut ids = vec![];
+        for entry in fs::read_dir(&self.path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                ids.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        ids.sort();
+        Ok(ids)
     }
 
-    fn close() {
-        unimplemented!()
+    fn close(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LevelDBBlockStoreProvider;
+    use tempfile::TempDir;
+
+    fn provider() -> (TempDir, LevelDBBlockStoreProvider) {
+        let temp_dir = TempDir::new().unwrap();
+        let provider = LevelDBBlockStoreProvider::new(temp_dir.path());
+        (temp_dir, provider)
     }
+
+    block_store_tests!(provider);
 }
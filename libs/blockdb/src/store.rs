@@ -48,6 +48,32 @@ impl Store {
             }),
         }
     }
+
+    // index_txs maps the id of each transaction of a block to the block hash and the
+    // validation code of the transaction
+    fn index_txs(batch: &mut WriteBatch, block_hash: &[u8], block: &Block) -> Result<()> {
+        let data = match &block.data {
+            Some(data) => data,
+            None => return Ok(()),
+        };
+        let flags = utils::utils::get_tx_validation_flags(block);
+        for (index, evn) in data.data.iter().enumerate() {
+            let (_, tx_header) = utils::utils::get_tx_header_from_data(evn)?;
+            let code = flags.get(index).copied().unwrap_or(TxValidationCode::Valid);
+
+            // mapping tx_id -> TxIdIndexValProto
+            let index_val = TxIdIndexValProto {
+                block_hash: block_hash.to_vec(),
+                tx_validation_code: code as i32,
+            };
+            debug!("tx: {:?} index value: {:?}", tx_header.tx_id, index_val);
+            batch.put(
+                &keys::construct_tx_hash_key(&tx_header.tx_id),
+                &utils::proto::marshal(&index_val)?,
+            );
+        }
+        Ok(())
+    }
 }
 
 impl BlockStore for Store {
@@ -98,20 +124,7 @@ impl BlockStore for Store {
             batch.put(&keys::construct_block_num_key(header.number), &hash);
 
             // record txs id mapping block hash
-            for evn in data.data {
-                let (_, tx_header) = utils::utils::get_tx_header_from_data(&evn)?;
-
-                // mapping tx_id -> TxIdIndexValProto
-                let index_val = TxIdIndexValProto {
-                    block_hash: hash.to_vec(),
-                    tx_validation_code: TxValidationCode::Valid as i32,
-                };
-                debug!("tx: {:?} index value: {:?}", tx_header.tx_id, index_val);
-                batch.put(
-                    &keys::construct_tx_hash_key(&tx_header.tx_id),
-                    &utils::proto::marshal(&index_val)?,
-                );
-            }
+            Store::index_txs(&mut batch, &hash, block)?;
 
             self.db.write(batch)?;
             self.db.flush()?;
@@ -229,222 +242,98 @@ impl BlockStore for Store {
         self.get_tx_validation_code_by_txid(tx_id)
             .map(|v| tx_validation_code_from(v.tx_validation_code))
     }
-}
 
-// ResultsIterator iterates over query results
-pub struct BlockIterator {
-    curr: u64,
-}
-
-impl Iterator for BlockIterator {
-    type Item = Result<Option<Block>>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        unimplemented!()
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use crate::store::Store;
-    use crate::BlockStore;
-    use error::*;
-    use silk_proto::*;
-    use tempfile::TempDir;
-
-    fn init() -> Result<Store> {
-        let temp_dir = TempDir::new().unwrap();
-        let mut store = Store::open(temp_dir.into_path())?;
-
-        store.add_block(&create_block(
-            0,
-            vec![],
-            vec![create_tx("tx_0".to_string())?],
-        ))?;
-
-        for i in 1..=100 {
-            let info = store.get_blockchain_info()?;
-            let block = create_block(
-                i as u64,
-                info.current_block_hash,
-                vec![create_tx(format!("tx_{:}", i))?],
-            );
-            store.add_block(&block)?;
+    fn rollback(&mut self, block_num: u64) -> Result<()> {
+        let mut check_point: keys::CheckPoint = self
+            .get(&keys::construct_check_point_key())?
+            .ok_or_else(|| from_str("block store is empty"))?;
+        if block_num > check_point.block_num {
+            return Err(from_str(&format!(
+                "can not roll back to block {:}, the last block is {:}",
+                block_num, check_point.block_num
+            )));
         }
-        Ok(store)
-    }
-
-    fn create_block(num: u64, prev_hash: Vec<u8>, txs: Vec<Transaction>) -> Block {
-        let data: Vec<Vec<u8>> = txs
-            .iter()
-            .map(|t| utils::proto::marshal(t).unwrap())
-            .collect();
-
-        Block {
-            header: Some(BlockHeader {
-                number: num,
-                previous_hash: prev_hash,
-                data_hash: utils::hash::compute_vec_sha256(&data).to_vec(),
-            }),
-            data: Some(BlockData { data }),
-            metadata: None,
-        }
-    }
 
-    fn create_tx(txid: String) -> Result<Transaction> {
-        let payload = ContractProposalPayload {
-            contract_id: None,
-            input: None,
-            transient_map: Default::default(),
-            timeout: 0,
-        };
-
-        let proposal = Proposal {
-            header: Some(Header {
-                header_type: HeaderType::Invoke as i32,
-                version: 0,
-                timestamp: Some(utils::time::timestamp()),
-                channel_id: "chain_id".to_string(),
-                tx_id: txid,
-                tls_cert_hash: vec![],
-                creator: vec![],
-                nonce: utils::random::get_random_nonce(),
-            }),
-            payload: utils::proto::marshal(&payload)?,
-        };
-        let sp = SignedProposal {
-            proposal_bytes: utils::proto::marshal(&proposal)?,
-            signature: vec![],
-        };
+        let target = self
+            .retrieve_block_by_number(block_num)?
+            .and_then(|b| b.header)
+            .ok_or_else(|| from_str(&format!("block {:} not found", block_num)))?;
 
-        let payload = ProposalResponsePayload {
-            results: vec![],
-            events: vec![],
-        };
-        let proposal_response = ProposalResponse {
-            version: 0,
-            timestamp: None,
-            response: None,
-            payload: utils::proto::marshal(&payload)?,
-            endorsement: None,
-        };
+        let mut batch = WriteBatch::default();
+        for num in block_num + 1..=check_point.block_num {
+            let hash = self
+                .db
+                .get(&keys::construct_block_num_key(num))?
+                .ok_or_else(|| from_str(&format!("block {:} not found", num)))?;
+            if let Some(data) = self.retrieve_block_by_hash(&hash)?.and_then(|b| b.data) {
+                for evn in &data.data {
+                    let (_, tx_header) = utils::utils::get_tx_header_from_data(evn)?;
+                    // keep the entry of a duplicated tx id indexed by a remaining block
+                    let index_val = self.get_tx_validation_code_by_txid(&tx_header.tx_id)?;
+                    if index_val.block_hash == hash {
+                        batch.delete(&keys::construct_tx_hash_key(&tx_header.tx_id));
+                    }
+                }
+                check_point.tx_total_count -= data.data.len() as u128;
+            }
+            batch.delete(&keys::construct_block_hash_key(&hash));
+            batch.delete(&keys::construct_block_num_key(num));
+        }
 
-        let tx = Transaction {
-            signed_proposal: Some(sp),
-            response: vec![proposal_response],
-        };
-        Ok(tx)
+        check_point.block_num = block_num;
+        check_point.block_hash =
+            utils::hash::compute_sha256(&utils::proto::marshal(&target)?).to_vec();
+        check_point.previous_block_hash = target.previous_hash;
+        batch.put(
+            &keys::construct_check_point_key(),
+            &serde_json::to_vec(&check_point)?,
+        );
+
+        self.db.write(batch)?;
+        self.db.flush()?;
+        Ok(())
     }
 
-    #[test]
-    fn test_get_blockchain_info() {
-        let store = init().unwrap();
-        let info = store.get_blockchain_info().unwrap();
-        assert_eq!(info.height, 100);
-        println!("{:?}", info);
-    }
+    fn rebuild_tx_index(&mut self) -> Result<()> {
+        let check_point: Option<keys::CheckPoint> = self.get(&keys::construct_check_point_key())?;
+        let mut batch = WriteBatch::default();
 
-    #[test]
-    fn test_retrieve_blocks() {}
-
-    #[test]
-    fn test_retrieve_block_by_hash() {
-        let store = init().unwrap();
-        let info = store.get_blockchain_info().unwrap();
-
-        let blk = store
-            .retrieve_block_by_hash(&info.current_block_hash)
-            .unwrap()
-            .unwrap();
-        println!("{:?}", blk);
-        let mut hash = blk.header.unwrap().previous_hash;
-        while hash.len() > 0 {
-            let blk = store.retrieve_block_by_hash(&hash).unwrap().unwrap();
-            let header = blk.header.unwrap();
-            println!(
-                "{:?} : {:?} {:?}",
-                header.number,
-                header.data_hash.len(),
-                header.previous_hash.len()
-            );
-            hash = header.previous_hash;
+        let prefix = [keys::TX_ID_IDX_KEY_PREFIX];
+        for (k, _) in self.db.prefix_iterator(&prefix) {
+            if !k.starts_with(&prefix) {
+                break;
+            }
+            batch.delete(&k);
         }
-    }
 
-    #[test]
-    fn test_retrieve_block_by_number() {
-        let store = init().unwrap();
-
-        for i in 0..=100 {
-            let blk = store.retrieve_block_by_number(i).unwrap().unwrap();
-            let header = blk.header.unwrap();
-            println!(
-                "{:?} : {:?} {:?}",
-                header.number,
-                header.data_hash.len(),
-                header.previous_hash
-            );
-            assert_eq!(i, header.number)
+        if let Some(cp) = check_point {
+            for num in 0..=cp.block_num {
+                let hash = self
+                    .db
+                    .get(&keys::construct_block_num_key(num))?
+                    .ok_or_else(|| from_str(&format!("block {:} not found", num)))?;
+                let block = self
+                    .retrieve_block_by_hash(&hash)?
+                    .ok_or_else(|| from_str(&format!("block {:} not found", num)))?;
+                Store::index_txs(&mut batch, &hash, &block)?;
+            }
         }
 
-        let blk1000 = store.retrieve_block_by_number(1000).unwrap();
-        assert!(blk1000.is_none())
-    }
-
-    #[test]
-    fn test_retrieve_tx_by_id() {
-        let store = init().unwrap();
-
-        let tx = store.retrieve_tx_by_id("tx1").unwrap();
-        assert!(tx.is_none());
-
-        let tx = store.retrieve_tx_by_id("tx_12").unwrap().unwrap();
-        println!("{:?}", tx);
-
-        let signed_proposal = tx.signed_proposal.unwrap();
-
-        let proposal =
-            utils::proto::unmarshal::<Proposal>(&signed_proposal.proposal_bytes).unwrap();
-        println!("{:?}", proposal)
-    }
-
-    #[test]
-    fn test_retrieve_tx_by_blocknum_txnum() {
-        let store = init().unwrap();
-
-        let tx = store.retrieve_tx_by_blocknum_txnum(99, 2).unwrap();
-        assert!(tx.is_none());
-
-        let tx = store.retrieve_tx_by_blocknum_txnum(100, 1).unwrap();
-        assert!(tx.is_none());
-
-        let tx = store.retrieve_tx_by_blocknum_txnum(10, 0).unwrap().unwrap();
-        println!("{:?}", tx)
-    }
-
-    #[test]
-    fn test_retrieve_block_by_txid() {
-        let store = init().unwrap();
-
-        let blk = store.retrieve_block_by_txid("tx1").unwrap();
-        assert!(blk.is_none());
-
-        let blk = store.retrieve_block_by_txid("tx_99").unwrap().unwrap();
-        println!("{:?}", blk);
+        self.db.write(batch)?;
+        self.db.flush()?;
+        Ok(())
     }
+}
 
-    #[test]
-    fn test_retrieve_tx_validation_code_by_txid() {
-        let store = init().unwrap();
+// ResultsIterator iterates over query results
+pub struct BlockIterator {
+    curr: u64,
+}
 
-        let code = store.retrieve_tx_validation_code_by_txid("tx1").unwrap();
-        assert_eq!(code, TxValidationCode::NilEnvelope);
+impl Iterator for BlockIterator {
+    type Item = Result<Option<Block>>;
 
-        for i in 0..=100 {
-            let code = store
-                .retrieve_tx_validation_code_by_txid(&format!("tx_{:}", i))
-                .unwrap();
-            assert_eq!(code, TxValidationCode::Valid)
-        }
+    fn next(&mut self) -> Option<Self::Item> {
+        unimplemented!()
     }
 }
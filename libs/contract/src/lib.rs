@@ -1,3 +1,6 @@
+#[macro_use]
+extern crate log;
+
 mod stub;
 pub use stub::*;
 
@@ -5,9 +8,10 @@ mod shim;
 pub use shim::*;
 use silk_proto::*;
 
-pub trait Contract {
-    fn init<'a>(&self, stub: &'a mut ContractStub) -> Response;
-    fn invoke<'a>(&self, stub: &'a mut ContractStub) -> Response;
+#[async_trait::async_trait]
+pub trait Contract: Send + Sync {
+    async fn init(&self, stub: &mut ContractStub) -> Response;
+    async fn invoke(&self, stub: &mut ContractStub) -> Response;
 }
 
 #[repr(i32)]
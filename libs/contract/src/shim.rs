@@ -1,54 +1,153 @@
+use std::collections::HashMap;
 use std::error::Error;
 
 use tonic::transport::Channel;
 
 use silk_proto::contract_client::ContractClient;
 use silk_proto::*;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 
-use super::Contract;
+use super::{response_failed, Contract};
 use crate::ContractStub;
 use silk_proto::message::MessageType;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tonic::Request;
 
+// PeerHandle sends the messages of the contract to the peer and routes the replies of the peer
+// to the pending requests. A transaction has one request pending at most, the requests are keyed
+// by the correlation id of their transaction
+#[derive(Clone)]
+pub(crate) struct PeerHandle {
+    sender: mpsc::Sender<Message>,
+    pending: Arc<Mutex<HashMap<String, oneshot::Sender<Message>>>>,
+}
+
+impl PeerHandle {
+    pub(crate) fn new(sender: mpsc::Sender<Message>) -> Self {
+        PeerHandle {
+            sender,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub(crate) async fn send(&self, msg: Message) -> error::Result<()> {
+        self.sender.send(msg).await?;
+        Ok(())
+    }
+
+    // request sends a message to the peer and waits for its reply, a state error of the peer is
+    // returned as an error
+    pub(crate) async fn request(&self, msg: Message) -> error::Result<Message> {
+        let (tx, rx) = oneshot::channel();
+        let key = msg.correlation_id.clone();
+        self.pending
+            .lock()
+            .map_err(|e| error::from_str(&e.to_string()))?
+            .insert(key.clone(), tx);
+        if let Err(e) = self.sender.send(msg).await {
+            self.pending
+                .lock()
+                .map_err(|e| error::from_str(&e.to_string()))?
+                .remove(&key);
+            return Err(e.into());
+        }
+
+        let reply = rx.await?;
+        if reply.message_type == MessageType::ContractStateError as i32 {
+            return Err(error::from_str(&String::from_utf8_lossy(&reply.content)));
+        }
+        Ok(reply)
+    }
+
+    // respond hands a reply of the peer to the request waiting for it
+    pub(crate) fn respond(&self, msg: Message) -> error::Result<()> {
+        let waiter = self
+            .pending
+            .lock()
+            .map_err(|e| error::from_str(&e.to_string()))?
+            .remove(&msg.correlation_id);
+        match waiter {
+            Some(waiter) => waiter
+                .send(msg)
+                .map_err(|_| error::from_str("request canceled")),
+            None => Err(error::from_str(&format!(
+                "no request pending for {:}",
+                msg.correlation_id
+            ))),
+        }
+    }
+}
+
 async fn run_register(
     client: &mut ContractClient<Channel>,
     name: String,
     contract: Arc<Box<dyn Contract>>,
 ) -> Result<(), Box<dyn Error>> {
     let (tx, rx) = mpsc::channel(1000);
+    let peer = PeerHandle::new(tx);
 
     let reg = ContractRegister {
         name,
         decorations: Default::default(),
+        version: std::env::var("SILK_CONTRACT_VERSION").unwrap_or_default(),
+        package_hash: package_hash()?,
+        launch_id: std::env::var("SILK_CONTRACT_LAUNCH_ID").unwrap_or_default(),
     };
 
     let msg = silk_proto::Message {
         message_type: silk_proto::message::MessageType::ContractRegister as i32,
         correlation_id: "".to_string(),
-        content: utils::proto::marshal(&reg).unwrap(),
+        content: utils::proto::marshal(&reg)?,
     };
     // send register message to peer
-    tx.send(msg).await?;
+    peer.send(msg).await.map_err(|e| e.to_string())?;
 
     let response = client.register(Request::new(rx)).await?;
     let mut inbound = response.into_inner();
 
-    while let Some(msg) = inbound.message().await.unwrap() {
-        println!("MESSAGE = {:?}", msg);
+    while let Some(msg) = inbound.message().await? {
+        debug!("message {:?}", msg);
         match msg.message_type {
             t if t == message::MessageType::ContractTransaction as i32 => {
-                println!("start transaction ...");
-                let ct: ContractTransaction =
-                    utils::proto::unmarshal(msg.content.as_slice()).unwrap();
-                let ret = start_transaction(client, contract.clone(), ct);
-                let m = Message {
-                    message_type: MessageType::ContractTransactionCompletedRequest as i32,
-                    correlation_id: msg.correlation_id,
-                    content: utils::proto::marshal(&ret).map_err(Box::new)?,
+                let ct: ContractTransaction = match utils::proto::unmarshal(&msg.content) {
+                    Ok(ct) => ct,
+                    Err(e) => {
+                        warn!("invalid transaction {:?}: {:}", msg.correlation_id, e);
+                        continue;
+                    }
                 };
-                tx.send(m).await?
+                // the transaction runs apart, its state requests are answered by this loop
+                let peer = peer.clone();
+                let contract = contract.clone();
+                tokio::spawn(async move {
+                    let correlation_id = msg.correlation_id;
+                    let ret =
+                        start_transaction(peer.clone(), correlation_id.clone(), contract, ct).await;
+                    let content = match utils::proto::marshal(&ret) {
+                        Ok(content) => content,
+                        Err(e) => {
+                            error!("failed to marshal transaction {:?}: {:}", correlation_id, e);
+                            return;
+                        }
+                    };
+                    let m = Message {
+                        message_type: MessageType::ContractTransactionCompletedRequest as i32,
+                        correlation_id,
+                        content,
+                    };
+                    if let Err(e) = peer.send(m).await {
+                        error!("send transaction completed error {:?}", e);
+                    }
+                });
+            }
+            t if t == message::MessageType::ContractGetStateResponse as i32
+                || t == message::MessageType::ContractPutStateResponse as i32
+                || t == message::MessageType::ContractDelStateResponse as i32
+                || t == message::MessageType::ContractStateError as i32 =>
+            {
+                if let Err(e) = peer.respond(msg) {
+                    warn!("unexpected state response: {:?}", e);
+                }
             }
             t if t == message::MessageType::Unregister as i32 => {
                 // todo: close client
@@ -57,7 +156,7 @@ async fn run_register(
                 ));
             }
             _ => {
-                println!("unhandled massage type {:?}", msg.message_type);
+                warn!("unhandled message type {:?}", msg.message_type);
             }
         }
     }
@@ -65,14 +164,44 @@ async fn run_register(
     Ok(())
 }
 
-fn start_transaction(
-    client: &mut ContractClient<Channel>,
+// package_hash returns the hash of the package of the contract, the peers only run the instances
+// whose package is the one of the contract definition. It is read in hex from the
+// SILK_CONTRACT_PACKAGE_HASH environment variable, the sha256 of the executable by default
+fn package_hash() -> Result<Vec<u8>, Box<dyn Error>> {
+    if let Ok(hash) = std::env::var("SILK_CONTRACT_PACKAGE_HASH") {
+        return Ok(hex::decode(hash)?);
+    }
+    let exe = std::fs::read(std::env::current_exe()?)?;
+    Ok(utils::hash::compute_sha256(&exe).to_vec())
+}
+
+async fn start_transaction(
+    peer: PeerHandle,
+    correlation_id: String,
     contract: Arc<Box<dyn Contract>>,
     tx: ContractTransaction,
 ) -> TransactionCompleted {
-    let proposal = tx.proposal.unwrap();
-    let mut stub = ContractStub::new(client, proposal.clone());
-    let resp = contract.invoke(&mut stub);
+    let proposal = match tx.proposal {
+        Some(proposal) => proposal,
+        None => {
+            return TransactionCompleted {
+                proposal: None,
+                response: Some(response_failed("proposal is null".to_string())),
+                event: None,
+            }
+        }
+    };
+    let is_init = utils::proto::unmarshal::<ContractProposalPayload>(&proposal.payload)
+        .ok()
+        .and_then(|payload| payload.input)
+        .map(|input| input.is_init)
+        .unwrap_or(false);
+    let mut stub = ContractStub::new(peer, correlation_id, proposal.clone());
+    let resp = if is_init {
+        contract.init(&mut stub).await
+    } else {
+        contract.invoke(&mut stub).await
+    };
     TransactionCompleted {
         proposal: Some(proposal),
         response: Some(resp),
@@ -80,11 +209,45 @@ fn start_transaction(
     }
 }
 
+// connect connects to the peer at address, over TLS when the
+// SILK_CONTRACT_TLS_ROOT_CERT_FILE, SILK_CONTRACT_TLS_CERT_FILE and SILK_CONTRACT_TLS_KEY_FILE
+// environment variables name the TLS material
+async fn connect(address: String) -> Result<ContractClient<Channel>, Box<dyn Error>> {
+    let tls = std::env::var("SILK_CONTRACT_TLS_ROOT_CERT_FILE").ok();
+    match tls {
+        #[cfg(feature = "tls")]
+        Some(root_cert) => {
+            use tonic::transport::{Certificate, ClientTlsConfig, Identity};
+
+            let root_cert = std::fs::read(root_cert)?;
+            let cert = std::fs::read(std::env::var("SILK_CONTRACT_TLS_CERT_FILE")?)?;
+            let key = std::fs::read(std::env::var("SILK_CONTRACT_TLS_KEY_FILE")?)?;
+            let tls = ClientTlsConfig::new()
+                .ca_certificate(Certificate::from_pem(root_cert))
+                .identity(Identity::from_pem(cert, key));
+            let channel = Channel::from_shared(address)?
+                .tls_config(tls)?
+                .connect()
+                .await?;
+            Ok(ContractClient::new(channel))
+        }
+        #[cfg(not(feature = "tls"))]
+        Some(_) => Err("the contract is built without the tls feature".into()),
+        None => Ok(ContractClient::connect(address).await?),
+    }
+}
+
+// start registers the contract at the peer and serves its transactions. The peer address and the
+// contract name are overridden by the SILK_PEER_ADDRESS and SILK_CONTRACT_NAME environment
+// variables, which the peer sets for the contracts it launches
 pub async fn start(
     name: String,
     contract: Box<dyn Contract>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = ContractClient::connect("http://127.0.0.1:8081").await?;
+    let address =
+        std::env::var("SILK_PEER_ADDRESS").unwrap_or_else(|_| "http://127.0.0.1:8081".to_string());
+    let name = std::env::var("SILK_CONTRACT_NAME").unwrap_or(name);
+    let mut client = connect(address).await?;
 
     run_register(&mut client, name, Arc::new(contract)).await?;
 
@@ -1,18 +1,24 @@
+use crate::shim::PeerHandle;
 use error::*;
-use silk_proto::contract_client::ContractClient;
+use silk_proto::message::MessageType;
 use silk_proto::*;
-use tonic::transport::Channel;
+use std::collections::HashMap;
 
-pub struct ContractStub<'a> {
-    client: &'a ContractClient<Channel>,
+// VALIDATION_PARAMETER is the metadata entry the peers read the endorsement policy of a key from
+const VALIDATION_PARAMETER: &str = "VALIDATION_PARAMETER";
+
+pub struct ContractStub {
+    peer: PeerHandle,
+    correlation_id: String,
     proposal: Proposal,
     event: Option<ContractEvent>,
 }
 
-impl<'a> ContractStub<'a> {
-    pub fn new(client: &'a ContractClient<Channel>, proposal: Proposal) -> Self {
+impl ContractStub {
+    pub(crate) fn new(peer: PeerHandle, correlation_id: String, proposal: Proposal) -> Self {
         ContractStub {
-            client,
+            peer,
+            correlation_id,
             proposal,
             event: None,
         }
@@ -22,28 +28,291 @@ impl<'a> ContractStub<'a> {
         unimplemented!()
     }
 
-    fn get_txid(&self) -> String {
-        unimplemented!()
+    pub fn get_txid(&self) -> String {
+        self.proposal
+            .header
+            .as_ref()
+            .map(|h| h.tx_id.clone())
+            .unwrap_or_default()
     }
 
-    fn get_channel_id(&self) -> String {
-        unimplemented!()
+    pub fn get_channel_id(&self) -> String {
+        self.proposal
+            .header
+            .as_ref()
+            .map(|h| h.channel_id.clone())
+            .unwrap_or_default()
     }
 
     fn get_address(&self) -> Result<String> {
         unimplemented!()
     }
 
-    async fn get_state(&mut self, _key: &str) -> Result<Vec<u8>> {
-        unimplemented!()
+    // request sends a state request of the transaction to the peer and checks the type of the reply
+    async fn request(
+        &self,
+        message_type: MessageType,
+        content: Vec<u8>,
+        expected: MessageType,
+    ) -> Result<Message> {
+        let reply = self
+            .peer
+            .request(Message {
+                message_type: message_type as i32,
+                correlation_id: self.correlation_id.clone(),
+                content,
+            })
+            .await?;
+        if reply.message_type != expected as i32 {
+            return Err(from_str(&format!(
+                "unexpected reply {:} to {:?}",
+                reply.message_type, message_type
+            )));
+        }
+        Ok(reply)
     }
 
-    fn put_state(&mut self, _key: String, _value: &[u8]) -> Result<()> {
-        unimplemented!()
+    // get_state returns the value of a key of the contract, empty if the key does not exist
+    pub async fn get_state(&mut self, key: &str) -> Result<Vec<u8>> {
+        let req = GetStateRequest {
+            key: key.to_string(),
+            channel_id: self.get_channel_id(),
+            tx_id: self.get_txid(),
+        };
+        let reply = self
+            .request(
+                MessageType::ContractGetStateRequest,
+                utils::proto::marshal(&req)?,
+                MessageType::ContractGetStateResponse,
+            )
+            .await?;
+        let resp: GetStateResponse = utils::proto::unmarshal(&reply.content)?;
+        Ok(resp.value)
     }
 
-    fn del_state(&mut self, _key: &str) -> Result<()> {
-        unimplemented!()
+    // put_state writes the value of a key of the contract in the transaction
+    pub async fn put_state(&mut self, key: &str, value: &[u8]) -> Result<()> {
+        let req = PutStateRequest {
+            key: key.to_string(),
+            value: value.to_vec(),
+            channel_id: self.get_channel_id(),
+            tx_id: self.get_txid(),
+        };
+        self.request(
+            MessageType::ContractPutStateRequest,
+            utils::proto::marshal(&req)?,
+            MessageType::ContractPutStateResponse,
+        )
+        .await?;
+        Ok(())
+    }
+
+    // del_state deletes a key of the contract in the transaction
+    pub async fn del_state(&mut self, key: &str) -> Result<()> {
+        let req = DelStateRequest {
+            key: key.to_string(),
+            channel_id: self.get_channel_id(),
+            tx_id: self.get_txid(),
+        };
+        self.request(
+            MessageType::ContractDelStateRequest,
+            utils::proto::marshal(&req)?,
+            MessageType::ContractDelStateResponse,
+        )
+        .await?;
+        Ok(())
+    }
+
+    // set_state_metadata sets an entry of the metadata of a key, the value of the key and the other
+    // entries are unchanged
+    pub async fn set_state_metadata(&mut self, key: &str, name: &str, value: &[u8]) -> Result<()> {
+        let mut entries = self.get_state_metadata(key).await?;
+        entries.insert(name.to_string(), value.to_vec());
+        let req = PutStateMetadataRequest {
+            key: key.to_string(),
+            entries,
+            channel_id: self.get_channel_id(),
+            tx_id: self.get_txid(),
+        };
+        self.request(
+            MessageType::ContractPutStateMetadataRequest,
+            utils::proto::marshal(&req)?,
+            MessageType::ContractPutStateMetadataResponse,
+        )
+        .await?;
+        Ok(())
+    }
+
+    // get_state_metadata returns the entries of the metadata of a key
+    pub async fn get_state_metadata(&mut self, key: &str) -> Result<HashMap<String, Vec<u8>>> {
+        let req = GetStateMetadataRequest {
+            key: key.to_string(),
+            channel_id: self.get_channel_id(),
+            tx_id: self.get_txid(),
+        };
+        let reply = self
+            .request(
+                MessageType::ContractGetStateMetadataRequest,
+                utils::proto::marshal(&req)?,
+                MessageType::ContractGetStateMetadataResponse,
+            )
+            .await?;
+        let resp: GetStateMetadataResponse = utils::proto::unmarshal(&reply.content)?;
+        Ok(resp.entries)
+    }
+
+    // set_state_validation_parameter sets the endorsement policy of a key, the transactions
+    // writing the key must then be endorsed by all the organizations of the policy
+    pub async fn set_state_validation_parameter(
+        &mut self,
+        key: &str,
+        policy: &KeyEndorsementPolicy,
+    ) -> Result<()> {
+        let policy = utils::proto::marshal(policy)?;
+        self.set_state_metadata(key, VALIDATION_PARAMETER, &policy)
+            .await
+    }
+
+    // get_state_validation_parameter returns the endorsement policy of a key, if any
+    pub async fn get_state_validation_parameter(
+        &mut self,
+        key: &str,
+    ) -> Result<Option<KeyEndorsementPolicy>> {
+        match self
+            .get_state_metadata(key)
+            .await?
+            .get(VALIDATION_PARAMETER)
+        {
+            Some(policy) => Ok(Some(utils::proto::unmarshal(policy)?)),
+            None => Ok(None),
+        }
+    }
+
+    // get_state_by_range returns the key-values of the contract between start_key (included) and
+    // end_key (excluded), an empty end_key refers to the last key
+    pub async fn get_state_by_range(&mut self, start_key: &str, end_key: &str) -> Result<Vec<Kv>> {
+        let (kvs, _) = self.query_range(start_key, end_key, "", 0).await?;
+        Ok(kvs)
+    }
+
+    // get_state_by_range_with_pagination returns a page of at most page_size key-values of the
+    // range, from the bookmark of the previous page if not empty, with the bookmark of the next
+    // page. The bookmark is empty on the last page
+    pub async fn get_state_by_range_with_pagination(
+        &mut self,
+        start_key: &str,
+        end_key: &str,
+        page_size: u32,
+        bookmark: &str,
+    ) -> Result<(Vec<Kv>, String)> {
+        if page_size == 0 {
+            return Err(from_str("page size must be positive"));
+        }
+        self.query_range(start_key, end_key, bookmark, page_size)
+            .await
+    }
+
+    async fn query_range(
+        &mut self,
+        start_key: &str,
+        end_key: &str,
+        bookmark: &str,
+        page_size: u32,
+    ) -> Result<(Vec<Kv>, String)> {
+        let req = GetStateByRangeRequest {
+            start_key: start_key.to_string(),
+            end_key: end_key.to_string(),
+            channel_id: self.get_channel_id(),
+            tx_id: self.get_txid(),
+            bookmark: bookmark.to_string(),
+            page_size,
+        };
+        let reply = self
+            .request(
+                MessageType::ContractGetStateByRangeRequest,
+                utils::proto::marshal(&req)?,
+                MessageType::ContractQueryStateResponse,
+            )
+            .await?;
+        let resp: QueryStateResponse = utils::proto::unmarshal(&reply.content)?;
+        Ok((resp.kvs, resp.bookmark))
+    }
+
+    // get_query_result returns the key-values of the contract matching a rich query
+    pub async fn get_query_result(&mut self, query: &str) -> Result<Vec<Kv>> {
+        let (kvs, _) = self.query(query, "", 0).await?;
+        Ok(kvs)
+    }
+
+    // get_query_result_with_pagination returns a page of at most page_size results of a rich
+    // query, from the bookmark of the previous page if not empty, with the bookmark of the next
+    // page. The bookmark is empty on the last page
+    pub async fn get_query_result_with_pagination(
+        &mut self,
+        query: &str,
+        page_size: u32,
+        bookmark: &str,
+    ) -> Result<(Vec<Kv>, String)> {
+        if page_size == 0 {
+            return Err(from_str("page size must be positive"));
+        }
+        self.query(query, bookmark, page_size).await
+    }
+
+    async fn query(
+        &mut self,
+        query: &str,
+        bookmark: &str,
+        page_size: u32,
+    ) -> Result<(Vec<Kv>, String)> {
+        let req = GetQueryResultRequest {
+            query: query.to_string(),
+            channel_id: self.get_channel_id(),
+            tx_id: self.get_txid(),
+            bookmark: bookmark.to_string(),
+            page_size,
+        };
+        let reply = self
+            .request(
+                MessageType::ContractGetQueryResultRequest,
+                utils::proto::marshal(&req)?,
+                MessageType::ContractQueryStateResponse,
+            )
+            .await?;
+        let resp: QueryStateResponse = utils::proto::unmarshal(&reply.content)?;
+        Ok((resp.kvs, resp.bookmark))
+    }
+
+    // get_state_by_partial_composite_key returns the key-values whose composite key starts with
+    // the given object type and attributes
+    pub async fn get_state_by_partial_composite_key(
+        &mut self,
+        object_type: &str,
+        attributes: &[&str],
+    ) -> Result<Vec<Kv>> {
+        let req = GetStateByPartialCompositeKeyRequest {
+            object_type: object_type.to_string(),
+            attributes: attributes.iter().map(|a| a.to_string()).collect(),
+            channel_id: self.get_channel_id(),
+            tx_id: self.get_txid(),
+        };
+        let reply = self
+            .request(
+                MessageType::ContractGetStateByPartialCompositeKeyRequest,
+                utils::proto::marshal(&req)?,
+                MessageType::ContractQueryStateResponse,
+            )
+            .await?;
+        let resp: QueryStateResponse = utils::proto::unmarshal(&reply.content)?;
+        Ok(resp.kvs)
+    }
+
+    pub fn create_composite_key(&self, object_type: &str, attributes: &[&str]) -> Result<String> {
+        utils::composite_key::create_composite_key(object_type, attributes)
+    }
+
+    pub fn split_composite_key(&self, composite_key: &str) -> Result<(String, Vec<String>)> {
+        utils::composite_key::split_composite_key(composite_key)
     }
 
     fn get_time(&self) -> Result<prost_types::Timestamp> {
@@ -58,3 +327,247 @@ impl<'a> ContractStub<'a> {
         self.event.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ContractStub;
+    use crate::shim::PeerHandle;
+    use silk_proto::message::MessageType;
+    use silk_proto::*;
+    use std::collections::{BTreeMap, HashMap};
+    use tokio::sync::mpsc;
+
+    // range returns the key-values of the state between start_key and end_key
+    fn range(state: &BTreeMap<String, Vec<u8>>, start_key: &str, end_key: &str) -> Vec<Kv> {
+        state
+            .iter()
+            .filter(|(k, _)| {
+                k.as_str() >= start_key && (end_key.is_empty() || k.as_str() < end_key)
+            })
+            .map(|(k, v)| Kv {
+                namespace: "cc".to_string(),
+                key: k.clone(),
+                value: v.clone(),
+            })
+            .collect()
+    }
+
+    // page keeps the first page_size key-values from the bookmark, the key of the next one is the
+    // bookmark of the next page
+    fn page(kvs: Vec<Kv>, bookmark: &str, page_size: u32) -> Vec<u8> {
+        let mut kvs: Vec<Kv> = kvs
+            .into_iter()
+            .filter(|kv| kv.key.as_str() >= bookmark)
+            .collect();
+        let mut bookmark = String::new();
+        if page_size > 0 && kvs.len() > page_size as usize {
+            bookmark = kvs[page_size as usize].key.clone();
+            kvs.truncate(page_size as usize);
+        }
+        utils::proto::marshal(&QueryStateResponse { kvs, bookmark }).unwrap()
+    }
+
+    // fake_peer answers the state requests of the contract from a map
+    async fn fake_peer(peer: PeerHandle, mut rx: mpsc::Receiver<Message>) {
+        let mut state: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+        let mut metadata: HashMap<String, HashMap<String, Vec<u8>>> = HashMap::new();
+        while let Some(msg) = rx.recv().await {
+            let (message_type, content) = match msg.message_type {
+                t if t == MessageType::ContractGetStateRequest as i32 => {
+                    let req: GetStateRequest = utils::proto::unmarshal(&msg.content).unwrap();
+                    let value = state.get(&req.key).cloned().unwrap_or_default();
+                    (
+                        MessageType::ContractGetStateResponse,
+                        utils::proto::marshal(&GetStateResponse { value }).unwrap(),
+                    )
+                }
+                t if t == MessageType::ContractPutStateRequest as i32 => {
+                    let req: PutStateRequest = utils::proto::unmarshal(&msg.content).unwrap();
+                    assert_eq!(req.tx_id, "tx1");
+                    if req.key.is_empty() {
+                        (MessageType::ContractStateError, b"key is empty".to_vec())
+                    } else {
+                        state.insert(req.key, req.value);
+                        (MessageType::ContractPutStateResponse, vec![])
+                    }
+                }
+                t if t == MessageType::ContractGetStateMetadataRequest as i32 => {
+                    let req: GetStateMetadataRequest =
+                        utils::proto::unmarshal(&msg.content).unwrap();
+                    let entries = metadata.get(&req.key).cloned().unwrap_or_default();
+                    (
+                        MessageType::ContractGetStateMetadataResponse,
+                        utils::proto::marshal(&GetStateMetadataResponse { entries }).unwrap(),
+                    )
+                }
+                t if t == MessageType::ContractPutStateMetadataRequest as i32 => {
+                    let req: PutStateMetadataRequest =
+                        utils::proto::unmarshal(&msg.content).unwrap();
+                    metadata.insert(req.key, req.entries);
+                    (MessageType::ContractPutStateMetadataResponse, vec![])
+                }
+                t if t == MessageType::ContractDelStateRequest as i32 => {
+                    let req: DelStateRequest = utils::proto::unmarshal(&msg.content).unwrap();
+                    state.remove(&req.key);
+                    (MessageType::ContractDelStateResponse, vec![])
+                }
+                t if t == MessageType::ContractGetStateByRangeRequest as i32 => {
+                    let req: GetStateByRangeRequest =
+                        utils::proto::unmarshal(&msg.content).unwrap();
+                    (
+                        MessageType::ContractQueryStateResponse,
+                        page(
+                            range(&state, &req.start_key, &req.end_key),
+                            &req.bookmark,
+                            req.page_size,
+                        ),
+                    )
+                }
+                t if t == MessageType::ContractGetQueryResultRequest as i32 => {
+                    // the fake query selects the values equal to the query
+                    let req: GetQueryResultRequest = utils::proto::unmarshal(&msg.content).unwrap();
+                    let kvs = range(&state, "", "")
+                        .into_iter()
+                        .filter(|kv| kv.value == req.query.as_bytes())
+                        .collect();
+                    (
+                        MessageType::ContractQueryStateResponse,
+                        page(kvs, &req.bookmark, req.page_size),
+                    )
+                }
+                t if t == MessageType::ContractGetStateByPartialCompositeKeyRequest as i32 => {
+                    let req: GetStateByPartialCompositeKeyRequest =
+                        utils::proto::unmarshal(&msg.content).unwrap();
+                    let attributes: Vec<&str> = req.attributes.iter().map(|a| a.as_str()).collect();
+                    let (start_key, end_key) = utils::composite_key::partial_composite_key_range(
+                        &req.object_type,
+                        &attributes,
+                    )
+                    .unwrap();
+                    (
+                        MessageType::ContractQueryStateResponse,
+                        page(range(&state, &start_key, &end_key), "", 0),
+                    )
+                }
+                _ => panic!("unexpected message {:?}", msg),
+            };
+            peer.respond(Message {
+                message_type: message_type as i32,
+                correlation_id: msg.correlation_id,
+                content,
+            })
+            .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_state() {
+        let (tx, rx) = mpsc::channel(10);
+        let peer = PeerHandle::new(tx);
+        tokio::spawn(fake_peer(peer.clone(), rx));
+
+        let proposal = Proposal {
+            header: Some(Header {
+                channel_id: "ch1".to_string(),
+                tx_id: "tx1".to_string(),
+                ..Default::default()
+            }),
+            payload: vec![],
+        };
+        let mut stub = ContractStub::new(peer, "ch1tx1".to_string(), proposal);
+        assert!(stub.get_state("k1").await.unwrap().is_empty());
+        stub.put_state("k1", b"v1").await.unwrap();
+        assert_eq!(stub.get_state("k1").await.unwrap(), b"v1".to_vec());
+        stub.del_state("k1").await.unwrap();
+        assert!(stub.get_state("k1").await.unwrap().is_empty());
+
+        let err = stub.put_state("", b"v").await.unwrap_err();
+        assert_eq!(err.to_string(), "key is empty");
+
+        assert!(stub
+            .get_state_validation_parameter("k1")
+            .await
+            .unwrap()
+            .is_none());
+        let policy = KeyEndorsementPolicy {
+            orgs: vec!["org1".to_string()],
+        };
+        stub.set_state_validation_parameter("k1", &policy)
+            .await
+            .unwrap();
+        stub.set_state_metadata("k1", "owner", b"alice")
+            .await
+            .unwrap();
+        assert_eq!(
+            stub.get_state_validation_parameter("k1").await.unwrap(),
+            Some(policy)
+        );
+        let entries = stub.get_state_metadata("k1").await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries.get("owner"), Some(&b"alice".to_vec()));
+
+        for (object_type, attributes) in &[
+            ("color", vec!["blue", "a1"]),
+            ("color", vec!["blue", "a2"]),
+            ("color", vec!["red", "a3"]),
+        ] {
+            let key = stub.create_composite_key(object_type, attributes).unwrap();
+            stub.put_state(&key, b"v").await.unwrap();
+        }
+        stub.put_state("k1", b"v1").await.unwrap();
+        stub.put_state("k2", b"v2").await.unwrap();
+
+        let kvs = stub.get_state_by_range("k1", "k2").await.unwrap();
+        assert_eq!(kvs.len(), 1);
+        assert_eq!(kvs[0].key, "k1");
+        stub.put_state("k3", b"v2").await.unwrap();
+        let (kvs, bookmark) = stub
+            .get_state_by_range_with_pagination("k", "", 2, "")
+            .await
+            .unwrap();
+        let keys: Vec<&str> = kvs.iter().map(|kv| kv.key.as_str()).collect();
+        assert_eq!(keys, vec!["k1", "k2"]);
+        assert_eq!(bookmark, "k3");
+        let (kvs, bookmark) = stub
+            .get_state_by_range_with_pagination("k", "", 2, &bookmark)
+            .await
+            .unwrap();
+        assert_eq!(kvs.len(), 1);
+        assert_eq!(kvs[0].key, "k3");
+        assert!(bookmark.is_empty());
+        assert!(stub
+            .get_state_by_range_with_pagination("k", "", 0, "")
+            .await
+            .is_err());
+
+        assert_eq!(stub.get_query_result("v2").await.unwrap().len(), 2);
+        let (kvs, bookmark) = stub
+            .get_query_result_with_pagination("v2", 1, "")
+            .await
+            .unwrap();
+        assert_eq!(kvs[0].key, "k2");
+        assert_eq!(bookmark, "k3");
+
+        let kvs = stub
+            .get_state_by_partial_composite_key("color", &["blue"])
+            .await
+            .unwrap();
+        let keys: Vec<(String, Vec<String>)> = kvs
+            .iter()
+            .map(|kv| stub.split_composite_key(&kv.key).unwrap())
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                (
+                    "color".to_string(),
+                    vec!["blue".to_string(), "a1".to_string()]
+                ),
+                (
+                    "color".to_string(),
+                    vec!["blue".to_string(), "a2".to_string()]
+                ),
+            ]
+        );
+    }
+}
@@ -1,14 +1,20 @@
+use error::from_str;
 use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa};
 use webpki::{trust_anchor_util, SignatureAlgorithm};
 use webpki::{DNSNameRef, Time};
 use webpki::{EndEntityCert, TLSServerTrustAnchors};
 
+// the DER encoded object identifier of the organizational unit attribute, 2.5.4.11
+const OID_ORG_UNIT: &[u8] = &[0x55, 0x04, 0x0b];
+
 pub struct Register {
     pub is_ca: bool,
     pub dns: String,
     pub domain: String,
     pub org_name: String,
     pub common_name: String,
+    // org_unit is the organizational unit of the subject, none if empty
+    pub org_unit: String,
 }
 
 impl Default for Register {
@@ -19,6 +25,7 @@ impl Default for Register {
             domain: "www.silk.com".to_string(),
             org_name: "www.silk.org".to_string(),
             common_name: "www.silk.com".to_string(),
+            org_unit: String::new(),
         }
     }
 }
@@ -49,6 +56,11 @@ pub fn cert_params(
     params
         .distinguished_name
         .push(DnType::CommonName, register.common_name);
+    if !register.org_unit.is_empty() {
+        params
+            .distinguished_name
+            .push(DnType::CustomDnType(vec![2, 5, 4, 11]), register.org_unit);
+    }
     if let Some(alg) = alg {
         params.alg = alg
     }
@@ -74,6 +86,59 @@ pub fn create_ca_cert() -> Certificate {
     Certificate::from_params(params).unwrap()
 }
 
+// subject_org_units returns the organizational units of the subject of a DER encoded certificate
+pub fn subject_org_units(cert_der: &[u8]) -> error::Result<Vec<String>> {
+    let (_, cert, _) = der_element(cert_der)?;
+    let (_, mut tbs, _) = der_element(cert)?;
+    // the subject follows the version, explicitly tagged and optional, the serial number, the
+    // signature algorithm, the issuer and the validity
+    let skip = if tbs.first() == Some(&0xa0) { 5 } else { 4 };
+    for _ in 0..skip {
+        tbs = der_element(tbs)?.2;
+    }
+    let (_, mut subject, _) = der_element(tbs)?;
+    let mut units = vec![];
+    while !subject.is_empty() {
+        let (_, mut rdn, rest) = der_element(subject)?;
+        subject = rest;
+        while !rdn.is_empty() {
+            let (_, attribute, rest) = der_element(rdn)?;
+            rdn = rest;
+            let (_, oid, value) = der_element(attribute)?;
+            if oid == OID_ORG_UNIT {
+                let (_, value, _) = der_element(value)?;
+                units.push(String::from_utf8(value.to_vec())?);
+            }
+        }
+    }
+    Ok(units)
+}
+
+// der_element splits the DER element opening the input into its tag and content, the rest of the
+// input follows
+fn der_element(input: &[u8]) -> error::Result<(u8, &[u8], &[u8])> {
+    let invalid = || from_str("invalid DER encoding");
+    let (&tag, rest) = input.split_first().ok_or_else(invalid)?;
+    let (&first, mut rest) = rest.split_first().ok_or_else(invalid)?;
+    let len = if first < 0x80 {
+        first as usize
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n == 0 || n > 4 || rest.len() < n {
+            return Err(invalid());
+        }
+        let len = rest[..n]
+            .iter()
+            .fold(0usize, |len, b| (len << 8) | *b as usize);
+        rest = &rest[n..];
+        len
+    };
+    if rest.len() < len {
+        return Err(invalid());
+    }
+    Ok((tag, &rest[..len], &rest[len..]))
+}
+
 fn check_cert1<'a, 'b>(
     cert_der: &[u8],
     cert: &'a Certificate,
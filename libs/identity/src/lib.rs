@@ -1 +1,4 @@
 pub mod cert;
+pub mod msp;
+pub mod policy;
+pub mod signer;
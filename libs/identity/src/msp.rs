@@ -0,0 +1,230 @@
+use crate::cert::subject_org_units;
+use error::*;
+use silk_proto::{OrganizationConfig, SerializedIdentity};
+use std::collections::HashMap;
+use std::time::SystemTime;
+use webpki::trust_anchor_util::cert_der_as_trust_anchor;
+use webpki::{EndEntityCert, SignatureAlgorithm, TLSClientTrustAnchors, Time};
+
+// the algorithms the certificates and the signatures of the identities may use
+static SUPPORTED_SIG_ALGS: &[&SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+];
+
+// Identity is the certificate of a member of an organization
+#[derive(Clone, Debug, PartialEq)]
+pub struct Identity {
+    org_id: String,
+    cert_der: Vec<u8>,
+    // the organizational units of the subject of the certificate
+    org_units: Vec<String>,
+}
+
+impl Identity {
+    pub fn new(org_id: &str, cert_der: Vec<u8>) -> Self {
+        // a certificate that can not be decoded has no unit, it never verifies anyway
+        let org_units = subject_org_units(&cert_der).unwrap_or_default();
+        Identity {
+            org_id: org_id.to_string(),
+            cert_der,
+            org_units,
+        }
+    }
+
+    // from_bytes decodes a marshaled SerializedIdentity
+    pub fn from_bytes(serialized: &[u8]) -> Result<Self> {
+        let id: SerializedIdentity = utils::proto::unmarshal(serialized)?;
+        if id.org_id.is_empty() {
+            return Err(from_str("identity org id is empty"));
+        }
+        if id.id_bytes.is_empty() {
+            return Err(from_str("identity certificate is empty"));
+        }
+        Ok(Identity::new(&id.org_id, id.id_bytes))
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(utils::proto::marshal(&SerializedIdentity {
+            org_id: self.org_id.clone(),
+            id_bytes: self.cert_der.clone(),
+        })?)
+    }
+
+    pub fn org_id(&self) -> &str {
+        &self.org_id
+    }
+
+    pub fn cert_der(&self) -> &[u8] {
+        &self.cert_der
+    }
+
+    // has_org_unit tells whether the certificate belongs to an organizational unit, regardless of
+    // the case
+    pub fn has_org_unit(&self, org_unit: &str) -> bool {
+        self.org_units
+            .iter()
+            .any(|unit| unit.eq_ignore_ascii_case(org_unit))
+    }
+
+    // verify checks the signature of a message by the key of the certificate
+    pub fn verify(&self, msg: &[u8], signature: &[u8]) -> Result<()> {
+        let cert = end_entity_cert(&self.cert_der)?;
+        if SUPPORTED_SIG_ALGS
+            .iter()
+            .any(|alg| cert.verify_signature(alg, msg, signature).is_ok())
+        {
+            Ok(())
+        } else {
+            Err(from_str("invalid signature"))
+        }
+    }
+}
+
+// Msp keeps the root certificates of the certificate authorities each organization of a channel
+// trusts, the identities of the channel members must be issued by them
+#[derive(Clone, Debug, Default)]
+pub struct Msp {
+    // org id -> DER encoded root certificates
+    roots: HashMap<String, Vec<Vec<u8>>>,
+}
+
+impl Msp {
+    pub fn new() -> Self {
+        Msp::default()
+    }
+
+    // from_config builds the msp of the organizations of a channel config
+    pub fn from_config(orgs: &[OrganizationConfig]) -> Result<Self> {
+        let mut msp = Msp::new();
+        for org in orgs {
+            for cert in &org.root_certs {
+                msp.add_root_cert(&org.org_id, cert.clone())?;
+            }
+        }
+        Ok(msp)
+    }
+
+    pub fn add_root_cert(&mut self, org_id: &str, cert_der: Vec<u8>) -> Result<()> {
+        cert_der_as_trust_anchor(&cert_der).map_err(|e| {
+            from_str(&format!(
+                "invalid root certificate of org {:}: {:?}",
+                org_id, e
+            ))
+        })?;
+        self.roots
+            .entry(org_id.to_string())
+            .or_default()
+            .push(cert_der);
+        Ok(())
+    }
+
+    // deserialize_identity decodes a marshaled SerializedIdentity and validates it
+    pub fn deserialize_identity(&self, serialized: &[u8]) -> Result<Identity> {
+        let identity = Identity::from_bytes(serialized)?;
+        self.validate(&identity)?;
+        Ok(identity)
+    }
+
+    // validate checks the certificate of an identity is currently valid and chains to a root
+    // certificate of its organization
+    pub fn validate(&self, identity: &Identity) -> Result<()> {
+        let roots = self
+            .roots
+            .get(&identity.org_id)
+            .ok_or_else(|| from_str(&format!("unknown org {:}", identity.org_id)))?;
+        let anchors = roots
+            .iter()
+            .map(|der| cert_der_as_trust_anchor(der))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| from_str(&format!("{:?}", e)))?;
+        let time = Time::try_from(SystemTime::now()).map_err(|e| from_str(&e.to_string()))?;
+
+        end_entity_cert(&identity.cert_der)?
+            .verify_is_valid_tls_client_cert(
+                SUPPORTED_SIG_ALGS,
+                &TLSClientTrustAnchors(&anchors),
+                &[],
+                time,
+            )
+            .map_err(|e| {
+                from_str(&format!(
+                    "the certificate is not trusted by org {:}: {:?}",
+                    identity.org_id, e
+                ))
+            })
+    }
+}
+
+fn end_entity_cert(cert_der: &[u8]) -> Result<EndEntityCert<'_>> {
+    EndEntityCert::from(cert_der).map_err(|e| from_str(&format!("invalid certificate: {:?}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Identity, Msp};
+    use crate::cert::{cert_params, create_ca_cert, Register};
+    use rcgen::Certificate;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+    use silk_proto::OrganizationConfig;
+
+    // member issues a certificate signed by the ca, returns the identity and its key pair
+    fn member(org_id: &str, ca: &Certificate) -> (Identity, EcdsaKeyPair) {
+        let cert = Certificate::from_params(cert_params(None, Register::default())).unwrap();
+        let der = cert.serialize_der_with_signer(ca).unwrap();
+        let key = EcdsaKeyPair::from_pkcs8(
+            &ECDSA_P256_SHA256_ASN1_SIGNING,
+            &cert.serialize_private_key_der(),
+        )
+        .unwrap();
+        (Identity::new(org_id, der), key)
+    }
+
+    #[test]
+    fn test_validate() {
+        let ca1 = create_ca_cert();
+        let ca2 = create_ca_cert();
+        let msp = Msp::from_config(&[OrganizationConfig {
+            org_id: "org1".to_string(),
+            root_certs: vec![ca1.serialize_der().unwrap()],
+        }])
+        .unwrap();
+
+        let (id, _) = member("org1", &ca1);
+        msp.validate(&id).unwrap();
+        let bytes = id.to_bytes().unwrap();
+        assert_eq!(msp.deserialize_identity(&bytes).unwrap(), id);
+
+        // issued by a ca the org does not trust
+        let (id, _) = member("org1", &ca2);
+        assert!(msp.validate(&id).is_err());
+        // unknown org
+        let (id, _) = member("org2", &ca1);
+        assert!(msp.validate(&id).is_err());
+
+        assert!(Identity::from_bytes(b"garbage").is_err());
+        assert!(Msp::new()
+            .add_root_cert("org1", b"garbage".to_vec())
+            .is_err());
+    }
+
+    #[test]
+    fn test_verify() {
+        let ca = create_ca_cert();
+        let (id, key) = member("org1", &ca);
+        let msg = b"proposal bytes";
+        let signature = key.sign(&SystemRandom::new(), msg).unwrap();
+
+        id.verify(msg, signature.as_ref()).unwrap();
+        assert!(id.verify(b"other bytes", signature.as_ref()).is_err());
+        let (other, _) = member("org1", &ca);
+        assert!(other.verify(msg, signature.as_ref()).is_err());
+    }
+}
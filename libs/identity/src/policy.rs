@@ -0,0 +1,495 @@
+use crate::msp::Identity;
+use error::*;
+use std::iter::Peekable;
+use std::str::Chars;
+
+// Role is the role a principal of an organization plays, carried by the organizational unit of
+// the certificate: peer, admin or client. Every identity of the organization is a member
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+    Member,
+    Peer,
+    Admin,
+    Client,
+}
+
+impl Role {
+    // org_unit returns the organizational unit of the certificates playing the role
+    fn org_unit(self) -> Option<&'static str> {
+        match self {
+            Role::Member => None,
+            Role::Peer => Some("peer"),
+            Role::Admin => Some("admin"),
+            Role::Client => Some("client"),
+        }
+    }
+}
+
+// Principal is a role in an organization, written 'Org1.peer'
+#[derive(Clone, Debug, PartialEq)]
+pub struct Principal {
+    pub org_id: String,
+    pub role: Role,
+}
+
+impl Principal {
+    fn parse(s: &str) -> Result<Self> {
+        let (org_id, role) = match s.rfind('.') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => return Err(from_str(&format!("invalid principal '{:}'", s))),
+        };
+        let role = match role.to_lowercase().as_str() {
+            "member" => Role::Member,
+            "peer" => Role::Peer,
+            "admin" => Role::Admin,
+            "client" => Role::Client,
+            _ => return Err(from_str(&format!("unknown role '{:}'", role))),
+        };
+        if org_id.is_empty() {
+            return Err(from_str(&format!("invalid principal '{:}'", s)));
+        }
+        Ok(Principal {
+            org_id: org_id.to_string(),
+            role,
+        })
+    }
+
+    pub fn matches(&self, identity: &Identity) -> bool {
+        identity.org_id() == self.org_id
+            && self
+                .role
+                .org_unit()
+                .map(|unit| identity.has_org_unit(unit))
+                .unwrap_or(true)
+    }
+}
+
+// MAX_POLICY_LEN is the length in bytes of the longest policy parsed
+pub const MAX_POLICY_LEN: usize = 4096;
+// MAX_POLICY_DEPTH is the number of operators a principal of a policy is nested in at most
+pub const MAX_POLICY_DEPTH: usize = 8;
+// MAX_POLICY_PRINCIPALS is the number of principals of a policy at most
+pub const MAX_POLICY_PRINCIPALS: usize = 64;
+
+// SignaturePolicy is an N-of-M endorsement policy, written in the policy language:
+//
+//   'Org1.peer'             an endorsement by the principal
+//   AND(p1, p2, ...)        all the policies
+//   OR(p1, p2, ...)         one of the policies
+//   OutOf(n, p1, p2, ...)   n of the policies
+#[derive(Clone, Debug, PartialEq)]
+pub enum SignaturePolicy {
+    SignedBy(Principal),
+    OutOf(usize, Vec<SignaturePolicy>),
+}
+
+impl SignaturePolicy {
+    pub fn parse(s: &str) -> Result<Self> {
+        if s.len() > MAX_POLICY_LEN {
+            return Err(from_str(&format!(
+                "policy of {:} bytes, at most {:}",
+                s.len(),
+                MAX_POLICY_LEN
+            )));
+        }
+        let mut parser = Parser {
+            chars: s.chars().peekable(),
+            depth: 0,
+            principals: 0,
+        };
+        let policy = parser.policy()?;
+        parser.skip_whitespace();
+        if parser.chars.peek().is_some() {
+            return Err(from_str(&format!("unexpected input after policy '{:}'", s)));
+        }
+        Ok(policy)
+    }
+
+    // evaluate tells whether the endorsements of the identities satisfy the policy. An identity
+    // endorses for one principal only, so an OutOf policy is only satisfied by distinct
+    // endorsers. All the ways to hand out the identities to the principals are tried, the
+    // identities matching the same principals being interchangeable only one of them is tried
+    // for a principal and no more of them are kept than there are principals
+    pub fn evaluate(&self, identities: &[Identity]) -> bool {
+        let mut principals = vec![];
+        self.principals(&mut principals);
+        let mut classes: Vec<Vec<bool>> = vec![];
+        let mut counts: Vec<usize> = vec![];
+        let mut distinct: Vec<(&Identity, usize)> = vec![];
+        for id in identities {
+            if distinct.iter().any(|(other, _)| *other == id) {
+                continue;
+            }
+            let matched: Vec<bool> = principals.iter().map(|p| p.matches(id)).collect();
+            if !matched.contains(&true) {
+                continue;
+            }
+            let class = match classes.iter().position(|c| *c == matched) {
+                Some(class) => class,
+                None => {
+                    classes.push(matched);
+                    counts.push(0);
+                    classes.len() - 1
+                }
+            };
+            if counts[class] < principals.len() {
+                counts[class] += 1;
+                distinct.push((id, class));
+            }
+        }
+        let mut used = vec![false; distinct.len()];
+        self.satisfy(&distinct, &mut used, &mut |_| true)
+    }
+
+    // principals appends the principals of the policy
+    fn principals<'a>(&'a self, principals: &mut Vec<&'a Principal>) {
+        match self {
+            SignaturePolicy::SignedBy(principal) => principals.push(principal),
+            SignaturePolicy::OutOf(_, policies) => {
+                for policy in policies {
+                    policy.principals(principals);
+                }
+            }
+        }
+    }
+
+    // satisfy tells whether the policy is satisfied by identities not used yet, in a way that
+    // lets the continuation succeed with the identities left. Each identity comes with its class,
+    // the identities of a class matching the same principals
+    fn satisfy(
+        &self,
+        identities: &[(&Identity, usize)],
+        used: &mut Vec<bool>,
+        then: &mut dyn FnMut(&mut Vec<bool>) -> bool,
+    ) -> bool {
+        match self {
+            SignaturePolicy::SignedBy(principal) => {
+                let mut tried = vec![];
+                for (i, (id, class)) in identities.iter().enumerate() {
+                    if used[i] || tried.contains(class) || !principal.matches(id) {
+                        continue;
+                    }
+                    tried.push(*class);
+                    used[i] = true;
+                    if then(used) {
+                        return true;
+                    }
+                    used[i] = false;
+                }
+                false
+            }
+            SignaturePolicy::OutOf(n, policies) => out_of(*n, policies, identities, used, then),
+        }
+    }
+}
+
+// out_of tells whether n of the policies are satisfied by distinct identities, in a way that lets
+// the continuation succeed with the identities left. Each policy satisfied takes an identity at
+// least, n policies are never satisfied by fewer identities left
+fn out_of(
+    n: usize,
+    policies: &[SignaturePolicy],
+    identities: &[(&Identity, usize)],
+    used: &mut Vec<bool>,
+    then: &mut dyn FnMut(&mut Vec<bool>) -> bool,
+) -> bool {
+    if n == 0 {
+        return then(used);
+    }
+    if used.iter().filter(|used| !**used).count() < n {
+        return false;
+    }
+    let (first, rest) = match policies.split_first() {
+        Some(split) if policies.len() >= n => split,
+        _ => return false,
+    };
+    first.satisfy(identities, used, &mut |used| {
+        out_of(n - 1, rest, identities, used, then)
+    }) || out_of(n, rest, identities, used, then)
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    // depth is the number of operators around the policy being parsed
+    depth: usize,
+    // principals is the number of principals parsed
+    principals: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.chars.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(from_str(&format!(
+                "expected '{:}', found '{:}'",
+                expected, c
+            ))),
+            None => Err(from_str(&format!(
+                "expected '{:}', found the end",
+                expected
+            ))),
+        }
+    }
+
+    fn word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(c) = self.chars.peek() {
+            if !c.is_alphanumeric() && *c != '_' {
+                break;
+            }
+            word.push(*c);
+            self.chars.next();
+        }
+        word
+    }
+
+    fn policy(&mut self) -> Result<SignaturePolicy> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('\'') | Some('"') => {
+                let quote = self.chars.next().unwrap_or_default();
+                let mut principal = String::new();
+                loop {
+                    match self.chars.next() {
+                        Some(c) if c == quote => break,
+                        Some(c) => principal.push(c),
+                        None => return Err(from_str("unterminated principal")),
+                    }
+                }
+                self.principals += 1;
+                if self.principals > MAX_POLICY_PRINCIPALS {
+                    return Err(from_str(&format!(
+                        "policy of more than {:} principals",
+                        MAX_POLICY_PRINCIPALS
+                    )));
+                }
+                Ok(SignaturePolicy::SignedBy(Principal::parse(&principal)?))
+            }
+            Some(_) => {
+                let op = self.word();
+                self.expect('(')?;
+                if self.depth == MAX_POLICY_DEPTH {
+                    return Err(from_str(&format!(
+                        "policy nested more than {:} levels deep",
+                        MAX_POLICY_DEPTH
+                    )));
+                }
+                self.depth += 1;
+                let policy = self.operator(&op);
+                self.depth -= 1;
+                policy
+            }
+            None => Err(from_str("empty policy")),
+        }
+    }
+
+    // operator parses the arguments of an operator after its opening parenthesis
+    fn operator(&mut self, op: &str) -> Result<SignaturePolicy> {
+        match op.to_lowercase().as_str() {
+            "and" => {
+                let policies = self.arguments()?;
+                Ok(SignaturePolicy::OutOf(policies.len(), policies))
+            }
+            "or" => Ok(SignaturePolicy::OutOf(1, self.arguments()?)),
+            "outof" => {
+                self.skip_whitespace();
+                let n = self
+                    .word()
+                    .parse::<usize>()
+                    .map_err(|_| from_str("OutOf expects a number of policies"))?;
+                self.expect(',')?;
+                let policies = self.arguments()?;
+                if n == 0 || n > policies.len() {
+                    return Err(from_str(&format!(
+                        "OutOf({:}) of {:} policies",
+                        n,
+                        policies.len()
+                    )));
+                }
+                Ok(SignaturePolicy::OutOf(n, policies))
+            }
+            _ => Err(from_str(&format!("unknown operator '{:}'", op))),
+        }
+    }
+
+    // arguments parses the comma separated policies closing an operator
+    fn arguments(&mut self) -> Result<Vec<SignaturePolicy>> {
+        let mut policies = vec![self.policy()?];
+        loop {
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => policies.push(self.policy()?),
+                Some(')') => return Ok(policies),
+                _ => return Err(from_str("expected ',' or ')'")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Principal, Role, SignaturePolicy, MAX_POLICY_DEPTH, MAX_POLICY_LEN, MAX_POLICY_PRINCIPALS,
+    };
+    use crate::cert::{cert_params, create_cert, Register};
+    use crate::msp::Identity;
+
+    fn identity(org: &str, org_unit: &str) -> Identity {
+        let register = Register {
+            org_unit: org_unit.to_string(),
+            ..Register::default()
+        };
+        let cert = create_cert(cert_params(None, register)).unwrap();
+        Identity::new(org, cert.serialize_der().unwrap())
+    }
+
+    fn ids(orgs: &[&str]) -> Vec<Identity> {
+        orgs.iter().map(|org| identity(org, "peer")).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let policy =
+            SignaturePolicy::parse("AND('Org1.peer', OR('Org2.peer', \"Org3.admin\"))").unwrap();
+        let principal = |org: &str, role| {
+            SignaturePolicy::SignedBy(Principal {
+                org_id: org.to_string(),
+                role,
+            })
+        };
+        assert_eq!(
+            policy,
+            SignaturePolicy::OutOf(
+                2,
+                vec![
+                    principal("Org1", Role::Peer),
+                    SignaturePolicy::OutOf(
+                        1,
+                        vec![
+                            principal("Org2", Role::Peer),
+                            principal("Org3", Role::Admin)
+                        ]
+                    )
+                ]
+            )
+        );
+        assert_eq!(
+            SignaturePolicy::parse("OutOf(2, 'a.member', 'b.member', 'c.client')").unwrap(),
+            SignaturePolicy::OutOf(
+                2,
+                vec![
+                    principal("a", Role::Member),
+                    principal("b", Role::Member),
+                    principal("c", Role::Client)
+                ]
+            )
+        );
+
+        for invalid in &[
+            "",
+            "'Org1'",
+            "'Org1.owner'",
+            "'Org1.peer",
+            "AND()",
+            "AND('Org1.peer'",
+            "XOR('Org1.peer')",
+            "OutOf(0, 'Org1.peer')",
+            "OutOf(2, 'Org1.peer')",
+            "'Org1.peer' 'Org2.peer'",
+        ] {
+            assert!(SignaturePolicy::parse(invalid).is_err(), "{:}", invalid);
+        }
+
+        // nested deeper than the limit
+        let nested = |depth| "AND(".repeat(depth) + "'Org1.peer'" + &")".repeat(depth);
+        assert!(SignaturePolicy::parse(&nested(MAX_POLICY_DEPTH)).is_ok());
+        assert!(SignaturePolicy::parse(&nested(MAX_POLICY_DEPTH + 1)).is_err());
+        // with more principals than the limit
+        let principals = |n| vec!["'Org1.peer'"; n].join(",");
+        assert!(
+            SignaturePolicy::parse(&format!("OR({:})", principals(MAX_POLICY_PRINCIPALS))).is_ok()
+        );
+        assert!(
+            SignaturePolicy::parse(&format!("OR({:})", principals(MAX_POLICY_PRINCIPALS + 1)))
+                .is_err()
+        );
+        // longer than the limit
+        let long = format!("'{:}.peer'", "o".repeat(MAX_POLICY_LEN));
+        assert!(SignaturePolicy::parse(&long).is_err());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let policy =
+            SignaturePolicy::parse("AND('Org1.peer', OR('Org2.peer', 'Org3.peer'))").unwrap();
+        assert!(policy.evaluate(&ids(&["Org1", "Org2"])));
+        assert!(policy.evaluate(&ids(&["Org3", "Org1"])));
+        assert!(!policy.evaluate(&ids(&["Org1"])));
+        assert!(!policy.evaluate(&ids(&["Org2", "Org3"])));
+
+        // an endorser counts once
+        let policy = SignaturePolicy::parse("OutOf(2, 'Org1.peer', 'Org1.peer')").unwrap();
+        assert!(!policy.evaluate(&ids(&["Org1"])));
+        let one = ids(&["Org1"]);
+        assert!(!policy.evaluate(&[one[0].clone(), one[0].clone()]));
+        assert!(policy.evaluate(&ids(&["Org1", "Org1"])));
+
+        let policy =
+            SignaturePolicy::parse("OutOf(2, 'Org1.peer', 'Org2.peer', 'Org3.peer')").unwrap();
+        assert!(policy.evaluate(&ids(&["Org3", "Org2"])));
+        assert!(!policy.evaluate(&ids(&["Org3", "Org4"])));
+
+        // the first principal must not keep the only endorser the second one accepts
+        let policy =
+            SignaturePolicy::parse("AND(OR('Org1.peer', 'Org2.peer'), 'Org1.peer')").unwrap();
+        assert!(policy.evaluate(&ids(&["Org1", "Org2"])));
+        assert!(policy.evaluate(&ids(&["Org2", "Org1"])));
+        assert!(!policy.evaluate(&ids(&["Org2", "Org2"])));
+        let policy = SignaturePolicy::parse(
+            "OutOf(2, OR('Org1.peer', 'Org2.peer'), 'Org1.peer', 'Org3.peer')",
+        )
+        .unwrap();
+        assert!(policy.evaluate(&ids(&["Org1", "Org3"])));
+
+        // the endorsers of an organization are interchangeable, one too few fails at once
+        let policy = SignaturePolicy::parse(&format!(
+            "OutOf(32, {:})",
+            ["'Org1.peer'", "'Org2.peer'"].repeat(32).join(",")
+        ))
+        .unwrap();
+        assert!(!policy.evaluate(&ids(&["Org1"; 31])));
+        assert!(policy.evaluate(&ids(&["Org1"; 40])));
+    }
+
+    #[test]
+    fn test_role() {
+        let client = identity("Org1", "CLIENT");
+        let principal = |role| Principal {
+            org_id: "Org1".to_string(),
+            role,
+        };
+        assert!(principal(Role::Member).matches(&client));
+        assert!(principal(Role::Client).matches(&client));
+        assert!(!principal(Role::Peer).matches(&client));
+        assert!(!principal(Role::Admin).matches(&client));
+        assert!(!Principal {
+            org_id: "Org2".to_string(),
+            role: Role::Client
+        }
+        .matches(&client));
+
+        let policy = SignaturePolicy::parse("OR('Org1.peer', 'Org1.admin')").unwrap();
+        assert!(!policy.evaluate(&[client.clone(), identity("Org1", "")]));
+        assert!(policy.evaluate(&[client, identity("Org1", "admin")]));
+    }
+}
@@ -0,0 +1,174 @@
+use crate::msp::{Identity, Msp};
+use error::*;
+use ring::rand::SystemRandom;
+use ring::signature::{
+    EcdsaKeyPair, Ed25519KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING, ECDSA_P384_SHA384_ASN1_SIGNING,
+};
+use silk_proto::Endorsement;
+use std::path::Path;
+use std::sync::Arc;
+
+enum SigningKey {
+    Ecdsa(EcdsaKeyPair),
+    Ed25519(Ed25519KeyPair),
+}
+
+// SigningIdentity is an identity holding the private key of its certificate
+#[derive(Clone)]
+pub struct SigningIdentity {
+    identity: Identity,
+    key: Arc<SigningKey>,
+    rng: SystemRandom,
+}
+
+impl SigningIdentity {
+    // new builds a signing identity from the DER encoded certificate and PKCS#8 private key, the
+    // ECDSA P-256, ECDSA P-384 and Ed25519 keys are supported
+    pub fn new(org_id: &str, cert_der: Vec<u8>, key_der: &[u8]) -> Result<Self> {
+        let key = if let Ok(key) =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, key_der)
+        {
+            SigningKey::Ecdsa(key)
+        } else if let Ok(key) = EcdsaKeyPair::from_pkcs8(&ECDSA_P384_SHA384_ASN1_SIGNING, key_der) {
+            SigningKey::Ecdsa(key)
+        } else if let Ok(key) = Ed25519KeyPair::from_pkcs8_maybe_unchecked(key_der) {
+            SigningKey::Ed25519(key)
+        } else {
+            return Err(from_str("unsupported private key"));
+        };
+
+        let signer = SigningIdentity {
+            identity: Identity::new(org_id, cert_der),
+            key: Arc::new(key),
+            rng: SystemRandom::new(),
+        };
+        // the key must be the one of the certificate
+        let msg = b"silk signing identity";
+        signer
+            .identity
+            .verify(msg, &signer.sign(msg)?)
+            .map_err(|_| from_str("the private key does not match the certificate"))?;
+        Ok(signer)
+    }
+
+    // from_files loads the certificate and the private key of a signing identity, the files may be
+    // DER or PEM encoded
+    pub fn from_files(
+        org_id: &str,
+        cert_path: impl AsRef<Path>,
+        key_path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let cert = read_der(cert_path.as_ref())?;
+        let key = read_der(key_path.as_ref())?;
+        SigningIdentity::new(org_id, cert, &key)
+    }
+
+    pub fn identity(&self) -> &Identity {
+        &self.identity
+    }
+
+    pub fn sign(&self, msg: &[u8]) -> Result<Vec<u8>> {
+        match self.key.as_ref() {
+            SigningKey::Ecdsa(key) => Ok(key
+                .sign(&self.rng, msg)
+                .map_err(|_| from_str("failed to sign"))?
+                .as_ref()
+                .to_vec()),
+            SigningKey::Ed25519(key) => Ok(key.sign(msg).as_ref().to_vec()),
+        }
+    }
+
+    // endorse signs the payload of a proposal response concatenated with the serialized identity
+    // of the endorser
+    pub fn endorse(&self, payload: &[u8]) -> Result<Endorsement> {
+        let endorser = self.identity.to_bytes()?;
+        let signature = self.sign(&[payload, &endorser].concat())?;
+        Ok(Endorsement {
+            endorser,
+            signature,
+        })
+    }
+}
+
+// verify_endorsement checks the endorser of a proposal response payload is a member of the channel
+// and signed the payload, it returns the endorser
+pub fn verify_endorsement(
+    msp: &Msp,
+    payload: &[u8],
+    endorsement: &Endorsement,
+) -> Result<Identity> {
+    let endorser = msp.deserialize_identity(&endorsement.endorser)?;
+    endorser.verify(
+        &[payload, &endorsement.endorser].concat(),
+        &endorsement.signature,
+    )?;
+    Ok(endorser)
+}
+
+// read_der reads a DER file, or decodes the first block of a PEM file
+fn read_der(path: &Path) -> Result<Vec<u8>> {
+    let data = std::fs::read(path)
+        .map_err(|e| from_str(&format!("failed to read {:}: {:}", path.display(), e)))?;
+    if !data.starts_with(b"-----BEGIN") {
+        return Ok(data);
+    }
+    let text = String::from_utf8(data)?;
+    let body = text
+        .lines()
+        .skip(1)
+        .take_while(|line| !line.starts_with("-----END"))
+        .collect::<String>();
+    Ok(utils::base64::decode(body.trim())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_endorsement, SigningIdentity};
+    use crate::cert::{cert_params, create_ca_cert, Register};
+    use crate::msp::Msp;
+    use rcgen::{Certificate, PKCS_ED25519};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_endorse() {
+        let ca = create_ca_cert();
+        let mut msp = Msp::new();
+        msp.add_root_cert("org1", ca.serialize_der().unwrap())
+            .unwrap();
+
+        for alg in &[None, Some(&PKCS_ED25519)] {
+            let cert = Certificate::from_params(cert_params(*alg, Register::default())).unwrap();
+            let signer = SigningIdentity::new(
+                "org1",
+                cert.serialize_der_with_signer(&ca).unwrap(),
+                &cert.serialize_private_key_der(),
+            )
+            .unwrap();
+
+            let endorsement = signer.endorse(b"payload").unwrap();
+            let endorser = verify_endorsement(&msp, b"payload", &endorsement).unwrap();
+            assert_eq!(&endorser, signer.identity());
+            assert!(verify_endorsement(&msp, b"other payload", &endorsement).is_err());
+            assert!(verify_endorsement(&Msp::new(), b"payload", &endorsement).is_err());
+        }
+    }
+
+    #[test]
+    fn test_from_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let cert = Certificate::from_params(cert_params(None, Register::default())).unwrap();
+        let cert_path = temp_dir.path().join("cert.pem");
+        let key_path = temp_dir.path().join("key.der");
+        std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_der()).unwrap();
+
+        let signer = SigningIdentity::from_files("org1", &cert_path, &key_path).unwrap();
+        let signature = signer.sign(b"msg").unwrap();
+        signer.identity().verify(b"msg", &signature).unwrap();
+
+        // a key that is not the one of the certificate
+        let other = Certificate::from_params(cert_params(None, Register::default())).unwrap();
+        std::fs::write(&key_path, other.serialize_private_key_der()).unwrap();
+        assert!(SigningIdentity::from_files("org1", &cert_path, &key_path).is_err());
+    }
+}
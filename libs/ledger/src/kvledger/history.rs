@@ -1,6 +1,306 @@
+use crate::rwset::builder::TxRwSet;
+use crate::rwset::key::CompositeKey;
+use crate::statedb::{Height, VersionedKV, VersionedValue};
 use crate::HistoryQueryExecutor;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use dashmap::DashMap;
 use error::*;
-use silk_proto::KeyModification;
+use rocksdb::{Direction, IteratorMode, WriteBatch, DB};
+use silk_proto::*;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const HISTORY_KEY_PREFIX: u8 = b'k';
+const NS_KEY_SEP: u8 = 0x00;
+const SAVE_POINT_KEY: u8 = b's';
+
+// HistoryDBProvider provides the history databases of the ledgers
+pub struct HistoryDBProvider {
+    path: PathBuf,
+    handler: DashMap<String, HistoryDB>,
+}
+
+impl HistoryDBProvider {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        HistoryDBProvider {
+            path: path.into().join("history_db"),
+            handler: DashMap::new(),
+        }
+    }
+
+    pub fn get_db_handle(&self, id: &str) -> Result<HistoryDB> {
+        if !self.handler.contains_key(id) {
+            let db = DB::open_default(self.path.join(id))?;
+            self.handler.insert(
+                id.to_string(),
+                HistoryDB {
+                    db: Arc::new(db),
+                    name: id.to_string(),
+                },
+            );
+        }
+
+        let db = self.handler.get(id).unwrap();
+        Ok(db.clone())
+    }
+}
+
+// HistoryDB indexes the heights at which the keys of a ledger were written by valid transactions,
+// together with the value written, so that the state can be read as of a past block
+#[derive(Clone)]
+pub struct HistoryDB {
+    db: Arc<DB>,
+    name: String,
+}
+
+impl HistoryDB {
+    // commit indexes the writes of the valid transactions of a block and moves the savepoint to it
+    pub fn commit(&self, block: &Block) -> Result<()> {
+        let (header, data) = match (&block.header, &block.data) {
+            (Some(header), Some(data)) => (header, data),
+            _ => return Err(from_str("block content is null")),
+        };
+        let flags = utils::utils::get_tx_validation_flags(block);
+        let mut batch = WriteBatch::default();
+
+        for (index, env) in data.data.iter().enumerate() {
+            let code = flags.get(index).copied().unwrap_or(TxValidationCode::Valid);
+            if code != TxValidationCode::Valid {
+                debug!(
+                    "history db [{:}] skip invalid transaction [{:}] of block [{:}]",
+                    self.name, index, header.number
+                );
+                continue;
+            }
+
+            let (tx, _) = utils::utils::get_tx_header_from_data(env)?;
+            let resp = match tx.response.first() {
+                Some(resp) => resp,
+                None => continue,
+            };
+            let payload: ProposalResponsePayload = utils::proto::unmarshal(&resp.payload)?;
+            let tx_rw_set =
+                TxRwSet::try_from(utils::proto::unmarshal::<TxReadWriteSet>(&payload.results)?)?;
+            let height = Height::new(header.number, index as u64);
+            for ns_rw_set in &tx_rw_set.ns_rw_sets {
+                for kv_write in &ns_rw_set.kv_rw_set.writes {
+                    // a delete is kept as an empty value, as in the state database
+                    let value = VersionedValue {
+                        value: if kv_write.is_delete {
+                            vec![]
+                        } else {
+                            kv_write.value.clone()
+                        },
+                        metadata: vec![],
+                        version: height,
+                    };
+                    batch.put(
+                        encode_history_key(&ns_rw_set.namespace, &kv_write.key, height),
+                        value.encode_value()?,
+                    );
+                }
+            }
+        }
+
+        let savepoint = Height::new(header.number, (data.data.len() as u64).saturating_sub(1));
+        batch.put([SAVE_POINT_KEY], savepoint.to_bytes());
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    // get_last_savepoint returns the height of the last indexed block
+    pub fn get_last_savepoint(&self) -> Result<Option<Height>> {
+        match self.db.get([SAVE_POINT_KEY])? {
+            Some(v) => Ok(Some(Height::new_from_bytes(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    // should_recover tells whether the history lags behind `last_available_block` of the block
+    // store and, if so, the number of the first block to index again
+    pub fn should_recover(&self, last_available_block: u64) -> Result<(bool, u64)> {
+        match self.get_last_savepoint()? {
+            Some(savepoint) => Ok((
+                savepoint.block_num != last_available_block,
+                savepoint.block_num + 1,
+            )),
+            None => Ok((true, 0)),
+        }
+    }
+
+    // clear removes the whole history together with the savepoint
+    pub fn clear(&self) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        for (k, _) in self.db.iterator(IteratorMode::Start) {
+            batch.delete(k);
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    // get_key_history returns the heights of the transactions that wrote a key, oldest first
+    pub fn get_key_history(&self, namespace: &str, key: &str) -> Result<Vec<Height>> {
+        let prefix = encode_history_prefix(namespace, key);
+        let mut heights = vec![];
+        for (k, _) in self.db.prefix_iterator(&prefix) {
+            if !k.starts_with(&prefix) {
+                break;
+            }
+            heights.push(decode_history_height(&k[prefix.len()..])?);
+        }
+        Ok(heights)
+    }
+
+    // get_state_at returns the value of a key once the block `block_num` was committed, None if
+    // the key did not exist or was deleted then
+    pub fn get_state_at(
+        &self,
+        namespace: &str,
+        key: &str,
+        block_num: u64,
+    ) -> Result<Option<VersionedValue>> {
+        self.check_committed(block_num)?;
+        let prefix = encode_history_prefix(namespace, key);
+        let last = encode_history_key(namespace, key, Height::new(block_num, u64::MAX));
+        for (k, v) in self
+            .db
+            .iterator(IteratorMode::From(&last, Direction::Reverse))
+        {
+            if !k.starts_with(&prefix) {
+                break;
+            }
+            // skip the entries of the keys this key is a prefix of
+            if k.len() == prefix.len() + 16 {
+                let vv = decode_history_value(&v)?;
+                return Ok(if vv.is_delete() { None } else { Some(vv) });
+            }
+        }
+        Ok(None)
+    }
+
+    // get_state_range_at returns the key-values of a namespace between start_key (included) and
+    // end_key (excluded, empty for no end) once the block `block_num` was committed, by key order
+    pub fn get_state_range_at(
+        &self,
+        namespace: &str,
+        start_key: &str,
+        end_key: &str,
+        block_num: u64,
+    ) -> Result<Vec<VersionedKV>> {
+        self.check_committed(block_num)?;
+        let mut ns_prefix = vec![HISTORY_KEY_PREFIX];
+        ns_prefix.extend_from_slice(namespace.as_bytes());
+        ns_prefix.push(NS_KEY_SEP);
+        let mut start = ns_prefix.clone();
+        start.extend_from_slice(start_key.as_bytes());
+        let end = encode_history_range_end(&ns_prefix, end_key);
+
+        // the entries of a key are sorted by height, the last one up to the block wins. The keys
+        // themselves may contain the separator, so the entries of the keys after end_key that
+        // sort before the end of the scan are skipped
+        let mut states = BTreeMap::new();
+        for (k, v) in self
+            .db
+            .iterator(IteratorMode::From(&start, Direction::Forward))
+        {
+            if !k.starts_with(&ns_prefix) || end.as_ref().map(|e| k[..] >= e[..]).unwrap_or(false) {
+                break;
+            }
+            let (key, height) = decode_history_key(&k[ns_prefix.len()..])?;
+            if key.as_str() < start_key
+                || (!end_key.is_empty() && key.as_str() >= end_key)
+                || height.block_num > block_num
+            {
+                continue;
+            }
+            states.insert(key, v);
+        }
+
+        let mut kvs = vec![];
+        for (key, v) in states {
+            let vv = decode_history_value(&v)?;
+            if !vv.is_delete() {
+                kvs.push(VersionedKV {
+                    composite_key: CompositeKey::new(namespace, "", &key),
+                    versioned_value: vv,
+                });
+            }
+        }
+        Ok(kvs)
+    }
+
+    // check_committed fails for a block the history has not indexed yet, its state is unknown
+    fn check_committed(&self, block_num: u64) -> Result<()> {
+        match self.get_last_savepoint()? {
+            Some(savepoint) if savepoint.block_num >= block_num => Ok(()),
+            savepoint => Err(from_str(&format!(
+                "history db [{:}] has not committed block {:}, last savepoint {:?}",
+                self.name, block_num, savepoint
+            ))),
+        }
+    }
+}
+
+fn encode_history_prefix(ns: &str, key: &str) -> Vec<u8> {
+    let mut k = vec![HISTORY_KEY_PREFIX];
+    k.extend_from_slice(ns.as_bytes());
+    k.push(NS_KEY_SEP);
+    k.extend_from_slice(key.as_bytes());
+    k.push(NS_KEY_SEP);
+    k
+}
+
+// encode_history_range_end returns the history key that the entries of all the keys before end_key
+// sort before: end_key up to its first separator, followed by \x01. None for an empty end_key
+fn encode_history_range_end(ns_prefix: &[u8], end_key: &str) -> Option<Vec<u8>> {
+    if end_key.is_empty() {
+        return None;
+    }
+    let end_key = end_key.as_bytes();
+    let len = end_key
+        .iter()
+        .position(|b| *b == NS_KEY_SEP)
+        .unwrap_or(end_key.len());
+    let mut k = ns_prefix.to_vec();
+    k.extend_from_slice(&end_key[..len]);
+    k.push(NS_KEY_SEP + 1);
+    Some(k)
+}
+
+fn encode_history_key(ns: &str, key: &str, height: Height) -> Vec<u8> {
+    let mut k = encode_history_prefix(ns, key);
+    k.write_u64::<BigEndian>(height.block_num).unwrap();
+    k.write_u64::<BigEndian>(height.tx_num).unwrap();
+    k
+}
+
+// decode_history_key splits the part of a history key following the namespace into the key and
+// the height, the height taking the last 16 bytes
+fn decode_history_key(b: &[u8]) -> Result<(String, Height)> {
+    if b.len() < 17 {
+        return Err(from_str("history key is too short"));
+    }
+    let (key, height) = b.split_at(b.len() - 16);
+    let key = String::from_utf8(key[..key.len() - 1].to_vec())?;
+    Ok((key, decode_history_height(height)?))
+}
+
+fn decode_history_value(v: &[u8]) -> Result<VersionedValue> {
+    if v.is_empty() {
+        return Err(from_str(
+            "history entry without value, the history db must be rebuilt",
+        ));
+    }
+    VersionedValue::decode_value(v)
+}
+
+fn decode_history_height(mut b: &[u8]) -> Result<Height> {
+    let block_num = b.read_u64::<BigEndian>()?;
+    let tx_num = b.read_u64::<BigEndian>()?;
+    Ok(Height::new(block_num, tx_num))
+}
 
 pub struct KVHistoryQueryExecutor {}
 
@@ -1,10 +1,15 @@
 use byteorder::WriteBytesExt;
 use error::*;
-use rocksdb::DB;
-use silk_proto::Block;
+use rocksdb::{WriteBatch, DB};
+use silk_proto::{Block, LedgerMetadata, LedgerStatus};
 use std::io::Write;
 use std::path::PathBuf;
 
+const LEDGER_KEY_PREFIX: u8 = b'l';
+const METADATA_KEY_PREFIX: u8 = b's';
+
+// IDStore is the registry of the ledgers of a peer. It keeps the genesis block and the status
+// of every ledger, a ledger stays under construction until its genesis block is committed
 pub struct IDStore {
     db: DB,
 }
@@ -18,26 +23,182 @@ impl IDStore {
         })
     }
 
+    // create_ledger_id registers a ledger under construction with its genesis block
     pub fn create_ledger_id(&self, ledger_id: &str, block: &Block) -> Result<()> {
-        let key = self.encode_ledger_key(ledger_id);
+        let key = encode_ledger_key(ledger_id);
         if self.db.get(&key)?.is_some() {
             return Err(from_str(format!("ledger {:} exist", ledger_id).as_str()));
         }
 
-        self.db.put(key, utils::proto::marshal(block)?)?;
+        let mut batch = WriteBatch::default();
+        batch.put(key, utils::proto::marshal(block)?);
+        batch.put(
+            encode_metadata_key(ledger_id),
+            utils::proto::marshal(&LedgerMetadata {
+                status: LedgerStatus::UnderConstruction as i32,
+            })?,
+        );
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    // update_ledger_status sets the status of a registered ledger
+    pub fn update_ledger_status(&self, ledger_id: &str, status: LedgerStatus) -> Result<()> {
+        if !self.ledger_id_exists(ledger_id)? {
+            return Err(from_str(&format!("ledger {:} does not exist", ledger_id)));
+        }
+        self.db.put(
+            encode_metadata_key(ledger_id),
+            utils::proto::marshal(&LedgerMetadata {
+                status: status as i32,
+            })?,
+        )?;
+        Ok(())
+    }
+
+    // delete_ledger_id removes a ledger from the registry, to roll back its creation
+    pub fn delete_ledger_id(&self, ledger_id: &str) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        batch.delete(encode_ledger_key(ledger_id));
+        batch.delete(encode_metadata_key(ledger_id));
+        self.db.write(batch)?;
         Ok(())
     }
 
     pub fn ledger_id_exists(&self, ledger_id: &str) -> Result<bool> {
-        let key = self.encode_ledger_key(&ledger_id);
+        let key = encode_ledger_key(&ledger_id);
         let v = self.db.get(key)?;
         Ok(v.is_some())
     }
 
-    fn encode_ledger_key(&self, ledger_id: &str) -> Vec<u8> {
-        let mut buf = vec![];
-        buf.write_u8(b'l').unwrap();
-        let _ = buf.write(ledger_id.as_bytes()).unwrap();
-        buf
+    // get_ledger_status returns the status of a ledger, None if it is not registered. The ledgers
+    // registered before the statuses were kept are active
+    pub fn get_ledger_status(&self, ledger_id: &str) -> Result<Option<LedgerStatus>> {
+        if !self.ledger_id_exists(ledger_id)? {
+            return Ok(None);
+        }
+        match self.db.get(encode_metadata_key(ledger_id))? {
+            Some(v) => Ok(Some(decode_status(&v)?)),
+            None => Ok(Some(LedgerStatus::Active)),
+        }
+    }
+
+    // get_genesis_block returns the genesis block a ledger was created with
+    pub fn get_genesis_block(&self, ledger_id: &str) -> Result<Block> {
+        match self.db.get(encode_ledger_key(ledger_id))? {
+            Some(v) => Ok(utils::proto::unmarshal(&v)?),
+            None => Err(from_str(&format!("ledger {:} does not exist", ledger_id))),
+        }
+    }
+
+    // get_ledger_ids returns the ids of all the registered ledgers, sorted
+    pub fn get_ledger_ids(&self) -> Result<Vec<String>> {
+        let prefix = [LEDGER_KEY_PREFIX];
+        let mut ids = vec![];
+        for (k, _) in self.db.prefix_iterator(prefix) {
+            if !k.starts_with(&prefix) {
+                break;
+            }
+            ids.push(String::from_utf8(k[prefix.len()..].to_vec())?);
+        }
+        Ok(ids)
+    }
+
+    // get_ledger_ids_with_status returns the ids of the ledgers having the given status, sorted
+    pub fn get_ledger_ids_with_status(&self, status: LedgerStatus) -> Result<Vec<String>> {
+        let mut ids = vec![];
+        for id in self.get_ledger_ids()? {
+            if self.get_ledger_status(&id)? == Some(status) {
+                ids.push(id);
+            }
+        }
+        Ok(ids)
+    }
+}
+
+fn encode_ledger_key(ledger_id: &str) -> Vec<u8> {
+    let mut buf = vec![];
+    buf.write_u8(LEDGER_KEY_PREFIX).unwrap();
+    let _ = buf.write(ledger_id.as_bytes()).unwrap();
+    buf
+}
+
+fn encode_metadata_key(ledger_id: &str) -> Vec<u8> {
+    let mut buf = vec![];
+    buf.write_u8(METADATA_KEY_PREFIX).unwrap();
+    let _ = buf.write(ledger_id.as_bytes()).unwrap();
+    buf
+}
+
+fn decode_status(v: &[u8]) -> Result<LedgerStatus> {
+    let metadata: LedgerMetadata = utils::proto::unmarshal(v)?;
+    LedgerStatus::from_i32(metadata.status)
+        .ok_or_else(|| from_str(&format!("unknown ledger status {:}", metadata.status)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IDStore;
+    use silk_proto::*;
+    use tempfile::TempDir;
+
+    fn genesis_block(channel_id: &str) -> Block {
+        Block {
+            header: Some(BlockHeader::default()),
+            data: Some(BlockData {
+                data: vec![channel_id.as_bytes().to_vec()],
+            }),
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn test_ledger_status() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = IDStore::new(temp_dir.path()).unwrap();
+
+        store
+            .create_ledger_id("ch1", &genesis_block("ch1"))
+            .unwrap();
+        store
+            .create_ledger_id("ch2", &genesis_block("ch2"))
+            .unwrap();
+        assert!(store
+            .create_ledger_id("ch1", &genesis_block("ch1"))
+            .is_err());
+        assert_eq!(
+            store.get_ledger_status("ch1").unwrap(),
+            Some(LedgerStatus::UnderConstruction)
+        );
+        assert!(store.get_ledger_status("ch3").unwrap().is_none());
+        assert_eq!(
+            store.get_genesis_block("ch2").unwrap(),
+            genesis_block("ch2")
+        );
+        assert!(store.get_genesis_block("ch3").is_err());
+
+        store
+            .update_ledger_status("ch2", LedgerStatus::Active)
+            .unwrap();
+        assert!(store
+            .update_ledger_status("ch3", LedgerStatus::Active)
+            .is_err());
+        assert_eq!(store.get_ledger_ids().unwrap(), vec!["ch1", "ch2"]);
+        assert_eq!(
+            store
+                .get_ledger_ids_with_status(LedgerStatus::Active)
+                .unwrap(),
+            vec!["ch2"]
+        );
+        assert_eq!(
+            store
+                .get_ledger_ids_with_status(LedgerStatus::UnderConstruction)
+                .unwrap(),
+            vec!["ch1"]
+        );
+
+        store.delete_ledger_id("ch1").unwrap();
+        assert!(!store.ledger_id_exists("ch1").unwrap());
+        assert_eq!(store.get_ledger_ids().unwrap(), vec!["ch2"]);
     }
 }
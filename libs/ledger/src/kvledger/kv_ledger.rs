@@ -1,18 +1,177 @@
-use crate::kvledger::history;
+use crate::kvledger::history::{self, HistoryDB};
+use crate::pvtdata::transient::TransientStore;
 use crate::simulator::TxSimulator;
+use crate::statedb::{IndexCapable, VersionedDB};
+use crate::txmgr::{LockBasedTxMgr, TxMgr};
 use crate::QueryExecutor;
+use blockdb::BlockStore;
 use error::*;
 use silk_proto::*;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-pub struct KVLedger {}
+pub struct KVLedger<S: BlockStore, V: VersionedDB> {
+    ledger_id: String,
+    block_store: Mutex<S>,
+    txmgr: LockBasedTxMgr<V>,
+    history_db: HistoryDB,
+    transient_store: Option<Arc<TransientStore>>,
+}
+
+impl<S: BlockStore, V: VersionedDB + IndexCapable + Clone + Sync> KVLedger<S, V> {
+    pub fn new(
+        ledger_id: &str,
+        block_store: S,
+        vdb: V,
+        history_db: HistoryDB,
+        lock_timeout: Duration,
+    ) -> Result<Self> {
+        KVLedger::new_with_transient_store(
+            ledger_id,
+            block_store,
+            vdb,
+            history_db,
+            None,
+            lock_timeout,
+        )
+    }
+
+    // new_with_transient_store returns a ledger committing the private writes of its transactions
+    // from the private rwsets kept in the transient store
+    pub fn new_with_transient_store(
+        ledger_id: &str,
+        block_store: S,
+        vdb: V,
+        history_db: HistoryDB,
+        transient_store: Option<Arc<TransientStore>>,
+        lock_timeout: Duration,
+    ) -> Result<Self> {
+        let mut txmgr = LockBasedTxMgr::new(vdb, lock_timeout);
+        if let Some(transient_store) = &transient_store {
+            txmgr = txmgr.with_transient_store(transient_store.clone());
+        }
+        let l = KVLedger {
+            ledger_id: ledger_id.to_string(),
+            block_store: Mutex::new(block_store),
+            txmgr,
+            history_db,
+            transient_store,
+        };
+        l.load_channel_config()?;
+        l.recover_dbs()?;
+        Ok(l)
+    }
+
+    // rebuild drops the state, the history and the transaction index of a ledger and rebuilds
+    // them by replaying the blocks of the block store
+    pub fn rebuild(
+        ledger_id: &str,
+        mut block_store: S,
+        vdb: V,
+        history_db: HistoryDB,
+        lock_timeout: Duration,
+    ) -> Result<Self> {
+        info!("ledger {:} rebuilding databases", ledger_id);
+        block_store.rebuild_tx_index()?;
+        vdb.clear()?;
+        history_db.clear()?;
+        KVLedger::new(ledger_id, block_store, vdb, history_db, lock_timeout)
+    }
 
-impl KVLedger {
-    pub fn new() -> Self {
-        KVLedger {}
+    // rollback removes the blocks above `block_num` from the block store and rebuilds the state
+    // and the history up to it
+    pub fn rollback(
+        ledger_id: &str,
+        mut block_store: S,
+        block_num: u64,
+        vdb: V,
+        history_db: HistoryDB,
+        lock_timeout: Duration,
+    ) -> Result<Self> {
+        info!("ledger {:} rolling back to block {:}", ledger_id, block_num);
+        block_store.rollback(block_num)?;
+        vdb.clear()?;
+        history_db.clear()?;
+        KVLedger::new(ledger_id, block_store, vdb, history_db, lock_timeout)
+    }
+
+    // load_channel_config sets the endorsement policies of the channel from the config carried
+    // by its genesis block, the blocks committed after are checked against them
+    fn load_channel_config(&self) -> Result<()> {
+        if let Some(config) = self.genesis_channel_config()? {
+            self.txmgr.set_channel_config(&config)?;
+        }
+        Ok(())
+    }
+
+    fn genesis_channel_config(&self) -> Result<Option<ChannelConfig>> {
+        let block_store = self
+            .block_store
+            .lock()
+            .map_err(|e| from_str(&e.to_string()))?;
+        match block_store.retrieve_block_by_number(0)? {
+            Some(genesis) => utils::utils::get_channel_config_from_block(&genesis),
+            None => Ok(None),
+        }
+    }
+
+    // recover_dbs brings the state and the history databases up to the last block of the block
+    // store. They lag behind when the peer stopped between appending a block to the block store
+    // and applying its updates, the missing blocks are validated and applied again
+    fn recover_dbs(&self) -> Result<()> {
+        let block_store = self
+            .block_store
+            .lock()
+            .map_err(|e| from_str(&e.to_string()))?;
+        let info = block_store.get_blockchain_info()?;
+        if info.current_block_hash.is_empty() {
+            // nothing has been committed yet
+            return Ok(());
+        }
+        let last_available_block = info.height;
+
+        let (recover_state, state_from) = self.txmgr.should_recover(last_available_block)?;
+        let (recover_history, history_from) =
+            self.history_db.should_recover(last_available_block)?;
+        if !recover_state && !recover_history {
+            return Ok(());
+        }
+
+        let first_block = match (recover_state, recover_history) {
+            (true, true) => state_from.min(history_from),
+            (true, false) => state_from,
+            _ => history_from,
+        };
+        if first_block > last_available_block {
+            return Err(from_str(&format!(
+                "ledger {:} databases are ahead of the block store, last available block {:}",
+                self.ledger_id, last_available_block
+            )));
+        }
+
+        info!(
+            "ledger {:} recovering blocks [{:}, {:}], state: {:}, history: {:}",
+            self.ledger_id, first_block, last_available_block, recover_state, recover_history
+        );
+        for num in first_block..=last_available_block {
+            let block = block_store
+                .retrieve_block_by_number(num)?
+                .ok_or_else(|| from_str(&format!("block {:} not found", num)))?;
+            if recover_state && num >= state_from {
+                self.txmgr.validate_and_prepare(&block)?;
+                self.txmgr.commit()?;
+            }
+            if recover_history && num >= history_from {
+                self.history_db.commit(&block)?;
+            }
+        }
+        Ok(())
     }
 }
 
-impl crate::Ledger for KVLedger {
+impl<S: BlockStore, V: VersionedDB + IndexCapable + Clone + Send + Sync + 'static> crate::Ledger
+    for KVLedger<S, V>
+{
     type HQE = history::KVHistoryQueryExecutor;
 
     fn get_blockchain_info(&self) -> Result<BlockchainInfo> {
@@ -46,23 +205,421 @@ impl crate::Ledger for KVLedger {
         unimplemented!()
     }
 
-    fn new_tx_simulator(&self, _txid: String) -> Result<Box<dyn TxSimulator>> {
-        unimplemented!()
+    fn new_tx_simulator(&self, txid: String) -> Result<Box<dyn TxSimulator + Send>> {
+        Ok(Box::new(self.txmgr.new_tx_simulator(txid)?))
     }
 
     fn new_query_executor(&self) -> Result<Box<dyn QueryExecutor>> {
-        unimplemented!()
+        let qe = self
+            .txmgr
+            .new_query_executor()?
+            .with_history(self.history_db.clone());
+        Ok(Box::new(qe))
     }
 
     fn new_history_query_executor(&self) -> Result<Self::HQE> {
         unimplemented!()
     }
 
-    fn commit_legacy(&self, _block: Block) -> Result<()> {
-        unimplemented!()
+    fn get_state_root(&self, block_num: u64) -> Result<Option<Vec<u8>>> {
+        self.txmgr.get_state_root(block_num)
+    }
+
+    fn get_channel_config(&self) -> Result<Option<ChannelConfig>> {
+        self.genesis_channel_config()
+    }
+
+    fn persist_pvt_rwset(&self, tx_id: &str, pvt_rwset: &TxPvtReadWriteSet) -> Result<()> {
+        let transient_store = self.transient_store.as_ref().ok_or_else(|| {
+            from_str(&format!(
+                "ledger {:} has no transient store",
+                self.ledger_id
+            ))
+        })?;
+        let block_height = match self.txmgr.get_last_savepoint()? {
+            Some(height) => height.block_num + 1,
+            None => 0,
+        };
+        transient_store.persist(tx_id, block_height, pvt_rwset)
+    }
+
+    fn commit_legacy(&self, mut block: Block) -> Result<()> {
+        let tx_codes = self.txmgr.validate_and_prepare(&block)?;
+        utils::utils::set_tx_validation_flags(&mut block, &tx_codes.flags());
+
+        // the block store is written first, a crash before the state and the history databases
+        // are updated is repaired by recover_dbs when the ledger is opened again. The prepared
+        // updates hold the commit lock until they are committed or discarded
+        let added = self
+            .block_store
+            .lock()
+            .map_err(|e| from_str(&e.to_string()))
+            .and_then(|mut block_store| block_store.add_block(&block));
+        if let Err(e) = added {
+            self.txmgr.discard();
+            return Err(e);
+        }
+        self.txmgr.commit()?;
+        self.history_db.commit(&block)
     }
 
     fn close(&self) {
         unimplemented!()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::KVLedger;
+    use crate::kvledger::history::HistoryDBProvider;
+    use crate::rwset::policy::tests::{channel, config_tx, invoke};
+    use crate::simulator::sim::BasedTxSimulator;
+    use crate::simulator::TxSimulator;
+    use crate::statedb::{
+        Height, RocksDBVersion, VersionedDB, VersionedDBProvider, VersionedDBRocksProvider,
+    };
+    use crate::txmgr::DEFAULT_LOCK_TIMEOUT;
+    use crate::Ledger;
+    use blockdb::store::Store;
+    use blockdb::BlockStore;
+    use identity::signer::SigningIdentity;
+    use silk_proto::*;
+    use tempfile::TempDir;
+
+    // create_block returns a block writing the key endorsed by the peer, the genesis block opens
+    // with the channel config
+    fn create_block(
+        num: u64,
+        vdb: &RocksDBVersion,
+        key: &str,
+        value: &str,
+        (config, peer): &(ChannelConfig, SigningIdentity),
+    ) -> Block {
+        let tx_id = format!("tx_{:}", num);
+        let mut sim = BasedTxSimulator::new(tx_id.clone(), vdb.clone());
+        sim.set_state("ns", key, value.as_bytes().to_vec()).unwrap();
+        let results = sim.get_tx_simulation_results().unwrap();
+
+        let mut txs = vec![];
+        if num == 0 {
+            txs.push(config_tx(config));
+        }
+        txs.push(invoke(&tx_id, "ns", &results.simulation_results, &[peer]));
+        Block {
+            header: Some(BlockHeader {
+                number: num,
+                ..Default::default()
+            }),
+            data: Some(BlockData {
+                data: txs
+                    .iter()
+                    .map(|tx| utils::proto::marshal(tx).unwrap())
+                    .collect(),
+            }),
+            metadata: None,
+        }
+    }
+
+    // org1 returns the config of a channel of org1 with its peer
+    fn org1() -> (ChannelConfig, SigningIdentity) {
+        let (config, mut signers) = channel(&["org1"]);
+        (config, signers.remove(0))
+    }
+
+    #[test]
+    fn test_recover_dbs() {
+        let temp_dir = TempDir::new().unwrap();
+        let vdb = VersionedDBRocksProvider::new(temp_dir.path())
+            .unwrap()
+            .get_db_handle("chain_id")
+            .unwrap();
+        let org1 = org1();
+        let history_provider = HistoryDBProvider::new(temp_dir.path());
+        let history_db = history_provider.get_db_handle("chain_id").unwrap();
+
+        {
+            let store = Store::open(temp_dir.path()).unwrap();
+            let ledger = KVLedger::new(
+                "chain_id",
+                store,
+                vdb.clone(),
+                history_db.clone(),
+                DEFAULT_LOCK_TIMEOUT,
+            )
+            .unwrap();
+            ledger
+                .commit_legacy(create_block(0, &vdb, "k", "v0", &org1))
+                .unwrap();
+            ledger
+                .commit_legacy(create_block(1, &vdb, "k", "v1", &org1))
+                .unwrap();
+        }
+
+        // the peer stops after appending block 2 to the block store
+        {
+            let mut store = Store::open(temp_dir.path()).unwrap();
+            store
+                .add_block(&create_block(2, &vdb, "k", "v2", &org1))
+                .unwrap();
+        }
+        assert_eq!(
+            vdb.get_latest_save_point().unwrap(),
+            Some(Height::new(1, 0))
+        );
+        assert_eq!(history_db.get_key_history("ns", "k").unwrap().len(), 2);
+
+        let store = Store::open(temp_dir.path()).unwrap();
+        let _ledger = KVLedger::new(
+            "chain_id",
+            store,
+            vdb.clone(),
+            history_db.clone(),
+            DEFAULT_LOCK_TIMEOUT,
+        )
+        .unwrap();
+
+        assert_eq!(
+            vdb.get_latest_save_point().unwrap(),
+            Some(Height::new(2, 0))
+        );
+        let vv = vdb.get_state("ns", "k").unwrap().unwrap();
+        assert_eq!(vv.value, b"v2".to_vec());
+        assert_eq!(vv.version, Height::new(2, 0));
+        assert_eq!(
+            history_db.get_key_history("ns", "k").unwrap(),
+            vec![Height::new(0, 1), Height::new(1, 0), Height::new(2, 0)]
+        );
+    }
+
+    #[test]
+    fn test_state_at() {
+        let temp_dir = TempDir::new().unwrap();
+        let vdb = VersionedDBRocksProvider::new(temp_dir.path())
+            .unwrap()
+            .get_db_handle("chain_id")
+            .unwrap();
+        let org1 = org1();
+        let history_db = HistoryDBProvider::new(temp_dir.path())
+            .get_db_handle("chain_id")
+            .unwrap();
+        let store = Store::open(temp_dir.path()).unwrap();
+        let ledger = KVLedger::new(
+            "chain_id",
+            store,
+            vdb.clone(),
+            history_db,
+            DEFAULT_LOCK_TIMEOUT,
+        )
+        .unwrap();
+        ledger
+            .commit_legacy(create_block(0, &vdb, "k", "v0", &org1))
+            .unwrap();
+        ledger
+            .commit_legacy(create_block(1, &vdb, "k", "v1", &org1))
+            .unwrap();
+        ledger
+            .commit_legacy(create_block(2, &vdb, "j", "v2", &org1))
+            .unwrap();
+
+        let qe = ledger.new_query_executor().unwrap();
+        let vv = qe.get_state_at("ns", "k", 0).unwrap().unwrap();
+        assert_eq!(vv.value, b"v0".to_vec());
+        assert_eq!(vv.version, Height::new(0, 1));
+        let vv = qe.get_state_at("ns", "k", 2).unwrap().unwrap();
+        assert_eq!(vv.value, b"v1".to_vec());
+        assert_eq!(vv.version, Height::new(1, 0));
+        assert!(qe.get_state_at("ns", "j", 1).unwrap().is_none());
+
+        let keys_at = |block_num| {
+            qe.get_state_range_scan_iterator_at("ns", "", "", block_num)
+                .unwrap()
+                .map(|kv| (kv.key, kv.value))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(keys_at(0), vec![("k".to_string(), b"v0".to_vec())]);
+        let kvs: Vec<String> = qe
+            .get_state_range_scan_iterator_at("ns", "", "k", 2)
+            .unwrap()
+            .map(|kv| kv.key)
+            .collect();
+        assert_eq!(kvs, vec!["j".to_string()]);
+        assert_eq!(
+            keys_at(2),
+            vec![
+                ("j".to_string(), b"v2".to_vec()),
+                ("k".to_string(), b"v1".to_vec())
+            ]
+        );
+
+        // the state of a block not committed yet is unknown
+        assert!(qe.get_state_at("ns", "k", 3).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_tx_id() {
+        let temp_dir = TempDir::new().unwrap();
+        let vdb = VersionedDBRocksProvider::new(temp_dir.path())
+            .unwrap()
+            .get_db_handle("chain_id")
+            .unwrap();
+        let org1 = org1();
+        let history_db = HistoryDBProvider::new(temp_dir.path())
+            .get_db_handle("chain_id")
+            .unwrap();
+        let store = Store::open(temp_dir.path()).unwrap();
+        let ledger = KVLedger::new(
+            "chain_id",
+            store,
+            vdb.clone(),
+            history_db.clone(),
+            DEFAULT_LOCK_TIMEOUT,
+        )
+        .unwrap();
+        ledger
+            .commit_legacy(create_block(0, &vdb, "k", "v0", &org1))
+            .unwrap();
+
+        // the second transaction reuses the id of the first one
+        let mut block = create_block(1, &vdb, "k", "v1", &org1);
+        let replay = create_block(1, &vdb, "j", "v1", &org1);
+        let data = block.data.as_mut().unwrap();
+        data.data.push(replay.data.unwrap().data.remove(0));
+        ledger.commit_legacy(block).unwrap();
+
+        let block = ledger
+            .block_store
+            .lock()
+            .unwrap()
+            .retrieve_block_by_number(1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            utils::utils::get_tx_validation_flags(&block),
+            vec![TxValidationCode::Valid, TxValidationCode::DuplicateTxid]
+        );
+        assert_eq!(vdb.get_state("ns", "k").unwrap().unwrap().value, b"v1");
+        assert!(vdb.get_state("ns", "j").unwrap().is_none());
+        assert!(history_db.get_key_history("ns", "j").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_tx_simulator() {
+        let temp_dir = TempDir::new().unwrap();
+        let vdb = VersionedDBRocksProvider::new(temp_dir.path())
+            .unwrap()
+            .get_db_handle("chain_id")
+            .unwrap();
+        let org1 = org1();
+        let history_db = HistoryDBProvider::new(temp_dir.path())
+            .get_db_handle("chain_id")
+            .unwrap();
+        let store = Store::open(temp_dir.path()).unwrap();
+        let ledger = KVLedger::new(
+            "chain_id",
+            store,
+            vdb.clone(),
+            history_db,
+            DEFAULT_LOCK_TIMEOUT,
+        )
+        .unwrap();
+        ledger
+            .commit_legacy(create_block(0, &vdb, "k", "v0", &org1))
+            .unwrap();
+
+        let mut sim = ledger.new_tx_simulator("tx_1".to_string()).unwrap();
+        assert_eq!(sim.get_state("ns", "k").unwrap(), b"v0".to_vec());
+        sim.set_state("ns", "j", b"v1".to_vec()).unwrap();
+        let results = sim.get_tx_simulation_results().unwrap().simulation_results;
+        sim.done();
+
+        let ns_rw_set = &results.ns_rwset[0];
+        assert_eq!(ns_rw_set.namespace, "ns");
+        let kv_rw_set: KvrwSet = utils::proto::unmarshal(&ns_rw_set.rwset).unwrap();
+        assert_eq!(kv_rw_set.reads[0].key, "k");
+        assert_eq!(
+            kv_rw_set.reads[0].version.clone().map(Height::from),
+            Some(Height::new(0, 1))
+        );
+        assert_eq!(kv_rw_set.writes[0].key, "j");
+    }
+
+    #[test]
+    fn test_rebuild_and_rollback() {
+        let temp_dir = TempDir::new().unwrap();
+        let vdb = VersionedDBRocksProvider::new(temp_dir.path())
+            .unwrap()
+            .get_db_handle("chain_id")
+            .unwrap();
+        let org1 = org1();
+        let history_provider = HistoryDBProvider::new(temp_dir.path());
+        let history_db = history_provider.get_db_handle("chain_id").unwrap();
+
+        {
+            let store = Store::open(temp_dir.path()).unwrap();
+            let ledger = KVLedger::new(
+                "chain_id",
+                store,
+                vdb.clone(),
+                history_db.clone(),
+                DEFAULT_LOCK_TIMEOUT,
+            )
+            .unwrap();
+            for num in 0..5 {
+                let block = create_block(num, &vdb, "k", &format!("v{:}", num), &org1);
+                ledger.commit_legacy(block).unwrap();
+            }
+        }
+
+        {
+            let store = Store::open(temp_dir.path()).unwrap();
+            let _ledger = KVLedger::rebuild(
+                "chain_id",
+                store,
+                vdb.clone(),
+                history_db.clone(),
+                DEFAULT_LOCK_TIMEOUT,
+            )
+            .unwrap();
+        }
+        assert_eq!(
+            vdb.get_latest_save_point().unwrap(),
+            Some(Height::new(4, 0))
+        );
+        assert_eq!(
+            vdb.get_state("ns", "k").unwrap().unwrap().value,
+            b"v4".to_vec()
+        );
+        assert_eq!(history_db.get_key_history("ns", "k").unwrap().len(), 5);
+
+        let store = Store::open(temp_dir.path()).unwrap();
+        let ledger = KVLedger::rollback(
+            "chain_id",
+            store,
+            2,
+            vdb.clone(),
+            history_db.clone(),
+            DEFAULT_LOCK_TIMEOUT,
+        )
+        .unwrap();
+        assert_eq!(
+            vdb.get_latest_save_point().unwrap(),
+            Some(Height::new(2, 0))
+        );
+        let vv = vdb.get_state("ns", "k").unwrap().unwrap();
+        assert_eq!(vv.value, b"v2".to_vec());
+        assert_eq!(vv.version, Height::new(2, 0));
+        assert_eq!(
+            history_db.get_key_history("ns", "k").unwrap(),
+            vec![Height::new(0, 1), Height::new(1, 0), Height::new(2, 0)]
+        );
+
+        // the chain goes on from the rolled back block
+        ledger
+            .commit_legacy(create_block(3, &vdb, "k", "v3'", &org1))
+            .unwrap();
+        assert_eq!(
+            vdb.get_state("ns", "k").unwrap().unwrap().value,
+            b"v3'".to_vec()
+        );
+    }
+}
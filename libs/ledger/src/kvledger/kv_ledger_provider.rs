@@ -1,34 +1,117 @@
+use crate::kvledger::history::HistoryDBProvider;
 use crate::kvledger::id_store::IDStore;
 use crate::kvledger::kv_ledger::KVLedger;
-use crate::statedb::VersionedDBProvider;
-use crate::Initializer;
-use blockdb::BlockStoreProvider;
+use crate::pvtdata::transient::TransientStoreProvider;
+use crate::statedb::{IndexCapable, VersionedDBProvider};
+use crate::{Initializer, Ledger};
+use blockdb::{BlockStore, BlockStoreProvider};
 use error::*;
-use silk_proto::Block;
+use silk_proto::{Block, LedgerStatus};
+use std::time::Duration;
 use utils::utils;
 
 pub struct Provider<VP: VersionedDBProvider, BSP: BlockStoreProvider> {
     id_store: IDStore,
     block_store_provider: BSP,
     vdb_provider: VP,
-    history_db_provider: String,
+    history_db_provider: HistoryDBProvider,
+    transient_store_provider: TransientStoreProvider,
+    lock_timeout: Duration,
 }
 
-impl<VP: VersionedDBProvider, BSP: BlockStoreProvider> Provider<VP, BSP> {
+impl<VP: VersionedDBProvider, BSP: BlockStoreProvider> Provider<VP, BSP>
+where
+    VP::V: IndexCapable + Clone + Send + Sync + 'static,
+{
+    // new returns a provider over the ledgers of the peer. The creation of the ledgers left under
+    // construction by a crash is completed, or rolled back if it can not be
     pub fn new(init: Initializer, vdb_provider: VP, block_store_provider: BSP) -> Result<Self> {
         let p = Provider {
             id_store: IDStore::new(&init.root_fs_path)?,
             block_store_provider,
             vdb_provider,
-            history_db_provider: "".to_string(),
+            history_db_provider: HistoryDBProvider::new(&init.root_fs_path),
+            transient_store_provider: TransientStoreProvider::new(&init.root_fs_path),
+            lock_timeout: init.lock_timeout,
         };
 
+        for ledger_id in p
+            .id_store
+            .get_ledger_ids_with_status(LedgerStatus::UnderConstruction)?
+        {
+            info!("ledger {:} is under construction, completing it", ledger_id);
+            let genesis_block = p.id_store.get_genesis_block(&ledger_id)?;
+            if let Err(e) = p.complete_creation(&ledger_id, &genesis_block) {
+                warn!(
+                    "ledger {:} can not be completed, rolling it back: {:}",
+                    ledger_id, e
+                );
+                p.rollback_creation(&ledger_id)?;
+            }
+        }
         Ok(p)
     }
+
+    // complete_creation commits the genesis block of a ledger under construction, unless the
+    // block store already holds it, and activates the ledger
+    fn complete_creation(
+        &self,
+        ledger_id: &str,
+        genesis_block: &Block,
+    ) -> Result<KVLedger<BSP::S, VP::V>> {
+        // the block store of a ledger whose creation was rolled back is left behind
+        let block_store = if self.block_store_provider.exists(ledger_id)? {
+            self.block_store_provider.open_block_store(ledger_id)?
+        } else {
+            self.block_store_provider.create_block_store(ledger_id)?
+        };
+        let committed = block_store.retrieve_block_by_number(0)?.is_some();
+        let vdb = self.vdb_provider.get_db_handle(ledger_id)?;
+        let history_db = self.history_db_provider.get_db_handle(ledger_id)?;
+        let transient_store = self.transient_store_provider.get_store(ledger_id)?;
+
+        let kvl = KVLedger::new_with_transient_store(
+            ledger_id,
+            block_store,
+            vdb,
+            history_db,
+            Some(transient_store),
+            self.lock_timeout,
+        )?;
+        if !committed {
+            kvl.commit_legacy(genesis_block.clone())?;
+        }
+        self.id_store
+            .update_ledger_status(ledger_id, LedgerStatus::Active)?;
+        Ok(kvl)
+    }
+
+    // rollback_creation drops the state and the history of a ledger under construction and
+    // removes it from the registry
+    fn rollback_creation(&self, ledger_id: &str) -> Result<()> {
+        self.vdb_provider.drop_db(ledger_id)?;
+        self.history_db_provider.get_db_handle(ledger_id)?.clear()?;
+        self.id_store.delete_ledger_id(ledger_id)
+    }
+
+    // check_active fails for a ledger that does not exist or is not active
+    fn check_active(&self, ledger_id: &str) -> Result<()> {
+        match self.id_store.get_ledger_status(ledger_id)? {
+            Some(LedgerStatus::Active) => Ok(()),
+            Some(status) => Err(from_str(&format!(
+                "ledger {:} is not active, status {:?}",
+                ledger_id, status
+            ))),
+            None => Err(from_str(&format!("ledger {:} does not exist", ledger_id))),
+        }
+    }
 }
 
-impl<VP: VersionedDBProvider, BSP: BlockStoreProvider> crate::LedgerProvider for Provider<VP, BSP> {
-    type L = KVLedger;
+impl<VP: VersionedDBProvider, BSP: BlockStoreProvider> crate::LedgerProvider for Provider<VP, BSP>
+where
+    VP::V: IndexCapable + Clone + Send + Sync + 'static,
+{
+    type L = KVLedger<BSP::S, VP::V>;
 
     fn create(&self, genesis_block: &Block) -> Result<Self::L> {
         let ledger_id = utils::get_chain_id_from_block(genesis_block)?;
@@ -36,30 +119,187 @@ impl<VP: VersionedDBProvider, BSP: BlockStoreProvider> crate::LedgerProvider for
             return Err(from_str(format!("ledger {:} exist", ledger_id).as_str()));
         }
 
+        // the ledger stays under construction until the genesis block is committed
         self.id_store.create_ledger_id(&ledger_id, genesis_block)?;
+        match self.complete_creation(&ledger_id, genesis_block) {
+            Ok(kvl) => Ok(kvl),
+            Err(e) => {
+                if let Err(e) = self.rollback_creation(&ledger_id) {
+                    error!(
+                        "ledger {:} creation can not be rolled back: {:}",
+                        ledger_id, e
+                    );
+                }
+                Err(e)
+            }
+        }
+    }
 
-        // TODO: init block store
-        // TODO: init history db
-        let _vdb = self.vdb_provider.get_db_handle(&ledger_id);
+    fn open(&self, ledger_id: &str) -> Result<Self::L> {
+        self.check_active(ledger_id)?;
 
-        let kvl = KVLedger::new();
+        let block_store = self.block_store_provider.open_block_store(ledger_id)?;
+        let vdb = self.vdb_provider.get_db_handle(ledger_id)?;
+        let history_db = self.history_db_provider.get_db_handle(ledger_id)?;
+        let transient_store = self.transient_store_provider.get_store(ledger_id)?;
 
-        Ok(kvl)
+        // the state and the history databases are synced with the block store on opening
+        KVLedger::new_with_transient_store(
+            ledger_id,
+            block_store,
+            vdb,
+            history_db,
+            Some(transient_store),
+            self.lock_timeout,
+        )
     }
 
-    fn open(&self, _ledger_id: &str) -> Result<Self::L> {
-        unimplemented!()
+    fn rebuild_dbs(&self, ledger_id: &str) -> Result<()> {
+        self.check_active(ledger_id)?;
+
+        let block_store = self.block_store_provider.open_block_store(ledger_id)?;
+        let vdb = self.vdb_provider.get_db_handle(ledger_id)?;
+        let history_db = self.history_db_provider.get_db_handle(ledger_id)?;
+        KVLedger::rebuild(ledger_id, block_store, vdb, history_db, self.lock_timeout)?;
+        Ok(())
+    }
+
+    fn rollback(&self, ledger_id: &str, block_num: u64) -> Result<()> {
+        self.check_active(ledger_id)?;
+
+        let block_store = self.block_store_provider.open_block_store(ledger_id)?;
+        let vdb = self.vdb_provider.get_db_handle(ledger_id)?;
+        let history_db = self.history_db_provider.get_db_handle(ledger_id)?;
+        KVLedger::rollback(
+            ledger_id,
+            block_store,
+            block_num,
+            vdb,
+            history_db,
+            self.lock_timeout,
+        )?;
+        Ok(())
     }
 
-    fn exists(&self, _ledger_id: &str) -> Result<bool> {
-        unimplemented!()
+    fn exists(&self, ledger_id: &str) -> Result<bool> {
+        self.id_store.ledger_id_exists(ledger_id)
     }
 
     fn list(&self) -> Result<Vec<String>> {
-        unimplemented!()
+        self.id_store
+            .get_ledger_ids_with_status(LedgerStatus::Active)
     }
 
     fn close(&self) {
-        unimplemented!()
+        self.block_store_provider.close();
+        self.vdb_provider.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Provider;
+    use crate::kvledger::id_store::IDStore;
+    use crate::statedb::VersionedDBMemProvider;
+    use crate::txmgr::DEFAULT_LOCK_TIMEOUT;
+    use crate::{Initializer, LedgerProvider};
+    use blockdb::mem::MemBlockStoreProvider;
+    use blockdb::{BlockStore, BlockStoreProvider};
+    use silk_proto::*;
+    use tempfile::TempDir;
+
+    fn genesis_block(channel_id: &str) -> Block {
+        let proposal = Proposal {
+            header: Some(Header {
+                channel_id: channel_id.to_string(),
+                tx_id: format!("{:}_genesis", channel_id),
+                ..Default::default()
+            }),
+            payload: vec![],
+        };
+        let tx = Transaction {
+            signed_proposal: Some(SignedProposal {
+                proposal_bytes: utils::proto::marshal(&proposal).unwrap(),
+                signature: vec![],
+            }),
+            response: vec![ProposalResponse {
+                payload: utils::proto::marshal(&ProposalResponsePayload::default()).unwrap(),
+                ..Default::default()
+            }],
+        };
+        Block {
+            header: Some(BlockHeader::default()),
+            data: Some(BlockData {
+                data: vec![utils::proto::marshal(&tx).unwrap()],
+            }),
+            metadata: None,
+        }
+    }
+
+    fn new_provider(
+        temp_dir: &TempDir,
+        bsp: MemBlockStoreProvider,
+    ) -> Provider<VersionedDBMemProvider, MemBlockStoreProvider> {
+        let init = Initializer {
+            root_fs_path: temp_dir.path().to_str().unwrap().to_string(),
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+        };
+        Provider::new(init, VersionedDBMemProvider::new(), bsp).unwrap()
+    }
+
+    #[test]
+    fn test_create_and_open() {
+        let temp_dir = TempDir::new().unwrap();
+        let provider = new_provider(&temp_dir, MemBlockStoreProvider::new());
+
+        assert!(!provider.exists("ch1").unwrap());
+        assert!(provider.open("ch1").is_err());
+        provider.create(&genesis_block("ch1")).unwrap();
+        assert!(provider.create(&genesis_block("ch1")).is_err());
+        assert!(provider.exists("ch1").unwrap());
+        assert_eq!(provider.list().unwrap(), vec!["ch1"]);
+        provider.open("ch1").unwrap();
+
+        let mut block = genesis_block("ch2");
+        block.data = Some(BlockData::default());
+        assert!(provider.create(&block).is_err());
+        assert!(!provider.exists("ch2").unwrap());
+    }
+
+    #[test]
+    fn test_recover_under_construction() {
+        let temp_dir = TempDir::new().unwrap();
+        // the peer stops right after registering the ledgers, before their genesis blocks are
+        // committed
+        {
+            let id_store = IDStore::new(temp_dir.path()).unwrap();
+            id_store
+                .create_ledger_id("ch1", &genesis_block("ch1"))
+                .unwrap();
+            let mut broken = genesis_block("ch2");
+            broken.header = None;
+            id_store.create_ledger_id("ch2", &broken).unwrap();
+        }
+
+        let provider = new_provider(&temp_dir, MemBlockStoreProvider::new());
+        assert_eq!(provider.list().unwrap(), vec!["ch1"]);
+        assert!(!provider.exists("ch2").unwrap());
+        let block_store = provider
+            .block_store_provider
+            .open_block_store("ch1")
+            .unwrap();
+        assert_eq!(
+            block_store
+                .retrieve_block_by_number(0)
+                .unwrap()
+                .unwrap()
+                .data,
+            genesis_block("ch1").data
+        );
+        provider.open("ch1").unwrap();
+
+        // a ledger rolled back can be created again over its empty block store
+        provider.create(&genesis_block("ch2")).unwrap();
+        assert_eq!(provider.list().unwrap(), vec!["ch1", "ch2"]);
     }
 }
@@ -1,8 +1,10 @@
 use crate::kvledger::kv_ledger_provider::Provider;
 use crate::{Initializer, LedgerProvider};
+use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
 
 use crate::statedb::VersionedDBRocksProvider;
+use crate::txmgr::DEFAULT_LOCK_TIMEOUT;
 use blockdb::provider::LevelDBBlockStoreProvider;
 use error::*;
 use silk_proto::Block;
@@ -23,13 +25,60 @@ impl<P: LedgerProvider> LedgerMgr<P> {
 
     pub fn open_ledger(&self, id: &str) -> Result<Arc<P::L>> {
         debug!("open ledger {:?}", id);
+        match self.opened_ledgers.entry(String::from(id)) {
+            Entry::Occupied(_) => Err(from_str(&format!("ledger {:?} already opened", id))),
+            Entry::Vacant(entry) => {
+                let l = Arc::new(self.ledger_provider.open(id)?);
+                entry.insert(l.clone());
+                Ok(l)
+            }
+        }
+    }
+
+    // get_ledger returns the ledger with the given id, opening it if needed. The entry stays
+    // locked while the ledger opens, so concurrent callers share a single opened ledger
+    pub fn get_ledger(&self, id: &str) -> Result<Arc<P::L>> {
+        if let Some(l) = self.opened_ledgers.get(id) {
+            return Ok(l.clone());
+        }
+        match self.opened_ledgers.entry(String::from(id)) {
+            Entry::Occupied(entry) => Ok(entry.get().clone()),
+            Entry::Vacant(entry) => {
+                debug!("open ledger {:?}", id);
+                let l = Arc::new(self.ledger_provider.open(id)?);
+                entry.insert(l.clone());
+                Ok(l)
+            }
+        }
+    }
+
+    // ledger_exists tells whether the ledger with the given id was created
+    pub fn ledger_exists(&self, id: &str) -> Result<bool> {
+        self.ledger_provider.exists(id)
+    }
+
+    // list_ledgers lists the ids of the active ledgers
+    pub fn list_ledgers(&self) -> Result<Vec<String>> {
+        self.ledger_provider.list()
+    }
+
+    // rebuild_ledger rebuilds the state, the history and the transaction index of a closed ledger
+    // from its block store
+    pub fn rebuild_ledger(&self, id: &str) -> Result<()> {
         if self.opened_ledgers.contains_key(id) {
-            return Err(from_str(&format!("ledger {:?} already opened", id)));
+            return Err(from_str(&format!("ledger {:?} is opened", id)));
         }
+        info!("rebuild ledger {:?}", id);
+        self.ledger_provider.rebuild_dbs(id)
+    }
 
-        let l = Arc::new(self.ledger_provider.open(id)?);
-        self.opened_ledgers.insert(String::from(id), l.clone());
-        Ok(l)
+    // rollback_ledger rolls a closed ledger back to the block `block_num`
+    pub fn rollback_ledger(&self, id: &str, block_num: u64) -> Result<()> {
+        if self.opened_ledgers.contains_key(id) {
+            return Err(from_str(&format!("ledger {:?} is opened", id)));
+        }
+        info!("rollback ledger {:?} to block {:?}", id, block_num);
+        self.ledger_provider.rollback(id, block_num)
     }
 }
 
@@ -37,9 +86,10 @@ impl LedgerMgr<Provider<VersionedDBRocksProvider, LevelDBBlockStoreProvider>> {
     pub fn new() -> Result<Self> {
         let init = Initializer {
             root_fs_path: "/var/silk/production".to_string(),
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
         };
-        let vp = VersionedDBRocksProvider::new(&init.root_fs_path);
-        let bsp = LevelDBBlockStoreProvider::new();
+        let vp = VersionedDBRocksProvider::new(&init.root_fs_path)?;
+        let bsp = LevelDBBlockStoreProvider::new(&init.root_fs_path);
         let provider = Provider::new(init, vp, bsp)?;
         let l = LedgerMgr {
             opened_ledgers: DashMap::new(),
@@ -2,11 +2,15 @@
 extern crate log;
 
 use crate::simulator::TxSimulator;
+use crate::statedb::{StateProof, VersionedValue};
 use error::*;
 use silk_proto::*;
+use std::time::Duration;
 
 pub mod kvledger;
 pub mod ledger_mgmt;
+pub mod lifecycle;
+pub mod pvtdata;
 pub mod rwset;
 pub mod simulator;
 pub mod statedb;
@@ -16,6 +20,8 @@ pub mod txmgr;
 pub struct Initializer {
     // root_fs_path is the top-level directory where ledger files are stored.
     pub root_fs_path: String,
+    // lock_timeout bounds the wait for the commit lock of the ledgers
+    pub lock_timeout: Duration,
 }
 
 // LedgerProvider provides handle to ledger instances
@@ -25,12 +31,18 @@ pub trait LedgerProvider {
     // This function guarantees that the creation of ledger and committing the genesis block would an atomic action
     // The chain id retrieved from the genesis block is treated as a ledger id
     fn create(&self, genesis_block: &Block) -> Result<Self::L>;
-    // open opens an already created ledger
+    // open opens an already created ledger, it must be active
     fn open(&self, ledger_id: &str) -> Result<Self::L>;
     // exists tells whether the ledger with given id exists
     fn exists(&self, ledger_id: &str) -> Result<bool>;
-    // list lists the ids of the existing ledgers
+    // list lists the ids of the active ledgers
     fn list(&self) -> Result<Vec<String>>;
+    // rebuild_dbs drops the state, the history and the transaction index of a ledger and rebuilds
+    // them by replaying the blocks of the block store. The ledger must not be opened
+    fn rebuild_dbs(&self, ledger_id: &str) -> Result<()>;
+    // rollback removes the blocks of a ledger above `block_num` and rebuilds the state and the
+    // history up to it. The ledger must not be opened
+    fn rollback(&self, ledger_id: &str, block_num: u64) -> Result<()>;
     // close closes the PeerLedgerProvider
     fn close(&self);
 }
@@ -63,7 +75,7 @@ pub trait Ledger {
     // new_tx_simulator gives handle to a transaction simulator.
     // A client can obtain more than one 'TxSimulator's for parallel execution.
     // Any snapshoting/synchronization should be performed at the implementation level if required
-    fn new_tx_simulator(&self, txid: String) -> Result<Box<dyn TxSimulator>>;
+    fn new_tx_simulator(&self, txid: String) -> Result<Box<dyn TxSimulator + Send>>;
     // new_query_executor gives handle to a query executor.
     // A client can obtain more than one 'QueryExecutor's for parallel execution.
     // Any synchronization should be performed at the implementation level if required
@@ -72,6 +84,15 @@ pub trait Ledger {
     // A client can obtain more than one 'HistoryQueryExecutor's for parallel execution.
     // Any synchronization should be performed at the implementation level if required
     fn new_history_query_executor(&self) -> Result<Self::HQE>;
+    // get_state_root returns the root of the state tree once the given block was committed, peers
+    // having committed the same blocks have the same roots
+    fn get_state_root(&self, block_num: u64) -> Result<Option<Vec<u8>>>;
+    // get_channel_config returns the config of the channel carried by the genesis block, None
+    // when the genesis block is not a channel config transaction
+    fn get_channel_config(&self) -> Result<Option<ChannelConfig>>;
+    // persist_pvt_rwset keeps the private rwset of a transaction simulated by the peer until the
+    // transaction is committed, its private writes are then applied to the private state
+    fn persist_pvt_rwset(&self, tx_id: &str, pvt_rwset: &TxPvtReadWriteSet) -> Result<()>;
     // commit_legacy commits the block and the corresponding pvt data in an atomic operation following the v14 validation/commit path
     // TODO: add a new Commit() path that replaces commit_legacy() for the validation refactor described in FAB-12221
     fn commit_legacy(&self, block: Block) -> Result<()>;
@@ -80,7 +101,73 @@ pub trait Ledger {
 }
 
 // SimpleQueryExecutor encapsulates basic functions
-pub trait QueryExecutor {}
+pub trait QueryExecutor {
+    // get_state gets the value for given namespace and key. For a contract, the namespace corresponds to the contractId
+    fn get_state(&self, namespace: &str, key: &str) -> Result<Vec<u8>>;
+    // get_state_range_scan_iterator returns an iterator that contains all the key-values between given key ranges.
+    // start_key is included in the results and end_key is excluded. An empty end_key refers to the last available key
+    fn get_state_range_scan_iterator(
+        &self,
+        namespace: &str,
+        start_key: &str,
+        end_key: &str,
+    ) -> Result<Box<dyn Iterator<Item = Kv>>>;
+    // get_state_range_scan_iterator_with_pagination returns a page of at most page_size key-values
+    // of the range, starting at the bookmark of the previous page if not empty
+    fn get_state_range_scan_iterator_with_pagination(
+        &self,
+        namespace: &str,
+        start_key: &str,
+        end_key: &str,
+        bookmark: &str,
+        page_size: usize,
+    ) -> Result<QueryResultsPage>;
+    // execute_query_with_pagination returns a page of at most page_size results of the rich query,
+    // starting at the bookmark of the previous page if not empty
+    fn execute_query_with_pagination(
+        &self,
+        namespace: &str,
+        query: &str,
+        bookmark: &str,
+        page_size: usize,
+    ) -> Result<QueryResultsPage>;
+    // get_state_with_proof gets the versioned value of a key, None if missing, with a proof binding it
+    // to the state root of the block the proof names
+    fn get_state_with_proof(
+        &self,
+        namespace: &str,
+        key: &str,
+    ) -> Result<(Option<VersionedValue>, StateProof)>;
+    // get_state_at gets the versioned value of a key once the block `block_num` was committed, None
+    // if the key did not exist then
+    fn get_state_at(
+        &self,
+        namespace: &str,
+        key: &str,
+        block_num: u64,
+    ) -> Result<Option<VersionedValue>>;
+    // get_state_range_scan_iterator_at returns the key-values between the given key ranges once the
+    // block `block_num` was committed, with the same bounds as get_state_range_scan_iterator
+    fn get_state_range_scan_iterator_at(
+        &self,
+        namespace: &str,
+        start_key: &str,
+        end_key: &str,
+        block_num: u64,
+    ) -> Result<Box<dyn Iterator<Item = Kv>>>;
+    // execute_query executes the given rich query and returns an iterator over the matching key-values
+    fn execute_query(&self, namespace: &str, query: &str) -> Result<Box<dyn Iterator<Item = Kv>>>;
+    // done releases resources occupied by the QueryExecutor
+    fn done(&mut self);
+}
+
+// QueryResultsPage is a page of query results. The bookmark resumes the query after the last
+// result, it is empty once all the results were returned
+#[derive(Debug, Default)]
+pub struct QueryResultsPage {
+    pub results: Vec<Kv>,
+    pub bookmark: String,
+}
 
 // HistoryQueryExecutor executes the history queries
 pub trait HistoryQueryExecutor {
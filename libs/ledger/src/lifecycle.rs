@@ -0,0 +1,203 @@
+use crate::pvtdata::collection::{encode_collection_configs, CollectionConfig};
+use crate::rwset::key::validate_name;
+use crate::statedb::{IndexDefinition, UpdateBatch, VersionedDB};
+use error::*;
+use identity::policy::SignaturePolicy;
+use silk_proto::ContractDefinition;
+
+// LIFECYCLE_NS is the namespace holding the definitions of the contracts of a channel, keyed by
+// contract name, with the initialization marks of the contracts requiring it
+pub const LIFECYCLE_NS: &str = "_lifecycle";
+
+// definition_key returns the key of the definition of a contract in LIFECYCLE_NS
+pub fn definition_key(name: &str) -> String {
+    name.to_string()
+}
+
+// initialized_key returns the key holding the version a contract was initialized at. The contract
+// names have no '/', the key does not clash with a definition
+pub fn initialized_key(name: &str) -> String {
+    format!("{:}/initialized", name)
+}
+
+// validate_definition checks a contract definition can be committed
+pub fn validate_definition(definition: &ContractDefinition) -> Result<()> {
+    if validate_name(&definition.name).is_err() || definition.name.contains('/') {
+        return Err(from_str(&format!(
+            "invalid contract name '{:}'",
+            definition.name
+        )));
+    }
+    for collection in &definition.collections {
+        validate_name(&collection.name).map_err(|e| {
+            from_str(&format!(
+                "invalid collection of contract {:}: {:}",
+                definition.name, e
+            ))
+        })?;
+    }
+    if definition.name == LIFECYCLE_NS {
+        return Err(from_str("the lifecycle contract can not be defined"));
+    }
+    if definition.version.is_empty() {
+        return Err(from_str("contract version is empty"));
+    }
+    if definition.package_hash.is_empty() {
+        return Err(from_str("contract package hash is empty"));
+    }
+    if !definition.endorsement_policy.is_empty() {
+        SignaturePolicy::parse(&definition.endorsement_policy)?;
+    }
+    index_definitions(definition)?;
+    encode_collection_configs(&collection_configs(definition))?;
+    Ok(())
+}
+
+// collection_configs returns the private data collections of a contract definition, they are
+// committed in COLLECTION_CONFIG_NS along with the definition
+pub fn collection_configs(definition: &ContractDefinition) -> Vec<CollectionConfig> {
+    definition
+        .collections
+        .iter()
+        .map(|c| CollectionConfig::new(&c.name, c.member_orgs.clone()))
+        .collect()
+}
+
+// index_definitions parses the index definitions shipped with a contract definition
+pub fn index_definitions(definition: &ContractDefinition) -> Result<Vec<IndexDefinition>> {
+    definition
+        .indexes
+        .iter()
+        .map(|index| IndexDefinition::from_json(index))
+        .collect()
+}
+
+// updated_definitions returns the contract definitions written by a batch of updates
+pub fn updated_definitions(batch: &UpdateBatch) -> Result<Vec<ContractDefinition>> {
+    let mut definitions = vec![];
+    if let Some(updates) = batch.get_updates(LIFECYCLE_NS) {
+        for (key, vv) in updates {
+            // the initialization marks are the only keys with a '/'
+            if key.contains('/') {
+                continue;
+            }
+            if let Some(definition) = decode_definition(&vv.value)? {
+                definitions.push(definition);
+            }
+        }
+    }
+    Ok(definitions)
+}
+
+// decode_definition decodes a definition read from LIFECYCLE_NS, None for a missing key
+pub fn decode_definition(value: &[u8]) -> Result<Option<ContractDefinition>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(utils::proto::unmarshal(value)?))
+}
+
+// get_contract_definition returns the committed definition of a contract
+pub fn get_contract_definition<V: VersionedDB>(
+    vdb: &V,
+    name: &str,
+) -> Result<Option<ContractDefinition>> {
+    match vdb.get_state(LIFECYCLE_NS, &definition_key(name))? {
+        Some(vv) if !vv.is_delete() => decode_definition(&vv.value),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statedb::{Height, VersionedDBMemProvider, VersionedDBProvider};
+    use silk_proto::CollectionDefinition;
+
+    fn definition(name: &str, version: &str) -> ContractDefinition {
+        ContractDefinition {
+            name: name.to_string(),
+            version: version.to_string(),
+            package_hash: vec![1, 2, 3],
+            endorsement_policy: "OR('org1.peer', 'org2.peer')".to_string(),
+            init_required: false,
+            indexes: vec![br#"{"name":"owner","fields":["owner"]}"#.to_vec()],
+            collections: vec![CollectionDefinition {
+                name: "coll".to_string(),
+                member_orgs: vec!["org1".to_string()],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_contract_definition() {
+        assert!(validate_definition(&definition("cc", "1")).is_ok());
+        for invalid in &[
+            definition("", "1"),
+            definition("a/b", "1"),
+            definition("a$$pb", "1"),
+            definition(LIFECYCLE_NS, "1"),
+            definition("cc", ""),
+            ContractDefinition {
+                package_hash: vec![],
+                ..definition("cc", "1")
+            },
+            ContractDefinition {
+                endorsement_policy: "XOR('org1.peer')".to_string(),
+                ..definition("cc", "1")
+            },
+            ContractDefinition {
+                indexes: vec![br#"{"name":"owner","fields":[]}"#.to_vec()],
+                ..definition("cc", "1")
+            },
+            ContractDefinition {
+                collections: vec![CollectionDefinition::default()],
+                ..definition("cc", "1")
+            },
+            ContractDefinition {
+                collections: vec![CollectionDefinition {
+                    name: "co$$ll".to_string(),
+                    member_orgs: vec!["org1".to_string()],
+                }],
+                ..definition("cc", "1")
+            },
+        ] {
+            assert!(validate_definition(invalid).is_err(), "{:?}", invalid);
+        }
+
+        let vdb = VersionedDBMemProvider::new().get_db_handle("ch1").unwrap();
+        assert!(get_contract_definition(&vdb, "cc").unwrap().is_none());
+        let mut batch = UpdateBatch::new();
+        batch.put(
+            LIFECYCLE_NS,
+            &definition_key("cc"),
+            utils::proto::marshal(&definition("cc", "1")).unwrap(),
+            Height::new(1, 0),
+        );
+        batch.put(
+            LIFECYCLE_NS,
+            &initialized_key("cc"),
+            b"1".to_vec(),
+            Height::new(1, 0),
+        );
+        assert_eq!(
+            updated_definitions(&batch).unwrap(),
+            vec![definition("cc", "1")]
+        );
+        vdb.apply_updates(batch, Some(Height::new(1, 0))).unwrap();
+        assert_eq!(
+            get_contract_definition(&vdb, "cc").unwrap(),
+            Some(definition("cc", "1"))
+        );
+        assert!(get_contract_definition(&vdb, "other").unwrap().is_none());
+        assert!(decode_definition(&[]).unwrap().is_none());
+        assert_eq!(
+            index_definitions(&definition("cc", "1")).unwrap(),
+            vec![IndexDefinition::new("owner", vec!["owner".to_string()])]
+        );
+        assert_eq!(
+            collection_configs(&definition("cc", "1")),
+            vec![CollectionConfig::new("coll", vec!["org1".to_string()])]
+        );
+    }
+}
@@ -0,0 +1,61 @@
+use crate::statedb::VersionedDB;
+use error::*;
+use serde::{Deserialize, Serialize};
+
+// COLLECTION_CONFIG_NS is the namespace holding the collection definitions of the contracts,
+// keyed by contract name. The definitions are committed like any other state so that every
+// peer of the channel agrees on them
+pub const COLLECTION_CONFIG_NS: &str = "_collections";
+
+// CollectionConfig defines a private data collection of a contract
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CollectionConfig {
+    pub name: String,
+    // member_orgs lists the organizations whose peers receive and store the private data
+    pub member_orgs: Vec<String>,
+}
+
+impl CollectionConfig {
+    pub fn new(name: &str, member_orgs: Vec<String>) -> Self {
+        CollectionConfig {
+            name: String::from(name),
+            member_orgs,
+        }
+    }
+
+    pub fn is_member(&self, org: &str) -> bool {
+        self.member_orgs.iter().any(|o| o == org)
+    }
+}
+
+// encode_collection_configs encodes the collection definitions of a contract, the result is
+// written under the contract name in COLLECTION_CONFIG_NS
+pub fn encode_collection_configs(configs: &[CollectionConfig]) -> Result<Vec<u8>> {
+    for (i, c) in configs.iter().enumerate() {
+        if c.name.is_empty() {
+            return Err(from_str("collection name is empty"));
+        }
+        if configs[..i].iter().any(|o| o.name == c.name) {
+            return Err(from_str(&format!("collection {:} defined twice", c.name)));
+        }
+    }
+    Ok(serde_json::to_vec(configs)?)
+}
+
+// get_collection_configs returns the collection definitions committed for a contract
+pub fn get_collection_configs<V: VersionedDB>(vdb: &V, ns: &str) -> Result<Vec<CollectionConfig>> {
+    match vdb.get_state(COLLECTION_CONFIG_NS, ns)? {
+        Some(vv) if !vv.is_delete() => Ok(serde_json::from_slice(&vv.value)?),
+        _ => Ok(vec![]),
+    }
+}
+
+// get_collection_config returns the definition of a collection of a contract
+pub fn get_collection_config<V: VersionedDB>(
+    vdb: &V,
+    ns: &str,
+    coll: &str,
+) -> Result<Option<CollectionConfig>> {
+    let configs = get_collection_configs(vdb, ns)?;
+    Ok(configs.into_iter().find(|c| c.name == coll))
+}
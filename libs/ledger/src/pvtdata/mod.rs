@@ -0,0 +1,304 @@
+use crate::rwset::builder::{CollHashedRwSet, TxRwSet};
+use crate::rwset::key::derive_pvt_data_ns;
+use crate::rwset::validate::TxCodes;
+use crate::statedb::{Height, UpdateBatch};
+use error::*;
+use silk_proto::*;
+use std::convert::TryFrom;
+
+pub mod collection;
+pub mod transient;
+
+use transient::TransientStore;
+
+// prepare_pvt_updates builds the private state updates of the valid transactions of a block
+// from the private rwsets kept in the transient store. A private rwset that does not match the
+// hashes committed on chain is treated as missing and is not applied
+pub fn prepare_pvt_updates(
+    block: &Block,
+    tx_codes: &TxCodes,
+    transient_store: &TransientStore,
+) -> Result<UpdateBatch> {
+    let mut batch = UpdateBatch::new();
+    let (header, data) = match (&block.header, &block.data) {
+        (Some(header), Some(data)) => (header, data),
+        _ => return Err(from_str("block content is null")),
+    };
+
+    let flags = tx_codes.flags();
+    for (index, env) in data.data.iter().enumerate() {
+        if flags.get(index) != Some(&TxValidationCode::Valid) {
+            continue;
+        }
+        let (tx, tx_header) = utils::utils::get_tx_header_from_data(env)?;
+
+        let resp = tx
+            .response
+            .get(0)
+            .ok_or_else(|| from_str("transaction proposal response list is null"))?;
+        let payload: ProposalResponsePayload = utils::proto::unmarshal(&resp.payload)?;
+        let tx_rw_set =
+            TxRwSet::try_from(utils::proto::unmarshal::<TxReadWriteSet>(&payload.results)?)?;
+        if tx_rw_set
+            .ns_rw_sets
+            .iter()
+            .all(|ns| ns.coll_hashed_rw_sets.is_empty())
+        {
+            continue;
+        }
+
+        let pvt_rwset = match transient_store.get_tx_pvt_rwset(&tx_header.tx_id)? {
+            Some(pvt_rwset) => pvt_rwset,
+            None => {
+                warn!(
+                    "Block [{:?}] Transaction index [{:?}] TxId [{:?}] private data is missing",
+                    header.number, index, tx_header.tx_id
+                );
+                continue;
+            }
+        };
+
+        let pvt_writes = match verify_pvt_rwset(&tx_rw_set, &pvt_rwset) {
+            Ok(pvt_writes) => pvt_writes,
+            Err(e) => {
+                warn!(
+                    "Block [{:?}] Transaction index [{:?}] TxId [{:?}] private data is ignored: {:}",
+                    header.number, index, tx_header.tx_id, e
+                );
+                continue;
+            }
+        };
+
+        let height = Height::new(header.number, index as u64);
+        for (ns, coll, kv_write) in pvt_writes {
+            let pvt_ns = derive_pvt_data_ns(&ns, &coll);
+            if kv_write.is_delete {
+                batch.delete(&pvt_ns, &kv_write.key, height);
+            } else {
+                batch.put(&pvt_ns, &kv_write.key, kv_write.value, height);
+            }
+        }
+    }
+
+    Ok(batch)
+}
+
+// verify_pvt_rwset checks a private rwset against the hashed rwset committed on chain and
+// returns its writes as (namespace, collection, write) tuples. The private rwset must hold the
+// writes of every collection written on chain, a transaction missing some of them is missing all
+pub fn verify_pvt_rwset(
+    tx_rw_set: &TxRwSet,
+    pvt_rwset: &TxPvtReadWriteSet,
+) -> Result<Vec<(String, String, KvWrite)>> {
+    let mut writes = vec![];
+    for ns_pvt in &pvt_rwset.ns_pvt_rwset {
+        for coll_pvt in &ns_pvt.collection_pvt_rwset {
+            let coll_hashed =
+                find_coll_hashed_rw_set(tx_rw_set, &ns_pvt.namespace, &coll_pvt.collection_name)
+                    .ok_or_else(|| {
+                        from_str(&format!(
+                            "collection {:}/{:} is not part of the transaction",
+                            ns_pvt.namespace, coll_pvt.collection_name
+                        ))
+                    })?;
+
+            let hash = utils::hash::compute_sha256(&coll_pvt.rwset);
+            if hash[..] != coll_hashed.pvt_rwset_hash[..] {
+                return Err(from_str(&format!(
+                    "hash of collection {:}/{:} mismatch",
+                    ns_pvt.namespace, coll_pvt.collection_name
+                )));
+            }
+
+            let kv_rw_set: KvrwSet = utils::proto::unmarshal(&coll_pvt.rwset)?;
+            if kv_rw_set.writes.len() != coll_hashed.hashed_rw_set.hashed_writes.len() {
+                return Err(from_str(&format!(
+                    "collection {:}/{:} has {:} writes, {:} hashed",
+                    ns_pvt.namespace,
+                    coll_pvt.collection_name,
+                    kv_rw_set.writes.len(),
+                    coll_hashed.hashed_rw_set.hashed_writes.len()
+                )));
+            }
+            for kv_write in kv_rw_set.writes {
+                let key_hash = utils::hash::compute_sha256(kv_write.key.as_bytes());
+                let matched = coll_hashed.hashed_rw_set.hashed_writes.iter().any(|w| {
+                    w.key_hash[..] == key_hash[..]
+                        && w.is_delete == kv_write.is_delete
+                        && (kv_write.is_delete
+                            || w.value_hash[..] == utils::hash::compute_sha256(&kv_write.value)[..])
+                });
+                if !matched {
+                    return Err(from_str(&format!(
+                        "write of key {:?} in collection {:}/{:} mismatch the hashed write set",
+                        kv_write.key, ns_pvt.namespace, coll_pvt.collection_name
+                    )));
+                }
+                writes.push((
+                    ns_pvt.namespace.clone(),
+                    coll_pvt.collection_name.clone(),
+                    kv_write,
+                ));
+            }
+        }
+    }
+
+    for ns_rw_set in &tx_rw_set.ns_rw_sets {
+        for coll_hashed in &ns_rw_set.coll_hashed_rw_sets {
+            if coll_hashed.hashed_rw_set.hashed_writes.is_empty() {
+                continue;
+            }
+            let present = pvt_rwset
+                .ns_pvt_rwset
+                .iter()
+                .filter(|ns_pvt| ns_pvt.namespace == ns_rw_set.namespace)
+                .flat_map(|ns_pvt| ns_pvt.collection_pvt_rwset.iter())
+                .any(|coll_pvt| coll_pvt.collection_name == coll_hashed.collection_name);
+            if !present {
+                return Err(from_str(&format!(
+                    "private writes of collection {:}/{:} are missing",
+                    ns_rw_set.namespace, coll_hashed.collection_name
+                )));
+            }
+        }
+    }
+    Ok(writes)
+}
+
+fn find_coll_hashed_rw_set<'a>(
+    tx_rw_set: &'a TxRwSet,
+    ns: &str,
+    coll: &str,
+) -> Option<&'a CollHashedRwSet> {
+    tx_rw_set
+        .ns_rw_sets
+        .iter()
+        .filter(|ns_rw_set| ns_rw_set.namespace == ns)
+        .flat_map(|ns_rw_set| ns_rw_set.coll_hashed_rw_sets.iter())
+        .find(|coll_hashed| coll_hashed.collection_name == coll)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::collection::{encode_collection_configs, CollectionConfig, COLLECTION_CONFIG_NS};
+    use super::prepare_pvt_updates;
+    use super::transient::TransientStore;
+    use crate::rwset::key::derive_pvt_data_ns;
+    use crate::rwset::policy::tests::{channel, invoke};
+    use crate::rwset::policy::ChannelPolicies;
+    use crate::rwset::validate::Validator;
+    use crate::simulator::sim::BasedTxSimulator;
+    use crate::simulator::TxSimulator;
+    use crate::statedb::{
+        Height, UpdateBatch, VersionedDB, VersionedDBProvider, VersionedDBRocksProvider,
+    };
+    use identity::signer::SigningIdentity;
+    use silk_proto::*;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn new_tx(tx_id: &str, results: &TxReadWriteSet, signer: &SigningIdentity) -> Vec<u8> {
+        utils::proto::marshal(&invoke(tx_id, "ns", results, &[signer])).unwrap()
+    }
+
+    #[test]
+    fn test_prepare_pvt_updates() {
+        let temp_dir = TempDir::new().unwrap();
+        let provider = VersionedDBRocksProvider::new(temp_dir.path()).unwrap();
+        let vdb = provider.get_db_handle("chain_id").unwrap();
+        let store = TransientStore::new(temp_dir.path()).unwrap();
+        let (config, signers) = channel(&["org1"]);
+        let policies = Arc::new(ChannelPolicies::from_config(&config).unwrap());
+
+        let mut batch = UpdateBatch::new();
+        let configs = vec![
+            CollectionConfig::new("coll", vec!["org1".to_string()]),
+            CollectionConfig::new("coll2", vec!["org1".to_string()]),
+        ];
+        batch.put(
+            COLLECTION_CONFIG_NS,
+            "ns",
+            encode_collection_configs(&configs).unwrap(),
+            Height::new(0, 0),
+        );
+        vdb.apply_updates(batch, Some(Height::new(0, 0))).unwrap();
+
+        let mut sim = BasedTxSimulator::new("tx1".to_string(), vdb.clone());
+        assert!(sim
+            .set_private_data("ns", "unknown", "k", b"v".to_vec())
+            .is_err());
+        sim.set_private_data("ns", "coll", "k", b"secret".to_vec())
+            .unwrap();
+        let results = sim.get_tx_simulation_results().unwrap();
+        assert!(results.contains_pvt_writes());
+        let pvt_rwset = results.pvt_simulation_results.unwrap();
+
+        // the same transaction with a tampered private rwset
+        let mut sim = BasedTxSimulator::new("tx2".to_string(), vdb.clone());
+        sim.set_private_data("ns", "coll", "k2", b"secret".to_vec())
+            .unwrap();
+        let results2 = sim.get_tx_simulation_results().unwrap();
+        let mut tampered = results2.pvt_simulation_results.unwrap();
+        tampered.ns_pvt_rwset[0].collection_pvt_rwset[0].rwset = pvt_rwset.ns_pvt_rwset[0]
+            .collection_pvt_rwset[0]
+            .rwset
+            .clone();
+
+        // a transaction writing two collections with the private writes of one only
+        let mut sim = BasedTxSimulator::new("tx3".to_string(), vdb.clone());
+        sim.set_private_data("ns", "coll", "k3", b"secret".to_vec())
+            .unwrap();
+        sim.set_private_data("ns", "coll2", "k4", b"secret".to_vec())
+            .unwrap();
+        let results3 = sim.get_tx_simulation_results().unwrap();
+        let mut partial = results3.pvt_simulation_results.unwrap();
+        partial.ns_pvt_rwset[0]
+            .collection_pvt_rwset
+            .retain(|coll| coll.collection_name == "coll");
+
+        store.persist("tx1", 1, &pvt_rwset).unwrap();
+        store.persist("tx2", 1, &tampered).unwrap();
+        store.persist("tx3", 1, &partial).unwrap();
+
+        let block = Block {
+            header: Some(BlockHeader {
+                number: 1,
+                ..Default::default()
+            }),
+            data: Some(BlockData {
+                data: vec![
+                    new_tx("tx1", &results.simulation_results, &signers[0]),
+                    new_tx("tx2", &results2.simulation_results, &signers[0]),
+                    new_tx("tx3", &results3.simulation_results, &signers[0]),
+                ],
+            }),
+            metadata: None,
+        };
+
+        let validator = Validator::new(vdb.clone()).with_policies(policies);
+        let (mut batch, height, codes) =
+            validator.validate_and_prepare_batch(block.clone()).unwrap();
+        assert_eq!(codes.get("tx1"), Some(&TxValidationCode::Valid));
+        assert_eq!(codes.get("tx2"), Some(&TxValidationCode::Valid));
+        assert_eq!(codes.get("tx3"), Some(&TxValidationCode::Valid));
+
+        let pvt_batch = prepare_pvt_updates(&block, &codes, &store).unwrap();
+        batch.merge(pvt_batch);
+        vdb.apply_updates(batch, Some(height)).unwrap();
+
+        let pvt_ns = derive_pvt_data_ns("ns", "coll");
+        let vv = vdb.get_state(&pvt_ns, "k").unwrap().unwrap();
+        assert_eq!(vv.value, b"secret".to_vec());
+        assert_eq!(vv.version, Height::new(1, 0));
+        assert!(vdb.get_state(&pvt_ns, "k2").unwrap().is_none());
+        assert!(vdb.get_state(&pvt_ns, "k3").unwrap().is_none());
+        let pvt_ns2 = derive_pvt_data_ns("ns", "coll2");
+        assert!(vdb.get_state(&pvt_ns2, "k4").unwrap().is_none());
+
+        let mut sim = BasedTxSimulator::new("tx3".to_string(), vdb);
+        assert_eq!(
+            sim.get_private_data("ns", "coll", "k").unwrap(),
+            b"secret".to_vec()
+        );
+    }
+}
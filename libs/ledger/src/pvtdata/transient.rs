@@ -0,0 +1,183 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use error::*;
+use rocksdb::{Direction, IteratorMode, WriteBatch, DB};
+use silk_proto::TxPvtReadWriteSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const PVT_RWSET_KEY_PREFIX: u8 = b'p';
+const HEIGHT_KEY_PREFIX: u8 = b'h';
+const KEY_SEP: u8 = 0x00;
+
+// TransientStoreProvider provides the transient stores of the ledgers
+pub struct TransientStoreProvider {
+    path: PathBuf,
+    handler: DashMap<String, Arc<TransientStore>>,
+}
+
+impl TransientStoreProvider {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        TransientStoreProvider {
+            path: path.into().join("transient"),
+            handler: DashMap::new(),
+        }
+    }
+
+    // get_store returns the transient store of a ledger, opened on first use
+    pub fn get_store(&self, id: &str) -> Result<Arc<TransientStore>> {
+        match self.handler.entry(id.to_string()) {
+            Entry::Occupied(e) => Ok(e.get().clone()),
+            Entry::Vacant(e) => {
+                let store = Arc::new(TransientStore::new(self.path.join(id))?);
+                e.insert(store.clone());
+                Ok(store)
+            }
+        }
+    }
+}
+
+// TransientStore holds the private rwsets produced by the simulations until the corresponding
+// transactions get committed, or the entries get too old and are purged
+pub struct TransientStore {
+    db: DB,
+}
+
+impl TransientStore {
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let path = path.join("transient_store");
+        Ok(TransientStore {
+            db: DB::open_default(path)?,
+        })
+    }
+
+    // persist stores the private rwset of a transaction simulated at the given ledger height
+    pub fn persist(
+        &self,
+        tx_id: &str,
+        block_height: u64,
+        pvt_rwset: &TxPvtReadWriteSet,
+    ) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        batch.put(
+            encode_pvt_rwset_key(tx_id, block_height),
+            utils::proto::marshal(pvt_rwset)?,
+        );
+        batch.put(encode_height_key(block_height, tx_id), vec![]);
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    // get_tx_pvt_rwset returns the latest private rwset persisted for a transaction
+    pub fn get_tx_pvt_rwset(&self, tx_id: &str) -> Result<Option<TxPvtReadWriteSet>> {
+        match self.scan_prefix(&encode_pvt_rwset_prefix(tx_id)).pop() {
+            Some((_, v)) => Ok(Some(utils::proto::unmarshal(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    // purge_by_txids removes the private rwsets of the given transactions, typically
+    // after they got committed
+    pub fn purge_by_txids(&self, tx_ids: &[String]) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        for tx_id in tx_ids {
+            for (k, _) in self.scan_prefix(&encode_pvt_rwset_prefix(tx_id)) {
+                let height = (&k[k.len() - 8..]).read_u64::<BigEndian>()?;
+                batch.delete(encode_height_key(height, tx_id));
+                batch.delete(k);
+            }
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    // purge_below_height removes the private rwsets simulated below the given ledger height
+    pub fn purge_below_height(&self, max_block_num_to_retain: u64) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        for (k, _) in self.scan_prefix(&[HEIGHT_KEY_PREFIX]) {
+            let height = (&k[1..9]).read_u64::<BigEndian>()?;
+            if height >= max_block_num_to_retain {
+                break;
+            }
+            let tx_id = String::from_utf8(k[9..].to_vec())?;
+            batch.delete(encode_pvt_rwset_key(&tx_id, height));
+            batch.delete(k);
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.db
+            .iterator(IteratorMode::From(prefix, Direction::Forward))
+            .take_while(|(k, _)| k.starts_with(prefix))
+            .map(|(k, v)| (k.to_vec(), v.to_vec()))
+            .collect()
+    }
+}
+
+fn encode_pvt_rwset_prefix(tx_id: &str) -> Vec<u8> {
+    let mut v = vec![PVT_RWSET_KEY_PREFIX];
+    v.extend_from_slice(tx_id.as_bytes());
+    v.push(KEY_SEP);
+    v
+}
+
+fn encode_pvt_rwset_key(tx_id: &str, height: u64) -> Vec<u8> {
+    let mut v = encode_pvt_rwset_prefix(tx_id);
+    v.write_u64::<BigEndian>(height).unwrap();
+    v
+}
+
+fn encode_height_key(height: u64, tx_id: &str) -> Vec<u8> {
+    let mut v = vec![HEIGHT_KEY_PREFIX];
+    v.write_u64::<BigEndian>(height).unwrap();
+    v.extend_from_slice(tx_id.as_bytes());
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TransientStore;
+    use silk_proto::*;
+    use tempfile::TempDir;
+
+    fn pvt_rwset(ns: &str) -> TxPvtReadWriteSet {
+        TxPvtReadWriteSet {
+            data_model: 0,
+            ns_pvt_rwset: vec![NsPvtReadWriteSet {
+                namespace: ns.to_string(),
+                collection_pvt_rwset: vec![],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_transient_store() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = TransientStore::new(temp_dir.path()).unwrap();
+
+        store.persist("tx1", 1, &pvt_rwset("ns1")).unwrap();
+        store.persist("tx1", 3, &pvt_rwset("ns3")).unwrap();
+        store.persist("tx2", 2, &pvt_rwset("ns2")).unwrap();
+        store.persist("tx3", 5, &pvt_rwset("ns5")).unwrap();
+
+        assert_eq!(
+            store.get_tx_pvt_rwset("tx1").unwrap(),
+            Some(pvt_rwset("ns3"))
+        );
+        assert_eq!(store.get_tx_pvt_rwset("tx").unwrap(), None);
+
+        store.purge_by_txids(&["tx1".to_string()]).unwrap();
+        assert_eq!(store.get_tx_pvt_rwset("tx1").unwrap(), None);
+
+        store.purge_below_height(5).unwrap();
+        assert_eq!(store.get_tx_pvt_rwset("tx2").unwrap(), None);
+        assert_eq!(
+            store.get_tx_pvt_rwset("tx3").unwrap(),
+            Some(pvt_rwset("ns5"))
+        );
+    }
+}
@@ -8,27 +8,28 @@ use std::collections::HashMap;
 // RWSetBuilder helps building the read-write set
 pub struct RWSetBuilder {
     map: HashMap<String, NsRwBuilder>,
+    pvt_map: HashMap<String, NsPvtRwBuilder>,
 }
 
 impl RWSetBuilder {
     pub fn new() -> Self {
         RWSetBuilder {
             map: HashMap::new(),
+            pvt_map: HashMap::new(),
         }
     }
 
-    // add_to_read_set adds a key and corresponding version to the read-set
-    pub fn add_to_read_set(&mut self, ns: &str, key: &str, version: Height) {
+    // add_to_read_set adds a key and corresponding version to the read-set, None for a missing key
+    pub fn add_to_read_set(&mut self, ns: &str, key: &str, version: Option<Height>) {
         let ns_rw_builder = self.get_or_create_ns_rw_builder(ns);
-        let ver = Version {
-            block_num: version.block_num,
-            tx_num: version.tx_num,
-        };
         ns_rw_builder.read_map.insert(
             String::from(key),
             KvRead {
                 key: String::from(key),
-                version: Some(ver),
+                version: version.map(|h| Version {
+                    block_num: h.block_num,
+                    tx_num: h.tx_num,
+                }),
             },
         );
     }
@@ -47,6 +48,94 @@ impl RWSetBuilder {
         );
     }
 
+    // add_to_metadata_write_set adds the metadata of a key to the write-set, it replaces all the
+    // entries of the key. An empty metadata deletes them
+    pub fn add_to_metadata_write_set(
+        &mut self,
+        ns: &str,
+        key: &str,
+        metadata: &HashMap<String, Vec<u8>>,
+    ) {
+        let mut names = metadata.keys().collect::<Vec<_>>();
+        names.sort();
+        let entries = names
+            .into_iter()
+            .map(|name| KvMetadataEntry {
+                name: name.clone(),
+                value: metadata[name].clone(),
+            })
+            .collect();
+        self.get_or_create_ns_rw_builder(ns)
+            .metadata_write_map
+            .insert(
+                String::from(key),
+                KvMetadataWrite {
+                    key: String::from(key),
+                    entries,
+                },
+            );
+    }
+
+    // add_to_hashed_read_set adds a key hash and corresponding version to the hashed read-set
+    pub fn add_to_hashed_read_set(
+        &mut self,
+        ns: &str,
+        coll: &str,
+        key: &str,
+        version: Option<Height>,
+    ) {
+        let key_hash = utils::hash::compute_sha256(key.as_bytes()).to_vec();
+        let coll_builder = self.get_or_create_coll_hashed_rw_builder(ns, coll);
+        coll_builder.read_map.insert(
+            String::from(key),
+            KvReadHash {
+                key_hash,
+                version: version.map(|h| Version {
+                    block_num: h.block_num,
+                    tx_num: h.tx_num,
+                }),
+            },
+        );
+    }
+
+    // add_to_pvt_and_hashed_write_set adds a key and value to the private and the hashed write-set
+    pub fn add_to_pvt_and_hashed_write_set(
+        &mut self,
+        ns: &str,
+        coll: &str,
+        key: &str,
+        value: Vec<u8>,
+    ) {
+        let is_delete = value.is_empty();
+        let key_hash = utils::hash::compute_sha256(key.as_bytes()).to_vec();
+        let value_hash = if is_delete {
+            vec![]
+        } else {
+            utils::hash::compute_sha256(&value).to_vec()
+        };
+
+        self.get_or_create_coll_hashed_rw_builder(ns, coll)
+            .write_map
+            .insert(
+                String::from(key),
+                KvWriteHash {
+                    key_hash,
+                    is_delete,
+                    value_hash,
+                },
+            );
+        self.get_or_create_coll_pvt_rw_builder(ns, coll)
+            .write_map
+            .insert(
+                String::from(key),
+                KvWrite {
+                    key: String::from(key),
+                    is_delete,
+                    value,
+                },
+            );
+    }
+
     // add_to_range_query_set adds a range query info for performing phantom read validation
     pub fn add_to_range_query_set(&mut self, ns: &str, rqi: RangeQueryInfo) {
         let ns_rw_builder = self.get_or_create_ns_rw_builder(ns);
@@ -64,15 +153,52 @@ impl RWSetBuilder {
     // get_tx_simulation_results returns the proto bytes of public rwset
     // (public data + hashes of private data) and the private rwset for the transaction
     pub fn get_tx_simulation_results(&self) -> Result<TxSimulationResults> {
-        // Compute the proto bytes for pub rwset
-        let rwset = self.get_tx_read_write_set();
+        // Compute the proto bytes for pvt rwset first, the pub rwset carries their hashes
+        let pvt_rwset = self.get_tx_pvt_read_write_set()?;
+
+        let mut rwset = self.get_tx_read_write_set();
+        if let Some(pvt) = &pvt_rwset {
+            for ns_pvt in &pvt.ns_pvt_rwset {
+                for coll_pvt in &ns_pvt.collection_pvt_rwset {
+                    if let Some(coll_hashed) = rwset
+                        .ns_rw_sets
+                        .iter_mut()
+                        .filter(|ns| ns.namespace == ns_pvt.namespace)
+                        .flat_map(|ns| ns.coll_hashed_rw_sets.iter_mut())
+                        .find(|coll| coll.collection_name == coll_pvt.collection_name)
+                    {
+                        coll_hashed.pvt_rwset_hash =
+                            utils::hash::compute_sha256(&coll_pvt.rwset).to_vec();
+                    }
+                }
+            }
+        }
 
         let sim = TxSimulationResults {
             simulation_results: TxReadWriteSet::try_from(rwset)?,
+            pvt_simulation_results: pvt_rwset,
         };
         Ok(sim)
     }
 
+    // get_tx_pvt_read_write_set returns the private read-write set, none if the transaction
+    // did not write private data
+    fn get_tx_pvt_read_write_set(&self) -> Result<Option<TxPvtReadWriteSet>> {
+        if self.pvt_map.is_empty() {
+            return Ok(None);
+        }
+
+        let builders = get_values_by_sorted_keys(&self.pvt_map);
+        let mut ns_pvt_rwset = Vec::with_capacity(builders.len());
+        for builder in builders {
+            ns_pvt_rwset.push(NsPvtReadWriteSet::try_from(builder)?);
+        }
+        Ok(Some(TxPvtReadWriteSet {
+            data_model: DataModel::Kv as i32,
+            ns_pvt_rwset,
+        }))
+    }
+
     // get_tx_read_write_set returns the read-write set
     // TODO make this function private once txmgr starts using new function `get_tx_simulation_results` introduced here
     pub fn get_tx_read_write_set(&self) -> TxRwSet {
@@ -111,6 +237,30 @@ impl RWSetBuilder {
 
         ns_rw_builder.coll_hash_rw_builder.get_mut(coll).unwrap()
     }
+
+    fn get_or_create_coll_pvt_rw_builder(&mut self, ns: &str, coll: &str) -> &mut CollPvtRwBuilder {
+        if !self.pvt_map.contains_key(ns) {
+            self.pvt_map.insert(
+                String::from(ns),
+                NsPvtRwBuilder {
+                    namespace: String::from(ns),
+                    coll_pvt_rw_builders: Default::default(),
+                },
+            );
+        }
+        let ns_pvt_builder = self.pvt_map.get_mut(ns).unwrap();
+        if !ns_pvt_builder.coll_pvt_rw_builders.contains_key(coll) {
+            ns_pvt_builder.coll_pvt_rw_builders.insert(
+                String::from(coll),
+                CollPvtRwBuilder {
+                    collection_name: String::from(coll),
+                    write_map: Default::default(),
+                },
+            );
+        }
+
+        ns_pvt_builder.coll_pvt_rw_builders.get_mut(coll).unwrap()
+    }
 }
 
 pub fn get_values_by_sorted_keys<T: Clone>(map: &HashMap<String, T>) -> Vec<T> {
@@ -128,6 +278,7 @@ pub struct NsRwBuilder {
     namespace: String,
     read_map: HashMap<String, KvRead>, //for mvcc validation
     write_map: HashMap<String, KvWrite>,
+    metadata_write_map: HashMap<String, KvMetadataWrite>,
     range_queries_map: HashMap<RangeQueryKey, RangeQueryInfo>,
     range_queries_keys: Vec<RangeQueryKey>,
     coll_hash_rw_builder: HashMap<String, CollHashRwBuilder>,
@@ -139,6 +290,7 @@ impl NsRwBuilder {
             namespace,
             read_map: Default::default(),
             write_map: Default::default(),
+            metadata_write_map: Default::default(),
             range_queries_map: Default::default(),
             range_queries_keys: vec![],
             coll_hash_rw_builder: Default::default(),
@@ -150,6 +302,7 @@ impl From<NsRwBuilder> for NsRwSet {
     fn from(value: NsRwBuilder) -> Self {
         let read_set = get_values_by_sorted_keys(&value.read_map);
         let write_set = get_values_by_sorted_keys(&value.write_map);
+        let metadata_write_set = get_values_by_sorted_keys(&value.metadata_write_map);
 
         let range_queries_info = value
             .range_queries_keys
@@ -169,18 +322,58 @@ impl From<NsRwBuilder> for NsRwSet {
                 reads: read_set,
                 range_queries_info,
                 writes: write_set,
-                metadata_writes: vec![],
+                metadata_writes: metadata_write_set,
             },
             coll_hashed_rw_sets,
         }
     }
 }
 
+#[derive(Clone)]
+struct NsPvtRwBuilder {
+    namespace: String,
+    coll_pvt_rw_builders: HashMap<String, CollPvtRwBuilder>,
+}
+
+impl TryFrom<NsPvtRwBuilder> for NsPvtReadWriteSet {
+    type Error = Error;
+
+    fn try_from(value: NsPvtRwBuilder) -> Result<Self> {
+        let coll_builders = get_values_by_sorted_keys(&value.coll_pvt_rw_builders);
+        let mut collection_pvt_rwset = Vec::with_capacity(coll_builders.len());
+        for builder in coll_builders {
+            collection_pvt_rwset.push(CollectionPvtReadWriteSet::try_from(builder)?);
+        }
+        Ok(NsPvtReadWriteSet {
+            namespace: value.namespace,
+            collection_pvt_rwset,
+        })
+    }
+}
+
+#[derive(Clone)]
 struct CollPvtRwBuilder {
     collection_name: String,
     write_map: HashMap<String, KvWrite>,
 }
 
+impl TryFrom<CollPvtRwBuilder> for CollectionPvtReadWriteSet {
+    type Error = Error;
+
+    fn try_from(value: CollPvtRwBuilder) -> Result<Self> {
+        let kv_rw_set = KvrwSet {
+            reads: vec![],
+            range_queries_info: vec![],
+            writes: get_values_by_sorted_keys(&value.write_map),
+            metadata_writes: vec![],
+        };
+        Ok(CollectionPvtReadWriteSet {
+            collection_name: value.collection_name,
+            rwset: utils::proto::marshal(&kv_rw_set)?,
+        })
+    }
+}
+
 #[derive(Clone, Hash, Eq, PartialEq)]
 struct RangeQueryKey {
     start_key: String,
@@ -204,6 +397,7 @@ impl From<CollHashRwBuilder> for CollHashedRwSet {
                 hashed_writes: get_values_by_sorted_keys(&value.write_map),
                 metadata_writes: vec![],
             },
+            pvt_rwset_hash: vec![],
         }
     }
 }
@@ -212,6 +406,14 @@ impl From<CollHashRwBuilder> for CollHashedRwSet {
 #[derive(Debug)]
 pub struct TxSimulationResults {
     pub simulation_results: TxReadWriteSet,
+    pub pvt_simulation_results: Option<TxPvtReadWriteSet>,
+}
+
+impl TxSimulationResults {
+    // contains_pvt_writes returns true if the simulation results include private writes
+    pub fn contains_pvt_writes(&self) -> bool {
+        self.pvt_simulation_results.is_some()
+    }
 }
 
 // TxRwSet acts as a proxy of 'rwset.TxReadWriteSet' proto message and helps constructing Read-write set specifically for KV data model
@@ -265,6 +467,7 @@ impl TryFrom<NsRwSet> for NsReadWriteSet {
 pub struct CollHashedRwSet {
     pub collection_name: String,
     pub hashed_rw_set: HashedRwSet,
+    pub pvt_rwset_hash: Vec<u8>,
 }
 
 impl TryFrom<CollHashedRwSet> for CollectionHashedReadWriteSet {
@@ -274,7 +477,7 @@ impl TryFrom<CollHashedRwSet> for CollectionHashedReadWriteSet {
         Ok(CollectionHashedReadWriteSet {
             collection_name: value.collection_name,
             hashed_rwset: utils::proto::marshal(&value.hashed_rw_set)?,
-            pvt_rwset_hash: vec![],
+            pvt_rwset_hash: value.pvt_rwset_hash,
         })
     }
 }
@@ -304,6 +507,7 @@ impl TryFrom<NsReadWriteSet> for NsRwSet {
             let coll_rw_set = CollHashedRwSet {
                 collection_name: proto_msg.collection_name,
                 hashed_rw_set: utils::proto::unmarshal::<HashedRwSet>(&proto_msg.hashed_rwset)?,
+                pvt_rwset_hash: proto_msg.pvt_rwset_hash,
             };
             coll_hashed_rw_sets.push(coll_rw_set);
         }
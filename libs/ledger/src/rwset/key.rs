@@ -1,5 +1,5 @@
 use crate::rwset::builder::TxRwSet;
-use crate::statedb::{Height, UpdateBatch, VersionedValue};
+use crate::statedb::{Height, UpdateBatch, VersionedDB, VersionedValue};
 use error::*;
 use silk_proto::*;
 use std::collections::HashMap;
@@ -11,6 +11,28 @@ pub struct CompositeKey {
     key: String,
 }
 
+impl CompositeKey {
+    pub fn new(ns: &str, coll: &str, key: &str) -> Self {
+        CompositeKey {
+            ns: String::from(ns),
+            coll: String::from(coll),
+            key: String::from(key),
+        }
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.ns
+    }
+
+    pub fn collection(&self) -> &str {
+        &self.coll
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+}
+
 type KeyOpsFlag = u8;
 
 const UPSERT_VAL: KeyOpsFlag = 1;
@@ -41,6 +63,10 @@ impl KeyOps {
     fn is_only_upsert(&self) -> bool {
         self.flag | UPSERT_VAL == UPSERT_VAL
     }
+
+    fn is_only_metadata_update(&self) -> bool {
+        self.flag & (UPSERT_VAL | KEY_DELETE) == 0
+    }
 }
 
 #[derive(Default)]
@@ -85,6 +111,11 @@ impl TxOps {
         self.map.get_mut(&k).unwrap()
     }
 
+    // keys returns the keys written by the transaction, public and hashed ones
+    pub fn keys(&self) -> impl Iterator<Item = &CompositeKey> {
+        self.map.keys()
+    }
+
     pub fn apply_tx_rwset(&mut self, rwset: TxRwSet) -> Result<()> {
         for ns_reset in rwset.ns_rw_sets {
             let ns = ns_reset.namespace;
@@ -105,7 +136,7 @@ impl TxOps {
                         &ns,
                         &coll,
                         KvWrite {
-                            key: String::from_utf8(hashed_write.key_hash)?,
+                            key: utils::base64::encode(&hashed_write.key_hash),
                             is_delete: hashed_write.is_delete,
                             value: hashed_write.value_hash,
                         },
@@ -117,7 +148,7 @@ impl TxOps {
                         &ns,
                         &coll,
                         KvMetadataWrite {
-                            key: String::from_utf8(metadata_write.key_hash)?,
+                            key: utils::base64::encode(&metadata_write.key_hash),
                             entries: metadata_write.entries,
                         },
                     );
@@ -200,13 +231,34 @@ impl PubAndHashUpdates {
         }
     }
 
-    pub fn apply_write_set(&mut self, tx_rw_set: TxRwSet, tx_height: Height) -> Result<()> {
+    // apply_write_set adds the writes of a valid transaction to the updates. A value written alone
+    // keeps the metadata of the key and a metadata written alone keeps its value, as they were
+    // before the transaction in the block or in the committed state
+    pub fn apply_write_set<V: VersionedDB>(
+        &mut self,
+        tx_rw_set: TxRwSet,
+        tx_height: Height,
+        vdb: &V,
+    ) -> Result<()> {
         let mut tx_ops = TxOps::default();
         tx_ops.apply_tx_rwset(tx_rw_set)?;
 
-        for (ck, key_ops) in tx_ops.map {
+        for (ck, mut key_ops) in tx_ops.map {
             let CompositeKey { ns, coll, key } = ck;
             if coll.eq("") {
+                if !key_ops.is_delete() && key_ops.is_only_upsert() {
+                    key_ops.metadata = self
+                        .latest_state(vdb, &ns, &key)?
+                        .map(|vv| vv.metadata)
+                        .unwrap_or_default();
+                } else if key_ops.is_only_metadata_update() {
+                    match self.latest_state(vdb, &ns, &key)? {
+                        Some(vv) => key_ops.value = vv.value,
+                        // the metadata of a missing key is dropped
+                        None => continue,
+                    }
+                }
+
                 if key_ops.is_delete() {
                     self.pub_updates.update(
                         &ns,
@@ -251,6 +303,21 @@ impl PubAndHashUpdates {
 
         Ok(())
     }
+
+    // latest_state returns the state of a public key once the updates are applied, None if it
+    // does not exist
+    pub fn latest_state<V: VersionedDB>(
+        &self,
+        vdb: &V,
+        ns: &str,
+        key: &str,
+    ) -> Result<Option<VersionedValue>> {
+        let vv = match self.pub_updates.get(ns, key) {
+            Some(vv) => Some(vv),
+            None => vdb.get_state(ns, key)?,
+        };
+        Ok(vv.filter(|vv| !vv.is_delete()))
+    }
 }
 
 impl From<PubAndHashUpdates> for UpdateBatch {
@@ -269,6 +336,94 @@ impl From<PubAndHashUpdates> for UpdateBatch {
     }
 }
 
+// NS_SEPARATOR separates the namespace of a contract from the namespaces derived from it for its
+// collections, the names of the contracts and of the collections can not hold it
+pub const NS_SEPARATOR: &str = "$$";
+
 pub fn derive_hashed_data_ns(ns: &str, coll: &str) -> String {
-    ns.to_owned() + "$$" + "h" + coll
+    format!("{:}{:}h{:}", ns, NS_SEPARATOR, coll)
+}
+
+pub fn derive_pvt_data_ns(ns: &str, coll: &str) -> String {
+    format!("{:}{:}p{:}", ns, NS_SEPARATOR, coll)
+}
+
+// validate_name checks the name of a contract or of a collection: not empty and without the
+// separator of the derived namespaces
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        return Err(from_str("name is empty"));
+    }
+    if name.contains(NS_SEPARATOR) {
+        return Err(from_str(&format!(
+            "name '{:}' holds the reserved '{:}'",
+            name, NS_SEPARATOR
+        )));
+    }
+    Ok(())
+}
+
+// validate_namespaces checks the namespaces and the collections a transaction reads and writes
+// are named after contracts and collections, none of them is a derived namespace
+pub fn validate_namespaces(tx_rw_set: &TxRwSet) -> Result<()> {
+    for ns_rw_set in &tx_rw_set.ns_rw_sets {
+        validate_name(&ns_rw_set.namespace)?;
+        for coll in &ns_rw_set.coll_hashed_rw_sets {
+            validate_name(&coll.collection_name)?;
+        }
+    }
+    Ok(())
+}
+
+// parse_derived_ns splits a namespace derived for a collection into the namespace of the contract,
+// the kind of the namespace, 'p' for the private data and 'h' for their hashes, and the collection
+pub fn parse_derived_ns(ns: &str) -> Option<(&str, char, &str)> {
+    let i = ns.find(NS_SEPARATOR)?;
+    let (contract, rest) = (&ns[..i], &ns[i + NS_SEPARATOR.len()..]);
+    let kind = rest
+        .chars()
+        .next()
+        .filter(|kind| *kind == 'p' || *kind == 'h')?;
+    let coll = &rest[1..];
+    if validate_name(contract).is_err() || validate_name(coll).is_err() {
+        return None;
+    }
+    Some((contract, kind, coll))
+}
+
+// is_pvt_data_ns tells whether a namespace holds private data, which only some peers have
+pub fn is_pvt_data_ns(ns: &str) -> bool {
+    matches!(parse_derived_ns(ns), Some((_, 'p', _)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derived_ns() {
+        let pvt_ns = derive_pvt_data_ns("cc", "coll");
+        assert_eq!(parse_derived_ns(&pvt_ns), Some(("cc", 'p', "coll")));
+        assert!(is_pvt_data_ns(&pvt_ns));
+        let hashed_ns = derive_hashed_data_ns("cc", "coll");
+        assert_eq!(parse_derived_ns(&hashed_ns), Some(("cc", 'h', "coll")));
+        assert!(!is_pvt_data_ns(&hashed_ns));
+
+        // the separator is reserved, a name holding it derives no namespace
+        for ns in &[
+            "cc",
+            "cc$$",
+            "cc$$x",
+            "cc$$p",
+            "$$pcoll",
+            "cc$$pco$$pll",
+            "a$$p$$pb",
+        ] {
+            assert!(!is_pvt_data_ns(ns), "{:}", ns);
+        }
+        assert!(validate_name("cc").is_ok());
+        for name in &["", "$$", "c$$pc", "cc$$"] {
+            assert!(validate_name(name).is_err(), "{:}", name);
+        }
+    }
 }
@@ -1,3 +1,5 @@
 pub mod builder;
 pub mod key;
+pub mod parallel;
+pub mod policy;
 pub mod validate;
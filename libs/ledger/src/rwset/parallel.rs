@@ -0,0 +1,482 @@
+use error::*;
+
+use crate::rwset::builder::TxRwSet;
+use crate::rwset::key::{self, CompositeKey, PubAndHashUpdates, TxOps};
+use crate::rwset::policy::{self, ChannelPolicies, TxEndorsement};
+use crate::rwset::validate::{self, TxCodes};
+use crate::statedb::{self, Height, UpdateBatch, VersionedDB};
+use silk_proto::*;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+// KeyRead is a read of a transaction: the key as written by the transactions of the block,
+// the namespace it is committed under and the version the transaction read
+struct KeyRead {
+    key: CompositeKey,
+    db_ns: String,
+    version: Option<Height>,
+}
+
+struct BlockTx {
+    tx_id: String,
+    // rw_set is the validation code of the transaction when it is rejected before its reads are
+    // checked, for its endorsements or its write set
+    rw_set: std::result::Result<TxRwSet, TxValidationCode>,
+    reads: Vec<KeyRead>,
+    // range_queries are the range queries of the transaction with their namespace
+    range_queries: Vec<(String, RangeQueryInfo)>,
+    writes: HashSet<CompositeKey>,
+    // endorsement tells the policies the endorsements of the transaction satisfy
+    endorsement: TxEndorsement,
+}
+
+impl BlockTx {
+    fn rejected(tx_id: String, code: TxValidationCode) -> Self {
+        BlockTx {
+            tx_id,
+            rw_set: Err(code),
+            reads: vec![],
+            range_queries: vec![],
+            writes: HashSet::new(),
+            endorsement: TxEndorsement::default(),
+        }
+    }
+}
+
+// TxDeps are the earlier transactions of the block writing a key read by a transaction,
+// directly or through one of its range queries
+struct TxDeps {
+    reads: Vec<usize>,
+    range_queries: Vec<usize>,
+}
+
+// ParallelValidator gives the same results as the sequential Validator. The reads of every
+// transaction are checked against the committed state concurrently, then the conflicts inside
+// the block are resolved in block order along the dependency graph of the transactions, where a
+// transaction depends on the earlier ones writing a key it reads or a key in one of its ranges
+pub struct ParallelValidator<V: VersionedDB> {
+    vdb: V,
+    workers: usize,
+    policies: Option<Arc<ChannelPolicies>>,
+}
+
+impl<V: VersionedDB + Sync> ParallelValidator<V> {
+    pub fn new(vdb: V, workers: usize) -> Self {
+        ParallelValidator {
+            vdb,
+            workers: workers.max(1),
+            policies: None,
+        }
+    }
+
+    // with_policies makes the validator check the endorsements against the channel policies
+    pub fn with_policies(mut self, policies: Arc<ChannelPolicies>) -> Self {
+        self.policies = Some(policies);
+        self
+    }
+
+    pub fn validate_and_prepare_batch(
+        &self,
+        block: Block,
+    ) -> Result<(UpdateBatch, Height, TxCodes)> {
+        let (header, data) = match (block.header, block.data) {
+            (Some(header), Some(data)) => (header, data),
+            _ => return Err(from_str("block content is null")),
+        };
+
+        let mut txs = Vec::with_capacity(data.data.len());
+        for proto_msg in &data.data {
+            txs.push(self.decode_tx(proto_msg)?);
+        }
+
+        let committed = self.check_committed_versions(&txs)?;
+        let deps = dependencies(&txs);
+
+        let mut txs_filter = TxCodes::with_capacity(txs.len());
+        let mut valid = vec![false; txs.len()];
+        let mut updates = PubAndHashUpdates::new();
+        for (index, tx) in txs.into_iter().enumerate() {
+            let tx_rw_set = match tx.rw_set {
+                _ if txs_filter.is_duplicate(&tx.tx_id) => {
+                    txs_filter.push(tx.tx_id, TxValidationCode::DuplicateTxid);
+                    continue;
+                }
+                Ok(tx_rw_set) => tx_rw_set,
+                Err(code) => {
+                    txs_filter.push(tx.tx_id, code);
+                    continue;
+                }
+            };
+
+            // the key policies depend on the metadata written by the earlier valid transactions.
+            // As in the sequential validator, the reads are checked before the range queries, a
+            // read conflict inside the block wins over a phantom read against the committed state
+            let conflicts = |deps: &[usize]| deps.iter().any(|j| valid[*j]);
+            let validation_code = match committed[index] {
+                _ if !policy::satisfies_policies(
+                    &self.vdb,
+                    &updates,
+                    &tx_rw_set,
+                    &tx.endorsement,
+                )? =>
+                {
+                    TxValidationCode::EndorsementPolicyFailure
+                }
+                TxValidationCode::Valid | TxValidationCode::PhantomReadConflict
+                    if conflicts(&deps[index].reads) =>
+                {
+                    TxValidationCode::MvccReadConflict
+                }
+                TxValidationCode::Valid if conflicts(&deps[index].range_queries) => {
+                    TxValidationCode::PhantomReadConflict
+                }
+                code => code,
+            };
+
+            if validation_code == TxValidationCode::Valid {
+                debug!("Block [{:?}] Transaction index [{:?}] TxId [{:?}] marked as valid by state validator.  [{:?}]", header.number, index, tx.tx_id, validation_code);
+                valid[index] = true;
+                updates.apply_write_set(
+                    tx_rw_set,
+                    Height::new(header.number, index as u64),
+                    &self.vdb,
+                )?;
+            } else {
+                warn!("Block [{:?}] Transaction index [{:?}] TxId [{:?}] marked as invalid by state validator. Reason code [{:?}]",
+                      header.number, index, tx.tx_id, validation_code);
+            }
+            txs_filter.push(tx.tx_id, validation_code);
+        }
+
+        Ok((
+            UpdateBatch::from(updates),
+            Height::new(header.number, (data.data.len() - 1) as u64),
+            txs_filter,
+        ))
+    }
+
+    fn decode_tx(&self, proto_msg: &[u8]) -> Result<BlockTx> {
+        let (tx, tx_header) = utils::utils::get_tx_header_from_data(proto_msg)?;
+        let (code, endorsement) =
+            policy::check_responses(&self.vdb, self.policies.as_deref(), &tx)?;
+        if code != TxValidationCode::Valid {
+            return Ok(BlockTx::rejected(tx_header.tx_id, code));
+        }
+        let resp = tx
+            .response
+            .first()
+            .ok_or_else(|| from_str("transaction proposal response list is null"))?;
+        let payload: ProposalResponsePayload = utils::proto::unmarshal(&resp.payload)?;
+        let tx_read_write_set: TxReadWriteSet = utils::proto::unmarshal(&payload.results)?;
+        let tx_rw_set = TxRwSet::try_from(tx_read_write_set)?;
+
+        let writeset_valid = key::validate_namespaces(&tx_rw_set).is_ok()
+            && tx_rw_set.ns_rw_sets.iter().all(|rw_set| {
+                rw_set
+                    .kv_rw_set
+                    .writes
+                    .iter()
+                    .all(|w| self.vdb.validate_key_value(&w.key, &w.value).is_ok())
+            });
+        if !writeset_valid {
+            return Ok(BlockTx::rejected(
+                tx_header.tx_id,
+                TxValidationCode::InvalidWriteset,
+            ));
+        }
+
+        let mut reads = vec![];
+        let mut range_queries = vec![];
+        for rw_set in &tx_rw_set.ns_rw_sets {
+            let ns = &rw_set.namespace;
+            for rqi in &rw_set.kv_rw_set.range_queries_info {
+                range_queries.push((ns.clone(), rqi.clone()));
+            }
+            for kv_read in &rw_set.kv_rw_set.reads {
+                reads.push(KeyRead {
+                    key: CompositeKey::new(ns, "", &kv_read.key),
+                    db_ns: ns.clone(),
+                    version: kv_read.version.clone().map(Height::from),
+                });
+            }
+            for coll_hashed_rw_set in &rw_set.coll_hashed_rw_sets {
+                let coll = &coll_hashed_rw_set.collection_name;
+                for kv_read_hash in &coll_hashed_rw_set.hashed_rw_set.hashed_reads {
+                    let hash = utils::base64::encode(&kv_read_hash.key_hash);
+                    reads.push(KeyRead {
+                        key: CompositeKey::new(ns, coll, &hash),
+                        db_ns: key::derive_hashed_data_ns(ns, coll),
+                        version: kv_read_hash.version.clone().map(Height::from),
+                    });
+                }
+            }
+        }
+
+        let mut tx_ops = TxOps::default();
+        tx_ops.apply_tx_rwset(tx_rw_set.clone())?;
+        let writes = tx_ops.keys().cloned().collect();
+
+        Ok(BlockTx {
+            tx_id: tx_header.tx_id,
+            rw_set: Ok(tx_rw_set),
+            reads,
+            range_queries,
+            writes,
+            endorsement,
+        })
+    }
+
+    // check_committed_versions validates each transaction against the committed state alone: its
+    // reads must match the committed versions, then its range queries must find the same keys.
+    // The transactions are split between the workers, each of them fetching the versions of its
+    // keys with one multi-get per namespace
+    fn check_committed_versions(&self, txs: &[BlockTx]) -> Result<Vec<TxValidationCode>> {
+        if txs.is_empty() {
+            return Ok(vec![]);
+        }
+        let chunk_size = (txs.len() + self.workers - 1) / self.workers;
+
+        let results = crossbeam::scope(|s| {
+            let handles = txs
+                .chunks(chunk_size)
+                .map(|chunk| s.spawn(move |_| self.check_chunk(chunk)))
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| {
+                    h.join()
+                        .unwrap_or_else(|_| Err(from_str("validation worker panicked")))
+                })
+                .collect::<Vec<_>>()
+        })
+        .map_err(|_| from_str("validation worker panicked"))?;
+
+        let mut committed = Vec::with_capacity(txs.len());
+        for r in results {
+            committed.extend(r?);
+        }
+        Ok(committed)
+    }
+
+    fn check_chunk(&self, txs: &[BlockTx]) -> Result<Vec<TxValidationCode>> {
+        let mut keys_by_ns: HashMap<&str, Vec<String>> = HashMap::new();
+        for read in txs.iter().flat_map(|tx| tx.reads.iter()) {
+            keys_by_ns
+                .entry(&read.db_ns)
+                .or_default()
+                .push(read.key.key().to_string());
+        }
+
+        let mut versions: HashMap<(&str, String), Option<Height>> = HashMap::new();
+        for (ns, keys) in keys_by_ns {
+            let values = self.vdb.get_state_multiple_keys(ns, keys.clone())?;
+            for (k, v) in keys.into_iter().zip(values) {
+                versions.insert((ns, k), v.map(|vv| vv.version));
+            }
+        }
+
+        let mut codes = Vec::with_capacity(txs.len());
+        for tx in txs {
+            let reads_valid = tx.reads.iter().all(|read| {
+                let committed_version = versions
+                    .get(&(read.db_ns.as_str(), read.key.key().to_string()))
+                    .cloned()
+                    .flatten();
+                statedb::are_same(committed_version, read.version)
+            });
+            if !reads_valid {
+                codes.push(TxValidationCode::MvccReadConflict);
+                continue;
+            }
+
+            let mut code = TxValidationCode::Valid;
+            for (ns, rqi) in &tx.range_queries {
+                if !validate::validate_range_query(&self.vdb, ns, rqi)? {
+                    code = TxValidationCode::PhantomReadConflict;
+                    break;
+                }
+            }
+            codes.push(code);
+        }
+        Ok(codes)
+    }
+}
+
+// dependencies returns for each transaction the earlier transactions of the block that write
+// a key it reads. A transaction without dependency is decided by the committed state alone
+fn dependencies(txs: &[BlockTx]) -> Vec<TxDeps> {
+    let mut writers: HashMap<&CompositeKey, Vec<usize>> = HashMap::new();
+    let mut deps = Vec::with_capacity(txs.len());
+    for (index, tx) in txs.iter().enumerate() {
+        let mut reads = tx
+            .reads
+            .iter()
+            .filter_map(|read| writers.get(&read.key))
+            .flatten()
+            .copied()
+            .collect::<Vec<usize>>();
+        let mut range_queries = vec![];
+        for (ns, rqi) in &tx.range_queries {
+            for (key, key_writers) in &writers {
+                if key.namespace() == ns
+                    && key.collection().is_empty()
+                    && validate::in_range(rqi, key.key())
+                {
+                    range_queries.extend(key_writers);
+                }
+            }
+        }
+        reads.sort_unstable();
+        reads.dedup();
+        range_queries.sort_unstable();
+        range_queries.dedup();
+        deps.push(TxDeps {
+            reads,
+            range_queries,
+        });
+
+        for key in &tx.writes {
+            writers.entry(key).or_default().push(index);
+        }
+    }
+    deps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ParallelValidator;
+    use crate::rwset::builder::RWSetBuilder;
+    use crate::rwset::validate::Validator;
+    use crate::statedb::{
+        Height, UpdateBatch, VersionedDB, VersionedDBProvider, VersionedDBRocksProvider,
+    };
+    use silk_proto::range_query_info::ReadsInfo;
+    use silk_proto::*;
+    use tempfile::TempDir;
+
+    fn new_tx(tx_id: &str, results: TxReadWriteSet) -> Vec<u8> {
+        let proposal = Proposal {
+            header: Some(Header {
+                tx_id: tx_id.to_string(),
+                ..Default::default()
+            }),
+            payload: vec![],
+        };
+        let payload = ProposalResponsePayload {
+            results: utils::proto::marshal(&results).unwrap(),
+            events: vec![],
+        };
+        let tx = Transaction {
+            signed_proposal: Some(SignedProposal {
+                proposal_bytes: utils::proto::marshal(&proposal).unwrap(),
+                signature: vec![],
+            }),
+            response: vec![ProposalResponse {
+                payload: utils::proto::marshal(&payload).unwrap(),
+                ..Default::default()
+            }],
+        };
+        utils::proto::marshal(&tx).unwrap()
+    }
+
+    // pseudo random sequence, good enough to mix reads and writes
+    fn next(seed: &mut u64) -> u64 {
+        *seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+        *seed >> 33
+    }
+
+    #[test]
+    fn test_same_results_as_sequential() {
+        let temp_dir = TempDir::new().unwrap();
+        let provider = VersionedDBRocksProvider::new(temp_dir.path()).unwrap();
+        let vdb = provider.get_db_handle("chain_id").unwrap();
+
+        let mut batch = UpdateBatch::new();
+        for k in 0..10 {
+            batch.put("ns", &format!("k{:}", k), b"v".to_vec(), Height::new(1, k));
+        }
+        // stale reads and reads of missing keys must be rejected the same way
+        vdb.apply_updates(batch, Some(Height::new(1, 9))).unwrap();
+
+        let mut seed = 7;
+        for round in 0..20 {
+            let mut data = vec![];
+            for t in 0..30 {
+                let mut builder = RWSetBuilder::new();
+                for _ in 0..next(&mut seed) % 3 {
+                    let k = next(&mut seed) % 12;
+                    // a stale version now and then, keys above 9 are not committed
+                    let version = if next(&mut seed) % 5 == 0 {
+                        Height::new(0, 0)
+                    } else {
+                        Height::new(1, k)
+                    };
+                    builder.add_to_read_set("ns", &format!("k{:}", k), Some(version));
+                }
+                for _ in 0..next(&mut seed) % 2 {
+                    let (start, end) = (next(&mut seed) % 12, next(&mut seed) % 12);
+                    let (start_key, end_key) = (format!("k{:}", start), format!("k{:}", end));
+                    let mut kv_reads = vdb
+                        .get_state_range_scan_iterator("ns", &start_key, &end_key)
+                        .unwrap()
+                        .map(|kv| KvRead {
+                            key: kv.composite_key.key().to_string(),
+                            version: Some(Version {
+                                block_num: kv.versioned_value.version.block_num,
+                                tx_num: kv.versioned_value.version.tx_num,
+                            }),
+                        })
+                        .collect::<Vec<_>>();
+                    // a phantom against the committed state now and then
+                    if next(&mut seed) % 5 == 0 {
+                        kv_reads.pop();
+                    }
+                    builder.add_to_range_query_set(
+                        "ns",
+                        RangeQueryInfo {
+                            start_key,
+                            end_key,
+                            itr_exhausted: true,
+                            reads_info: Some(ReadsInfo::RawReads(QueryReads { kv_reads })),
+                        },
+                    );
+                }
+                for _ in 0..next(&mut seed) % 3 {
+                    let k = next(&mut seed) % 12;
+                    builder.add_to_write_set("ns", &format!("k{:}", k), b"w".to_vec());
+                }
+                let results = builder.get_tx_simulation_results().unwrap();
+                data.push(new_tx(
+                    &format!("tx_{:}_{:}", round, t),
+                    results.simulation_results,
+                ));
+            }
+            let block = Block {
+                header: Some(BlockHeader {
+                    number: 2,
+                    ..Default::default()
+                }),
+                data: Some(BlockData { data }),
+                metadata: None,
+            };
+
+            let (seq_batch, seq_height, seq_codes) = Validator::new(vdb.clone())
+                .validate_and_prepare_batch(block.clone())
+                .unwrap();
+            let (par_batch, par_height, par_codes) = ParallelValidator::new(vdb.clone(), 4)
+                .validate_and_prepare_batch(block)
+                .unwrap();
+
+            assert_eq!(seq_codes, par_codes);
+            assert_eq!(seq_height, par_height);
+            assert_eq!(
+                seq_batch.get_updated_namespaces(),
+                par_batch.get_updated_namespaces()
+            );
+            for ns in seq_batch.get_updated_namespaces() {
+                assert_eq!(seq_batch.get_updates(&ns), par_batch.get_updates(&ns));
+            }
+        }
+    }
+}
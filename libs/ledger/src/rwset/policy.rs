@@ -0,0 +1,502 @@
+use crate::lifecycle::{self, LIFECYCLE_NS};
+use crate::pvtdata::collection::COLLECTION_CONFIG_NS;
+use crate::rwset::builder::TxRwSet;
+use crate::rwset::key::PubAndHashUpdates;
+use crate::statedb::VersionedDB;
+use error::*;
+use identity::msp::Msp;
+use identity::policy::{Principal, Role, SignaturePolicy};
+use identity::signer::verify_endorsement;
+use silk_proto::*;
+use std::collections::{HashMap, HashSet};
+
+// VALIDATION_PARAMETER is the metadata entry holding the endorsement policy of a key
+pub const VALIDATION_PARAMETER: &str = "VALIDATION_PARAMETER";
+
+// decode_metadata returns the entries of the metadata of a key as stored in the state database
+pub fn decode_metadata(metadata: &[u8]) -> Result<HashMap<String, Vec<u8>>> {
+    if metadata.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let msg: KvMetadataWrite = utils::proto::unmarshal(metadata)?;
+    Ok(msg
+        .entries
+        .into_iter()
+        .map(|entry| (entry.name, entry.value))
+        .collect())
+}
+
+// key_policy returns the endorsement policy set in the metadata of a key, if any
+pub fn key_policy(metadata: &[u8]) -> Result<Option<KeyEndorsementPolicy>> {
+    match decode_metadata(metadata)?.get(VALIDATION_PARAMETER) {
+        Some(policy) => Ok(Some(utils::proto::unmarshal(policy)?)),
+        None => Ok(None),
+    }
+}
+
+// TxEndorsement tells which policies the endorsements of a transaction satisfy
+#[derive(Debug, Clone, Default)]
+pub struct TxEndorsement {
+    // contract is the contract the transaction invokes, the only one whose namespace it writes
+    pub contract: String,
+    // orgs are the organizations of the verified endorsers
+    pub orgs: HashSet<String>,
+    // contract_policy tells whether the endorsements satisfy the policy of the contract
+    pub contract_policy: bool,
+    // lifecycle_policy tells whether the endorsements satisfy the policy of the lifecycle
+    pub lifecycle_policy: bool,
+}
+
+// ChannelPolicies are the endorsement policies of the contracts of a channel with the msp of its
+// organizations. A contract without a policy must be endorsed by a member of any organization.
+// The contract definitions are endorsed by a majority of the organizations, unless the channel
+// config sets a policy for LIFECYCLE_NS
+pub struct ChannelPolicies {
+    msp: Msp,
+    contracts: HashMap<String, SignaturePolicy>,
+    default: SignaturePolicy,
+    lifecycle: SignaturePolicy,
+}
+
+impl ChannelPolicies {
+    pub fn from_config(config: &ChannelConfig) -> Result<Self> {
+        let mut contracts = HashMap::new();
+        for (contract, policy) in &config.endorsement_policies {
+            let policy = SignaturePolicy::parse(policy).map_err(|e| {
+                from_str(&format!(
+                    "invalid endorsement policy of contract {:}: {:}",
+                    contract, e
+                ))
+            })?;
+            contracts.insert(contract.clone(), policy);
+        }
+        let members: Vec<SignaturePolicy> = config
+            .organizations
+            .iter()
+            .map(|org| {
+                SignaturePolicy::SignedBy(Principal {
+                    org_id: org.org_id.clone(),
+                    role: Role::Member,
+                })
+            })
+            .collect();
+        let lifecycle = contracts
+            .remove(LIFECYCLE_NS)
+            .unwrap_or_else(|| SignaturePolicy::OutOf(members.len() / 2 + 1, members.clone()));
+        Ok(ChannelPolicies {
+            msp: Msp::from_config(&config.organizations)?,
+            contracts,
+            default: SignaturePolicy::OutOf(1, members),
+            lifecycle,
+        })
+    }
+
+    // endorsement returns the policies the endorsements of the responses satisfy, the policy of
+    // the contract being the one of its definition first. None when there is no response or a
+    // signature is invalid
+    fn endorsement(
+        &self,
+        contract: &str,
+        defined: Option<&SignaturePolicy>,
+        responses: &[ProposalResponse],
+    ) -> Option<TxEndorsement> {
+        if responses.is_empty() {
+            return None;
+        }
+        let mut endorsers = Vec::with_capacity(responses.len());
+        for resp in responses {
+            let endorsement = resp.endorsement.as_ref()?;
+            match verify_endorsement(&self.msp, &resp.payload, endorsement) {
+                Ok(endorser) => endorsers.push(endorser),
+                Err(e) => {
+                    debug!("invalid endorsement of contract {:?}: {:?}", contract, e);
+                    return None;
+                }
+            }
+        }
+        let policy = if contract == LIFECYCLE_NS {
+            &self.lifecycle
+        } else {
+            defined
+                .or_else(|| self.contracts.get(contract))
+                .unwrap_or(&self.default)
+        };
+        Some(TxEndorsement {
+            contract: contract.to_string(),
+            orgs: endorsers
+                .iter()
+                .map(|endorser| endorser.org_id().to_string())
+                .collect(),
+            contract_policy: policy.evaluate(&endorsers),
+            lifecycle_policy: self.lifecycle.evaluate(&endorsers),
+        })
+    }
+}
+
+// check_responses checks the proposal responses of a transaction before its read write set. The
+// responses must carry the same results and valid endorsements, a channel without policies fails
+// every transaction but its config. A proposal naming a version of a defined contract must match
+// the committed definition. With a valid code, the policies the endorsements satisfy are
+// returned, the policy of the contract is only enforced with the written keys, see
+// satisfies_policies
+pub fn check_responses<V: VersionedDB>(
+    vdb: &V,
+    policies: Option<&ChannelPolicies>,
+    tx: &Transaction,
+) -> Result<(TxValidationCode, TxEndorsement)> {
+    let mut results = None;
+    for resp in &tx.response {
+        let payload = match utils::proto::unmarshal::<ProposalResponsePayload>(&resp.payload) {
+            Ok(payload) => payload,
+            Err(_) => {
+                return Ok((
+                    TxValidationCode::BadResponsePayload,
+                    TxEndorsement::default(),
+                ))
+            }
+        };
+        match &results {
+            None => results = Some(payload.results),
+            Some(results) if *results != payload.results => {
+                return Ok((
+                    TxValidationCode::BadResponsePayload,
+                    TxEndorsement::default(),
+                ))
+            }
+            _ => {}
+        }
+    }
+
+    let signed_proposal = tx
+        .signed_proposal
+        .as_ref()
+        .ok_or_else(|| from_str("transaction signed proposal is null"))?;
+    let proposal: Proposal = utils::proto::unmarshal(&signed_proposal.proposal_bytes)?;
+    let header = proposal
+        .header
+        .ok_or_else(|| from_str("transaction header is null"))?;
+    // the channel config transaction is not endorsed, it invokes no contract and writes nothing
+    if header.header_type == HeaderType::CreateChannel as i32 {
+        let endorsement = TxEndorsement {
+            contract_policy: true,
+            ..TxEndorsement::default()
+        };
+        return Ok((TxValidationCode::Valid, endorsement));
+    }
+    let contract_id = utils::proto::unmarshal::<ContractProposalPayload>(&proposal.payload)
+        .ok()
+        .and_then(|payload| payload.contract_id);
+
+    // the definition is the committed one, a transaction reading the definition it was endorsed
+    // with conflicts with the redefinitions earlier in the block
+    let definition = match &contract_id {
+        Some(contract_id) => lifecycle::get_contract_definition(vdb, &contract_id.name)?,
+        None => None,
+    };
+    if let (Some(contract_id), Some(definition)) = (&contract_id, &definition) {
+        if !contract_id.version.is_empty() && contract_id.version != definition.version {
+            return Ok((
+                TxValidationCode::ChaincodeVersionConflict,
+                TxEndorsement::default(),
+            ));
+        }
+    }
+
+    // the endorsements can not be verified before the channel config is known
+    let policies = match policies {
+        Some(policies) => policies,
+        None => {
+            return Ok((
+                TxValidationCode::EndorsementPolicyFailure,
+                TxEndorsement::default(),
+            ))
+        }
+    };
+    let contract = match contract_id {
+        Some(contract_id) => contract_id.name,
+        None => return Ok((TxValidationCode::BadPayload, TxEndorsement::default())),
+    };
+    let defined = match definition.filter(|d| !d.endorsement_policy.is_empty()) {
+        Some(definition) => match SignaturePolicy::parse(&definition.endorsement_policy) {
+            Ok(policy) => Some(policy),
+            Err(_) => {
+                return Ok((
+                    TxValidationCode::EndorsementPolicyFailure,
+                    TxEndorsement::default(),
+                ))
+            }
+        },
+        None => None,
+    };
+    match policies.endorsement(&contract, defined.as_ref(), &tx.response) {
+        Some(endorsement) => Ok((TxValidationCode::Valid, endorsement)),
+        None => Ok((
+            TxValidationCode::EndorsementPolicyFailure,
+            TxEndorsement::default(),
+        )),
+    }
+}
+
+// satisfies_policies tells whether the endorsements of a transaction satisfy the policies of the
+// keys it writes. A transaction only writes the namespace of the contract it invokes, with its
+// private collections, the lifecycle contract the definitions in LIFECYCLE_NS and
+// COLLECTION_CONFIG_NS. The only other key a contract writes is its initialization mark in
+// LIFECYCLE_NS, which needs the policy of the lifecycle on top of the one of the contract.
+//
+// The policy of a key is the one in force before the transaction, after the updates of the
+// earlier transactions of the block. A public key with a policy in its metadata must be endorsed
+// by all the organizations of the policy, which replaces the policy of the contract for the key.
+// The transaction must satisfy the policy of the contract as soon as it writes a key without
+// policy, a private key, or no key at all
+pub fn satisfies_policies<V: VersionedDB>(
+    vdb: &V,
+    updates: &PubAndHashUpdates,
+    tx_rw_set: &TxRwSet,
+    endorsement: &TxEndorsement,
+) -> Result<bool> {
+    let mut contract_policy_needed = true;
+    let mut keys_without_policy = false;
+    for rw_set in &tx_rw_set.ns_rw_sets {
+        let ns = &rw_set.namespace;
+        let kv_rw_set = &rw_set.kv_rw_set;
+        let hashed_writes = rw_set
+            .coll_hashed_rw_sets
+            .iter()
+            .any(|coll| !coll.hashed_rw_set.hashed_writes.is_empty());
+        if kv_rw_set.writes.is_empty() && kv_rw_set.metadata_writes.is_empty() && !hashed_writes {
+            continue;
+        }
+        if ns == LIFECYCLE_NS || ns == COLLECTION_CONFIG_NS {
+            if !lifecycle_write_allowed(&endorsement.contract, ns, kv_rw_set, hashed_writes) {
+                debug!(
+                    "contract {:?} can not write namespace [{:?}]",
+                    endorsement.contract, ns
+                );
+                return Ok(false);
+            }
+            if !endorsement.lifecycle_policy {
+                debug!(
+                    "the endorsements do not satisfy the policy of the lifecycle to write [{:?}]",
+                    ns
+                );
+                return Ok(false);
+            }
+            continue;
+        }
+        if *ns != endorsement.contract {
+            debug!(
+                "contract {:?} can not write namespace [{:?}]",
+                endorsement.contract, ns
+            );
+            return Ok(false);
+        }
+
+        // a policy that can not be decoded is never committed
+        let invalid_policy = kv_rw_set
+            .metadata_writes
+            .iter()
+            .flat_map(|w| w.entries.iter())
+            .filter(|entry| entry.name == VALIDATION_PARAMETER)
+            .any(|entry| utils::proto::unmarshal::<KeyEndorsementPolicy>(&entry.value).is_err());
+        if invalid_policy {
+            debug!("invalid endorsement policy written in namespace [{:?}]", ns);
+            return Ok(false);
+        }
+        if hashed_writes {
+            keys_without_policy = true;
+        }
+
+        let keys = kv_rw_set
+            .writes
+            .iter()
+            .map(|w| &w.key)
+            .chain(kv_rw_set.metadata_writes.iter().map(|w| &w.key));
+        for key in keys {
+            let policy = match updates.latest_state(vdb, ns, key)? {
+                Some(vv) => key_policy(&vv.metadata)?,
+                None => None,
+            };
+            let policy = match policy {
+                Some(policy) => policy,
+                None => {
+                    keys_without_policy = true;
+                    continue;
+                }
+            };
+            contract_policy_needed = false;
+            if let Some(org) = policy
+                .orgs
+                .iter()
+                .find(|org| !endorsement.orgs.contains(*org))
+            {
+                debug!(
+                    "key [{:?}:{:?}] is not endorsed by organization {:?}",
+                    ns, key, org
+                );
+                return Ok(false);
+            }
+        }
+    }
+    if (contract_policy_needed || keys_without_policy) && !endorsement.contract_policy {
+        debug!("the endorsements do not satisfy the policy of the contract");
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+// lifecycle_write_allowed tells whether a contract may write a namespace of the lifecycle: the
+// lifecycle contract writes the definitions and their collections, the other contracts their
+// initialization mark only
+fn lifecycle_write_allowed(
+    contract: &str,
+    ns: &str,
+    kv_rw_set: &KvrwSet,
+    hashed_writes: bool,
+) -> bool {
+    if contract == LIFECYCLE_NS {
+        return true;
+    }
+    !contract.is_empty()
+        && ns == LIFECYCLE_NS
+        && !hashed_writes
+        && kv_rw_set.metadata_writes.is_empty()
+        && kv_rw_set
+            .writes
+            .iter()
+            .all(|w| w.key == lifecycle::initialized_key(contract))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{check_responses, ChannelPolicies};
+    use crate::statedb::{VersionedDBProvider, VersionedDBRocksProvider};
+    use identity::cert::{cert_params, create_ca_cert, create_cert, Register};
+    use identity::signer::SigningIdentity;
+    use silk_proto::*;
+    use tempfile::TempDir;
+
+    // channel returns the config of a channel of the organizations with a peer signing for each
+    pub(crate) fn channel(orgs: &[&str]) -> (ChannelConfig, Vec<SigningIdentity>) {
+        let mut config = ChannelConfig {
+            channel_id: "chain_id".to_string(),
+            ..Default::default()
+        };
+        let mut signers = vec![];
+        for org in orgs {
+            let ca = create_ca_cert();
+            let register = Register {
+                org_unit: "peer".to_string(),
+                ..Register::default()
+            };
+            let cert = create_cert(cert_params(None, register)).unwrap();
+            signers.push(
+                SigningIdentity::new(
+                    org,
+                    cert.serialize_der_with_signer(&ca).unwrap(),
+                    &cert.serialize_private_key_der(),
+                )
+                .unwrap(),
+            );
+            config.organizations.push(OrganizationConfig {
+                org_id: org.to_string(),
+                root_certs: vec![ca.serialize_der().unwrap()],
+            });
+        }
+        (config, signers)
+    }
+
+    // invoke returns a transaction of the contract with the results, endorsed by the signers
+    pub(crate) fn invoke(
+        tx_id: &str,
+        contract: &str,
+        results: &TxReadWriteSet,
+        signers: &[&SigningIdentity],
+    ) -> Transaction {
+        let payload = ContractProposalPayload {
+            contract_id: Some(ContractId {
+                name: contract.to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let proposal = Proposal {
+            header: Some(Header {
+                header_type: HeaderType::Invoke as i32,
+                channel_id: "chain_id".to_string(),
+                tx_id: tx_id.to_string(),
+                ..Default::default()
+            }),
+            payload: utils::proto::marshal(&payload).unwrap(),
+        };
+        let payload = utils::proto::marshal(&ProposalResponsePayload {
+            results: utils::proto::marshal(results).unwrap(),
+            events: vec![],
+        })
+        .unwrap();
+        Transaction {
+            signed_proposal: Some(SignedProposal {
+                proposal_bytes: utils::proto::marshal(&proposal).unwrap(),
+                signature: vec![],
+            }),
+            response: signers
+                .iter()
+                .map(|signer| ProposalResponse {
+                    endorsement: Some(signer.endorse(&payload).unwrap()),
+                    payload: payload.clone(),
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+
+    // config_tx returns the transaction creating the channel with the config, as opening its
+    // genesis block
+    pub(crate) fn config_tx(config: &ChannelConfig) -> Transaction {
+        let proposal = Proposal {
+            header: Some(Header {
+                header_type: HeaderType::CreateChannel as i32,
+                channel_id: config.channel_id.clone(),
+                tx_id: "genesis".to_string(),
+                ..Default::default()
+            }),
+            payload: utils::proto::marshal(config).unwrap(),
+        };
+        Transaction {
+            signed_proposal: Some(SignedProposal {
+                proposal_bytes: utils::proto::marshal(&proposal).unwrap(),
+                signature: vec![],
+            }),
+            response: vec![ProposalResponse {
+                payload: utils::proto::marshal(&ProposalResponsePayload::default()).unwrap(),
+                ..Default::default()
+            }],
+        }
+    }
+
+    #[test]
+    fn test_check_responses() {
+        let temp_dir = TempDir::new().unwrap();
+        let vdb = VersionedDBRocksProvider::new(temp_dir.path())
+            .unwrap()
+            .get_db_handle("chain_id")
+            .unwrap();
+        let (config, signers) = channel(&["org1"]);
+        let policies = ChannelPolicies::from_config(&config).unwrap();
+        let tx = invoke("tx1", "ns", &TxReadWriteSet::default(), &[&signers[0]]);
+
+        let (code, endorsement) = check_responses(&vdb, Some(&policies), &tx).unwrap();
+        assert_eq!(code, TxValidationCode::Valid);
+        assert_eq!(endorsement.contract, "ns");
+        assert!(endorsement.orgs.contains("org1"));
+        assert!(endorsement.contract_policy);
+
+        // the endorsements can not be checked without the policies of the channel
+        let (code, _) = check_responses(&vdb, None, &tx).unwrap();
+        assert_eq!(code, TxValidationCode::EndorsementPolicyFailure);
+
+        let mut unsigned = tx;
+        unsigned.response[0].endorsement = None;
+        let (code, _) = check_responses(&vdb, Some(&policies), &unsigned).unwrap();
+        assert_eq!(code, TxValidationCode::EndorsementPolicyFailure);
+    }
+}
@@ -2,36 +2,99 @@ use error::*;
 
 use crate::rwset::builder::TxRwSet;
 use crate::rwset::key::{self, PubAndHashUpdates};
+use crate::rwset::policy::{self, ChannelPolicies};
 use crate::statedb::{self, Height, UpdateBatch, VersionedDB};
+use silk_proto::range_query_info::ReadsInfo;
 use silk_proto::*;
-use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::sync::Arc;
+
+// TxCodes are the validation codes of the transactions of a block, in the order of the block
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TxCodes(Vec<(String, TxValidationCode)>);
+
+impl TxCodes {
+    pub fn with_capacity(capacity: usize) -> Self {
+        TxCodes(Vec::with_capacity(capacity))
+    }
+
+    // push records the code of the next transaction of the block
+    pub fn push(&mut self, tx_id: String, code: TxValidationCode) {
+        self.0.push((tx_id, code));
+    }
+
+    // get returns the code of the first transaction of the block with the id
+    pub fn get(&self, tx_id: &str) -> Option<&TxValidationCode> {
+        self.0
+            .iter()
+            .find(|(id, _)| id == tx_id)
+            .map(|(_, code)| code)
+    }
+
+    // is_duplicate tells whether the id was used by an earlier transaction of the block
+    pub fn is_duplicate(&self, tx_id: &str) -> bool {
+        self.get(tx_id).is_some()
+    }
+
+    // tx_ids returns the ids of the transactions in the order of the block
+    pub fn tx_ids(&self) -> impl Iterator<Item = &String> {
+        self.0.iter().map(|(tx_id, _)| tx_id)
+    }
+
+    // flags returns the codes in the order of the block, as recorded in its metadata
+    pub fn flags(&self) -> Vec<TxValidationCode> {
+        self.0.iter().map(|(_, code)| *code).collect()
+    }
+}
 
 pub struct Validator<V: VersionedDB> {
     vdb: V,
+    policies: Option<Arc<ChannelPolicies>>,
 }
 
 impl<V: VersionedDB> Validator<V> {
     pub fn new(vdb: V) -> Self {
-        Validator { vdb }
+        Validator {
+            vdb,
+            policies: None,
+        }
+    }
+
+    // with_policies makes the validator check the endorsements against the channel policies
+    pub fn with_policies(mut self, policies: Arc<ChannelPolicies>) -> Self {
+        self.policies = Some(policies);
+        self
     }
 
     pub fn validate_and_prepare_batch(
         &self,
         block: Block,
-    ) -> Result<(UpdateBatch, Height, HashMap<String, TxValidationCode>)> {
+    ) -> Result<(UpdateBatch, Height, TxCodes)> {
         if let (Some(header), Some(data)) = (block.header, block.data) {
-            let mut txs_filter = HashMap::new();
+            let mut txs_filter = TxCodes::with_capacity(data.data.len());
             let mut updates = PubAndHashUpdates::new();
 
             for (index, proto_msg) in data.data.iter().enumerate() {
                 let tx: Transaction = utils::proto::unmarshal(proto_msg)?;
+                let (code, endorsement) =
+                    policy::check_responses(&self.vdb, self.policies.as_deref(), &tx)?;
                 let proposal = tx
                     .signed_proposal
                     .ok_or_else(|| from_str("proposal is null"))?;
                 let tx_header = utils::proto::unmarshal::<Proposal>(&proposal.proposal_bytes)?
                     .header
                     .ok_or_else(|| from_str("transaction header is null"))?;
+                let code = if txs_filter.is_duplicate(&tx_header.tx_id) {
+                    TxValidationCode::DuplicateTxid
+                } else {
+                    code
+                };
+                if code != TxValidationCode::Valid {
+                    warn!("Block [{:?}] Transaction index [{:?}] TxId [{:?}] marked as invalid by endorsement validator. Reason code [{:?}]",
+                          header.number, index, tx_header.tx_id, code);
+                    txs_filter.push(tx_header.tx_id.clone(), code);
+                    continue;
+                }
 
                 let resp = tx
                     .response
@@ -42,21 +105,35 @@ impl<V: VersionedDB> Validator<V> {
                 let tx_rw_set = TxRwSet::try_from(tx_read_write_set)?;
                 if self.validate_writeset(&tx_rw_set).is_err() {
                     // TODO:record this transaction
-                    txs_filter.insert(tx_header.tx_id.clone(), TxValidationCode::InvalidWriteset);
+                    txs_filter.push(tx_header.tx_id.clone(), TxValidationCode::InvalidWriteset);
                     continue;
                 }
 
-                let validation_code = self.validate_tx(&tx_rw_set, &mut updates)?;
+                // the keys with an endorsement policy must be endorsed by its organizations, the
+                // other keys under the policy of the contract
+                let validation_code = if !policy::satisfies_policies(
+                    &self.vdb,
+                    &updates,
+                    &tx_rw_set,
+                    &endorsement,
+                )? {
+                    TxValidationCode::EndorsementPolicyFailure
+                } else {
+                    self.validate_tx(&tx_rw_set, &mut updates)?
+                };
 
                 if validation_code == TxValidationCode::Valid {
                     debug!("Block [{:?}] Transaction index [{:?}] TxId [{:?}] marked as valid by state validator.  [{:?}]", header.number, index, tx_header.tx_id, validation_code);
-                    let _ = updates
-                        .apply_write_set(tx_rw_set, Height::new(header.number, index as u64));
+                    updates.apply_write_set(
+                        tx_rw_set,
+                        Height::new(header.number, index as u64),
+                        &self.vdb,
+                    )?;
                 } else {
                     warn!("Block [{:?}] Transaction index [{:?}] TxId [{:?}] marked as invalid by state validator. Reason code [{:?}]",
                           header.number, index, tx_header.tx_id, validation_code);
                 }
-                txs_filter.insert(tx_header.tx_id.clone(), validation_code);
+                txs_filter.push(tx_header.tx_id.clone(), validation_code);
             }
 
             return Ok((
@@ -70,6 +147,7 @@ impl<V: VersionedDB> Validator<V> {
     }
 
     fn validate_writeset(&self, tx_rw_set: &TxRwSet) -> Result<()> {
+        key::validate_namespaces(tx_rw_set)?;
         for rw_set in &tx_rw_set.ns_rw_sets {
             //Validation of write set
             for kv_write in &rw_set.kv_rw_set.writes {
@@ -111,16 +189,6 @@ impl<V: VersionedDB> Validator<V> {
                 }
             }
 
-            // Validate range queries for phantom items
-            for rgi in &rw_set.kv_rw_set.range_queries_info {
-                debug!(
-                    "validate range query: ns={:?}, RangeQueryInfo={:?}",
-                    ns.clone(),
-                    rgi
-                )
-                // TODO:
-            }
-
             // Validate hashes for private reads
             for coll_hashed_rw_set in &rw_set.coll_hashed_rw_sets {
                 let coll = coll_hashed_rw_set.collection_name.clone();
@@ -153,6 +221,79 @@ impl<V: VersionedDB> Validator<V> {
             }
         }
 
+        // Validate range queries for phantom items, once all the reads are known to be valid
+        for rw_set in &tx_rw_set.ns_rw_sets {
+            let ns = &rw_set.namespace;
+            let ns_updates = updates.pub_updates.get_updates(ns);
+            for rqi in &rw_set.kv_rw_set.range_queries_info {
+                debug!(
+                    "validate range query: ns={:?}, RangeQueryInfo={:?}",
+                    ns, rqi
+                );
+                let updated_in_block = ns_updates
+                    .as_ref()
+                    .map(|ns_updates| ns_updates.keys().any(|k| in_range(rqi, k)))
+                    .unwrap_or(false);
+                if updated_in_block || !validate_range_query(&self.vdb, ns, rqi)? {
+                    debug!(
+                        "Phantom read for range [{:?}, {:?}) of namespace [{:?}]",
+                        rqi.start_key, rqi.end_key, ns
+                    );
+                    return Ok(TxValidationCode::PhantomReadConflict);
+                }
+            }
+        }
+
         Ok(TxValidationCode::Valid)
     }
 }
+
+// in_range tells whether a key falls in the part of a range the query went through. When the
+// iterator was not exhausted it stopped at the last key read
+pub fn in_range(rqi: &RangeQueryInfo, key: &str) -> bool {
+    if key < rqi.start_key.as_str() || (!rqi.end_key.is_empty() && key >= rqi.end_key.as_str()) {
+        return false;
+    }
+    if rqi.itr_exhausted {
+        return true;
+    }
+    match &rqi.reads_info {
+        Some(ReadsInfo::RawReads(reads)) => reads
+            .kv_reads
+            .last()
+            .map(|last| key <= last.key.as_str())
+            .unwrap_or(false),
+        _ => true,
+    }
+}
+
+// validate_range_query runs a range query again on the committed state, the same keys must be
+// found with the same versions
+pub fn validate_range_query<V: VersionedDB>(
+    vdb: &V,
+    ns: &str,
+    rqi: &RangeQueryInfo,
+) -> Result<bool> {
+    let reads = match &rqi.reads_info {
+        Some(ReadsInfo::RawReads(reads)) => &reads.kv_reads,
+        _ => {
+            debug!("range query without raw reads can not be validated");
+            return Ok(false);
+        }
+    };
+
+    let committed = vdb
+        .get_state_range_scan_iterator(ns, &rqi.start_key, &rqi.end_key)?
+        .take_while(|kv| in_range(rqi, kv.composite_key.key()))
+        .collect::<Vec<_>>();
+    if committed.len() != reads.len() {
+        return Ok(false);
+    }
+    Ok(committed.iter().zip(reads).all(|(kv, read)| {
+        kv.composite_key.key() == read.key
+            && statedb::are_same(
+                Some(kv.versioned_value.version),
+                read.version.clone().map(Height::from),
+            )
+    }))
+}
@@ -1,6 +1,8 @@
 use crate::rwset::builder::TxSimulationResults;
+use crate::rwset::policy::VALIDATION_PARAMETER;
+use crate::QueryResultsPage;
 use error::*;
-use silk_proto::Kv;
+use silk_proto::{KeyEndorsementPolicy, Kv};
 use std::collections::HashMap;
 
 pub mod sim;
@@ -15,6 +17,23 @@ pub trait TxSimulator {
     // delete_state deletes the given namespace and key
     fn delete_state(&mut self, namespace: &str, key: &str) -> Result<()>;
 
+    // get_private_data gets the value of a key in a private data collection of the given namespace
+    fn get_private_data(&mut self, namespace: &str, collection: &str, key: &str)
+        -> Result<Vec<u8>>;
+
+    // set_private_data sets the value of a key in a private data collection. Only the hashes of the key
+    // and value are part of the public simulation results, the value itself goes to the private results
+    fn set_private_data(
+        &mut self,
+        namespace: &str,
+        collection: &str,
+        key: &str,
+        value: Vec<u8>,
+    ) -> Result<()>;
+
+    // delete_private_data deletes a key from a private data collection
+    fn delete_private_data(&mut self, namespace: &str, collection: &str, key: &str) -> Result<()>;
+
     // SetMultipleKeys sets the values for multiple keys in a single call
     fn set_state_multiple_keys(
         &mut self,
@@ -44,6 +63,46 @@ pub trait TxSimulator {
         key: &str,
     ) -> Result<HashMap<String, Vec<u8>>>;
 
+    // set_state_metadata sets the metadata of a key, replacing all its entries. The value of the
+    // key is left unchanged, the metadata of a missing key is dropped at commit
+    fn set_state_metadata(
+        &mut self,
+        namespace: &str,
+        key: &str,
+        metadata: HashMap<String, Vec<u8>>,
+    ) -> Result<()>;
+
+    // set_state_validation_parameter sets the endorsement policy of a key, a transaction writing the
+    // key is then valid only if endorsed by the organizations of the policy
+    fn set_state_validation_parameter(
+        &mut self,
+        namespace: &str,
+        key: &str,
+        policy: &KeyEndorsementPolicy,
+    ) -> Result<()> {
+        let mut metadata = self.get_state_metadata(namespace, key)?;
+        metadata.insert(
+            VALIDATION_PARAMETER.to_string(),
+            utils::proto::marshal(policy)?,
+        );
+        self.set_state_metadata(namespace, key, metadata)
+    }
+
+    // get_state_validation_parameter returns the endorsement policy of a key, if any
+    fn get_state_validation_parameter(
+        &mut self,
+        namespace: &str,
+        key: &str,
+    ) -> Result<Option<KeyEndorsementPolicy>> {
+        match self
+            .get_state_metadata(namespace, key)?
+            .get(VALIDATION_PARAMETER)
+        {
+            Some(policy) => Ok(Some(utils::proto::unmarshal(policy)?)),
+            None => Ok(None),
+        }
+    }
+
     // get_state_multiple_keys gets the values for multiple keys in a single call
     fn get_state_multiple_keys(
         &mut self,
@@ -63,6 +122,27 @@ pub trait TxSimulator {
         end_key: &str,
     ) -> Result<Box<dyn Iterator<Item = Kv>>>;
 
+    // get_state_range_scan_iterator_with_pagination returns a page of at most page_size key-values of
+    // the range, starting at the bookmark of the previous page if not empty. The part of the range
+    // read by the page is recorded for the phantom read validation
+    fn get_state_range_scan_iterator_with_pagination(
+        &mut self,
+        namespace: &str,
+        start_key: &str,
+        end_key: &str,
+        bookmark: &str,
+        page_size: usize,
+    ) -> Result<QueryResultsPage>;
+
+    // get_state_by_partial_composite_key returns the key-values whose composite key starts with the given
+    // object type and attributes, see utils::composite_key. It is a range scan, recorded the same way
+    fn get_state_by_partial_composite_key(
+        &mut self,
+        namespace: &str,
+        object_type: &str,
+        attributes: &[&str],
+    ) -> Result<Box<dyn Iterator<Item = Kv>>>;
+
     // execute_query executes the given query and returns an iterator that contains results of type specific to the underlying data store.
     // Only used for state databases that support query
     // For a chaincode, the namespace corresponds to the chaincodeId
@@ -73,6 +153,17 @@ pub trait TxSimulator {
         query: &str,
     ) -> Result<Box<dyn Iterator<Item = Kv>>>;
 
+    // execute_query_with_pagination returns a page of at most page_size results of the rich query,
+    // starting at the bookmark of the previous page if not empty. As for execute_query, the results
+    // are not recorded, no phantom read of a rich query is detected at commit
+    fn execute_query_with_pagination(
+        &mut self,
+        namespace: &str,
+        query: &str,
+        bookmark: &str,
+        page_size: usize,
+    ) -> Result<QueryResultsPage>;
+
     // done releases resources occupied by the QueryExecutor
     fn done(&mut self);
 }
@@ -1,14 +1,23 @@
+use crate::pvtdata::collection::get_collection_config;
 use crate::rwset::builder::{RWSetBuilder, TxSimulationResults};
-use crate::statedb::{Height, VersionedDB, VersionedValue};
+use crate::rwset::key::{derive_hashed_data_ns, derive_pvt_data_ns};
+use crate::rwset::policy::decode_metadata;
+use crate::statedb::{decode_range_bookmark, VersionedDB, VersionedKV};
+use crate::txmgr::lock::ReadGuard;
+use crate::txmgr::query_executor::{to_kv, to_results_page};
+use crate::QueryResultsPage;
 use error::*;
-use silk_proto::Kv;
+use silk_proto::range_query_info::ReadsInfo;
+use silk_proto::{Kv, KvRead, QueryReads, RangeQueryInfo, Version};
 use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
+use utils::composite_key::partial_composite_key_range;
 
 pub struct BasedTxSimulator<V: VersionedDB> {
     tx_id: String,
     rw_set_builder: RWSetBuilder,
     vdb: V,
+    commit_lock: Option<ReadGuard>,
 }
 impl<V: VersionedDB> BasedTxSimulator<V> {
     pub fn new(tx_id: String, vdb: V) -> Self {
@@ -16,24 +25,66 @@ impl<V: VersionedDB> BasedTxSimulator<V> {
             tx_id,
             rw_set_builder: RWSetBuilder::new(),
             vdb,
+            commit_lock: None,
+        }
+    }
+
+    // new_with_lock returns a simulator holding the read side of the commit lock until it is done
+    pub fn new_with_lock(tx_id: String, vdb: V, commit_lock: ReadGuard) -> Self {
+        BasedTxSimulator {
+            tx_id,
+            rw_set_builder: RWSetBuilder::new(),
+            vdb,
+            commit_lock: Some(commit_lock),
+        }
+    }
+
+    fn add_to_range_query_set(
+        &mut self,
+        namespace: &str,
+        start_key: &str,
+        end_key: &str,
+        itr_exhausted: bool,
+        kvs: &[VersionedKV],
+    ) {
+        let kv_reads = kvs
+            .iter()
+            .map(|kv| KvRead {
+                key: kv.composite_key.key().to_string(),
+                version: Some(Version {
+                    block_num: kv.versioned_value.version.block_num,
+                    tx_num: kv.versioned_value.version.tx_num,
+                }),
+            })
+            .collect();
+        self.rw_set_builder.add_to_range_query_set(
+            namespace,
+            RangeQueryInfo {
+                start_key: start_key.to_string(),
+                end_key: end_key.to_string(),
+                itr_exhausted,
+                reads_info: Some(ReadsInfo::RawReads(QueryReads { kv_reads })),
+            },
+        );
+    }
+
+    fn check_collection(&self, namespace: &str, collection: &str) -> Result<()> {
+        match get_collection_config(&self.vdb, namespace, collection)? {
+            Some(_) => Ok(()),
+            None => Err(from_str(&format!(
+                "collection {:} is not defined for namespace {:}",
+                collection, namespace
+            ))),
         }
     }
 }
 
 impl<V: VersionedDB> super::TxSimulator for BasedTxSimulator<V> {
     fn get_state(&mut self, namespace: &str, key: &str) -> Result<Vec<u8>> {
-        let v = self.vdb.get_state(namespace, key)?;
-        let vv = v.unwrap_or(VersionedValue {
-            value: vec![],
-            metadata: vec![],
-            version: Height {
-                block_num: 0,
-                tx_num: 0,
-            },
-        });
+        let vv = self.vdb.get_state(namespace, key)?;
         self.rw_set_builder
-            .add_to_read_set(namespace, key, vv.version);
-        Ok(vv.value)
+            .add_to_read_set(namespace, key, vv.as_ref().map(|vv| vv.version));
+        Ok(vv.map(|vv| vv.value).unwrap_or_default())
     }
 
     fn set_state(&mut self, namespace: &str, key: &str, value: Vec<u8>) -> Result<()> {
@@ -45,6 +96,42 @@ impl<V: VersionedDB> super::TxSimulator for BasedTxSimulator<V> {
         self.set_state(namespace, key, vec![])
     }
 
+    fn get_private_data(
+        &mut self,
+        namespace: &str,
+        collection: &str,
+        key: &str,
+    ) -> Result<Vec<u8>> {
+        self.check_collection(namespace, collection)?;
+        let v = self
+            .vdb
+            .get_state(&derive_pvt_data_ns(namespace, collection), key)?;
+        let key_hash = utils::base64::encode(utils::hash::compute_sha256(key.as_bytes()));
+        let version = self
+            .vdb
+            .get_version(&derive_hashed_data_ns(namespace, collection), &key_hash)?;
+        self.rw_set_builder
+            .add_to_hashed_read_set(namespace, collection, key, version);
+        Ok(v.map(|vv| vv.value).unwrap_or_default())
+    }
+
+    fn set_private_data(
+        &mut self,
+        namespace: &str,
+        collection: &str,
+        key: &str,
+        value: Vec<u8>,
+    ) -> Result<()> {
+        self.check_collection(namespace, collection)?;
+        self.rw_set_builder
+            .add_to_pvt_and_hashed_write_set(namespace, collection, key, value);
+        Ok(())
+    }
+
+    fn delete_private_data(&mut self, namespace: &str, collection: &str, key: &str) -> Result<()> {
+        self.set_private_data(namespace, collection, key, vec![])
+    }
+
     fn set_state_multiple_keys(
         &mut self,
         _namespace: &str,
@@ -63,10 +150,27 @@ impl<V: VersionedDB> super::TxSimulator for BasedTxSimulator<V> {
 
     fn get_state_metadata(
         &mut self,
-        _namespace: &str,
-        _key: &str,
+        namespace: &str,
+        key: &str,
     ) -> Result<HashMap<String, Vec<u8>, RandomState>> {
-        unimplemented!()
+        let vv = self.vdb.get_state(namespace, key)?;
+        self.rw_set_builder
+            .add_to_read_set(namespace, key, vv.as_ref().map(|vv| vv.version));
+        match vv {
+            Some(vv) => decode_metadata(&vv.metadata),
+            None => Ok(HashMap::new()),
+        }
+    }
+
+    fn set_state_metadata(
+        &mut self,
+        namespace: &str,
+        key: &str,
+        metadata: HashMap<String, Vec<u8>, RandomState>,
+    ) -> Result<()> {
+        self.rw_set_builder
+            .add_to_metadata_write_set(namespace, key, &metadata);
+        Ok(())
     }
 
     fn get_state_multiple_keys(
@@ -79,22 +183,81 @@ impl<V: VersionedDB> super::TxSimulator for BasedTxSimulator<V> {
 
     fn get_state_range_scan_iterator(
         &mut self,
-        _namespace: &str,
-        _start_key: &str,
-        _end_key: &str,
+        namespace: &str,
+        start_key: &str,
+        end_key: &str,
     ) -> Result<Box<dyn Iterator<Item = Kv>>> {
-        unimplemented!()
+        let kvs = self
+            .vdb
+            .get_state_range_scan_iterator(namespace, start_key, end_key)?
+            .collect::<Vec<VersionedKV>>();
+
+        // the whole range is read, the validation checks that no key appeared or changed in it
+        self.add_to_range_query_set(namespace, start_key, end_key, true, &kvs);
+        Ok(Box::new(kvs.into_iter().map(to_kv)))
+    }
+
+    fn get_state_range_scan_iterator_with_pagination(
+        &mut self,
+        namespace: &str,
+        start_key: &str,
+        end_key: &str,
+        bookmark: &str,
+        page_size: usize,
+    ) -> Result<QueryResultsPage> {
+        let page = self
+            .vdb
+            .get_state_range_scan_page(namespace, start_key, end_key, bookmark, page_size)?;
+
+        // the page covers the range from its bookmark up to the bookmark of the next page, no key
+        // must appear in between for the page to stay the same
+        let page_start = if bookmark.is_empty() {
+            start_key.to_string()
+        } else {
+            decode_range_bookmark(bookmark)?
+        };
+        let page_end = if page.bookmark.is_empty() {
+            end_key.to_string()
+        } else {
+            decode_range_bookmark(&page.bookmark)?
+        };
+        self.add_to_range_query_set(namespace, &page_start, &page_end, true, &page.kvs);
+        Ok(to_results_page(page))
+    }
+
+    fn get_state_by_partial_composite_key(
+        &mut self,
+        namespace: &str,
+        object_type: &str,
+        attributes: &[&str],
+    ) -> Result<Box<dyn Iterator<Item = Kv>>> {
+        let (start_key, end_key) = partial_composite_key_range(object_type, attributes)?;
+        self.get_state_range_scan_iterator(namespace, &start_key, &end_key)
     }
 
     fn execute_query(
         &mut self,
-        _namespace: &str,
-        _query: &str,
+        namespace: &str,
+        query: &str,
     ) -> Result<Box<dyn Iterator<Item = Kv>>> {
-        unimplemented!()
+        let iter = self.vdb.execute_query(namespace, query)?;
+        Ok(Box::new(iter.map(to_kv)))
+    }
+
+    fn execute_query_with_pagination(
+        &mut self,
+        namespace: &str,
+        query: &str,
+        bookmark: &str,
+        page_size: usize,
+    ) -> Result<QueryResultsPage> {
+        let page = self
+            .vdb
+            .execute_query_page(namespace, query, bookmark, page_size)?;
+        Ok(to_results_page(page))
     }
 
     fn done(&mut self) {
-        unimplemented!()
+        self.commit_lock.take();
     }
 }
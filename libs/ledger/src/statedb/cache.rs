@@ -0,0 +1,145 @@
+use crate::statedb::VersionedValue;
+use lru::LruCache;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+// CacheStats counts the lookups served by the state cache and the ones that went to the db
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+pub(crate) struct CacheEntries {
+    lru: LruCache<(String, String), Option<VersionedValue>>,
+    // generation changes with every update of the db, a value read from the db is only cached
+    // if no update happened meanwhile
+    generation: u64,
+}
+
+impl CacheEntries {
+    // update records the value committed for a key, an empty value is a delete
+    pub(crate) fn update(&mut self, ns: &str, key: &str, vv: &VersionedValue) {
+        let v = if vv.value.is_empty() {
+            None
+        } else {
+            Some(vv.clone())
+        };
+        self.lru.put((ns.to_string(), key.to_string()), v);
+    }
+
+    // invalidate drops all the entries, used when the db is cleared or rolled back
+    pub(crate) fn invalidate(&mut self) {
+        self.lru.clear();
+        self.generation += 1;
+    }
+
+    pub(crate) fn bump_generation(&mut self) {
+        self.generation += 1;
+    }
+}
+
+// StateCache is a bounded LRU cache of the decoded states of a channel, absent keys included.
+// It is kept in sync by the db: every commit updates the cached keys while holding the entries
+pub struct StateCache {
+    entries: Mutex<CacheEntries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl StateCache {
+    pub fn new(capacity: usize) -> Self {
+        StateCache {
+            entries: Mutex::new(CacheEntries {
+                lru: LruCache::new(capacity),
+                generation: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn entries(&self) -> MutexGuard<'_, CacheEntries> {
+        // the entries are dropped on a poisoned lock, they may be half updated
+        self.entries.lock().unwrap_or_else(|e| {
+            let mut entries = e.into_inner();
+            entries.invalidate();
+            entries
+        })
+    }
+
+    // lookup returns the cached value of a key, or the generation to pass to `fill` on a miss
+    pub(crate) fn lookup(
+        &self,
+        ns: &str,
+        key: &str,
+    ) -> std::result::Result<Option<VersionedValue>, u64> {
+        let mut entries = self.entries();
+        let k = (ns.to_string(), key.to_string());
+        match entries.lru.get(&k) {
+            Some(v) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Ok(v.clone())
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                Err(entries.generation)
+            }
+        }
+    }
+
+    // fill caches a value read from the db, unless the db was updated since the lookup
+    pub(crate) fn fill(&self, ns: &str, key: &str, v: Option<VersionedValue>, generation: u64) {
+        let mut entries = self.entries();
+        if entries.generation == generation {
+            entries.lru.put((ns.to_string(), key.to_string()), v);
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheStats, StateCache};
+    use crate::statedb::{Height, VersionedValue};
+
+    fn vv(value: &[u8]) -> VersionedValue {
+        VersionedValue {
+            value: value.to_vec(),
+            metadata: vec![],
+            version: Height::new(1, 0),
+        }
+    }
+
+    #[test]
+    fn test_state_cache() {
+        let cache = StateCache::new(2);
+
+        let generation = cache.lookup("ns", "k1").unwrap_err();
+        cache.fill("ns", "k1", Some(vv(b"v1")), generation);
+        assert_eq!(cache.lookup("ns", "k1"), Ok(Some(vv(b"v1"))));
+
+        // a value read before an update is not cached
+        let generation = cache.lookup("ns", "k2").unwrap_err();
+        cache.entries().bump_generation();
+        cache.fill("ns", "k2", None, generation);
+        assert!(cache.lookup("ns", "k2").is_err());
+
+        cache.entries().update("ns", "k2", &vv(b""));
+        assert_eq!(cache.lookup("ns", "k2"), Ok(None));
+
+        // k1 is the least recently used entry
+        cache.entries().update("ns", "k3", &vv(b"v3"));
+        assert!(cache.lookup("ns", "k1").is_err());
+
+        cache.entries().invalidate();
+        assert!(cache.lookup("ns", "k3").is_err());
+        assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 5 });
+    }
+}
@@ -0,0 +1,425 @@
+// The tests every state db must pass. state_db_tests! runs them against the dbs of a provider,
+// built by a function returning the provider with a guard to keep alive meanwhile
+use crate::statedb::{
+    encode_range_bookmark, Height, IndexCapable, IndexDefinition, UpdateBatch, VersionedDB,
+    VersionedDBProvider, VersionedKVPage, VersionedValue,
+};
+
+macro_rules! state_db_tests {
+    ($provider:ident) => {
+        #[test]
+        fn test_get_state() {
+            let (_guard, provider) = $provider();
+            crate::statedb::conformance::test_get_state(&provider);
+        }
+
+        #[test]
+        fn test_clear() {
+            let (_guard, provider) = $provider();
+            crate::statedb::conformance::test_clear(&provider);
+        }
+
+        #[test]
+        fn test_drop_db() {
+            let (_guard, provider) = $provider();
+            crate::statedb::conformance::test_drop_db(&provider);
+        }
+
+        #[test]
+        fn test_index() {
+            let (_guard, provider) = $provider();
+            crate::statedb::conformance::test_index(&provider);
+        }
+
+        #[test]
+        fn test_range_scan() {
+            let (_guard, provider) = $provider();
+            crate::statedb::conformance::test_range_scan(&provider);
+        }
+
+        #[test]
+        fn test_state_root() {
+            let (_guard, provider) = $provider();
+            crate::statedb::conformance::test_state_root(&provider);
+        }
+
+        #[test]
+        fn test_pagination() {
+            let (_guard, provider) = $provider();
+            crate::statedb::conformance::test_pagination(&provider);
+        }
+    };
+}
+
+fn query_keys(vdb: &impl VersionedDB, query: &str) -> Vec<String> {
+    let mut keys = vdb
+        .execute_query("ns", query)
+        .unwrap()
+        .map(|kv| kv.composite_key.key().to_string())
+        .collect::<Vec<String>>();
+    keys.sort();
+    keys
+}
+
+pub fn test_get_state<P: VersionedDBProvider>(provider: &P) {
+    let vdb = provider.get_db_handle("chain_id").unwrap();
+    assert!(vdb.get_latest_save_point().unwrap().is_none());
+
+    let mut batch = UpdateBatch::new();
+    batch.put("ns", "k1", b"v1".to_vec(), Height::new(1, 0));
+    batch.put_val_and_metadata(
+        "ns",
+        "k2",
+        b"v2".to_vec(),
+        b"m2".to_vec(),
+        Height::new(1, 1),
+    );
+    batch.put("ns1", "k1", b"other".to_vec(), Height::new(1, 2));
+    vdb.apply_updates(batch, Some(Height::new(1, 2))).unwrap();
+
+    assert_eq!(
+        vdb.get_state("ns", "k2").unwrap(),
+        Some(VersionedValue {
+            value: b"v2".to_vec(),
+            metadata: b"m2".to_vec(),
+            version: Height::new(1, 1),
+        })
+    );
+    assert_eq!(
+        vdb.get_version("ns", "k1").unwrap(),
+        Some(Height::new(1, 0))
+    );
+    assert!(vdb.get_version("ns", "k3").unwrap().is_none());
+    let values = vdb
+        .get_state_multiple_keys("ns", vec!["k1".to_string(), "k3".to_string()])
+        .unwrap();
+    assert_eq!(values[0].as_ref().unwrap().value, b"v1");
+    assert!(values[1].is_none());
+    assert_eq!(
+        vdb.get_latest_save_point().unwrap(),
+        Some(Height::new(1, 2))
+    );
+
+    // a batch without height leaves the savepoint where it is
+    let mut batch = UpdateBatch::new();
+    batch.delete("ns", "k1", Height::new(2, 0));
+    batch.put("ns", "k2", b"v2'".to_vec(), Height::new(2, 0));
+    vdb.apply_updates(batch, None).unwrap();
+    assert!(vdb.get_state("ns", "k1").unwrap().is_none());
+    assert_eq!(vdb.get_state("ns", "k2").unwrap().unwrap().value, b"v2'");
+    assert_eq!(vdb.get_state("ns1", "k1").unwrap().unwrap().value, b"other");
+    assert_eq!(
+        vdb.get_latest_save_point().unwrap(),
+        Some(Height::new(1, 2))
+    );
+
+    vdb.clear().unwrap();
+    assert!(vdb.get_state("ns", "k2").unwrap().is_none());
+    assert!(vdb.get_latest_save_point().unwrap().is_none());
+}
+
+pub fn test_clear<P: VersionedDBProvider>(provider: &P) {
+    let ch1 = provider.get_db_handle("ch1").unwrap();
+    let ch2 = provider.get_db_handle("ch2").unwrap();
+    for vdb in &[&ch1, &ch2] {
+        let mut batch = UpdateBatch::new();
+        batch.put("ns", "k", b"v".to_vec(), Height::new(1, 0));
+        vdb.apply_updates(batch, Some(Height::new(1, 0))).unwrap();
+    }
+
+    // the state of the other ledgers is left untouched
+    ch1.clear().unwrap();
+    assert!(ch1.get_state("ns", "k").unwrap().is_none());
+    assert!(ch1.get_latest_save_point().unwrap().is_none());
+    assert_eq!(ch2.get_state("ns", "k").unwrap().unwrap().value, b"v");
+    assert_eq!(
+        ch2.get_latest_save_point().unwrap(),
+        Some(Height::new(1, 0))
+    );
+}
+
+pub fn test_drop_db<P: VersionedDBProvider>(provider: &P) {
+    let ch1 = provider.get_db_handle("ch1").unwrap();
+    let ch2 = provider.get_db_handle("ch2").unwrap();
+
+    let mut batch = UpdateBatch::new();
+    batch.put("ns", "k", b"v1".to_vec(), Height::new(1, 0));
+    ch1.apply_updates(batch, Some(Height::new(1, 0))).unwrap();
+    let mut batch = UpdateBatch::new();
+    batch.put("ns", "k", b"v2".to_vec(), Height::new(5, 0));
+    ch2.apply_updates(batch, Some(Height::new(5, 0))).unwrap();
+
+    assert_eq!(ch1.get_state("ns", "k").unwrap().unwrap().value, b"v1");
+    assert_eq!(ch2.get_state("ns", "k").unwrap().unwrap().value, b"v2");
+
+    provider.drop_db("ch1").unwrap();
+    assert!(ch1.get_state("ns", "k").is_err());
+    assert_eq!(ch2.get_state("ns", "k").unwrap().unwrap().value, b"v2");
+    let ch1 = provider.get_db_handle("ch1").unwrap();
+    assert!(ch1.get_state("ns", "k").unwrap().is_none());
+    assert!(ch1.get_latest_save_point().unwrap().is_none());
+    provider.drop_db("unknown").unwrap();
+}
+
+pub fn test_index<P: VersionedDBProvider>(provider: &P)
+where
+    P::V: IndexCapable,
+{
+    let vdb = provider.get_db_handle("chain_id").unwrap();
+
+    let mut batch = UpdateBatch::new();
+    batch.put(
+        "ns",
+        "a1",
+        br#"{"owner":"alice","createdAt":1}"#.to_vec(),
+        Height::new(1, 0),
+    );
+    batch.put(
+        "ns",
+        "a2",
+        br#"{"owner":"alice","createdAt":2}"#.to_vec(),
+        Height::new(1, 1),
+    );
+    batch.put(
+        "ns",
+        "b1",
+        br#"{"owner":"bob","createdAt":1}"#.to_vec(),
+        Height::new(1, 2),
+    );
+    batch.put("ns", "raw", b"not json".to_vec(), Height::new(1, 3));
+    vdb.apply_updates(batch, Some(Height::new(1, 3))).unwrap();
+
+    let def = IndexDefinition::new(
+        "owner-created",
+        vec!["owner".to_string(), "createdAt".to_string()],
+    );
+    vdb.process_index_definitions("ns", vec![def.clone()])
+        .unwrap();
+    assert_eq!(vdb.get_index_definitions("ns").unwrap(), vec![def]);
+    assert!(vdb.get_index_definitions("other").unwrap().is_empty());
+
+    let by_alice = r#"{"selector":{"owner":"alice"}}"#;
+    assert_eq!(query_keys(&vdb, by_alice), vec!["a1", "a2"]);
+    assert_eq!(
+        query_keys(&vdb, r#"{"selector":{"createdAt":{"$lt":2}}}"#),
+        vec!["a1", "b1"]
+    );
+
+    // index entries follow the updates
+    let mut batch = UpdateBatch::new();
+    batch.put(
+        "ns",
+        "a1",
+        br#"{"owner":"bob","createdAt":1}"#.to_vec(),
+        Height::new(2, 0),
+    );
+    batch.delete("ns", "a2", Height::new(2, 1));
+    batch.put(
+        "ns",
+        "a3",
+        br#"{"owner":"alice","createdAt":3}"#.to_vec(),
+        Height::new(2, 2),
+    );
+    vdb.apply_updates(batch, Some(Height::new(2, 2))).unwrap();
+
+    assert_eq!(query_keys(&vdb, by_alice), vec!["a3"]);
+    assert_eq!(
+        query_keys(&vdb, r#"{"selector":{"owner":"bob","createdAt":1}}"#),
+        vec!["a1", "b1"]
+    );
+
+    vdb.rebuild_indexes("ns").unwrap();
+    assert_eq!(query_keys(&vdb, by_alice), vec!["a3"]);
+
+    // the definitions survive a clear, the entries are rebuilt with the states
+    vdb.clear().unwrap();
+    assert_eq!(vdb.get_index_definitions("ns").unwrap().len(), 1);
+    assert!(query_keys(&vdb, by_alice).is_empty());
+    let mut batch = UpdateBatch::new();
+    batch.put(
+        "ns",
+        "c1",
+        br#"{"owner":"alice","createdAt":4}"#.to_vec(),
+        Height::new(1, 0),
+    );
+    vdb.apply_updates(batch, Some(Height::new(1, 0))).unwrap();
+    assert_eq!(query_keys(&vdb, by_alice), vec!["c1"]);
+
+    // the indexes defined by a batch are built over the states it results in, with its savepoint
+    let def = IndexDefinition::new("tag", vec!["tag".to_string()]);
+    let mut batch = UpdateBatch::new();
+    batch.put("ns", "t1", br#"{"tag":"x"}"#.to_vec(), Height::new(2, 0));
+    batch.delete("ns", "c1", Height::new(2, 1));
+    batch.define_indexes("ns", vec![def.clone()]);
+    vdb.apply_updates(batch, Some(Height::new(2, 1))).unwrap();
+    assert_eq!(
+        vdb.get_latest_save_point().unwrap(),
+        Some(Height::new(2, 1))
+    );
+    assert!(vdb.get_index_definitions("ns").unwrap().contains(&def));
+    assert_eq!(query_keys(&vdb, r#"{"selector":{"tag":"x"}}"#), vec!["t1"]);
+    assert!(query_keys(&vdb, by_alice).is_empty());
+
+    // the indexed values do not run into the keys, whatever bytes they hold
+    let mut batch = UpdateBatch::new();
+    batch.put(
+        "ns",
+        "k",
+        br#"{"tag":"x\u0000"}"#.to_vec(),
+        Height::new(3, 0),
+    );
+    batch.put(
+        "ns",
+        "\u{0}k",
+        br#"{"tag":"x"}"#.to_vec(),
+        Height::new(3, 1),
+    );
+    vdb.apply_updates(batch, Some(Height::new(3, 1))).unwrap();
+    assert_eq!(
+        query_keys(&vdb, r#"{"selector":{"tag":"x"}}"#),
+        vec!["\u{0}k", "t1"]
+    );
+    assert_eq!(
+        query_keys(&vdb, r#"{"selector":{"tag":"x\u0000"}}"#),
+        vec!["k"]
+    );
+    let mut batch = UpdateBatch::new();
+    batch.delete("ns", "k", Height::new(4, 0));
+    vdb.apply_updates(batch, Some(Height::new(4, 0))).unwrap();
+    assert_eq!(
+        query_keys(&vdb, r#"{"selector":{"tag":"x"}}"#),
+        vec!["\u{0}k", "t1"]
+    );
+}
+
+pub fn test_range_scan<P: VersionedDBProvider>(provider: &P) {
+    let vdb = provider.get_db_handle("chain_id").unwrap();
+
+    let mut batch = UpdateBatch::new();
+    for (i, k) in ["k1", "k2", "k3"].iter().enumerate() {
+        batch.put("ns", k, b"v".to_vec(), Height::new(1, i as u64));
+    }
+    batch.put("ns1", "k0", b"v".to_vec(), Height::new(1, 3));
+    vdb.apply_updates(batch, Some(Height::new(1, 3))).unwrap();
+
+    let keys = |start: &str, end: &str| {
+        vdb.get_state_range_scan_iterator("ns", start, end)
+            .unwrap()
+            .map(|kv| kv.composite_key.key().to_string())
+            .collect::<Vec<String>>()
+    };
+    assert_eq!(keys("", ""), vec!["k1", "k2", "k3"]);
+    assert_eq!(keys("k2", ""), vec!["k2", "k3"]);
+    assert_eq!(keys("k1", "k3"), vec!["k1", "k2"]);
+    assert!(keys("k4", "").is_empty());
+}
+
+pub fn test_state_root<P: VersionedDBProvider>(provider: &P) {
+    let ch1 = provider.get_db_handle("ch1").unwrap();
+    let ch2 = provider.get_db_handle("ch2").unwrap();
+
+    let mut batch = UpdateBatch::new();
+    batch.put("ns", "k1", b"v1".to_vec(), Height::new(1, 0));
+    batch.put("ns", "k2", b"v2".to_vec(), Height::new(1, 0));
+    ch1.apply_updates(batch, Some(Height::new(1, 0))).unwrap();
+    let mut batch = UpdateBatch::new();
+    batch.delete("ns", "k2", Height::new(2, 0));
+    // private data do not change the root
+    batch.put("ns$$pcoll", "k", b"secret".to_vec(), Height::new(2, 0));
+    ch1.apply_updates(batch, Some(Height::new(2, 0))).unwrap();
+
+    // the same states reached through other blocks give the same root
+    let mut batch = UpdateBatch::new();
+    batch.put("ns", "k1", b"v1".to_vec(), Height::new(1, 0));
+    ch2.apply_updates(batch, Some(Height::new(2, 0))).unwrap();
+
+    let root1 = ch1.get_state_root(1).unwrap().unwrap();
+    let root2 = ch1.get_state_root(2).unwrap().unwrap();
+    assert_ne!(root1, root2);
+    assert_eq!(ch2.get_state_root(2).unwrap(), Some(root2.clone()));
+    assert!(ch1.get_state_root(3).unwrap().is_none());
+
+    let (value, proof) = ch1.get_state_with_proof("ns", "k1").unwrap();
+    assert_eq!(proof.block_num, Some(2));
+    assert!(proof.verify(&root2, "ns", "k1", value.as_ref()));
+    assert!(!proof.verify(&root1, "ns", "k1", value.as_ref()));
+
+    let (value, proof) = ch1.get_state_with_proof("ns", "k2").unwrap();
+    assert!(value.is_none());
+    assert!(proof.verify(&root2, "ns", "k2", None));
+
+    ch1.clear().unwrap();
+    assert!(ch1.get_state_root(2).unwrap().is_none());
+}
+
+pub fn test_pagination<P: VersionedDBProvider>(provider: &P)
+where
+    P::V: IndexCapable,
+{
+    let vdb = provider.get_db_handle("chain_id").unwrap();
+
+    let mut batch = UpdateBatch::new();
+    for (i, owner) in ["alice", "bob", "alice", "carol", "alice"]
+        .iter()
+        .enumerate()
+    {
+        let value = format!(r#"{{"owner":"{:}"}}"#, owner);
+        batch.put(
+            "ns",
+            &format!("k{:}", i),
+            value.into_bytes(),
+            Height::new(1, 0),
+        );
+    }
+    vdb.apply_updates(batch, Some(Height::new(1, 0))).unwrap();
+
+    // pages collects the keys of all the pages, a page at a time
+    let pages = |next: &dyn Fn(&str) -> VersionedKVPage| {
+        let mut pages = vec![];
+        let mut bookmark = String::new();
+        loop {
+            let page = next(&bookmark);
+            pages.push(
+                page.kvs
+                    .iter()
+                    .map(|kv| kv.composite_key.key().to_string())
+                    .collect::<Vec<String>>(),
+            );
+            if page.bookmark.is_empty() {
+                return pages;
+            }
+            bookmark = page.bookmark;
+        }
+    };
+
+    let range = pages(&|bookmark| {
+        vdb.get_state_range_scan_page("ns", "k1", "", bookmark, 2)
+            .unwrap()
+    });
+    assert_eq!(range, vec![vec!["k1", "k2"], vec!["k3", "k4"]]);
+
+    let by_alice = r#"{"selector":{"owner":"alice"}}"#;
+    let scan = pages(&|bookmark| vdb.execute_query_page("ns", by_alice, bookmark, 2).unwrap());
+    assert_eq!(scan, vec![vec!["k0", "k2"], vec!["k4"]]);
+    let first = vdb.execute_query_page("ns", by_alice, "", 2).unwrap();
+    vdb.process_index_definitions(
+        "ns",
+        vec![IndexDefinition::new("owner", vec!["owner".to_string()])],
+    )
+    .unwrap();
+    let indexed = pages(&|bookmark| vdb.execute_query_page("ns", by_alice, bookmark, 1).unwrap());
+    assert_eq!(indexed, vec![vec!["k0"], vec!["k2"], vec!["k4"]]);
+
+    // a bookmark of a scan does not resume a query served by an index
+    assert!(vdb
+        .execute_query_page("ns", by_alice, &first.bookmark, 2)
+        .is_err());
+    let bookmark = encode_range_bookmark("k0");
+    assert!(vdb
+        .get_state_range_scan_page("ns", "k1", "", &bookmark, 2)
+        .is_err());
+    assert!(vdb
+        .execute_query_page("ns", by_alice, &bookmark, 2)
+        .is_err());
+    assert!(vdb.get_state_range_scan_page("ns", "", "", "", 0).is_err());
+}
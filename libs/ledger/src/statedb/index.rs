@@ -0,0 +1,152 @@
+use byteorder::{BigEndian, WriteBytesExt};
+use error::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const NULL_TYPE: u8 = b'0';
+const BOOL_TYPE: u8 = b'b';
+const NUMBER_TYPE: u8 = b'n';
+const STRING_TYPE: u8 = b's';
+
+// IndexDefinition describes a secondary index declared by a contract over the json fields of
+// the values stored in a namespace, e.g. {"name":"owner-created","fields":["owner","createdAt"]}
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct IndexDefinition {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+impl IndexDefinition {
+    pub fn new(name: &str, fields: Vec<String>) -> Self {
+        IndexDefinition {
+            name: String::from(name),
+            fields,
+        }
+    }
+
+    // from_json parses and validates an index definition shipped with a contract
+    pub fn from_json(bytes: &[u8]) -> Result<Self> {
+        let def: IndexDefinition = serde_json::from_slice(bytes)?;
+        def.validate()?;
+        Ok(def)
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.name.is_empty() || self.name.as_bytes().contains(&0) {
+            return Err(from_str(&format!("invalid index name {:?}", self.name)));
+        }
+        if self.fields.is_empty() {
+            return Err(from_str(&format!("index {:?} has no fields", self.name)));
+        }
+        if self.fields.iter().any(|f| f.is_empty()) {
+            return Err(from_str(&format!(
+                "index {:?} has an empty field",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+
+    // index_values returns the encoded values of the indexed fields of a json document.
+    // None is returned when the value is not a json object or misses one of the fields,
+    // such values are simply not indexed
+    pub fn index_values(&self, value: &[u8]) -> Option<Vec<Vec<u8>>> {
+        let doc: Value = serde_json::from_slice(value).ok()?;
+        if !doc.is_object() {
+            return None;
+        }
+        let mut values = Vec::with_capacity(self.fields.len());
+        for f in &self.fields {
+            values.push(encode_index_value(field_value(&doc, f)?)?);
+        }
+        Some(values)
+    }
+}
+
+// field_value looks up a field of a json document, nested fields are separated by dots
+pub fn field_value<'a>(doc: &'a Value, field: &str) -> Option<&'a Value> {
+    let mut v = doc;
+    for name in field.split('.') {
+        v = v.as_object()?.get(name)?;
+    }
+    Some(v)
+}
+
+// encode_index_value encodes a json scalar so that the byte order of the encoding follows the
+// natural order of the values of the same type. Arrays and objects can not be indexed
+pub fn encode_index_value(value: &Value) -> Option<Vec<u8>> {
+    let mut buf = vec![];
+    match value {
+        Value::Null => buf.push(NULL_TYPE),
+        Value::Bool(b) => {
+            buf.push(BOOL_TYPE);
+            buf.push(*b as u8);
+        }
+        Value::Number(n) => {
+            let f = n.as_f64()?;
+            let bits = f.to_bits();
+            let sortable = if f.is_sign_negative() {
+                !bits
+            } else {
+                bits ^ (1 << 63)
+            };
+            buf.push(NUMBER_TYPE);
+            buf.write_u64::<BigEndian>(sortable).ok()?;
+        }
+        Value::String(s) => {
+            buf.push(STRING_TYPE);
+            buf.extend_from_slice(s.as_bytes());
+        }
+        Value::Array(_) | Value::Object(_) => return None,
+    }
+    Some(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_index_value, IndexDefinition};
+    use serde_json::json;
+
+    #[test]
+    fn test_definition() {
+        let def = IndexDefinition::from_json(br#"{"name":"owner","fields":["owner","createdAt"]}"#)
+            .unwrap();
+        assert_eq!(
+            def,
+            IndexDefinition::new("owner", vec!["owner".to_string(), "createdAt".to_string()])
+        );
+
+        assert!(IndexDefinition::from_json(br#"{"name":"owner","fields":[]}"#).is_err());
+        assert!(IndexDefinition::from_json(br#"{"name":"","fields":["owner"]}"#).is_err());
+    }
+
+    #[test]
+    fn test_index_values() {
+        let def = IndexDefinition::new("idx", vec!["owner".to_string(), "meta.size".to_string()]);
+
+        let values = def
+            .index_values(br#"{"owner":"alice","meta":{"size":3}}"#)
+            .unwrap();
+        assert_eq!(values[0], b"salice".to_vec());
+        assert_eq!(values[1], encode_index_value(&json!(3)).unwrap());
+
+        assert!(def.index_values(br#"{"owner":"alice"}"#).is_none());
+        assert!(def.index_values(b"not json").is_none());
+    }
+
+    #[test]
+    fn test_number_order() {
+        let nums = [-100.5, -3.0, -0.5, 0.0, 0.25, 7.0, 1000.0];
+        let encoded = nums
+            .iter()
+            .map(|n| encode_index_value(&json!(n)).unwrap())
+            .collect::<Vec<Vec<u8>>>();
+        let mut sorted = encoded.clone();
+        sorted.sort();
+        assert_eq!(encoded, sorted);
+    }
+}
@@ -1,11 +1,24 @@
+mod cache;
+#[cfg(test)]
+#[macro_use]
+mod conformance;
+mod index;
+mod query;
+pub mod smt;
 mod statedb;
+mod statemem;
 mod staterocksdb;
 mod version;
 
 use crate::rwset::key::CompositeKey;
+pub use cache::{CacheStats, StateCache};
 use error::*;
+pub use index::IndexDefinition;
+pub use query::{Query, QueryPlan};
 use silk_proto::VersionedValueProto;
+pub use smt::StateProof;
 pub use statedb::*;
+pub use statemem::*;
 pub use staterocksdb::*;
 pub use version::{are_same, Height};
 
@@ -13,7 +26,9 @@ pub use version::{are_same, Height};
 pub trait VersionedDBProvider {
     type V: VersionedDB;
     // get_db_handle returns a handle to a VersionedDB
-    fn get_db_handle(&self, id: &str) -> Self::V;
+    fn get_db_handle(&self, id: &str) -> Result<Self::V>;
+    // drop_db removes the whole state of a db, the handles obtained before must not be used anymore
+    fn drop_db(&self, id: &str) -> Result<()>;
     // close closes all the VersionedDB instances and releases any resources held by VersionedDBProvider
     fn close(&self) {}
 }
@@ -26,12 +41,13 @@ pub trait VersionedDB {
     // get_version gets the version for given namespace and key. For a contract, the namespace corresponds to the contractId
     fn get_version(&self, namespace: &str, key: &str) -> Result<Option<Height>>;
 
-    // get_state_multiple_keys gets the values for multiple keys in a single call
+    // get_state_multiple_keys gets the values for multiple keys in a single call.
+    // The values are returned in the order of the keys, None for a missing key
     fn get_state_multiple_keys(
         &self,
         namespace: &str,
         keys: Vec<String>,
-    ) -> Result<Vec<VersionedValue>>;
+    ) -> Result<Vec<Option<VersionedValue>>>;
 
     // get_state_range_scan_iterator returns an iterator that contains all the key-values between given key ranges.
     // start_key is inclusive
@@ -51,6 +67,27 @@ pub trait VersionedDB {
         query: &str,
     ) -> Result<Box<dyn Iterator<Item = VersionedKV>>>;
 
+    // get_state_range_scan_page returns at most page_size key-values of the range, resuming from the
+    // bookmark of the previous page if not empty. The returned bookmark is empty after the last page
+    fn get_state_range_scan_page(
+        &self,
+        namespace: &str,
+        start_key: &str,
+        end_key: &str,
+        bookmark: &str,
+        page_size: usize,
+    ) -> Result<VersionedKVPage>;
+
+    // execute_query_page returns at most page_size results of the given query, resuming from the
+    // bookmark of the previous page if not empty. The returned bookmark is empty after the last page
+    fn execute_query_page(
+        &self,
+        namespace: &str,
+        query: &str,
+        bookmark: &str,
+        page_size: usize,
+    ) -> Result<VersionedKVPage>;
+
     // apply_updates applies the batch to the underlying db.
     // height is the height of the highest transaction in the Batch that
     // a state db implementation is expected to ues as a save point
@@ -60,6 +97,22 @@ pub trait VersionedDB {
     // the state db is consistent
     fn get_latest_save_point(&self) -> Result<Option<Height>>;
 
+    // get_state_root returns the root of the sparse merkle tree over the states, as it was once
+    // the given block was committed. None is returned for a block that is not committed
+    fn get_state_root(&self, block_num: u64) -> Result<Option<Vec<u8>>>;
+
+    // get_state_with_proof returns the value of a key with a proof binding it, or its absence, to the
+    // state root of the last committed block. The caller must hold back the commits meanwhile
+    fn get_state_with_proof(
+        &self,
+        namespace: &str,
+        key: &str,
+    ) -> Result<(Option<VersionedValue>, StateProof)>;
+
+    // clear removes all the states together with the save point, so that the db can be rebuilt
+    // by replaying the blocks from the block store
+    fn clear(&self) -> Result<()>;
+
     // validate_key_value tests whether the key and value is supported by the db implementation.
     // For instance, leveldb supports any bytes for the key while the couchdb supports only valid utf-8 string
     // TODO make the function validate_key_value return a specific error say ErrInvalidKeyValue
@@ -78,6 +131,24 @@ pub trait VersionedDB {
     fn close(&self);
 }
 
+// IndexCapable is implemented by the state databases that maintain secondary indexes
+// over the json values of a namespace
+pub trait IndexCapable {
+    // process_index_definitions stores the index definitions declared by a contract for a namespace
+    // and builds their entries from the state already committed
+    fn process_index_definitions(
+        &self,
+        namespace: &str,
+        definitions: Vec<IndexDefinition>,
+    ) -> Result<()>;
+
+    // get_index_definitions returns the index definitions declared for a namespace
+    fn get_index_definitions(&self, namespace: &str) -> Result<Vec<IndexDefinition>>;
+
+    // rebuild_indexes drops the index entries of a namespace and recomputes them from the state
+    fn rebuild_indexes(&self, namespace: &str) -> Result<()>;
+}
+
 // VersionedValue encloses value and corresponding version
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct VersionedValue {
@@ -119,6 +190,24 @@ pub struct VersionedKV {
     pub versioned_value: VersionedValue,
 }
 
+// VersionedKVPage is a page of the results of a query
+pub struct VersionedKVPage {
+    pub kvs: Vec<VersionedKV>,
+    pub bookmark: String,
+}
+
+// encode_range_bookmark returns the bookmark resuming a range scan at the given key. Range
+// bookmarks are the same for all the state databases, so that the key can be recorded for
+// the phantom read validation
+pub fn encode_range_bookmark(key: &str) -> String {
+    utils::base64::encode(key)
+}
+
+pub fn decode_range_bookmark(bookmark: &str) -> Result<String> {
+    let key = utils::base64::decode(bookmark).map_err(|_| from_str("invalid bookmark"))?;
+    String::from_utf8(key).map_err(|_| from_str("invalid bookmark"))
+}
+
 #[cfg(test)]
 mod tests {
     use crate::statedb::{Height, UpdateBatch, VersionedDB, VersionedDBProvider, VersionedValue};
@@ -134,9 +223,10 @@ mod tests {
         let support = Support {
             s: super::staterocksdb::VersionedDBRocksProvider::new(
                 temp_dir.path().to_str().unwrap(),
-            ),
+            )
+            .unwrap(),
         };
-        let vdb = support.s.get_db_handle("chain_id");
+        let vdb = support.s.get_db_handle("chain_id").unwrap();
 
         let mut batch = UpdateBatch::new();
         batch.put(
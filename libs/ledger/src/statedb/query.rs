@@ -0,0 +1,197 @@
+use crate::statedb::index::{encode_index_value, field_value, IndexDefinition};
+use error::*;
+use serde_json::{Map, Value};
+use std::cmp::Ordering;
+
+#[derive(Debug)]
+enum Condition {
+    Eq(Value),
+    Ne(Value),
+    Gt(Value),
+    Gte(Value),
+    Lt(Value),
+    Lte(Value),
+}
+
+impl Condition {
+    fn parse(field: &str, value: &Value) -> Result<Vec<Condition>> {
+        let ops = match value.as_object() {
+            Some(ops) if ops.keys().all(|k| k.starts_with('$')) && !ops.is_empty() => ops,
+            _ => return Ok(vec![Condition::Eq(value.clone())]),
+        };
+
+        let mut conditions = Vec::with_capacity(ops.len());
+        for (op, v) in ops {
+            let c = match op.as_str() {
+                "$eq" => Condition::Eq(v.clone()),
+                "$ne" => Condition::Ne(v.clone()),
+                "$gt" => Condition::Gt(v.clone()),
+                "$gte" => Condition::Gte(v.clone()),
+                "$lt" => Condition::Lt(v.clone()),
+                "$lte" => Condition::Lte(v.clone()),
+                _ => {
+                    return Err(from_str(&format!(
+                        "unsupported operator {:} on field {:}",
+                        op, field
+                    )))
+                }
+            };
+            conditions.push(c);
+        }
+        Ok(conditions)
+    }
+
+    fn matches(&self, value: &Value) -> bool {
+        match self {
+            Condition::Eq(v) => value == v,
+            Condition::Ne(v) => value != v,
+            Condition::Gt(v) => compare(value, v) == Some(Ordering::Greater),
+            Condition::Gte(v) => matches!(
+                compare(value, v),
+                Some(Ordering::Greater) | Some(Ordering::Equal)
+            ),
+            Condition::Lt(v) => compare(value, v) == Some(Ordering::Less),
+            Condition::Lte(v) => {
+                matches!(
+                    compare(value, v),
+                    Some(Ordering::Less) | Some(Ordering::Equal)
+                )
+            }
+        }
+    }
+}
+
+// compare orders two json scalars of the same type, values of different types are not comparable
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+// Query is a rich query over the json values of a namespace, e.g.
+// {"selector":{"owner":"alice","createdAt":{"$gte":1600000000}}}
+// All the conditions of the selector must hold for a value to match
+#[derive(Debug)]
+pub struct Query {
+    selector: Vec<(String, Vec<Condition>)>,
+}
+
+// QueryPlan tells the state db how to execute a query: scanning the entries of `index` whose
+// leading fields equal `prefix` or, when no index can serve the query, scanning the namespace
+pub struct QueryPlan {
+    pub index: Option<IndexDefinition>,
+    pub prefix: Vec<Vec<u8>>,
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Result<Query> {
+        let q: Value = serde_json::from_str(query)?;
+        let selector = q
+            .get("selector")
+            .and_then(Value::as_object)
+            .ok_or_else(|| from_str("query selector is null"))?;
+        Query::from_selector(selector)
+    }
+
+    fn from_selector(selector: &Map<String, Value>) -> Result<Query> {
+        let mut conditions = Vec::with_capacity(selector.len());
+        for (field, value) in selector {
+            conditions.push((field.clone(), Condition::parse(field, value)?));
+        }
+        Ok(Query {
+            selector: conditions,
+        })
+    }
+
+    // matches evaluates the selector against a stored value
+    pub fn matches(&self, value: &[u8]) -> bool {
+        let doc: Value = match serde_json::from_slice(value) {
+            Ok(doc) => doc,
+            Err(_) => return false,
+        };
+        self.selector.iter().all(|(field, conditions)| {
+            field_value(&doc, field)
+                .map(|v| conditions.iter().all(|c| c.matches(v)))
+                .unwrap_or(false)
+        })
+    }
+
+    // plan picks the index with the longest run of leading fields fixed by equality conditions
+    pub fn plan(&self, indexes: &[IndexDefinition]) -> QueryPlan {
+        let mut best = QueryPlan {
+            index: None,
+            prefix: vec![],
+        };
+        for index in indexes {
+            let mut prefix = vec![];
+            for field in &index.fields {
+                match self.eq_value(field).and_then(encode_index_value) {
+                    Some(v) => prefix.push(v),
+                    None => break,
+                }
+            }
+            if prefix.len() > best.prefix.len() {
+                best = QueryPlan {
+                    index: Some(index.clone()),
+                    prefix,
+                };
+            }
+        }
+        best
+    }
+
+    fn eq_value(&self, field: &str) -> Option<&Value> {
+        let (_, conditions) = self.selector.iter().find(|(f, _)| f == field)?;
+        conditions.iter().find_map(|c| match c {
+            Condition::Eq(v) => Some(v),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Query;
+    use crate::statedb::index::IndexDefinition;
+
+    #[test]
+    fn test_matches() {
+        let q = Query::parse(
+            r#"{"selector":{"owner":"alice","size":{"$gt":1,"$lte":10},"color":{"$ne":"red"}}}"#,
+        )
+        .unwrap();
+
+        assert!(q.matches(br#"{"owner":"alice","size":10,"color":"blue"}"#));
+        assert!(!q.matches(br#"{"owner":"alice","size":1,"color":"blue"}"#));
+        assert!(!q.matches(br#"{"owner":"bob","size":5,"color":"blue"}"#));
+        assert!(!q.matches(br#"{"owner":"alice","size":5,"color":"red"}"#));
+        assert!(!q.matches(br#"{"owner":"alice","color":"blue"}"#));
+        assert!(!q.matches(b"alice"));
+
+        assert!(Query::parse(r#"{"selector":{"size":{"$in":[1]}}}"#).is_err());
+        assert!(Query::parse(r#"{"fields":["owner"]}"#).is_err());
+    }
+
+    #[test]
+    fn test_plan() {
+        let by_owner = IndexDefinition::new("owner", vec!["owner".to_string()]);
+        let by_owner_color = IndexDefinition::new(
+            "owner-color",
+            vec!["owner".to_string(), "color".to_string()],
+        );
+        let by_size = IndexDefinition::new("size", vec!["size".to_string()]);
+        let indexes = vec![by_owner, by_owner_color.clone(), by_size];
+
+        let q = Query::parse(r#"{"selector":{"owner":"alice","color":"blue"}}"#).unwrap();
+        let plan = q.plan(&indexes);
+        assert_eq!(plan.index, Some(by_owner_color));
+        assert_eq!(plan.prefix.len(), 2);
+
+        let q = Query::parse(r#"{"selector":{"size":{"$gt":3}}}"#).unwrap();
+        let plan = q.plan(&indexes);
+        assert!(plan.index.is_none());
+    }
+}
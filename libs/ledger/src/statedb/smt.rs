@@ -0,0 +1,471 @@
+use crate::statedb::VersionedValue;
+use byteorder::{BigEndian, WriteBytesExt};
+use error::*;
+use std::collections::BTreeMap;
+
+// TREE_DEPTH is the number of levels under the root, one per bit of the path of a leaf
+pub const TREE_DEPTH: usize = 256;
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+// EMPTY_HASH is the hash of an empty subtree, whatever its height
+const EMPTY_HASH: [u8; 32] = [0u8; 32];
+
+// Path identifies a leaf of the tree. A node at some height above the leaves is identified by the
+// path of its leftmost leaf, i.e. with the bits below its height cleared
+pub type Path = [u8; 32];
+
+// NodeStore gives the encoded nodes of a tree, see Node
+pub trait NodeStore {
+    fn get_node(&self, height: usize, path: &Path) -> Result<Option<Vec<u8>>>;
+}
+
+// leaf_path places a state in the tree, at the leaf given by the hash of its namespace and key
+pub fn leaf_path(ns: &str, key: &str) -> Path {
+    let hash = utils::hash::compute_vec_sha256(&[
+        ns.as_bytes().to_vec(),
+        vec![0],
+        key.as_bytes().to_vec(),
+    ]);
+    let mut path = [0u8; 32];
+    path.copy_from_slice(&hash);
+    path
+}
+
+// leaf_hash binds a leaf to the namespace, the key, the value and the version of a state
+pub fn leaf_hash(ns: &str, key: &str, vv: &VersionedValue) -> Vec<u8> {
+    let mut version = vec![];
+    let _ = version.write_u64::<BigEndian>(vv.version.block_num);
+    let _ = version.write_u64::<BigEndian>(vv.version.tx_num);
+    let data = vec![
+        ns.as_bytes().to_vec(),
+        vec![0],
+        key.as_bytes().to_vec(),
+        vec![0],
+        utils::hash::compute_sha256(&vv.value).to_vec(),
+        utils::hash::compute_sha256(&vv.metadata).to_vec(),
+        version,
+    ];
+    utils::hash::compute_vec_sha256(&data).to_vec()
+}
+
+// leaf_node_hash is the hash of a subtree holding a single leaf, it binds the path of the leaf so
+// that a proof can not move a leaf to another path
+fn leaf_node_hash(path: &Path, hash: &[u8]) -> Vec<u8> {
+    utils::hash::compute_vec_sha256(&[vec![LEAF_PREFIX], path.to_vec(), hash.to_vec()]).to_vec()
+}
+
+fn node_hash(left: &[u8], right: &[u8]) -> Vec<u8> {
+    utils::hash::compute_vec_sha256(&[vec![NODE_PREFIX], left.to_vec(), right.to_vec()]).to_vec()
+}
+
+// bit returns the i-th bit of a path, starting from the most significant one. The bit of a leaf
+// at index TREE_DEPTH - h tells whether its ancestor at height h - 1 is a left or a right child
+fn bit(path: &Path, i: usize) -> bool {
+    path[i / 8] & (0x80 >> (i % 8)) != 0
+}
+
+fn flip_bit(path: &Path, i: usize) -> Path {
+    let mut p = *path;
+    p[i / 8] ^= 0x80 >> (i % 8);
+    p
+}
+
+// same_prefix tells whether two paths share their first n bits
+fn same_prefix(a: &Path, b: &Path, n: usize) -> bool {
+    (0..n).all(|i| bit(a, i) == bit(b, i))
+}
+
+// Node is a subtree of the tree. A subtree holding a single leaf is kept as that leaf, at the
+// highest node above it alone, so that a key costs about log2(states) nodes instead of one per
+// level
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Node {
+    Empty,
+    // the path of the leaf and the hash of its state
+    Leaf(Path, Vec<u8>),
+    // a subtree with at least two leaves, its children are stored one level below
+    Internal(Vec<u8>),
+}
+
+impl Node {
+    fn hash(&self) -> Vec<u8> {
+        match self {
+            Node::Empty => EMPTY_HASH.to_vec(),
+            Node::Leaf(path, hash) => leaf_node_hash(path, hash),
+            Node::Internal(hash) => hash.clone(),
+        }
+    }
+
+    fn encode(&self) -> Option<Vec<u8>> {
+        match self {
+            Node::Empty => None,
+            Node::Leaf(path, hash) => Some([&[LEAF_PREFIX][..], path, hash].concat()),
+            Node::Internal(hash) => Some([&[NODE_PREFIX][..], hash].concat()),
+        }
+    }
+
+    fn decode(bytes: Option<Vec<u8>>) -> Result<Self> {
+        let bytes = match bytes {
+            Some(bytes) => bytes,
+            None => return Ok(Node::Empty),
+        };
+        match bytes.split_first() {
+            Some((&LEAF_PREFIX, rest)) if rest.len() > 32 => {
+                let mut path = [0u8; 32];
+                path.copy_from_slice(&rest[..32]);
+                Ok(Node::Leaf(path, rest[32..].to_vec()))
+            }
+            Some((&NODE_PREFIX, hash)) if !hash.is_empty() => Ok(Node::Internal(hash.to_vec())),
+            _ => Err(from_str("invalid state tree node")),
+        }
+    }
+}
+
+// SparseMerkleTree hashes the states into a root. Empty subtrees are not stored and subtrees
+// holding a single leaf are stored as that leaf, see Node
+#[derive(Default)]
+pub struct SparseMerkleTree;
+
+// NodeUpdate is a node changed by an update, its encoding is None when it is removed
+pub type NodeUpdate = (usize, Path, Option<Vec<u8>>);
+
+impl SparseMerkleTree {
+    pub fn new() -> Self {
+        SparseMerkleTree
+    }
+
+    pub fn root(&self, store: &impl NodeStore) -> Result<Vec<u8>> {
+        Ok(self.node(store, TREE_DEPTH, &[0u8; 32])?.hash())
+    }
+
+    fn node(&self, store: &impl NodeStore, height: usize, path: &Path) -> Result<Node> {
+        Node::decode(store.get_node(height, path)?)
+    }
+
+    // update sets the hashes of the given leaves, None removing a leaf, and returns the new root
+    // with all the nodes to write. The updates of a block are applied at once, so that a node
+    // shared by several updated leaves is computed and written once
+    pub fn update(
+        &self,
+        store: &impl NodeStore,
+        leaves: Vec<(Path, Option<Vec<u8>>)>,
+    ) -> Result<(Vec<u8>, Vec<NodeUpdate>)> {
+        let leaves: Vec<(Path, Option<Vec<u8>>)> = leaves
+            .into_iter()
+            .collect::<BTreeMap<Path, Option<Vec<u8>>>>()
+            .into_iter()
+            .collect();
+        let root = [0u8; 32];
+        let current = self.node(store, TREE_DEPTH, &root)?;
+        let mut updates = vec![];
+        let node = self.update_node(
+            store,
+            TREE_DEPTH,
+            &root,
+            current.clone(),
+            &leaves,
+            &mut updates,
+        )?;
+        place(&mut updates, TREE_DEPTH, &root, &current, &node);
+        Ok((node.hash(), updates))
+    }
+
+    // update_node applies the updates of the leaves, sorted by path, under the node at the given
+    // height and path. The nodes below it are written into updates, the node itself is placed by
+    // its parent
+    fn update_node(
+        &self,
+        store: &impl NodeStore,
+        height: usize,
+        path: &Path,
+        current: Node,
+        leaves: &[(Path, Option<Vec<u8>>)],
+        updates: &mut Vec<NodeUpdate>,
+    ) -> Result<Node> {
+        if leaves.is_empty() {
+            return Ok(current);
+        }
+
+        // a subtree left with at most one leaf is that leaf, there is nothing to split
+        if let Node::Empty | Node::Leaf(..) = current {
+            let mut remaining: BTreeMap<Path, Vec<u8>> = BTreeMap::new();
+            if let Node::Leaf(p, hash) = &current {
+                remaining.insert(*p, hash.clone());
+            }
+            for (p, hash) in leaves {
+                match hash {
+                    Some(hash) => remaining.insert(*p, hash.clone()),
+                    None => remaining.remove(p),
+                };
+            }
+            match remaining.len() {
+                0 => return Ok(Node::Empty),
+                1 => {
+                    let (p, hash) = remaining.into_iter().next().unwrap();
+                    return Ok(Node::Leaf(p, hash));
+                }
+                _ => {}
+            }
+        }
+
+        // the leaves of a subtree share the bits of its path above it
+        let i = TREE_DEPTH - height;
+        let left = *path;
+        let right = flip_bit(path, i);
+        let (stored_left, stored_right) = match &current {
+            Node::Internal(_) => (
+                self.node(store, height - 1, &left)?,
+                self.node(store, height - 1, &right)?,
+            ),
+            _ => (Node::Empty, Node::Empty),
+        };
+        let (current_left, current_right) = match &current {
+            Node::Leaf(p, _) if bit(p, i) => (Node::Empty, current.clone()),
+            Node::Leaf(..) => (current.clone(), Node::Empty),
+            _ => (stored_left.clone(), stored_right.clone()),
+        };
+        let split = leaves
+            .iter()
+            .position(|(p, _)| bit(p, i))
+            .unwrap_or(leaves.len());
+        let new_left = self.update_node(
+            store,
+            height - 1,
+            &left,
+            current_left,
+            &leaves[..split],
+            updates,
+        )?;
+        let new_right = self.update_node(
+            store,
+            height - 1,
+            &right,
+            current_right,
+            &leaves[split..],
+            updates,
+        )?;
+
+        let node = match (&new_left, &new_right) {
+            (Node::Empty, Node::Empty) => Node::Empty,
+            (Node::Leaf(..), Node::Empty) => new_left.clone(),
+            (Node::Empty, Node::Leaf(..)) => new_right.clone(),
+            _ => Node::Internal(node_hash(&new_left.hash(), &new_right.hash())),
+        };
+        // the children are stored below an internal node only, a leaf moves up to its parent
+        let (placed_left, placed_right) = match &node {
+            Node::Internal(_) => (new_left, new_right),
+            _ => (Node::Empty, Node::Empty),
+        };
+        place(updates, height - 1, &left, &stored_left, &placed_left);
+        place(updates, height - 1, &right, &stored_right, &placed_right);
+        Ok(node)
+    }
+
+    // prove returns the proof of the leaf at the given path, without its block
+    pub fn prove(&self, store: &impl NodeStore, path: &Path) -> Result<StateProof> {
+        let mut siblings = vec![];
+        let mut node_path = [0u8; 32];
+        let mut node = self.node(store, TREE_DEPTH, &node_path)?;
+        let mut height = TREE_DEPTH;
+        while let Node::Internal(_) = node {
+            let i = TREE_DEPTH - height;
+            let sibling_path = if bit(path, i) {
+                let left = node_path;
+                node_path = flip_bit(&node_path, i);
+                left
+            } else {
+                flip_bit(&node_path, i)
+            };
+            siblings.push(self.node(store, height - 1, &sibling_path)?.hash());
+            node = self.node(store, height - 1, &node_path)?;
+            height -= 1;
+        }
+        siblings.reverse();
+        let leaf = match node {
+            Node::Leaf(p, hash) if &p != path => Some((p, hash)),
+            _ => None,
+        };
+        Ok(StateProof {
+            block_num: None,
+            siblings,
+            leaf,
+        })
+    }
+}
+
+// place writes the node at the given height and path when it changed
+fn place(updates: &mut Vec<NodeUpdate>, height: usize, path: &Path, stored: &Node, node: &Node) {
+    if stored != node {
+        updates.push((height, *path, node.encode()));
+    }
+}
+
+// StateProof binds the value of a key, or its absence, to the state root of a block
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StateProof {
+    // block_num is the last block committed when the proof was built, None for an empty state
+    pub block_num: Option<u64>,
+    // siblings are the hashes along the path of the key, from the node where the path ends up to
+    // the root
+    pub siblings: Vec<Vec<u8>>,
+    // leaf is the other leaf the path of a missing key ends on, with the hash of its state
+    pub leaf: Option<(Path, Vec<u8>)>,
+}
+
+impl StateProof {
+    // verify tells whether the proof leads from the given value of a key, None for a missing key,
+    // to the root
+    pub fn verify(&self, root: &[u8], ns: &str, key: &str, value: Option<&VersionedValue>) -> bool {
+        let path = leaf_path(ns, key);
+        let depth = self.siblings.len();
+        if depth > TREE_DEPTH {
+            return false;
+        }
+        let mut hash = match (value, &self.leaf) {
+            (Some(vv), None) if !vv.is_delete() => leaf_node_hash(&path, &leaf_hash(ns, key, vv)),
+            (Some(vv), _) if !vv.is_delete() => return false,
+            (_, None) => EMPTY_HASH.to_vec(),
+            // the path of the key ends on another leaf
+            (_, Some((p, h))) => {
+                if p == &path || !same_prefix(p, &path, depth) {
+                    return false;
+                }
+                leaf_node_hash(p, h)
+            }
+        };
+        for (j, sibling) in self.siblings.iter().enumerate() {
+            hash = if bit(&path, depth - 1 - j) {
+                node_hash(sibling, &hash)
+            } else {
+                node_hash(&hash, sibling)
+            };
+        }
+        hash == root
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{leaf_hash, leaf_path, NodeStore, Path, SparseMerkleTree, StateProof};
+    use crate::statedb::{Height, VersionedValue};
+    use error::*;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct MemStore(HashMap<(usize, Path), Vec<u8>>);
+
+    impl NodeStore for MemStore {
+        fn get_node(&self, height: usize, path: &Path) -> Result<Option<Vec<u8>>> {
+            Ok(self.0.get(&(height, *path)).cloned())
+        }
+    }
+
+    impl MemStore {
+        fn update(
+            &mut self,
+            tree: &SparseMerkleTree,
+            states: &[(&str, Option<&VersionedValue>)],
+        ) -> Vec<u8> {
+            let leaves = states
+                .iter()
+                .map(|(k, v)| (leaf_path("ns", k), v.map(|vv| leaf_hash("ns", k, vv))))
+                .collect();
+            let (root, nodes) = tree.update(self, leaves).unwrap();
+            for (h, path, hash) in nodes {
+                match hash {
+                    Some(hash) => self.0.insert((h, path), hash),
+                    None => self.0.remove(&(h, path)),
+                };
+            }
+            root
+        }
+    }
+
+    fn vv(value: &[u8], block_num: u64) -> VersionedValue {
+        VersionedValue {
+            value: value.to_vec(),
+            metadata: vec![],
+            version: Height::new(block_num, 0),
+        }
+    }
+
+    #[test]
+    fn test_incremental_root() {
+        let tree = SparseMerkleTree::new();
+        let mut store = MemStore::default();
+        let empty_root = tree.root(&store).unwrap();
+
+        let (a, b, c) = (vv(b"a", 1), vv(b"b", 1), vv(b"c", 2));
+        store.update(&tree, &[("k1", Some(&a)), ("k2", Some(&b))]);
+        let root = store.update(&tree, &[("k3", Some(&c))]);
+
+        // the root only depends on the states, not on the order they were committed in
+        let mut other = MemStore::default();
+        let other_root = other.update(
+            &tree,
+            &[("k3", Some(&c)), ("k2", Some(&b)), ("k1", Some(&a))],
+        );
+        assert_eq!(root, other_root);
+
+        // removing all the states empties the store
+        let root = store.update(&tree, &[("k1", None), ("k2", None), ("k3", None)]);
+        assert_eq!(root, empty_root);
+        assert!(store.0.is_empty());
+    }
+
+    #[test]
+    fn test_compressed_nodes() {
+        let tree = SparseMerkleTree::new();
+        let mut store = MemStore::default();
+        let a = vv(b"a", 1);
+
+        // a single state is stored as the root
+        store.update(&tree, &[("k1", Some(&a))]);
+        assert_eq!(store.0.len(), 1);
+
+        // the nodes of a state are about the depth of the tree, not one per level
+        let keys: Vec<String> = (0..1000).map(|i| format!("key{}", i)).collect();
+        let states: Vec<(&str, Option<&VersionedValue>)> =
+            keys.iter().map(|k| (k.as_str(), Some(&a))).collect();
+        store.update(&tree, &states);
+        assert!(store.0.len() < 3000);
+        let leaves = vec![(
+            leaf_path("ns", "key7"),
+            Some(leaf_hash("ns", "key7", &vv(b"b", 2))),
+        )];
+        let (_, nodes) = tree.update(&store, leaves).unwrap();
+        assert!(nodes.len() < 40);
+    }
+
+    #[test]
+    fn test_proof() {
+        let tree = SparseMerkleTree::new();
+        let mut store = MemStore::default();
+        let (a, b) = (vv(b"a", 1), vv(b"b", 1));
+        let root = store.update(&tree, &[("k1", Some(&a)), ("k2", Some(&b))]);
+
+        let proof = tree.prove(&store, &leaf_path("ns", "k1")).unwrap();
+        assert!(proof.leaf.is_none());
+        assert!(proof.verify(&root, "ns", "k1", Some(&a)));
+        assert!(!proof.verify(&root, "ns", "k1", Some(&vv(b"a", 2))));
+        assert!(!proof.verify(&root, "ns", "k1", None));
+
+        // the absence of a key is proven the same way, its path ends on an empty subtree or on
+        // another leaf
+        for k in (3..20).map(|i| format!("k{}", i)) {
+            let proof = tree.prove(&store, &leaf_path("ns", &k)).unwrap();
+            assert!(proof.verify(&root, "ns", &k, None));
+            assert!(!proof.verify(&root, "ns", &k, Some(&a)));
+        }
+
+        // a leaf can not be passed as another one
+        let proof = tree.prove(&store, &leaf_path("ns", "k1")).unwrap();
+        let forged = StateProof {
+            leaf: Some((leaf_path("ns", "k1"), leaf_hash("ns", "k1", &a))),
+            ..proof
+        };
+        assert!(!forged.verify(&root, "ns", "k1", None));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::statedb::{Height, VersionedValue};
+use crate::statedb::{Height, IndexDefinition, VersionedValue};
 use std::collections::HashMap;
 
 #[derive(Debug)]
@@ -16,6 +16,8 @@ impl NsUpdates {
 pub struct UpdateBatch {
     contains_post_order_writes: bool,
     updates: HashMap<String, NsUpdates>,
+    // namespace -> indexes defined along with the updates, built in the same write
+    index_definitions: HashMap<String, Vec<IndexDefinition>>,
 }
 
 impl UpdateBatch {
@@ -23,6 +25,7 @@ impl UpdateBatch {
         UpdateBatch {
             contains_post_order_writes: false,
             updates: HashMap::new(),
+            index_definitions: HashMap::new(),
         }
     }
 
@@ -93,6 +96,24 @@ impl UpdateBatch {
         self.updates.get(ns).map(|s| s.m.clone())
     }
 
+    // define_indexes adds index definitions of a namespace, the indexes are built over the state
+    // resulting from the batch when it is applied
+    pub fn define_indexes(&mut self, ns: &str, definitions: Vec<IndexDefinition>) {
+        self.index_definitions
+            .entry(String::from(ns))
+            .or_default()
+            .extend(definitions);
+    }
+
+    // get_indexed_namespaces returns the names of the namespaces whose indexes are defined
+    pub fn get_indexed_namespaces(&self) -> Vec<String> {
+        self.index_definitions.keys().cloned().collect()
+    }
+
+    pub fn get_index_definitions(&self, ns: &str) -> Vec<IndexDefinition> {
+        self.index_definitions.get(ns).cloned().unwrap_or_default()
+    }
+
     // merge merges another updates batch with this updates batch
     pub fn merge(&mut self, batch: UpdateBatch) {
         self.contains_post_order_writes =
@@ -102,6 +123,9 @@ impl UpdateBatch {
                 self.update(&ns, &key, vv)
             }
         }
+        for (ns, definitions) in batch.index_definitions {
+            self.define_indexes(&ns, definitions);
+        }
     }
 
     fn get_or_create_nsupdates(&mut self, ns: &str) -> &mut NsUpdates {
@@ -0,0 +1,513 @@
+use dashmap::DashMap;
+use error::*;
+
+use super::smt::{self, NodeStore, Path, SparseMerkleTree};
+use super::staterocksdb::{
+    check_page_size, encode_data_key, encode_index_key, encode_index_prefix, new_index_definitions,
+};
+use super::*;
+use crate::rwset::key::is_pvt_data_ns;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// VersionedDBMemProvider keeps the state of every channel in memory, it is lost with the
+// provider. It behaves as the VersionedDBRocksProvider, down to the bookmarks of the queries,
+// and is meant for the tests
+#[derive(Default)]
+pub struct VersionedDBMemProvider {
+    handler: DashMap<String, MemDBVersion>,
+}
+
+impl VersionedDBMemProvider {
+    pub fn new() -> Self {
+        VersionedDBMemProvider::default()
+    }
+}
+
+impl VersionedDBProvider for VersionedDBMemProvider {
+    type V = MemDBVersion;
+
+    fn get_db_handle(&self, id: &str) -> Result<MemDBVersion> {
+        Ok(self
+            .handler
+            .entry(id.to_string())
+            .or_insert_with(|| MemDBVersion {
+                state: Arc::new(RwLock::new(MemState::default())),
+                name: id.to_string(),
+            })
+            .clone())
+    }
+
+    fn drop_db(&self, id: &str) -> Result<()> {
+        if let Some((_, handle)) = self.handler.remove(id) {
+            let mut state = handle.state.write().map_err(|e| from_str(&e.to_string()))?;
+            *state = MemState {
+                dropped: true,
+                ..MemState::default()
+            };
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct MemState {
+    // dropped is set once the channel is dropped, its handles are not usable anymore
+    dropped: bool,
+    // namespace -> key -> state, the deleted keys are removed
+    states: BTreeMap<String, BTreeMap<String, VersionedValue>>,
+    // namespace -> index name -> definition
+    index_defs: BTreeMap<String, BTreeMap<String, IndexDefinition>>,
+    // index entries encoded as the keys of the rocksdb ones -> key of the state
+    index_entries: BTreeMap<Vec<u8>, String>,
+    // nodes of the state tree by height and path, the empty ones are left out
+    nodes: HashMap<(usize, Path), Vec<u8>>,
+    // state root by block
+    roots: BTreeMap<u64, Vec<u8>>,
+    save_point: Option<Height>,
+}
+
+#[derive(Clone)]
+pub struct MemDBVersion {
+    state: Arc<RwLock<MemState>>,
+    name: String,
+}
+
+impl VersionedDB for MemDBVersion {
+    fn get_state(&self, namespace: &str, key: &str) -> Result<Option<VersionedValue>> {
+        debug!("get_state(). ns={:}, key={:}", namespace, key);
+        Ok(self.read()?.get(namespace, key))
+    }
+
+    fn get_version(&self, namespace: &str, key: &str) -> Result<Option<Height>> {
+        let v = self.get_state(namespace, key)?;
+        Ok(v.map(|v| v.version))
+    }
+
+    fn get_state_multiple_keys(
+        &self,
+        namespace: &str,
+        keys: Vec<String>,
+    ) -> Result<Vec<Option<VersionedValue>>> {
+        let state = self.read()?;
+        Ok(keys.iter().map(|k| state.get(namespace, k)).collect())
+    }
+
+    fn get_state_range_scan_iterator(
+        &self,
+        namespace: &str,
+        start_key: &str,
+        end_key: &str,
+    ) -> Result<Box<dyn Iterator<Item = VersionedKV>>> {
+        let kvs = self
+            .read()?
+            .range_scan(namespace, start_key, end_key, usize::MAX);
+        Ok(Box::new(kvs.into_iter()))
+    }
+
+    fn execute_query(
+        &self,
+        namespace: &str,
+        query: &str,
+    ) -> Result<Box<dyn Iterator<Item = VersionedKV>>> {
+        let page = self.execute_query_page(namespace, query, "", usize::MAX)?;
+        Ok(Box::new(page.kvs.into_iter()))
+    }
+
+    fn get_state_range_scan_page(
+        &self,
+        namespace: &str,
+        start_key: &str,
+        end_key: &str,
+        bookmark: &str,
+        page_size: usize,
+    ) -> Result<VersionedKVPage> {
+        check_page_size(page_size)?;
+        let start_key = if bookmark.is_empty() {
+            start_key.to_string()
+        } else {
+            let key = decode_range_bookmark(bookmark)?;
+            if key.as_str() < start_key || (!end_key.is_empty() && key.as_str() >= end_key) {
+                return Err(from_str("bookmark is out of the range"));
+            }
+            key
+        };
+
+        let mut kvs =
+            self.read()?
+                .range_scan(namespace, &start_key, end_key, page_size.saturating_add(1));
+        let bookmark = if kvs.len() > page_size {
+            let next = kvs.pop().unwrap();
+            encode_range_bookmark(next.composite_key.key())
+        } else {
+            String::new()
+        };
+        Ok(VersionedKVPage { kvs, bookmark })
+    }
+
+    // the bookmarks are the keys the rocksdb state db would resume from, a data key or an index
+    // entry key depending on the plan
+    fn execute_query_page(
+        &self,
+        namespace: &str,
+        query: &str,
+        bookmark: &str,
+        page_size: usize,
+    ) -> Result<VersionedKVPage> {
+        check_page_size(page_size)?;
+        let query = Query::parse(query)?;
+        let state = self.read()?;
+        let plan = query.plan(&state.index_definitions(namespace));
+
+        let prefix = match &plan.index {
+            Some(index) => encode_index_prefix(namespace, &index.name, &plan.prefix),
+            None => encode_data_key(namespace, ""),
+        };
+        let resume = if bookmark.is_empty() {
+            prefix.clone()
+        } else {
+            match utils::base64::decode(bookmark) {
+                Ok(key) if key.starts_with(&prefix) => key,
+                _ => return Err(from_str("invalid bookmark")),
+            }
+        };
+
+        // the candidates are visited in the order of their db keys
+        let candidates: Box<dyn Iterator<Item = (Vec<u8>, String)> + '_> = match &plan.index {
+            Some(_) => Box::new(
+                state
+                    .index_entries
+                    .range(resume..)
+                    .take_while(|(k, _)| k.starts_with(&prefix))
+                    .map(|(k, key)| (k.clone(), key.clone())),
+            ),
+            None => {
+                let start = String::from_utf8(resume[prefix.len()..].to_vec())
+                    .map_err(|_| from_str("invalid bookmark"))?;
+                Box::new(
+                    state
+                        .states
+                        .get(namespace)
+                        .into_iter()
+                        .flat_map(move |states| states.range(start.clone()..))
+                        .map(|(key, _)| (encode_data_key(namespace, key), key.clone())),
+                )
+            }
+        };
+
+        let mut kvs = vec![];
+        let mut bookmark = String::new();
+        for (k, key) in candidates {
+            let vv = match state.get(namespace, &key) {
+                Some(vv) => vv,
+                None => continue,
+            };
+            if !query.matches(&vv.value) {
+                continue;
+            }
+            if kvs.len() == page_size {
+                bookmark = utils::base64::encode(&k);
+                break;
+            }
+            kvs.push(VersionedKV {
+                composite_key: CompositeKey::new(namespace, "", &key),
+                versioned_value: vv,
+            });
+        }
+        Ok(VersionedKVPage { kvs, bookmark })
+    }
+
+    fn apply_updates(&self, batch: UpdateBatch, height: Option<Height>) -> Result<()> {
+        let mut state = self.write()?;
+
+        // the indexes defined by the batch are built over the updated states, the existing ones
+        // are maintained by the updates
+        let mut defined = HashMap::new();
+        for ns in batch.get_indexed_namespaces() {
+            let existing = state.index_definitions(&ns);
+            let definitions = new_index_definitions(&existing, batch.get_index_definitions(&ns))?;
+            if !definitions.is_empty() {
+                defined.insert(ns, definitions);
+            }
+        }
+
+        for ns in batch.get_updated_namespaces() {
+            if let Some(updates) = batch.get_updates(&ns) {
+                let mut indexes = state.index_definitions(&ns);
+                if let Some(definitions) = defined.get(&ns) {
+                    indexes.retain(|index| definitions.iter().all(|def| def.name != index.name));
+                }
+                for (k, vv) in updates {
+                    debug!("Channel [{}]: Applying key(string)=[{}]", self.name, k);
+                    if !indexes.is_empty() {
+                        if let Some(old) = state.get(&ns, &k) {
+                            state.remove_index_entries(&indexes, &ns, &k, &old.value);
+                        }
+                        state.add_index_entries(&indexes, &ns, &k, &vv.value);
+                    }
+
+                    if vv.value.is_empty() {
+                        if let Some(states) = state.states.get_mut(&ns) {
+                            states.remove(&k);
+                        }
+                    } else {
+                        state.states.entry(ns.clone()).or_default().insert(k, vv);
+                    }
+                }
+            }
+        }
+
+        for (ns, definitions) in defined {
+            for def in definitions {
+                info!(
+                    "Channel [{}]: creating index {:?} on namespace {:}",
+                    self.name, def, ns
+                );
+                // a redefined index is rebuilt from scratch
+                let prefix = encode_index_prefix(&ns, &def.name, &[]);
+                state.index_entries.retain(|k, _| !k.starts_with(&prefix));
+                for kv in state.range_scan(&ns, "", "", usize::MAX) {
+                    state.add_index_entries(
+                        std::slice::from_ref(&def),
+                        &ns,
+                        kv.composite_key.key(),
+                        &kv.versioned_value.value,
+                    );
+                }
+                state
+                    .index_defs
+                    .entry(ns.clone())
+                    .or_default()
+                    .insert(def.name.clone(), def);
+            }
+        }
+
+        // the private data are left out of the state root, only some peers have them
+        let mut leaves = vec![];
+        for ns in batch.get_updated_namespaces() {
+            if is_pvt_data_ns(&ns) {
+                continue;
+            }
+            if let Some(updates) = batch.get_updates(&ns) {
+                for (k, vv) in updates {
+                    let leaf = if vv.is_delete() {
+                        None
+                    } else {
+                        Some(smt::leaf_hash(&ns, &k, &vv))
+                    };
+                    leaves.push((smt::leaf_path(&ns, &k), leaf));
+                }
+            }
+        }
+        let (root, nodes) = SparseMerkleTree::new().update(&MemNodeStore(&state.nodes), leaves)?;
+        for (h, path, node) in nodes {
+            match node {
+                Some(node) => state.nodes.insert((h, path), node),
+                None => state.nodes.remove(&(h, path)),
+            };
+        }
+
+        if let Some(h) = height {
+            state.roots.insert(h.block_num, root);
+            state.save_point = Some(h);
+        }
+        Ok(())
+    }
+
+    fn get_latest_save_point(&self) -> Result<Option<Height>> {
+        Ok(self.read()?.save_point)
+    }
+
+    fn get_state_root(&self, block_num: u64) -> Result<Option<Vec<u8>>> {
+        Ok(self.read()?.roots.get(&block_num).cloned())
+    }
+
+    fn get_state_with_proof(
+        &self,
+        namespace: &str,
+        key: &str,
+    ) -> Result<(Option<VersionedValue>, StateProof)> {
+        let state = self.read()?;
+        let proof = SparseMerkleTree::new()
+            .prove(&MemNodeStore(&state.nodes), &smt::leaf_path(namespace, key))?;
+        Ok((
+            state.get(namespace, key),
+            StateProof {
+                block_num: state.save_point.map(|h| h.block_num),
+                ..proof
+            },
+        ))
+    }
+
+    fn clear(&self) -> Result<()> {
+        // the index definitions are kept, their entries are rebuilt with the states
+        let mut state = self.write()?;
+        let index_defs = std::mem::take(&mut state.index_defs);
+        *state = MemState {
+            index_defs,
+            ..MemState::default()
+        };
+        Ok(())
+    }
+
+    fn validate_key_value(&self, _key: &str, _value: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    fn bytes_key_supported(&self) -> bool {
+        true
+    }
+
+    // the memory state lives as long as its handles, there is nothing to open or close
+    fn open(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn close(&self) {}
+}
+
+impl IndexCapable for MemDBVersion {
+    fn process_index_definitions(
+        &self,
+        namespace: &str,
+        definitions: Vec<IndexDefinition>,
+    ) -> Result<()> {
+        let mut batch = UpdateBatch::new();
+        batch.define_indexes(namespace, definitions);
+        self.apply_updates(batch, None)
+    }
+
+    fn get_index_definitions(&self, namespace: &str) -> Result<Vec<IndexDefinition>> {
+        Ok(self.read()?.index_definitions(namespace))
+    }
+
+    fn rebuild_indexes(&self, namespace: &str) -> Result<()> {
+        let mut state = self.write()?;
+        let indexes = state.index_definitions(namespace);
+        for index in &indexes {
+            let prefix = encode_index_prefix(namespace, &index.name, &[]);
+            state.index_entries.retain(|k, _| !k.starts_with(&prefix));
+        }
+        for kv in state.range_scan(namespace, "", "", usize::MAX) {
+            state.add_index_entries(
+                &indexes,
+                namespace,
+                kv.composite_key.key(),
+                &kv.versioned_value.value,
+            );
+        }
+        Ok(())
+    }
+}
+
+impl MemDBVersion {
+    fn read(&self) -> Result<RwLockReadGuard<'_, MemState>> {
+        let state = self.state.read().map_err(|e| from_str(&e.to_string()))?;
+        self.check_dropped(&state)?;
+        Ok(state)
+    }
+
+    fn write(&self) -> Result<RwLockWriteGuard<'_, MemState>> {
+        let state = self.state.write().map_err(|e| from_str(&e.to_string()))?;
+        self.check_dropped(&state)?;
+        Ok(state)
+    }
+
+    fn check_dropped(&self, state: &MemState) -> Result<()> {
+        if state.dropped {
+            return Err(from_str(&format!(
+                "the state db of channel {:} has been dropped",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl MemState {
+    fn get(&self, namespace: &str, key: &str) -> Option<VersionedValue> {
+        self.states.get(namespace)?.get(key).cloned()
+    }
+
+    // range_scan returns at most `limit` key-values of a range
+    fn range_scan(
+        &self,
+        namespace: &str,
+        start_key: &str,
+        end_key: &str,
+        limit: usize,
+    ) -> Vec<VersionedKV> {
+        let states = match self.states.get(namespace) {
+            Some(states) => states,
+            None => return vec![],
+        };
+        states
+            .range(start_key.to_string()..)
+            .take_while(|(k, _)| end_key.is_empty() || k.as_str() < end_key)
+            .take(limit)
+            .map(|(k, vv)| VersionedKV {
+                composite_key: CompositeKey::new(namespace, "", k),
+                versioned_value: vv.clone(),
+            })
+            .collect()
+    }
+
+    fn index_definitions(&self, namespace: &str) -> Vec<IndexDefinition> {
+        self.index_defs
+            .get(namespace)
+            .map(|defs| defs.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn add_index_entries(
+        &mut self,
+        indexes: &[IndexDefinition],
+        ns: &str,
+        key: &str,
+        value: &[u8],
+    ) {
+        for index in indexes {
+            if let Some(values) = index.index_values(value) {
+                self.index_entries.insert(
+                    encode_index_key(ns, &index.name, &values, key),
+                    key.to_string(),
+                );
+            }
+        }
+    }
+
+    fn remove_index_entries(
+        &mut self,
+        indexes: &[IndexDefinition],
+        ns: &str,
+        key: &str,
+        value: &[u8],
+    ) {
+        for index in indexes {
+            if let Some(values) = index.index_values(value) {
+                self.index_entries
+                    .remove(&encode_index_key(ns, &index.name, &values, key));
+            }
+        }
+    }
+}
+
+// MemNodeStore reads the nodes of the state tree of a channel
+struct MemNodeStore<'a>(&'a HashMap<(usize, Path), Vec<u8>>);
+
+impl NodeStore for MemNodeStore<'_> {
+    fn get_node(&self, height: usize, path: &Path) -> Result<Option<Vec<u8>>> {
+        Ok(self.0.get(&(height, *path)).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VersionedDBMemProvider;
+
+    fn provider() -> ((), VersionedDBMemProvider) {
+        ((), VersionedDBMemProvider::new())
+    }
+
+    state_db_tests!(provider);
+}
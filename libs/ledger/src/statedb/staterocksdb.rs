@@ -1,73 +1,136 @@
 use dashmap::DashMap;
 use error::*;
-use rocksdb::{WriteBatch, DB};
+use rocksdb::{ColumnFamily, Direction, IteratorMode, Options, WriteBatch, DB};
 
+use super::smt::{self, NodeStore, Path, SparseMerkleTree};
 use super::*;
+use crate::rwset::key::is_pvt_data_ns;
+use byteorder::{BigEndian, WriteBytesExt};
+use std::collections::HashMap;
 use std::iter::Iterator;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
 
 const DATA_KEY_PREFIX: u8 = b'd';
 const NS_KEY_SEP: u8 = 0x00;
 const LAST_KEY_INDICATOR: u8 = 0x01;
 const SAVE_POINT_KEY: u8 = b's';
+const INDEX_KEY_PREFIX: u8 = b'i';
+const INDEX_DEF_KEY_PREFIX: u8 = b'x';
+const MERKLE_NODE_KEY_PREFIX: u8 = b'm';
+const STATE_ROOT_KEY_PREFIX: u8 = b'r';
 
+// DEFAULT_STATE_CACHE_SIZE is the number of states cached per channel by default
+pub const DEFAULT_STATE_CACHE_SIZE: usize = 10_000;
+
+// VersionedDBRocksProvider keeps the state of every channel in its own column family of a
+// shared db, the savepoint of a channel included. Creating or dropping a column family needs
+// the db exclusively, all the other operations share it
 pub struct VersionedDBRocksProvider {
-    db: Arc<DB>,
+    db: Arc<RwLock<DB>>,
     handler: DashMap<String, RocksDBVersion>,
+    cache_size: usize,
 }
 
 impl VersionedDBRocksProvider {
-    pub fn new(path: impl Into<PathBuf>) -> Self {
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self> {
+        VersionedDBRocksProvider::new_with_cache_size(path, DEFAULT_STATE_CACHE_SIZE)
+    }
+
+    // new_with_cache_size returns a provider whose dbs cache up to `cache_size` states each,
+    // a size of 0 disables the cache
+    pub fn new_with_cache_size(path: impl Into<PathBuf>, cache_size: usize) -> Result<Self> {
         let path = path.into().join("version_db");
-        let db = DB::open_default(path).unwrap();
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        // all the existing column families must be opened with the db
+        let cfs = DB::list_cf(&opts, &path).unwrap_or_default();
+        let db = DB::open_cf(&opts, &path, cfs)?;
 
-        VersionedDBRocksProvider {
-            db: Arc::new(db),
+        Ok(VersionedDBRocksProvider {
+            db: Arc::new(RwLock::new(db)),
             handler: DashMap::new(),
-        }
+            cache_size,
+        })
     }
 }
 
 impl VersionedDBProvider for VersionedDBRocksProvider {
     type V = RocksDBVersion;
 
-    fn get_db_handle(&self, id: &str) -> RocksDBVersion {
+    fn get_db_handle(&self, id: &str) -> Result<RocksDBVersion> {
         if !self.handler.contains_key(id) {
+            {
+                let mut db = self.db.write().map_err(|e| from_str(&e.to_string()))?;
+                if db.cf_handle(id).is_none() {
+                    info!("Channel [{}]: creating the state column family", id);
+                    db.create_cf(id, &Options::default())?;
+                }
+            }
             self.handler.insert(
                 id.to_string(),
                 RocksDBVersion {
                     db: self.db.clone(),
                     name: id.to_string(),
+                    cache: if self.cache_size > 0 {
+                        Some(Arc::new(StateCache::new(self.cache_size)))
+                    } else {
+                        None
+                    },
                 },
             );
         }
 
-        let db = self.handler.get(id).unwrap();
+        // the handle is missing when the db was dropped meanwhile
+        let db = self.handler.get(id).ok_or_else(|| {
+            from_str(&format!("the state db of channel {:} has been dropped", id))
+        })?;
         let db = &*db;
-        db.clone()
+        Ok(db.clone())
+    }
+
+    fn drop_db(&self, id: &str) -> Result<()> {
+        let mut db = self.db.write().map_err(|e| from_str(&e.to_string()))?;
+        if let Some((_, handle)) = self.handler.remove(id) {
+            if let Some(cache) = &handle.cache {
+                cache.entries().invalidate();
+            }
+        }
+        if db.cf_handle(id).is_some() {
+            info!("Channel [{}]: dropping the state column family", id);
+            db.drop_cf(id)?;
+        }
+        Ok(())
     }
 }
 
 #[derive(Clone)]
 pub struct RocksDBVersion {
-    db: Arc<DB>,
+    db: Arc<RwLock<DB>>,
     name: String,
+    cache: Option<Arc<StateCache>>,
 }
 
 impl VersionedDB for RocksDBVersion {
     fn get_state(&self, namespace: &str, key: &str) -> Result<Option<VersionedValue>> {
         debug!("get_state(). ns={:}, key={:}", namespace, key);
-        let db_val = self.db.get(encode_data_key(namespace, key))?;
-        if db_val.is_none() {
-            return Ok(None);
-        }
-        let db_val = db_val.unwrap();
-        if db_val.is_empty() {
-            return Ok(None);
-        }
+        let generation = match &self.cache {
+            Some(cache) => match cache.lookup(namespace, key) {
+                Ok(v) => return Ok(v),
+                Err(generation) => Some(generation),
+            },
+            None => None,
+        };
 
-        VersionedValue::decode_value(&db_val).map(Some)
+        let v = {
+            let db = self.db()?;
+            read_state(&db, self.cf(&db)?, namespace, key)?
+        };
+        if let (Some(cache), Some(generation)) = (&self.cache, generation) {
+            cache.fill(namespace, key, v.clone(), generation);
+        }
+        Ok(v)
     }
 
     fn get_version(&self, namespace: &str, key: &str) -> Result<Option<Height>> {
@@ -78,34 +141,201 @@ impl VersionedDB for RocksDBVersion {
 
     fn get_state_multiple_keys(
         &self,
-        _namespace: &str,
-        _keys: Vec<String>,
-    ) -> Result<Vec<VersionedValue>> {
-        unimplemented!()
+        namespace: &str,
+        keys: Vec<String>,
+    ) -> Result<Vec<Option<VersionedValue>>> {
+        // the keys missing from the cache are fetched with a single multi-get
+        let mut values = vec![None; keys.len()];
+        let mut missing = vec![];
+        for (i, key) in keys.iter().enumerate() {
+            match self
+                .cache
+                .as_ref()
+                .map(|cache| cache.lookup(namespace, key))
+            {
+                Some(Ok(v)) => values[i] = v,
+                Some(Err(generation)) => missing.push((i, Some(generation))),
+                None => missing.push((i, None)),
+            }
+        }
+        if missing.is_empty() {
+            return Ok(values);
+        }
+
+        let db = self.db()?;
+        let cf = self.cf(&db)?;
+        let db_vals = db.multi_get_cf(
+            missing
+                .iter()
+                .map(|(i, _)| (cf, encode_data_key(namespace, &keys[*i]))),
+        );
+        for ((i, generation), db_val) in missing.into_iter().zip(db_vals) {
+            let v = decode_state(db_val?)?;
+            if let (Some(cache), Some(generation)) = (&self.cache, generation) {
+                cache.fill(namespace, &keys[i], v.clone(), generation);
+            }
+            values[i] = v;
+        }
+        Ok(values)
     }
 
     fn get_state_range_scan_iterator(
         &self,
-        _namespace: &str,
-        _start_key: &str,
-        _end_key: &str,
+        namespace: &str,
+        start_key: &str,
+        end_key: &str,
     ) -> Result<Box<dyn Iterator<Item = VersionedKV>>> {
-        unimplemented!()
+        let db = self.db()?;
+        let kvs = range_scan(
+            &db,
+            self.cf(&db)?,
+            namespace,
+            start_key,
+            end_key,
+            usize::MAX,
+        )?;
+        Ok(Box::new(kvs.into_iter()))
     }
 
     fn execute_query(
         &self,
-        _namespace: &str,
-        _query: &str,
+        namespace: &str,
+        query: &str,
     ) -> Result<Box<dyn Iterator<Item = VersionedKV>>> {
-        unimplemented!()
+        let page = self.execute_query_page(namespace, query, "", usize::MAX)?;
+        Ok(Box::new(page.kvs.into_iter()))
+    }
+
+    fn get_state_range_scan_page(
+        &self,
+        namespace: &str,
+        start_key: &str,
+        end_key: &str,
+        bookmark: &str,
+        page_size: usize,
+    ) -> Result<VersionedKVPage> {
+        check_page_size(page_size)?;
+        let start_key = if bookmark.is_empty() {
+            start_key.to_string()
+        } else {
+            let key = decode_range_bookmark(bookmark)?;
+            if key.as_str() < start_key || (!end_key.is_empty() && key.as_str() >= end_key) {
+                return Err(from_str("bookmark is out of the range"));
+            }
+            key
+        };
+
+        let db = self.db()?;
+        let mut kvs = range_scan(
+            &db,
+            self.cf(&db)?,
+            namespace,
+            &start_key,
+            end_key,
+            page_size.saturating_add(1),
+        )?;
+        let bookmark = if kvs.len() > page_size {
+            let next = kvs.pop().unwrap();
+            encode_range_bookmark(next.composite_key.key())
+        } else {
+            String::new()
+        };
+        Ok(VersionedKVPage { kvs, bookmark })
+    }
+
+    // the bookmark of a rich query is the db key of the next entry scanned, a data key or an
+    // index entry key depending on the plan
+    fn execute_query_page(
+        &self,
+        namespace: &str,
+        query: &str,
+        bookmark: &str,
+        page_size: usize,
+    ) -> Result<VersionedKVPage> {
+        check_page_size(page_size)?;
+        let query = Query::parse(query)?;
+        let db = self.db()?;
+        let cf = self.cf(&db)?;
+        let plan = query.plan(&index_definitions(&db, cf, namespace)?);
+
+        let prefix = match &plan.index {
+            Some(index) => {
+                debug!(
+                    "execute_query(). ns={:}, using index {:?}",
+                    namespace, index.name
+                );
+                encode_index_prefix(namespace, &index.name, &plan.prefix)
+            }
+            None => {
+                debug!("execute_query(). ns={:}, full namespace scan", namespace);
+                encode_data_key(namespace, "")
+            }
+        };
+        let resume = if bookmark.is_empty() {
+            prefix.clone()
+        } else {
+            match utils::base64::decode(bookmark) {
+                Ok(key) if key.starts_with(&prefix) => key,
+                _ => return Err(from_str("invalid bookmark")),
+            }
+        };
+
+        let mut kvs = vec![];
+        let mut bookmark = String::new();
+        for (k, v) in db.iterator_cf(cf, IteratorMode::From(&resume, Direction::Forward)) {
+            if !k.starts_with(&prefix) {
+                break;
+            }
+            let kv = if plan.index.is_some() {
+                let key = String::from_utf8(v.to_vec())?;
+                match read_state(&db, cf, namespace, &key)? {
+                    Some(vv) => VersionedKV {
+                        composite_key: CompositeKey::new(namespace, "", &key),
+                        versioned_value: vv,
+                    },
+                    None => continue,
+                }
+            } else {
+                let (ns, key) = decode_data_key(k.to_vec());
+                VersionedKV {
+                    composite_key: CompositeKey::new(&ns, "", &key),
+                    versioned_value: VersionedValue::decode_value(&v)?,
+                }
+            };
+            if !query.matches(&kv.versioned_value.value) {
+                continue;
+            }
+            if kvs.len() == page_size {
+                bookmark = utils::base64::encode(&k);
+                break;
+            }
+            kvs.push(kv);
+        }
+        Ok(VersionedKVPage { kvs, bookmark })
     }
 
     fn apply_updates(&self, batch: UpdateBatch, height: Option<Height>) -> Result<()> {
+        let db = self.db()?;
+        let cf = self.cf(&db)?;
         let mut db_batch = WriteBatch::default();
 
+        // the indexes defined by the batch are built in the same write as the updates and the
+        // savepoint, the existing ones are maintained by the updates
+        let mut defined = HashMap::new();
+        for ns in batch.get_indexed_namespaces() {
+            let existing = index_definitions(&db, cf, &ns)?;
+            let definitions = new_index_definitions(&existing, batch.get_index_definitions(&ns))?;
+            if !definitions.is_empty() {
+                defined.insert(ns, definitions);
+            }
+        }
+
         for ns in batch.get_updated_namespaces() {
             if let Some(updates) = batch.get_updates(&ns) {
+                let mut indexes = index_definitions(&db, cf, &ns)?;
+                if let Some(definitions) = defined.get(&ns) {
+                    indexes.retain(|index| definitions.iter().all(|def| def.name != index.name));
+                }
                 for (k, vv) in updates {
                     let data_key = encode_data_key(&ns, &k);
                     debug!(
@@ -113,57 +343,470 @@ impl VersionedDB for RocksDBVersion {
                         self.name, k, data_key
                     );
 
+                    if !indexes.is_empty() {
+                        if let Some(old) = read_state(&db, cf, &ns, &k)? {
+                            remove_index_entries(&mut db_batch, cf, &indexes, &ns, &k, &old.value);
+                        }
+                        add_index_entries(&mut db_batch, cf, &indexes, &ns, &k, &vv.value);
+                    }
+
                     if vv.value.is_empty() {
-                        db_batch.delete(data_key);
+                        db_batch.delete_cf(cf, data_key);
                     } else {
-                        db_batch.put(data_key, vv.encode_value()?);
+                        db_batch.put_cf(cf, data_key, vv.encode_value()?);
                     }
                 }
             }
         }
 
+        for (ns, definitions) in &defined {
+            self.build_indexes(&db, cf, &mut db_batch, ns, definitions, &batch)?;
+        }
+
+        // the private data are left out of the state root, only some peers have them
+        let mut leaves = vec![];
+        for ns in batch.get_updated_namespaces() {
+            if is_pvt_data_ns(&ns) {
+                continue;
+            }
+            if let Some(updates) = batch.get_updates(&ns) {
+                for (k, vv) in updates {
+                    let leaf = if vv.is_delete() {
+                        None
+                    } else {
+                        Some(smt::leaf_hash(&ns, &k, &vv))
+                    };
+                    leaves.push((smt::leaf_path(&ns, &k), leaf));
+                }
+            }
+        }
+        let (root, nodes) =
+            SparseMerkleTree::new().update(&RocksNodeStore { db: &db, cf }, leaves)?;
+        for (h, path, node) in nodes {
+            match node {
+                Some(node) => db_batch.put_cf(cf, encode_merkle_node_key(h, &path), node),
+                None => db_batch.delete_cf(cf, encode_merkle_node_key(h, &path)),
+            }
+        }
+
         if let Some(h) = height {
-            db_batch.put(vec![SAVE_POINT_KEY], h.to_bytes());
+            db_batch.put_cf(cf, encode_state_root_key(h.block_num), root);
+            db_batch.put_cf(cf, vec![SAVE_POINT_KEY], h.to_bytes());
         }
 
-        self.db.write(db_batch)?;
+        match &self.cache {
+            Some(cache) => {
+                // readers wait for the entries while the db and the cache are updated
+                let mut entries = cache.entries();
+                db.write(db_batch)?;
+                for ns in batch.get_updated_namespaces() {
+                    if let Some(updates) = batch.get_updates(&ns) {
+                        for (k, vv) in updates {
+                            entries.update(&ns, &k, &vv);
+                        }
+                    }
+                }
+                entries.bump_generation();
+            }
+            None => db.write(db_batch)?,
+        }
         Ok(())
     }
 
     fn get_latest_save_point(&self) -> Result<Option<Height>> {
-        let bytes = self.db.get(vec![SAVE_POINT_KEY])?;
+        // the savepoint of the first transaction of the genesis block encodes to empty bytes
+        let db = self.db()?;
+        let bytes = db.get_cf(self.cf(&db)?, vec![SAVE_POINT_KEY])?;
         if bytes.is_none() {
             return Ok(None);
         }
-        let bytes = bytes.unwrap();
-        if bytes.is_empty() {
-            return Ok(None);
-        }
 
-        let h = Height::new_from_bytes(&bytes)?;
+        let h = Height::new_from_bytes(&bytes.unwrap())?;
         Ok(Some(h))
     }
 
+    fn get_state_root(&self, block_num: u64) -> Result<Option<Vec<u8>>> {
+        let db = self.db()?;
+        Ok(db.get_cf(self.cf(&db)?, encode_state_root_key(block_num))?)
+    }
+
+    fn get_state_with_proof(
+        &self,
+        namespace: &str,
+        key: &str,
+    ) -> Result<(Option<VersionedValue>, StateProof)> {
+        let db = self.db()?;
+        let cf = self.cf(&db)?;
+        let block_num = match db.get_cf(cf, vec![SAVE_POINT_KEY])? {
+            Some(bytes) => Some(Height::new_from_bytes(&bytes)?.block_num),
+            None => None,
+        };
+        let value = read_state(&db, cf, namespace, key)?;
+        let proof = SparseMerkleTree::new().prove(
+            &RocksNodeStore { db: &db, cf },
+            &smt::leaf_path(namespace, key),
+        )?;
+        Ok((value, StateProof { block_num, ..proof }))
+    }
+
+    fn clear(&self) -> Result<()> {
+        // the index definitions are kept, their entries are rebuilt with the states
+        let db = self.db()?;
+        let cf = self.cf(&db)?;
+        let mut db_batch = WriteBatch::default();
+        for prefix in &[
+            DATA_KEY_PREFIX,
+            INDEX_KEY_PREFIX,
+            MERKLE_NODE_KEY_PREFIX,
+            STATE_ROOT_KEY_PREFIX,
+        ] {
+            for (k, _) in scan_prefix(&db, cf, &[*prefix]) {
+                db_batch.delete_cf(cf, k);
+            }
+        }
+        db_batch.delete_cf(cf, vec![SAVE_POINT_KEY]);
+        db.write(db_batch)?;
+        if let Some(cache) = &self.cache {
+            cache.entries().invalidate();
+        }
+        Ok(())
+    }
+
     fn validate_key_value(&self, _key: &str, _value: &[u8]) -> Result<()> {
         // TODO
         Ok(())
     }
 
+    // the keys are stored as their bytes, rocksdb takes any
     fn bytes_key_supported(&self) -> bool {
-        unimplemented!()
+        true
     }
 
+    // the db is opened by the provider and shared by the channels, a handle only needs the column
+    // family of its channel
     fn open(&self) -> Result<()> {
-        unimplemented!()
+        let db = self.db()?;
+        self.cf(&db)?;
+        Ok(())
     }
 
+    // close releases the cached states, the db is closed with the provider and the last handle
     fn close(&self) {
-        unimplemented!()
+        if let Some(cache) = &self.cache {
+            cache.entries().invalidate();
+        }
     }
 }
 
+impl IndexCapable for RocksDBVersion {
+    fn process_index_definitions(
+        &self,
+        namespace: &str,
+        definitions: Vec<IndexDefinition>,
+    ) -> Result<()> {
+        let mut batch = UpdateBatch::new();
+        batch.define_indexes(namespace, definitions);
+        self.apply_updates(batch, None)
+    }
+
+    fn get_index_definitions(&self, namespace: &str) -> Result<Vec<IndexDefinition>> {
+        let db = self.db()?;
+        index_definitions(&db, self.cf(&db)?, namespace)
+    }
+
+    fn rebuild_indexes(&self, namespace: &str) -> Result<()> {
+        let db = self.db()?;
+        let cf = self.cf(&db)?;
+        let indexes = index_definitions(&db, cf, namespace)?;
+        let mut db_batch = WriteBatch::default();
+
+        let mut prefix = vec![INDEX_KEY_PREFIX];
+        prefix.extend_from_slice(namespace.as_bytes());
+        prefix.push(NS_KEY_SEP);
+        for (k, _) in scan_prefix(&db, cf, &prefix) {
+            db_batch.delete_cf(cf, k);
+        }
+
+        for kv in range_scan(&db, cf, namespace, "", "", usize::MAX)? {
+            add_index_entries(
+                &mut db_batch,
+                cf,
+                &indexes,
+                namespace,
+                kv.composite_key.key(),
+                &kv.versioned_value.value,
+            );
+        }
+
+        db.write(db_batch)?;
+        Ok(())
+    }
+}
+
+impl RocksDBVersion {
+    // build_indexes writes the definitions and the entries of new or redefined indexes of a
+    // namespace, over the committed states overridden by the updates of the batch
+    fn build_indexes(
+        &self,
+        db: &DB,
+        cf: &ColumnFamily,
+        db_batch: &mut WriteBatch,
+        ns: &str,
+        definitions: &[IndexDefinition],
+        batch: &UpdateBatch,
+    ) -> Result<()> {
+        for def in definitions {
+            info!(
+                "Channel [{}]: creating index {:?} on namespace {:}",
+                self.name, def, ns
+            );
+            // a redefined index is rebuilt from scratch
+            for (k, _) in scan_prefix(db, cf, &encode_index_prefix(ns, &def.name, &[])) {
+                db_batch.delete_cf(cf, k);
+            }
+            db_batch.put_cf(cf, encode_index_def_key(ns, &def.name), def.to_json()?);
+        }
+
+        let updates = batch.get_updates(ns).unwrap_or_default();
+        for kv in range_scan(db, cf, ns, "", "", usize::MAX)? {
+            let key = kv.composite_key.key();
+            if !updates.contains_key(key) {
+                add_index_entries(
+                    db_batch,
+                    cf,
+                    definitions,
+                    ns,
+                    key,
+                    &kv.versioned_value.value,
+                );
+            }
+        }
+        for (k, vv) in &updates {
+            add_index_entries(db_batch, cf, definitions, ns, k, &vv.value);
+        }
+        Ok(())
+    }
+
+    // cache_stats returns the hit and miss counters of the state cache, None when it is disabled
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.cache.as_ref().map(|c| c.stats())
+    }
+
+    fn db(&self) -> Result<RwLockReadGuard<'_, DB>> {
+        self.db.read().map_err(|e| from_str(&e.to_string()))
+    }
+
+    // cf returns the column family of the channel, it is missing once the channel is dropped
+    fn cf<'a>(&self, db: &'a DB) -> Result<&'a ColumnFamily> {
+        db.cf_handle(&self.name).ok_or_else(|| {
+            from_str(&format!(
+                "the state db of channel {:} has been dropped",
+                self.name
+            ))
+        })
+    }
+}
+
+// RocksNodeStore reads the nodes of the state tree of a channel
+struct RocksNodeStore<'a> {
+    db: &'a DB,
+    cf: &'a ColumnFamily,
+}
+
+impl NodeStore for RocksNodeStore<'_> {
+    fn get_node(&self, height: usize, path: &Path) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .db
+            .get_cf(self.cf, encode_merkle_node_key(height, path))?)
+    }
+}
+
+// read_state reads a state from the db, bypassing the cache
+fn read_state(
+    db: &DB,
+    cf: &ColumnFamily,
+    namespace: &str,
+    key: &str,
+) -> Result<Option<VersionedValue>> {
+    decode_state(db.get_cf(cf, encode_data_key(namespace, key))?)
+}
+
+// decode_state decodes the value of a data key, None for a missing key
+fn decode_state(db_val: Option<Vec<u8>>) -> Result<Option<VersionedValue>> {
+    match db_val {
+        Some(db_val) if !db_val.is_empty() => VersionedValue::decode_value(&db_val).map(Some),
+        _ => Ok(None),
+    }
+}
+
+// range_scan returns at most `limit` key-values of a range
+fn range_scan(
+    db: &DB,
+    cf: &ColumnFamily,
+    namespace: &str,
+    start_key: &str,
+    end_key: &str,
+    limit: usize,
+) -> Result<Vec<VersionedKV>> {
+    let start = encode_data_key(namespace, start_key);
+    let end = if end_key.is_empty() {
+        encode_ns_end_key(DATA_KEY_PREFIX, namespace)
+    } else {
+        encode_data_key(namespace, end_key)
+    };
+
+    let mut kvs = vec![];
+    for (k, v) in db.iterator_cf(cf, IteratorMode::From(&start, Direction::Forward)) {
+        if k[..] >= end[..] || kvs.len() == limit {
+            break;
+        }
+        let (ns, key) = decode_data_key(k.to_vec());
+        kvs.push(VersionedKV {
+            composite_key: CompositeKey::new(&ns, "", &key),
+            versioned_value: VersionedValue::decode_value(&v)?,
+        });
+    }
+    Ok(kvs)
+}
+
+pub(super) fn check_page_size(page_size: usize) -> Result<()> {
+    if page_size == 0 {
+        return Err(from_str("page size must be greater than 0"));
+    }
+    Ok(())
+}
+
+// new_index_definitions validates the index definitions of a batch and returns those that are not
+// defined yet, the last definition of an index wins
+pub(super) fn new_index_definitions(
+    existing: &[IndexDefinition],
+    definitions: Vec<IndexDefinition>,
+) -> Result<Vec<IndexDefinition>> {
+    let mut defs: Vec<IndexDefinition> = vec![];
+    for def in definitions {
+        def.validate()?;
+        defs.retain(|d| d.name != def.name);
+        defs.push(def);
+    }
+    defs.retain(|def| !existing.contains(def));
+    Ok(defs)
+}
+
+fn index_definitions(db: &DB, cf: &ColumnFamily, namespace: &str) -> Result<Vec<IndexDefinition>> {
+    let prefix = encode_index_def_key(namespace, "");
+    let mut defs = vec![];
+    for (_, v) in scan_prefix(db, cf, &prefix) {
+        defs.push(IndexDefinition::from_json(&v)?);
+    }
+    Ok(defs)
+}
+
+// scan_prefix returns all the entries whose key starts with the given prefix
+fn scan_prefix(db: &DB, cf: &ColumnFamily, prefix: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+    db.iterator_cf(cf, IteratorMode::From(prefix, Direction::Forward))
+        .take_while(|(k, _)| k.starts_with(prefix))
+        .map(|(k, v)| (k.to_vec(), v.to_vec()))
+        .collect()
+}
+
+// add_index_entries writes one entry per index into the batch. The entry value holds the data key
+// so that keys containing the separator byte are recovered as-is
+fn add_index_entries(
+    db_batch: &mut WriteBatch,
+    cf: &ColumnFamily,
+    indexes: &[IndexDefinition],
+    ns: &str,
+    key: &str,
+    value: &[u8],
+) {
+    for index in indexes {
+        if let Some(values) = index.index_values(value) {
+            db_batch.put_cf(cf, encode_index_key(ns, &index.name, &values, key), key);
+        }
+    }
+}
+
+fn remove_index_entries(
+    db_batch: &mut WriteBatch,
+    cf: &ColumnFamily,
+    indexes: &[IndexDefinition],
+    ns: &str,
+    key: &str,
+    value: &[u8],
+) {
+    for index in indexes {
+        if let Some(values) = index.index_values(value) {
+            db_batch.delete_cf(cf, encode_index_key(ns, &index.name, &values, key));
+        }
+    }
+}
+
+pub(super) fn encode_index_prefix(ns: &str, index: &str, values: &[Vec<u8>]) -> Vec<u8> {
+    let mut v: Vec<u8> = Vec::new();
+    v.push(INDEX_KEY_PREFIX);
+    v.extend_from_slice(ns.as_bytes());
+    v.push(NS_KEY_SEP);
+    v.extend_from_slice(index.as_bytes());
+    v.push(NS_KEY_SEP);
+    for value in values {
+        encode_index_value_bytes(&mut v, value);
+    }
+    v
+}
+
+// encode_index_value_bytes appends an indexed value escaped so that no value is a prefix of
+// another one: a 0x00 byte is written 0x00 0xff and the value ends with 0x00 0x01. The escaping
+// keeps the byte order of the values, on which the index scans rely
+fn encode_index_value_bytes(v: &mut Vec<u8>, value: &[u8]) {
+    for b in value {
+        v.push(*b);
+        if *b == 0x00 {
+            v.push(0xff);
+        }
+    }
+    v.push(0x00);
+    v.push(0x01);
+}
+
+pub(super) fn encode_index_key(ns: &str, index: &str, values: &[Vec<u8>], key: &str) -> Vec<u8> {
+    let mut v = encode_index_prefix(ns, index, values);
+    v.extend_from_slice(key.as_bytes());
+    v
+}
+
+fn encode_index_def_key(ns: &str, index: &str) -> Vec<u8> {
+    let mut v: Vec<u8> = Vec::new();
+    v.push(INDEX_DEF_KEY_PREFIX);
+    v.extend_from_slice(ns.as_bytes());
+    v.push(NS_KEY_SEP);
+    v.extend_from_slice(index.as_bytes());
+    v
+}
+
+fn encode_merkle_node_key(height: usize, path: &Path) -> Vec<u8> {
+    let mut v: Vec<u8> = vec![MERKLE_NODE_KEY_PREFIX];
+    let _ = v.write_u16::<BigEndian>(height as u16);
+    v.extend_from_slice(path);
+    v
+}
+
+fn encode_state_root_key(block_num: u64) -> Vec<u8> {
+    let mut v: Vec<u8> = vec![STATE_ROOT_KEY_PREFIX];
+    let _ = v.write_u64::<BigEndian>(block_num);
+    v
+}
+
+// encode_ns_end_key returns the key right after all the keys of a namespace
+fn encode_ns_end_key(prefix: u8, ns: &str) -> Vec<u8> {
+    let mut v: Vec<u8> = Vec::new();
+    v.push(prefix);
+    v.extend_from_slice(ns.as_bytes());
+    v.push(LAST_KEY_INDICATOR);
+    v
+}
+
 #[warn(unused_unsafe)]
-fn encode_data_key(ns: &str, key: &str) -> Vec<u8> {
+pub(super) fn encode_data_key(ns: &str, key: &str) -> Vec<u8> {
     let mut v: Vec<u8> = Vec::new();
     v.push(DATA_KEY_PREFIX);
     unsafe {
@@ -174,7 +817,7 @@ fn encode_data_key(ns: &str, key: &str) -> Vec<u8> {
     v
 }
 
-fn decode_data_key(encoded_data_key: Vec<u8>) -> (String, String) {
+pub(super) fn decode_data_key(encoded_data_key: Vec<u8>) -> (String, String) {
     let mut find = false;
     let mut ns = vec![];
     let mut key = vec![];
@@ -182,7 +825,7 @@ fn decode_data_key(encoded_data_key: Vec<u8>) -> (String, String) {
         if index.eq(&0) {
             continue;
         }
-        if c.eq(&NS_KEY_SEP) {
+        if !find && c.eq(&NS_KEY_SEP) {
             find = true;
             continue;
         }
@@ -202,6 +845,100 @@ fn decode_data_key(encoded_data_key: Vec<u8>) -> (String, String) {
 #[cfg(test)]
 mod tests {
     use super::{decode_data_key, encode_data_key};
+    use crate::statedb::{
+        CacheStats, Height, UpdateBatch, VersionedDB, VersionedDBProvider, VersionedDBRocksProvider,
+    };
+    use tempfile::TempDir;
+
+    fn provider() -> (TempDir, VersionedDBRocksProvider) {
+        let temp_dir = TempDir::new().unwrap();
+        let provider = VersionedDBRocksProvider::new(temp_dir.path()).unwrap();
+        (temp_dir, provider)
+    }
+
+    state_db_tests!(provider);
+
+    #[test]
+    fn test_state_cache() {
+        let temp_dir = TempDir::new().unwrap();
+        let provider = VersionedDBRocksProvider::new(temp_dir.path()).unwrap();
+        let vdb = provider.get_db_handle("chain_id").unwrap();
+
+        let mut batch = UpdateBatch::new();
+        batch.put("ns", "k1", b"v1".to_vec(), Height::new(1, 0));
+        vdb.apply_updates(batch, Some(Height::new(1, 0))).unwrap();
+
+        // the commit updates the cached keys, absent keys are cached too
+        assert_eq!(vdb.get_state("ns", "k1").unwrap().unwrap().value, b"v1");
+        assert!(vdb.get_state("ns", "k2").unwrap().is_none());
+        assert!(vdb.get_state("ns", "k2").unwrap().is_none());
+        assert_eq!(vdb.cache_stats(), Some(CacheStats { hits: 2, misses: 1 }));
+
+        let mut batch = UpdateBatch::new();
+        batch.put("ns", "k2", b"v2".to_vec(), Height::new(2, 0));
+        batch.delete("ns", "k1", Height::new(2, 0));
+        vdb.apply_updates(batch, Some(Height::new(2, 0))).unwrap();
+        assert!(vdb.get_state("ns", "k1").unwrap().is_none());
+        assert_eq!(vdb.get_state("ns", "k2").unwrap().unwrap().value, b"v2");
+
+        vdb.clear().unwrap();
+        assert!(vdb.get_state("ns", "k2").unwrap().is_none());
+        assert_eq!(vdb.cache_stats(), Some(CacheStats { hits: 4, misses: 2 }));
+
+        let provider =
+            VersionedDBRocksProvider::new_with_cache_size(temp_dir.path().join("nocache"), 0)
+                .unwrap();
+        assert!(provider
+            .get_db_handle("chain_id")
+            .unwrap()
+            .cache_stats()
+            .is_none());
+    }
+
+    #[test]
+    fn test_channel_column_families() {
+        let temp_dir = TempDir::new().unwrap();
+        let provider = VersionedDBRocksProvider::new(temp_dir.path()).unwrap();
+        let ch1 = provider.get_db_handle("ch1").unwrap();
+        let ch2 = provider.get_db_handle("ch2").unwrap();
+
+        let mut batch = UpdateBatch::new();
+        batch.put("ns", "k", b"v1".to_vec(), Height::new(1, 0));
+        ch1.apply_updates(batch, Some(Height::new(1, 0))).unwrap();
+        let mut batch = UpdateBatch::new();
+        batch.put("ns", "k", b"v2".to_vec(), Height::new(5, 0));
+        ch2.apply_updates(batch, Some(Height::new(5, 0))).unwrap();
+
+        assert_eq!(ch1.get_state("ns", "k").unwrap().unwrap().value, b"v1");
+        assert_eq!(ch2.get_state("ns", "k").unwrap().unwrap().value, b"v2");
+        assert_eq!(
+            ch1.get_latest_save_point().unwrap(),
+            Some(Height::new(1, 0))
+        );
+        assert_eq!(
+            ch2.get_latest_save_point().unwrap(),
+            Some(Height::new(5, 0))
+        );
+
+        assert!(ch1.bytes_key_supported());
+        ch1.open().unwrap();
+        ch1.close();
+        assert_eq!(ch1.get_state("ns", "k").unwrap().unwrap().value, b"v1");
+
+        provider.drop_db("ch1").unwrap();
+        assert!(ch1.open().is_err());
+        assert!(ch1.get_state("ns", "k").is_err());
+        assert_eq!(ch2.get_state("ns", "k").unwrap().unwrap().value, b"v2");
+        let ch1 = provider.get_db_handle("ch1").unwrap();
+        assert!(ch1.get_state("ns", "k").unwrap().is_none());
+        assert!(ch1.get_latest_save_point().unwrap().is_none());
+
+        // the column families are found again when the db is reopened
+        drop((ch1, ch2, provider));
+        let provider = VersionedDBRocksProvider::new(temp_dir.path()).unwrap();
+        let ch2 = provider.get_db_handle("ch2").unwrap();
+        assert_eq!(ch2.get_state("ns", "k").unwrap().unwrap().value, b"v2");
+    }
 
     #[test]
     fn test_key() {
@@ -209,5 +946,9 @@ mod tests {
         let (ns, key) = decode_data_key(encode_key);
         assert_eq!(ns, "mychain".to_string());
         assert_eq!(key, "kvdb".to_string());
+
+        let (ns, key) = decode_data_key(encode_data_key("ns", "a\u{0}b"));
+        assert_eq!(ns, "ns".to_string());
+        assert_eq!(key, "a\u{0}b".to_string());
     }
 }
@@ -0,0 +1,162 @@
+use error::*;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct LockState {
+    readers: usize,
+    writer: bool,
+    waiting_writers: usize,
+}
+
+struct Inner {
+    state: Mutex<LockState>,
+    cond: Condvar,
+}
+
+impl Inner {
+    fn state(&self) -> MutexGuard<'_, LockState> {
+        // the state is only made of counters, it stays consistent even if a holder panicked
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// CommitLock is the reader/writer lock between the readers of the state (simulators and query
+// executors) and the commit of a block. Its guards are owned so that a reader can hold the lock
+// for its whole lifetime. Waiting writers block new readers, so that a stream of simulations
+// can not starve the commits
+#[derive(Clone)]
+pub struct CommitLock {
+    inner: Arc<Inner>,
+    timeout: Duration,
+}
+
+impl CommitLock {
+    // new returns a lock whose acquisitions fail after waiting for `timeout`
+    pub fn new(timeout: Duration) -> Self {
+        CommitLock {
+            inner: Arc::new(Inner {
+                state: Mutex::new(LockState::default()),
+                cond: Condvar::new(),
+            }),
+            timeout,
+        }
+    }
+
+    pub fn read(&self) -> Result<ReadGuard> {
+        let deadline = Instant::now() + self.timeout;
+        let mut state = self.inner.state();
+        while state.writer || state.waiting_writers > 0 {
+            state = self.wait(state, deadline, "read")?;
+        }
+        state.readers += 1;
+        Ok(ReadGuard {
+            inner: self.inner.clone(),
+        })
+    }
+
+    pub fn write(&self) -> Result<WriteGuard> {
+        let deadline = Instant::now() + self.timeout;
+        let mut state = self.inner.state();
+        state.waiting_writers += 1;
+        while state.writer || state.readers > 0 {
+            state = match self.wait(state, deadline, "write") {
+                Ok(state) => state,
+                Err(e) => {
+                    let mut state = self.inner.state();
+                    state.waiting_writers -= 1;
+                    self.inner.cond.notify_all();
+                    return Err(e);
+                }
+            };
+        }
+        state.waiting_writers -= 1;
+        state.writer = true;
+        Ok(WriteGuard {
+            inner: self.inner.clone(),
+        })
+    }
+
+    fn wait<'a>(
+        &self,
+        state: MutexGuard<'a, LockState>,
+        deadline: Instant,
+        kind: &str,
+    ) -> Result<MutexGuard<'a, LockState>> {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(from_str(&format!(
+                "timeout acquiring the commit {:} lock after {:?}",
+                kind, self.timeout
+            )));
+        }
+        let (state, _) = self
+            .inner
+            .cond
+            .wait_timeout(state, deadline - now)
+            .unwrap_or_else(|e| e.into_inner());
+        Ok(state)
+    }
+}
+
+// ReadGuard releases the read side of the commit lock when dropped
+pub struct ReadGuard {
+    inner: Arc<Inner>,
+}
+
+impl Drop for ReadGuard {
+    fn drop(&mut self) {
+        self.inner.state().readers -= 1;
+        self.inner.cond.notify_all();
+    }
+}
+
+// WriteGuard releases the write side of the commit lock when dropped
+pub struct WriteGuard {
+    inner: Arc<Inner>,
+}
+
+impl Drop for WriteGuard {
+    fn drop(&mut self) {
+        self.inner.state().writer = false;
+        self.inner.cond.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CommitLock;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_commit_lock() {
+        let lock = CommitLock::new(Duration::from_millis(50));
+
+        let r1 = lock.read().unwrap();
+        let r2 = lock.read().unwrap();
+        assert!(lock.write().is_err());
+        drop(r1);
+        drop(r2);
+
+        let w = lock.write().unwrap();
+        assert!(lock.read().is_err());
+        assert!(lock.write().is_err());
+        drop(w);
+        let _r = lock.read().unwrap();
+    }
+
+    #[test]
+    fn test_writer_waits_for_readers() {
+        let lock = CommitLock::new(Duration::from_secs(5));
+        let r = lock.read().unwrap();
+
+        let writer = {
+            let lock = lock.clone();
+            thread::spawn(move || lock.write().map(|_| ()).is_ok())
+        };
+        thread::sleep(Duration::from_millis(20));
+        drop(r);
+        assert!(writer.join().unwrap());
+    }
+}
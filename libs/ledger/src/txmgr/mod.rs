@@ -1,19 +1,201 @@
+use crate::lifecycle;
+use crate::pvtdata::{self, transient::TransientStore};
+use crate::rwset::parallel::ParallelValidator;
+use crate::rwset::policy::ChannelPolicies;
+use crate::rwset::validate::TxCodes;
+use crate::simulator::sim::BasedTxSimulator;
 use crate::simulator::TxSimulator;
-use crate::statedb::{Height, VersionedDB};
+use crate::statedb::{Height, IndexCapable, UpdateBatch, VersionedDB};
+use crate::QueryExecutor;
 use error::*;
-use silk_proto::Block;
+use silk_proto::{Block, ChannelConfig};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub mod lock;
+pub mod query_executor;
+
+use lock::{CommitLock, WriteGuard};
+use query_executor::LockBasedQueryExecutor;
+
+// DEFAULT_LOCK_TIMEOUT is how long the acquisition of the commit lock waits by default
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub trait TxMgr {
     type T: TxSimulator;
-    fn new_tx_simulator(txid: String) -> Result<Self::T>;
-    fn validate_and_prepare(block: Block) -> Result<()>;
-    fn get_last_savepoint() -> Result<Height>;
-    fn should_recover(last_available_block: u64) -> Result<(bool, u64)>;
-    fn commit() -> Result<()>;
+    type Q: QueryExecutor;
+    // new_tx_simulator returns a simulator over the committed state. The simulator holds the
+    // read side of the commit lock until it is done or dropped
+    fn new_tx_simulator(&self, txid: String) -> Result<Self::T>;
+    // new_query_executor returns a query executor over the committed state. The executor holds
+    // the read side of the commit lock until it is done or dropped
+    fn new_query_executor(&self) -> Result<Self::Q>;
+    // validate_and_prepare validates the transactions of a block and keeps the resulting updates
+    // until commit is called. The write side of the commit lock is held from here until commit
+    // or discard. The validation code of each transaction is returned
+    fn validate_and_prepare(&self, block: &Block) -> Result<TxCodes>;
+    // get_last_savepoint returns the height of the last committed block, None if no block has
+    // been committed yet
+    fn get_last_savepoint(&self) -> Result<Option<Height>>;
+    // should_recover tells whether the state lags behind `last_available_block` of the block
+    // store and, if so, the number of the first block to re-apply
+    fn should_recover(&self, last_available_block: u64) -> Result<(bool, u64)>;
+    // get_state_root returns the root of the state tree once the given block was committed
+    fn get_state_root(&self, block_num: u64) -> Result<Option<Vec<u8>>>;
+    // commit applies the updates prepared by the last validate_and_prepare
+    fn commit(&self) -> Result<()>;
+    // discard drops the updates prepared by the last validate_and_prepare
+    fn discard(&self);
+}
+
+struct Prepared {
+    batch: UpdateBatch,
+    height: Height,
+    // the transactions of the block, their private rwsets are purged from the transient store
+    tx_ids: Vec<String>,
+    _commit_lock: WriteGuard,
 }
 
 pub struct LockBasedTxMgr<V: VersionedDB> {
     vdb: V,
+    // the endorsement policies of the channel, set from its config
+    policies: Mutex<Option<Arc<ChannelPolicies>>>,
+    commit_lock: CommitLock,
+    current: Mutex<Option<Prepared>>,
+    // the private rwsets of the transactions simulated by the peer
+    transient_store: Option<Arc<TransientStore>>,
+}
+
+impl<V: VersionedDB + Clone + Sync> LockBasedTxMgr<V> {
+    // new returns a transaction manager whose commit lock acquisitions fail after `lock_timeout`.
+    // Blocks are validated with one worker per cpu
+    pub fn new(vdb: V, lock_timeout: Duration) -> Self {
+        LockBasedTxMgr {
+            vdb,
+            policies: Mutex::new(None),
+            commit_lock: CommitLock::new(lock_timeout),
+            current: Mutex::new(None),
+            transient_store: None,
+        }
+    }
+
+    // with_transient_store makes the private writes of the valid transactions committed along
+    // with the public ones, from the private rwsets kept in the transient store
+    pub fn with_transient_store(mut self, transient_store: Arc<TransientStore>) -> Self {
+        self.transient_store = Some(transient_store);
+        self
+    }
+
+    // set_channel_config makes the endorsements of the next blocks checked against the
+    // endorsement policies of the channel config. Until then no transaction is valid
+    pub fn set_channel_config(&self, config: &ChannelConfig) -> Result<()> {
+        let policies = ChannelPolicies::from_config(config)?;
+        *self.policies.lock().map_err(|e| from_str(&e.to_string()))? = Some(Arc::new(policies));
+        Ok(())
+    }
+}
+
+impl<V: VersionedDB + IndexCapable + Clone + Sync> TxMgr for LockBasedTxMgr<V> {
+    type T = BasedTxSimulator<V>;
+    type Q = LockBasedQueryExecutor<V>;
+
+    fn new_tx_simulator(&self, txid: String) -> Result<Self::T> {
+        let commit_lock = self.commit_lock.read()?;
+        Ok(BasedTxSimulator::new_with_lock(
+            txid,
+            self.vdb.clone(),
+            commit_lock,
+        ))
+    }
+
+    fn new_query_executor(&self) -> Result<Self::Q> {
+        let commit_lock = self.commit_lock.read()?;
+        Ok(LockBasedQueryExecutor::new(self.vdb.clone(), commit_lock))
+    }
+
+    fn validate_and_prepare(&self, block: &Block) -> Result<TxCodes> {
+        let commit_lock = self.commit_lock.write()?;
+        // a genesis block carries the config of the channel
+        if block.header.as_ref().map(|h| h.number) == Some(0) {
+            if let Some(config) = utils::utils::get_channel_config_from_block(block)? {
+                self.set_channel_config(&config)?;
+            }
+        }
+        let mut validator = ParallelValidator::new(self.vdb.clone(), num_cpus::get());
+        if let Some(policies) = self
+            .policies
+            .lock()
+            .map_err(|e| from_str(&e.to_string()))?
+            .clone()
+        {
+            validator = validator.with_policies(policies);
+        }
+        let (mut batch, height, tx_codes) = validator.validate_and_prepare_batch(block.clone())?;
+        if let Some(transient_store) = &self.transient_store {
+            batch.merge(pvtdata::prepare_pvt_updates(
+                block,
+                &tx_codes,
+                transient_store,
+            )?);
+        }
+        *self.current.lock().map_err(|e| from_str(&e.to_string()))? = Some(Prepared {
+            batch,
+            height,
+            tx_ids: tx_codes.tx_ids().cloned().collect(),
+            _commit_lock: commit_lock,
+        });
+        Ok(tx_codes)
+    }
+
+    fn get_last_savepoint(&self) -> Result<Option<Height>> {
+        self.vdb.get_latest_save_point()
+    }
+
+    fn should_recover(&self, last_available_block: u64) -> Result<(bool, u64)> {
+        match self.get_last_savepoint()? {
+            Some(savepoint) => Ok((
+                savepoint.block_num != last_available_block,
+                savepoint.block_num + 1,
+            )),
+            None => Ok((true, 0)),
+        }
+    }
+
+    fn get_state_root(&self, block_num: u64) -> Result<Option<Vec<u8>>> {
+        self.vdb.get_state_root(block_num)
+    }
+
+    fn commit(&self) -> Result<()> {
+        // the commit lock is released once the prepared updates are dropped
+        let prepared = self
+            .current
+            .lock()
+            .map_err(|e| from_str(&e.to_string()))?
+            .take()
+            .ok_or_else(|| from_str("validate_and_prepare must be called before commit"))?;
+        let mut batch = prepared.batch;
+        // the indexes of the contracts defined by the block are built along with its updates
+        for definition in lifecycle::updated_definitions(&batch)? {
+            match lifecycle::index_definitions(&definition) {
+                Ok(indexes) => batch.define_indexes(&definition.name, indexes),
+                Err(e) => warn!(
+                    "skip the indexes of version {:} of contract {:}: {:}",
+                    definition.version, definition.name, e
+                ),
+            }
+        }
+        self.vdb.apply_updates(batch, Some(prepared.height))?;
+        match &self.transient_store {
+            Some(transient_store) => transient_store.purge_by_txids(&prepared.tx_ids),
+            None => Ok(()),
+        }
+    }
+
+    fn discard(&self) {
+        if let Ok(mut current) = self.current.lock() {
+            current.take();
+        }
+    }
 }
 
 #[cfg(test)]
@@ -21,20 +203,40 @@ mod tests {
     use error::*;
     use silk_proto::*;
 
+    use crate::lifecycle::{self, definition_key, LIFECYCLE_NS};
+    use crate::pvtdata::collection::{
+        encode_collection_configs, CollectionConfig, COLLECTION_CONFIG_NS,
+    };
+    use crate::pvtdata::transient::TransientStore;
+    use crate::rwset::key::derive_pvt_data_ns;
+    use crate::rwset::parallel::ParallelValidator;
+    use crate::rwset::policy::tests::{channel, config_tx, invoke};
+    use crate::rwset::policy::ChannelPolicies;
     use crate::rwset::validate::Validator;
     use crate::simulator::sim::BasedTxSimulator;
     use crate::simulator::TxSimulator;
     use crate::statedb::{
-        Height, VersionedDB, VersionedDBProvider, VersionedDBRocksProvider, VersionedValue,
+        Height, IndexCapable, UpdateBatch, VersionedDB, VersionedDBProvider,
+        VersionedDBRocksProvider, VersionedValue,
     };
+    use crate::txmgr::{LockBasedTxMgr, TxMgr};
+    use crate::QueryExecutor;
+    use identity::cert::{cert_params, create_ca_cert, create_cert, Register};
+    use identity::signer::SigningIdentity;
+    use std::sync::Arc;
+    use std::time::Duration;
     use tempfile::TempDir;
+    use utils::composite_key::create_composite_key;
 
     #[test]
     fn it_works() {
         let temp_dir = TempDir::new().unwrap();
-        let provider = VersionedDBRocksProvider::new(temp_dir.into_path());
-        let vdb = provider.get_db_handle("chain_id");
-        let validate = Validator::new(vdb.clone());
+        let provider = VersionedDBRocksProvider::new(temp_dir.into_path()).unwrap();
+        let vdb = provider.get_db_handle("chain_id").unwrap();
+        let (config, signers) = channel(&["org1"]);
+        let peer = &signers[0];
+        let policies = Arc::new(ChannelPolicies::from_config(&config).unwrap());
+        let validate = Validator::new(vdb.clone()).with_policies(policies.clone());
 
         let mut sim = BasedTxSimulator::new("tx1".to_string(), vdb.clone());
         sim.set_state(
@@ -44,7 +246,7 @@ mod tests {
         )
         .unwrap();
         let results = sim.get_tx_simulation_results().unwrap();
-        let tx = create_tx(results.simulation_results, "tx1".to_string()).unwrap();
+        let tx = invoke("tx1", "contract_name", &results.simulation_results, &[peer]);
         let block = create_block(vec![tx], 1);
 
         let (batch, h, _tx_code) = validate.validate_and_prepare_batch(block).unwrap();
@@ -131,9 +333,12 @@ mod tests {
     #[test]
     fn test_mvcc() {
         let temp_dir = TempDir::new().unwrap();
-        let provider = VersionedDBRocksProvider::new(temp_dir.into_path());
-        let vdb = provider.get_db_handle("chain_id");
-        let validate = Validator::new(vdb.clone());
+        let provider = VersionedDBRocksProvider::new(temp_dir.into_path()).unwrap();
+        let vdb = provider.get_db_handle("chain_id").unwrap();
+        let (config, signers) = channel(&["org1"]);
+        let peer = &signers[0];
+        let policies = Arc::new(ChannelPolicies::from_config(&config).unwrap());
+        let validate = Validator::new(vdb.clone()).with_policies(policies.clone());
 
         {
             let mut sim = BasedTxSimulator::new("tx0".to_string(), vdb.clone());
@@ -145,7 +350,7 @@ mod tests {
                 .unwrap();
             let results = sim.get_tx_simulation_results().unwrap();
 
-            let tx = create_tx(results.simulation_results, "tx0".to_string()).unwrap();
+            let tx = invoke("tx0", "ns", &results.simulation_results, &[peer]);
             let block = create_block(vec![tx], 1);
 
             let (batch, h, _) = validate.validate_and_prepare_batch(block).unwrap();
@@ -164,7 +369,7 @@ mod tests {
                     .unwrap();
                 let results = sim.get_tx_simulation_results().unwrap();
 
-                create_tx(results.simulation_results, "tx1".to_string()).unwrap()
+                invoke("tx1", "ns", &results.simulation_results, &[peer])
             };
             let tx2 = {
                 let mut sim = BasedTxSimulator::new("tx2".to_string(), vdb.clone());
@@ -175,7 +380,7 @@ mod tests {
                     .unwrap();
                 let results = sim.get_tx_simulation_results().unwrap();
 
-                create_tx(results.simulation_results, "tx2".to_string()).unwrap()
+                invoke("tx2", "ns", &results.simulation_results, &[peer])
             };
 
             let block = create_block(vec![tx1, tx2], 2);
@@ -206,4 +411,741 @@ mod tests {
             )
         }
     }
+
+    #[test]
+    fn test_commit_lock() {
+        let temp_dir = TempDir::new().unwrap();
+        let provider = VersionedDBRocksProvider::new(temp_dir.path()).unwrap();
+        let vdb = provider.get_db_handle("chain_id").unwrap();
+        let (config, signers) = channel(&["org1"]);
+        let peer = &signers[0];
+        let txmgr = LockBasedTxMgr::new(vdb, Duration::from_millis(50));
+        txmgr.set_channel_config(&config).unwrap();
+
+        let mut sim = txmgr.new_tx_simulator("tx1".to_string()).unwrap();
+        sim.set_state("ns", "k1", Vec::from("v1")).unwrap();
+        let results = sim.get_tx_simulation_results().unwrap();
+        let block = create_block(
+            vec![invoke("tx1", "ns", &results.simulation_results, &[peer])],
+            1,
+        );
+
+        // a running simulation holds back the commit
+        assert!(txmgr.validate_and_prepare(&block).is_err());
+        sim.done();
+
+        let tx_codes = txmgr.validate_and_prepare(&block).unwrap();
+        assert_eq!(tx_codes.get("tx1"), Some(&TxValidationCode::Valid));
+        // and a pending commit holds back new simulations
+        assert!(txmgr.new_query_executor().is_err());
+        txmgr.commit().unwrap();
+
+        let mut qe = txmgr.new_query_executor().unwrap();
+        assert_eq!(qe.get_state("ns", "k1").unwrap(), Vec::from("v1"));
+        qe.done();
+        assert_eq!(txmgr.get_last_savepoint().unwrap(), Some(Height::new(1, 0)));
+    }
+
+    #[test]
+    fn test_define_indexes() {
+        let temp_dir = TempDir::new().unwrap();
+        let provider = VersionedDBRocksProvider::new(temp_dir.path()).unwrap();
+        let vdb = provider.get_db_handle("chain_id").unwrap();
+        let (config, signers) = channel(&["org1"]);
+        let peer = &signers[0];
+        let txmgr = LockBasedTxMgr::new(vdb.clone(), Duration::from_millis(50));
+        txmgr.set_channel_config(&config).unwrap();
+
+        let definition = ContractDefinition {
+            name: "cc".to_string(),
+            version: "1".to_string(),
+            package_hash: vec![1],
+            indexes: vec![br#"{"name":"owner","fields":["owner"]}"#.to_vec()],
+            ..Default::default()
+        };
+        let mut sim = txmgr.new_tx_simulator("tx1".to_string()).unwrap();
+        sim.set_state("cc", "k1", br#"{"owner":"alice"}"#.to_vec())
+            .unwrap();
+        let results = sim.get_tx_simulation_results().unwrap();
+        sim.done();
+        let tx1 = invoke("tx1", "cc", &results.simulation_results, &[peer]);
+        let mut sim = txmgr.new_tx_simulator("tx2".to_string()).unwrap();
+        sim.set_state(
+            LIFECYCLE_NS,
+            &definition_key("cc"),
+            utils::proto::marshal(&definition).unwrap(),
+        )
+        .unwrap();
+        let results = sim.get_tx_simulation_results().unwrap();
+        sim.done();
+        let tx2 = invoke("tx2", LIFECYCLE_NS, &results.simulation_results, &[peer]);
+        let block = create_block(vec![tx1, tx2], 1);
+        txmgr.validate_and_prepare(&block).unwrap();
+        txmgr.commit().unwrap();
+
+        // the indexes of the definition are built over the state committed with it
+        assert_eq!(
+            vdb.get_index_definitions("cc").unwrap(),
+            lifecycle::index_definitions(&definition).unwrap()
+        );
+        let keys: Vec<String> = vdb
+            .execute_query("cc", r#"{"selector":{"owner":"alice"}}"#)
+            .unwrap()
+            .map(|kv| kv.composite_key.key().to_string())
+            .collect();
+        assert_eq!(keys, vec!["k1"]);
+    }
+
+    #[test]
+    fn test_pvt_data() {
+        let temp_dir = TempDir::new().unwrap();
+        let provider = VersionedDBRocksProvider::new(temp_dir.path()).unwrap();
+        let vdb = provider.get_db_handle("chain_id").unwrap();
+        let (config, signers) = channel(&["org1"]);
+        let peer = &signers[0];
+        let transient_store = Arc::new(TransientStore::new(temp_dir.path()).unwrap());
+        let txmgr = LockBasedTxMgr::new(vdb.clone(), Duration::from_millis(50))
+            .with_transient_store(transient_store.clone());
+        txmgr.set_channel_config(&config).unwrap();
+
+        let configs = vec![CollectionConfig::new("coll", vec!["org1".to_string()])];
+        let mut batch = UpdateBatch::new();
+        batch.put(
+            COLLECTION_CONFIG_NS,
+            "cc",
+            encode_collection_configs(&configs).unwrap(),
+            Height::new(0, 0),
+        );
+        vdb.apply_updates(batch, Some(Height::new(0, 0))).unwrap();
+
+        let mut sim = txmgr.new_tx_simulator("tx1".to_string()).unwrap();
+        sim.set_private_data("cc", "coll", "k", b"secret".to_vec())
+            .unwrap();
+        let results = sim.get_tx_simulation_results().unwrap();
+        sim.done();
+        transient_store
+            .persist("tx1", 1, results.pvt_simulation_results.as_ref().unwrap())
+            .unwrap();
+        let block = create_block(
+            vec![invoke("tx1", "cc", &results.simulation_results, &[peer])],
+            1,
+        );
+        txmgr.validate_and_prepare(&block).unwrap();
+        txmgr.commit().unwrap();
+
+        // the private write is committed and the private rwset is purged
+        let vv = vdb
+            .get_state(&derive_pvt_data_ns("cc", "coll"), "k")
+            .unwrap()
+            .unwrap();
+        assert_eq!(vv.value, b"secret".to_vec());
+        assert!(transient_store.get_tx_pvt_rwset("tx1").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_phantom_read() {
+        let temp_dir = TempDir::new().unwrap();
+        let provider = VersionedDBRocksProvider::new(temp_dir.path()).unwrap();
+        let vdb = provider.get_db_handle("chain_id").unwrap();
+        let (config, signers) = channel(&["org1"]);
+        let peer = &signers[0];
+        let policies = Arc::new(ChannelPolicies::from_config(&config).unwrap());
+        let asset = |owner: &str, id: &str| create_composite_key("asset", &[owner, id]).unwrap();
+
+        let mut sim = BasedTxSimulator::new("tx0".to_string(), vdb.clone());
+        sim.set_state("ns", &asset("alice", "a1"), Vec::from("car"))
+            .unwrap();
+        sim.set_state("ns", &asset("bob", "b1"), Vec::from("bike"))
+            .unwrap();
+        let results = sim.get_tx_simulation_results().unwrap();
+        let block = create_block(
+            vec![invoke("tx0", "ns", &results.simulation_results, &[peer])],
+            1,
+        );
+        let (batch, h, _) = Validator::new(vdb.clone())
+            .with_policies(policies.clone())
+            .validate_and_prepare_batch(block)
+            .unwrap();
+        vdb.apply_updates(batch, Some(h)).unwrap();
+
+        // count_assets counts the assets of an owner and records the count under another key
+        let count_assets = |tx_id: &str, owner: &str| {
+            let mut sim = BasedTxSimulator::new(tx_id.to_string(), vdb.clone());
+            let count = sim
+                .get_state_by_partial_composite_key("ns", "asset", &[owner])
+                .unwrap()
+                .count();
+            sim.set_state(
+                "ns",
+                &format!("count_{:}", owner),
+                count.to_string().into_bytes(),
+            )
+            .unwrap();
+            let results = sim.get_tx_simulation_results().unwrap();
+            invoke(tx_id, "ns", &results.simulation_results, &[peer])
+        };
+        let add_asset = |tx_id: &str, owner: &str, id: &str| {
+            let mut sim = BasedTxSimulator::new(tx_id.to_string(), vdb.clone());
+            sim.set_state("ns", &asset(owner, id), Vec::from("boat"))
+                .unwrap();
+            let results = sim.get_tx_simulation_results().unwrap();
+            invoke(tx_id, "ns", &results.simulation_results, &[peer])
+        };
+
+        let mut sim = BasedTxSimulator::new("tx".to_string(), vdb.clone());
+        let keys = sim
+            .get_state_by_partial_composite_key("ns", "asset", &["alice"])
+            .unwrap()
+            .map(|kv| kv.key)
+            .collect::<Vec<String>>();
+        assert_eq!(keys, vec![asset("alice", "a1")]);
+
+        // tx2 and tx3 were simulated before tx1 added an asset of alice
+        let block = create_block(
+            vec![
+                add_asset("tx1", "alice", "a2"),
+                count_assets("tx2", "alice"),
+                count_assets("tx3", "bob"),
+            ],
+            2,
+        );
+        let (batch, h, tx_codes) = Validator::new(vdb.clone())
+            .with_policies(policies.clone())
+            .validate_and_prepare_batch(block.clone())
+            .unwrap();
+        let (_, _, par_tx_codes) = ParallelValidator::new(vdb.clone(), 2)
+            .with_policies(policies.clone())
+            .validate_and_prepare_batch(block)
+            .unwrap();
+        assert_eq!(tx_codes.get("tx1"), Some(&TxValidationCode::Valid));
+        assert_eq!(
+            tx_codes.get("tx2"),
+            Some(&TxValidationCode::PhantomReadConflict)
+        );
+        assert_eq!(tx_codes.get("tx3"), Some(&TxValidationCode::Valid));
+        assert_eq!(tx_codes, par_tx_codes);
+
+        // a simulation of the committed state is invalidated by a later commit in its range
+        let tx4 = count_assets("tx4", "bob");
+        vdb.apply_updates(batch, Some(h)).unwrap();
+        let tx5 = add_asset("tx5", "bob", "b2");
+        let block = create_block(vec![tx5], 3);
+        let (batch, h, _) = Validator::new(vdb.clone())
+            .with_policies(policies.clone())
+            .validate_and_prepare_batch(block)
+            .unwrap();
+        vdb.apply_updates(batch, Some(h)).unwrap();
+
+        let block = create_block(vec![tx4, count_assets("tx6", "alice")], 4);
+        let (_, _, tx_codes) = Validator::new(vdb.clone())
+            .with_policies(policies.clone())
+            .validate_and_prepare_batch(block.clone())
+            .unwrap();
+        let (_, _, par_tx_codes) = ParallelValidator::new(vdb.clone(), 2)
+            .with_policies(policies.clone())
+            .validate_and_prepare_batch(block)
+            .unwrap();
+        assert_eq!(
+            tx_codes.get("tx4"),
+            Some(&TxValidationCode::PhantomReadConflict)
+        );
+        assert_eq!(tx_codes.get("tx6"), Some(&TxValidationCode::Valid));
+        assert_eq!(tx_codes, par_tx_codes);
+    }
+
+    #[test]
+    fn test_paginated_range_query() {
+        let temp_dir = TempDir::new().unwrap();
+        let provider = VersionedDBRocksProvider::new(temp_dir.path()).unwrap();
+        let vdb = provider.get_db_handle("chain_id").unwrap();
+        let (config, signers) = channel(&["org1"]);
+        let peer = &signers[0];
+        let policies = Arc::new(ChannelPolicies::from_config(&config).unwrap());
+
+        let mut sim = BasedTxSimulator::new("tx0".to_string(), vdb.clone());
+        for k in &["k1", "k3", "k5"] {
+            sim.set_state("ns", k, Vec::from("v")).unwrap();
+        }
+        let results = sim.get_tx_simulation_results().unwrap();
+        let block = create_block(
+            vec![invoke("tx0", "ns", &results.simulation_results, &[peer])],
+            1,
+        );
+        let (batch, h, _) = Validator::new(vdb.clone())
+            .with_policies(policies.clone())
+            .validate_and_prepare_batch(block)
+            .unwrap();
+        vdb.apply_updates(batch, Some(h)).unwrap();
+
+        // read_pages reads the first pages of the range and records the number of keys read
+        let read_pages = |tx_id: &str, pages: usize| {
+            let mut sim = BasedTxSimulator::new(tx_id.to_string(), vdb.clone());
+            let mut bookmark = String::new();
+            let mut count = 0;
+            for _ in 0..pages {
+                let page = sim
+                    .get_state_range_scan_iterator_with_pagination("ns", "", "", &bookmark, 1)
+                    .unwrap();
+                count += page.results.len();
+                bookmark = page.bookmark;
+            }
+            sim.set_state("ns", "count", count.to_string().into_bytes())
+                .unwrap();
+            let results = sim.get_tx_simulation_results().unwrap();
+            invoke(tx_id, "ns", &results.simulation_results, &[peer])
+        };
+        let put = |tx_id: &str, key: &str| {
+            let mut sim = BasedTxSimulator::new(tx_id.to_string(), vdb.clone());
+            sim.set_state("ns", key, Vec::from("v")).unwrap();
+            let results = sim.get_tx_simulation_results().unwrap();
+            invoke(tx_id, "ns", &results.simulation_results, &[peer])
+        };
+
+        // the first two pages read k1 and k3 and end before k5, a key after it is not read
+        let block = create_block(
+            vec![put("tx1", "k6"), read_pages("tx2", 2), put("tx3", "k2")],
+            2,
+        );
+        let (batch, h, tx_codes) = Validator::new(vdb.clone())
+            .with_policies(policies.clone())
+            .validate_and_prepare_batch(block)
+            .unwrap();
+        assert_eq!(tx_codes.get("tx2"), Some(&TxValidationCode::Valid));
+        vdb.apply_updates(batch, Some(h)).unwrap();
+
+        let tx4 = read_pages("tx4", 2);
+        // the first two pages now read count and k1 and end before k2, a key inserted before k2 is a phantom
+        let block = create_block(vec![put("tx5", "k11"), tx4], 3);
+        let (_, _, tx_codes) = Validator::new(vdb.clone())
+            .with_policies(policies.clone())
+            .validate_and_prepare_batch(block.clone())
+            .unwrap();
+        let (_, _, par_tx_codes) = ParallelValidator::new(vdb.clone(), 2)
+            .with_policies(policies.clone())
+            .validate_and_prepare_batch(block)
+            .unwrap();
+        assert_eq!(
+            tx_codes.get("tx4"),
+            Some(&TxValidationCode::PhantomReadConflict)
+        );
+        assert_eq!(tx_codes, par_tx_codes);
+    }
+
+    // endorse makes the transaction invoke a contract, endorsed by the signers with one response each
+    fn endorse(mut tx: Transaction, contract: &str, signers: &[&SigningIdentity]) -> Transaction {
+        let sp = tx.signed_proposal.as_mut().unwrap();
+        let mut proposal: Proposal = utils::proto::unmarshal(&sp.proposal_bytes).unwrap();
+        proposal.payload = utils::proto::marshal(&ContractProposalPayload {
+            contract_id: Some(ContractId {
+                name: contract.to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+        sp.proposal_bytes = utils::proto::marshal(&proposal).unwrap();
+        let resp = tx.response.pop().unwrap();
+        tx.response = signers
+            .iter()
+            .map(|signer| ProposalResponse {
+                endorsement: Some(signer.endorse(&resp.payload).unwrap()),
+                ..resp.clone()
+            })
+            .collect();
+        tx
+    }
+
+    #[test]
+    fn test_key_endorsement_policy() {
+        let temp_dir = TempDir::new().unwrap();
+        let provider = VersionedDBRocksProvider::new(temp_dir.path()).unwrap();
+        let vdb = provider.get_db_handle("chain_id").unwrap();
+
+        let (mut config, signers) = channel(&["org1", "org2", "org3"]);
+        let (org1, org2, org3) = (&signers[0], &signers[1], &signers[2]);
+        // the contract alone needs org3
+        config
+            .endorsement_policies
+            .insert("ns".to_string(), "'org3.peer'".to_string());
+        let policies = Arc::new(ChannelPolicies::from_config(&config).unwrap());
+
+        let put = |tx_id: &str, key: &str, signers: &[&SigningIdentity]| {
+            let mut sim = BasedTxSimulator::new(tx_id.to_string(), vdb.clone());
+            sim.set_state("ns", key, Vec::from(tx_id)).unwrap();
+            let results = sim.get_tx_simulation_results().unwrap();
+            let tx = create_tx(results.simulation_results, tx_id.to_string()).unwrap();
+            endorse(tx, "ns", signers)
+        };
+        let commit = |block: Block| {
+            let (batch, h, tx_codes) = Validator::new(vdb.clone())
+                .with_policies(policies.clone())
+                .validate_and_prepare_batch(block.clone())
+                .unwrap();
+            let (_, _, par_tx_codes) = ParallelValidator::new(vdb.clone(), 2)
+                .with_policies(policies.clone())
+                .validate_and_prepare_batch(block)
+                .unwrap();
+            assert_eq!(tx_codes, par_tx_codes);
+            vdb.apply_updates(batch, Some(h)).unwrap();
+            tx_codes
+        };
+
+        let mut sim = BasedTxSimulator::new("tx0".to_string(), vdb.clone());
+        sim.set_state("ns", "k", Vec::from("v")).unwrap();
+        let policy = KeyEndorsementPolicy {
+            orgs: vec!["org1".to_string(), "org2".to_string()],
+        };
+        sim.set_state_validation_parameter("ns", "k", &policy)
+            .unwrap();
+        let results = sim.get_tx_simulation_results().unwrap();
+        let tx0 = create_tx(results.simulation_results, "tx0".to_string()).unwrap();
+        let tx_codes = commit(create_block(vec![endorse(tx0, "ns", &[org3])], 1));
+        assert_eq!(tx_codes.get("tx0"), Some(&TxValidationCode::Valid));
+
+        // the policy of the key replaces the policy of the contract, the other keys stay under it
+        let tx_codes = commit(create_block(
+            vec![
+                put("tx1", "k", &[org1, org3]),
+                put("tx2", "k", &[org2, org1]),
+                put("tx3", "other", &[org1, org2]),
+                put("tx4", "other", &[org3]),
+            ],
+            2,
+        ));
+        for (tx_id, code) in &[
+            ("tx1", TxValidationCode::EndorsementPolicyFailure),
+            ("tx2", TxValidationCode::Valid),
+            ("tx3", TxValidationCode::EndorsementPolicyFailure),
+            ("tx4", TxValidationCode::Valid),
+        ] {
+            assert_eq!(tx_codes.get(tx_id), Some(code), "{}", tx_id);
+        }
+
+        // a transaction writing keys with and without policy needs both policies
+        let both = |tx_id: &str, signers: &[&SigningIdentity]| {
+            let mut sim = BasedTxSimulator::new(tx_id.to_string(), vdb.clone());
+            sim.set_state("ns", "k", Vec::from(tx_id)).unwrap();
+            sim.set_state("ns", "other", Vec::from(tx_id)).unwrap();
+            let results = sim.get_tx_simulation_results().unwrap();
+            let tx = create_tx(results.simulation_results, tx_id.to_string()).unwrap();
+            endorse(tx, "ns", signers)
+        };
+        let tx_codes = commit(create_block(
+            vec![both("tx5", &[org1, org2]), both("tx6", &[org1, org2, org3])],
+            3,
+        ));
+        assert_eq!(
+            tx_codes.get("tx5"),
+            Some(&TxValidationCode::EndorsementPolicyFailure)
+        );
+        assert_eq!(tx_codes.get("tx6"), Some(&TxValidationCode::Valid));
+
+        // writing the value keeps the policy
+        let mut sim = BasedTxSimulator::new("tx7".to_string(), vdb.clone());
+        assert_eq!(
+            sim.get_state_validation_parameter("ns", "k").unwrap(),
+            Some(policy)
+        );
+        assert_eq!(sim.get_state("ns", "k").unwrap(), Vec::from("tx6"));
+
+        // a policy set alone keeps the value and applies to the next transactions of the block
+        sim.set_state_validation_parameter(
+            "ns",
+            "k",
+            &KeyEndorsementPolicy {
+                orgs: vec!["org3".to_string()],
+            },
+        )
+        .unwrap();
+        let results = sim.get_tx_simulation_results().unwrap();
+        let tx7 = create_tx(results.simulation_results, "tx7".to_string()).unwrap();
+        let tx_codes = commit(create_block(
+            vec![
+                endorse(tx7, "ns", &[org1, org2]),
+                put("tx8", "k", &[org1, org2]),
+            ],
+            4,
+        ));
+        assert_eq!(tx_codes.get("tx7"), Some(&TxValidationCode::Valid));
+        assert_eq!(
+            tx_codes.get("tx8"),
+            Some(&TxValidationCode::EndorsementPolicyFailure)
+        );
+        assert_eq!(vdb.get_state("ns", "k").unwrap().unwrap().value, b"tx6");
+
+        // without the policies of the channel the endorsers can not be verified, nothing is written
+        let block = create_block(
+            vec![
+                put("tx9", "k", &[org1, org2]),
+                put("tx10", "other", &[org3]),
+            ],
+            5,
+        );
+        let (_, _, tx_codes) = Validator::new(vdb.clone())
+            .validate_and_prepare_batch(block.clone())
+            .unwrap();
+        let (_, _, par_tx_codes) = ParallelValidator::new(vdb.clone(), 2)
+            .validate_and_prepare_batch(block)
+            .unwrap();
+        assert_eq!(tx_codes, par_tx_codes);
+        for tx_id in &["tx9", "tx10"] {
+            assert_eq!(
+                tx_codes.get(tx_id),
+                Some(&TxValidationCode::EndorsementPolicyFailure)
+            );
+        }
+    }
+
+    #[test]
+    fn test_lifecycle_policy() {
+        let temp_dir = TempDir::new().unwrap();
+        let provider = VersionedDBRocksProvider::new(temp_dir.path()).unwrap();
+        let vdb = provider.get_db_handle("chain_id").unwrap();
+
+        let (config, signers) = channel(&["org1", "org2", "org3"]);
+        let (org1, org2, org3) = (&signers[0], &signers[1], &signers[2]);
+        let policies = Arc::new(ChannelPolicies::from_config(&config).unwrap());
+
+        // write sets the keys through a contract, the responses are endorsed by the signers
+        let write =
+            |tx_id: &str, contract: &str, keys: &[(&str, &str)], signers: &[&SigningIdentity]| {
+                let mut sim = BasedTxSimulator::new(tx_id.to_string(), vdb.clone());
+                for (ns, key) in keys {
+                    sim.set_state(ns, key, Vec::from(tx_id)).unwrap();
+                }
+                let results = sim.get_tx_simulation_results().unwrap();
+                let tx = create_tx(results.simulation_results, tx_id.to_string()).unwrap();
+                endorse(tx, contract, signers)
+            };
+        // define writes a definition with its collections, as the lifecycle contract does
+        let define = |tx_id: &str, contract: &str, name: &str, signers: &[&SigningIdentity]| {
+            write(
+                tx_id,
+                contract,
+                &[(LIFECYCLE_NS, name), (COLLECTION_CONFIG_NS, name)],
+                signers,
+            )
+        };
+
+        // a contract writes its own namespace, the lifecycle contract the definitions and the
+        // contracts no other key of the lifecycle but their initialization mark
+        let initialized = lifecycle::initialized_key("ns");
+        let block = create_block(
+            vec![
+                define("tx1", LIFECYCLE_NS, "cc1", &[org1]),
+                define("tx2", LIFECYCLE_NS, "cc2", &[org1, org2]),
+                define("tx3", LIFECYCLE_NS, "cc3", &[org3, org3]),
+                define("tx4", "ns", "cc4", &[org1]),
+                write("tx5", "ns", &[(COLLECTION_CONFIG_NS, "cc5")], &[org2]),
+                write("tx6", "ns", &[(LIFECYCLE_NS, &initialized)], &[org2]),
+                define("tx7", "ns", "cc7", &[org1, org2, org3]),
+                write("tx8", "ns", &[(LIFECYCLE_NS, &initialized)], &[org1, org3]),
+                write(
+                    "tx9",
+                    "ns",
+                    &[(LIFECYCLE_NS, &lifecycle::initialized_key("cc"))],
+                    &[org1, org3],
+                ),
+                write(
+                    "tx10",
+                    "ns",
+                    &[("ns", "k"), ("cc", "k")],
+                    &[org1, org2, org3],
+                ),
+                write("tx11", "ns", &[("ns", "k")], &[org1]),
+            ],
+            1,
+        );
+        let (_, _, tx_codes) = Validator::new(vdb.clone())
+            .with_policies(policies.clone())
+            .validate_and_prepare_batch(block.clone())
+            .unwrap();
+        let (_, _, par_tx_codes) = ParallelValidator::new(vdb, 2)
+            .with_policies(policies)
+            .validate_and_prepare_batch(block)
+            .unwrap();
+        assert_eq!(tx_codes, par_tx_codes);
+        for (tx_id, code) in &[
+            ("tx1", TxValidationCode::EndorsementPolicyFailure),
+            ("tx2", TxValidationCode::Valid),
+            ("tx3", TxValidationCode::EndorsementPolicyFailure),
+            ("tx4", TxValidationCode::EndorsementPolicyFailure),
+            ("tx5", TxValidationCode::EndorsementPolicyFailure),
+            ("tx6", TxValidationCode::EndorsementPolicyFailure),
+            ("tx7", TxValidationCode::EndorsementPolicyFailure),
+            ("tx8", TxValidationCode::Valid),
+            ("tx9", TxValidationCode::EndorsementPolicyFailure),
+            ("tx10", TxValidationCode::EndorsementPolicyFailure),
+            ("tx11", TxValidationCode::Valid),
+        ] {
+            assert_eq!(tx_codes.get(tx_id), Some(code), "{}", tx_id);
+        }
+    }
+
+    #[test]
+    fn test_contract_endorsement_policy() {
+        let temp_dir = TempDir::new().unwrap();
+        let provider = VersionedDBRocksProvider::new(temp_dir.path()).unwrap();
+        let vdb = provider.get_db_handle("chain_id").unwrap();
+
+        let ca1 = create_ca_cert();
+        let ca2 = create_ca_cert();
+        let signer = |org: &str, ca| {
+            let register = Register {
+                org_unit: "peer".to_string(),
+                ..Register::default()
+            };
+            let cert = create_cert(cert_params(None, register)).unwrap();
+            SigningIdentity::new(
+                org,
+                cert.serialize_der_with_signer(ca).unwrap(),
+                &cert.serialize_private_key_der(),
+            )
+            .unwrap()
+        };
+        let (peer1, peer2) = (signer("org1", &ca1), signer("org2", &ca2));
+        let mut config = ChannelConfig {
+            channel_id: "chain_id".to_string(),
+            organizations: vec![
+                OrganizationConfig {
+                    org_id: "org1".to_string(),
+                    root_certs: vec![ca1.serialize_der().unwrap()],
+                },
+                OrganizationConfig {
+                    org_id: "org2".to_string(),
+                    root_certs: vec![ca2.serialize_der().unwrap()],
+                },
+            ],
+            endorsement_policies: Default::default(),
+        };
+        config.endorsement_policies.insert(
+            "cc".to_string(),
+            "AND('org1.peer', 'org2.peer')".to_string(),
+        );
+        let policies = Arc::new(ChannelPolicies::from_config(&config).unwrap());
+
+        // invoke writes a key through a contract, the responses are endorsed by the signers
+        let invoke = |tx_id: &str, contract: &str, signers: &[&SigningIdentity]| {
+            let mut sim = BasedTxSimulator::new(tx_id.to_string(), vdb.clone());
+            sim.set_state(contract, "k", Vec::from(tx_id)).unwrap();
+            let results = sim.get_tx_simulation_results().unwrap();
+            let tx = create_tx(results.simulation_results, tx_id.to_string()).unwrap();
+            endorse(tx, contract, signers)
+        };
+
+        let forged = {
+            let mut tx = invoke("tx3", "cc", &[&peer1, &peer2]);
+            tx.response[0].endorsement = Some(peer1.endorse(b"other payload").unwrap());
+            tx
+        };
+        let diverging = {
+            let mut tx = invoke("tx4", "cc", &[&peer1, &peer2]);
+            let other = invoke("other", "cc", &[&peer2]);
+            tx.response[1] = other.response[0].clone();
+            tx
+        };
+        let block = create_block(
+            vec![
+                invoke("tx1", "cc", &[&peer1, &peer2]),
+                invoke("tx2", "cc", &[&peer1]),
+                forged,
+                diverging,
+                invoke("tx5", "other_cc", &[&peer2]),
+                invoke("tx6", "other_cc", &[]),
+            ],
+            1,
+        );
+        let (_, _, tx_codes) = Validator::new(vdb.clone())
+            .with_policies(policies.clone())
+            .validate_and_prepare_batch(block.clone())
+            .unwrap();
+        let (_, _, par_tx_codes) = ParallelValidator::new(vdb.clone(), 2)
+            .with_policies(policies.clone())
+            .validate_and_prepare_batch(block.clone())
+            .unwrap();
+        assert_eq!(tx_codes, par_tx_codes);
+        assert_eq!(tx_codes.get("tx1"), Some(&TxValidationCode::Valid));
+        for tx_id in &["tx2", "tx3", "tx6"] {
+            assert_eq!(
+                tx_codes.get(tx_id),
+                Some(&TxValidationCode::EndorsementPolicyFailure)
+            );
+        }
+        assert_eq!(
+            tx_codes.get("tx4"),
+            Some(&TxValidationCode::BadResponsePayload)
+        );
+        assert_eq!(tx_codes.get("tx5"), Some(&TxValidationCode::Valid));
+
+        // the transaction manager takes the policies from the genesis block
+        let txmgr = LockBasedTxMgr::new(vdb.clone(), Duration::from_millis(50));
+        let tx_codes = txmgr
+            .validate_and_prepare(&create_block(vec![config_tx(&config)], 0))
+            .unwrap();
+        assert_eq!(tx_codes.get("genesis"), Some(&TxValidationCode::Valid));
+        txmgr.commit().unwrap();
+        let tx_codes = txmgr.validate_and_prepare(&block).unwrap();
+        assert_eq!(tx_codes, par_tx_codes);
+
+        // the policy and the version of a committed definition take precedence
+        let mut batch = UpdateBatch::new();
+        batch.put(
+            LIFECYCLE_NS,
+            &definition_key("other_cc"),
+            utils::proto::marshal(&ContractDefinition {
+                name: "other_cc".to_string(),
+                version: "1".to_string(),
+                package_hash: vec![1],
+                endorsement_policy: "AND('org1.peer', 'org2.peer')".to_string(),
+                init_required: false,
+                ..Default::default()
+            })
+            .unwrap(),
+            Height::new(1, 0),
+        );
+        vdb.apply_updates(batch, Some(Height::new(1, 0))).unwrap();
+        let versioned = |tx_id: &str, version: &str| {
+            let mut tx = invoke(tx_id, "other_cc", &[&peer1, &peer2]);
+            let sp = tx.signed_proposal.as_mut().unwrap();
+            let mut proposal: Proposal = utils::proto::unmarshal(&sp.proposal_bytes).unwrap();
+            proposal.payload = utils::proto::marshal(&ContractProposalPayload {
+                contract_id: Some(ContractId {
+                    name: "other_cc".to_string(),
+                    version: version.to_string(),
+                }),
+                ..Default::default()
+            })
+            .unwrap();
+            sp.proposal_bytes = utils::proto::marshal(&proposal).unwrap();
+            tx
+        };
+        let block = create_block(
+            vec![
+                invoke("tx7", "other_cc", &[&peer2]),
+                versioned("tx8", "2"),
+                versioned("tx9", "1"),
+                invoke("tx10", "other_cc", &[&peer1, &peer2]),
+            ],
+            2,
+        );
+        let (_, _, tx_codes) = Validator::new(vdb.clone())
+            .with_policies(policies.clone())
+            .validate_and_prepare_batch(block.clone())
+            .unwrap();
+        let (_, _, par_tx_codes) = ParallelValidator::new(vdb, 2)
+            .with_policies(policies)
+            .validate_and_prepare_batch(block)
+            .unwrap();
+        assert_eq!(tx_codes, par_tx_codes);
+        assert_eq!(
+            tx_codes.get("tx7"),
+            Some(&TxValidationCode::EndorsementPolicyFailure)
+        );
+        assert_eq!(
+            tx_codes.get("tx8"),
+            Some(&TxValidationCode::ChaincodeVersionConflict)
+        );
+        assert_eq!(tx_codes.get("tx9"), Some(&TxValidationCode::Valid));
+        assert_eq!(tx_codes.get("tx10"), Some(&TxValidationCode::Valid));
+    }
 }
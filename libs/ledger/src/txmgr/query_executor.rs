@@ -0,0 +1,137 @@
+use crate::kvledger::history::HistoryDB;
+use crate::statedb::{StateProof, VersionedDB, VersionedKV, VersionedKVPage, VersionedValue};
+use crate::txmgr::lock::ReadGuard;
+use crate::QueryResultsPage;
+use error::*;
+use silk_proto::Kv;
+
+// LockBasedQueryExecutor reads the committed state while holding the read side of the commit
+// lock, so that all its reads observe the same block
+pub struct LockBasedQueryExecutor<V: VersionedDB> {
+    vdb: V,
+    commit_lock: Option<ReadGuard>,
+    history: Option<HistoryDB>,
+}
+
+impl<V: VersionedDB> LockBasedQueryExecutor<V> {
+    pub fn new(vdb: V, commit_lock: ReadGuard) -> Self {
+        LockBasedQueryExecutor {
+            vdb,
+            commit_lock: Some(commit_lock),
+            history: None,
+        }
+    }
+
+    // with_history lets the executor read the state as of past blocks from the history of the
+    // ledger
+    pub fn with_history(mut self, history: HistoryDB) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    fn history(&self) -> Result<&HistoryDB> {
+        self.history
+            .as_ref()
+            .ok_or_else(|| from_str("the history of the ledger is not available"))
+    }
+}
+
+impl<V: VersionedDB> crate::QueryExecutor for LockBasedQueryExecutor<V> {
+    fn get_state(&self, namespace: &str, key: &str) -> Result<Vec<u8>> {
+        let vv = self.vdb.get_state(namespace, key)?;
+        Ok(vv.map(|vv| vv.value).unwrap_or_default())
+    }
+
+    fn get_state_range_scan_iterator(
+        &self,
+        namespace: &str,
+        start_key: &str,
+        end_key: &str,
+    ) -> Result<Box<dyn Iterator<Item = Kv>>> {
+        let iter = self
+            .vdb
+            .get_state_range_scan_iterator(namespace, start_key, end_key)?;
+        Ok(Box::new(iter.map(to_kv)))
+    }
+
+    fn get_state_range_scan_iterator_with_pagination(
+        &self,
+        namespace: &str,
+        start_key: &str,
+        end_key: &str,
+        bookmark: &str,
+        page_size: usize,
+    ) -> Result<QueryResultsPage> {
+        let page = self
+            .vdb
+            .get_state_range_scan_page(namespace, start_key, end_key, bookmark, page_size)?;
+        Ok(to_results_page(page))
+    }
+
+    fn execute_query_with_pagination(
+        &self,
+        namespace: &str,
+        query: &str,
+        bookmark: &str,
+        page_size: usize,
+    ) -> Result<QueryResultsPage> {
+        let page = self
+            .vdb
+            .execute_query_page(namespace, query, bookmark, page_size)?;
+        Ok(to_results_page(page))
+    }
+
+    fn get_state_with_proof(
+        &self,
+        namespace: &str,
+        key: &str,
+    ) -> Result<(Option<VersionedValue>, StateProof)> {
+        self.vdb.get_state_with_proof(namespace, key)
+    }
+
+    fn get_state_at(
+        &self,
+        namespace: &str,
+        key: &str,
+        block_num: u64,
+    ) -> Result<Option<VersionedValue>> {
+        self.history()?.get_state_at(namespace, key, block_num)
+    }
+
+    fn get_state_range_scan_iterator_at(
+        &self,
+        namespace: &str,
+        start_key: &str,
+        end_key: &str,
+        block_num: u64,
+    ) -> Result<Box<dyn Iterator<Item = Kv>>> {
+        let kvs = self
+            .history()?
+            .get_state_range_at(namespace, start_key, end_key, block_num)?;
+        Ok(Box::new(kvs.into_iter().map(to_kv)))
+    }
+
+    fn execute_query(&self, namespace: &str, query: &str) -> Result<Box<dyn Iterator<Item = Kv>>> {
+        let iter = self.vdb.execute_query(namespace, query)?;
+        Ok(Box::new(iter.map(to_kv)))
+    }
+
+    fn done(&mut self) {
+        self.commit_lock.take();
+    }
+}
+
+pub(crate) fn to_kv(kv: VersionedKV) -> Kv {
+    Kv {
+        namespace: kv.composite_key.namespace().to_string(),
+        key: kv.composite_key.key().to_string(),
+        value: kv.versioned_value.value,
+    }
+}
+
+pub(crate) fn to_results_page(page: VersionedKVPage) -> QueryResultsPage {
+    QueryResultsPage {
+        results: page.kvs.into_iter().map(to_kv).collect(),
+        bookmark: page.bookmark,
+    }
+}
@@ -0,0 +1,90 @@
+use error::*;
+
+// COMPOSITE_KEY_SEP separates the object type and the attributes of a composite key
+pub const COMPOSITE_KEY_SEP: char = '\u{0}';
+// MAX_UNICODE_RUNE sorts after any character, it closes the range of a partial composite key
+pub const MAX_UNICODE_RUNE: char = '\u{10FFFF}';
+
+// create_composite_key builds the key `objectType\0attr1\0attr2\0`. Keys sharing an object type
+// and a prefix of attributes are adjacent in the state, which is what partial key queries scan
+pub fn create_composite_key(object_type: &str, attributes: &[&str]) -> Result<String> {
+    validate_composite_key_attribute(object_type)?;
+    let mut key = String::from(object_type);
+    key.push(COMPOSITE_KEY_SEP);
+    for attr in attributes {
+        validate_composite_key_attribute(attr)?;
+        key.push_str(attr);
+        key.push(COMPOSITE_KEY_SEP);
+    }
+    Ok(key)
+}
+
+// split_composite_key returns the object type and the attributes of a composite key
+pub fn split_composite_key(composite_key: &str) -> Result<(String, Vec<String>)> {
+    if !composite_key.ends_with(COMPOSITE_KEY_SEP) {
+        return Err(from_str(&format!(
+            "{:?} is not a composite key",
+            composite_key
+        )));
+    }
+    let mut components = composite_key[..composite_key.len() - 1]
+        .split(COMPOSITE_KEY_SEP)
+        .map(String::from);
+    // split always yields at least one component
+    let object_type = components.next().unwrap();
+    if object_type.is_empty() {
+        return Err(from_str(&format!(
+            "composite key {:?} has no object type",
+            composite_key
+        )));
+    }
+    Ok((object_type, components.collect()))
+}
+
+// partial_composite_key_range returns the [start, end) range of the keys starting with the
+// given object type and attributes
+pub fn partial_composite_key_range(
+    object_type: &str,
+    attributes: &[&str],
+) -> Result<(String, String)> {
+    let start = create_composite_key(object_type, attributes)?;
+    let mut end = start.clone();
+    end.push(MAX_UNICODE_RUNE);
+    Ok((start, end))
+}
+
+fn validate_composite_key_attribute(attr: &str) -> Result<()> {
+    if attr.is_empty() {
+        return Err(from_str("composite key attribute is empty"));
+    }
+    if attr.contains(COMPOSITE_KEY_SEP) || attr.contains(MAX_UNICODE_RUNE) {
+        return Err(from_str(&format!(
+            "composite key attribute {:?} contains U+0000 or U+10FFFF",
+            attr
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::composite_key::*;
+
+    #[test]
+    fn test_composite_key() {
+        let key = create_composite_key("asset", &["alice", "car"]).unwrap();
+        assert_eq!(key, "asset\u{0}alice\u{0}car\u{0}");
+        let (object_type, attributes) = split_composite_key(&key).unwrap();
+        assert_eq!(object_type, "asset");
+        assert_eq!(attributes, vec!["alice", "car"]);
+
+        let (start, end) = partial_composite_key_range("asset", &["alice"]).unwrap();
+        assert!(start.as_str() <= key.as_str() && key.as_str() < end.as_str());
+        let other = create_composite_key("asset", &["alicia"]).unwrap();
+        assert!(other.as_str() >= end.as_str());
+
+        assert!(create_composite_key("asset", &["a\u{0}b"]).is_err());
+        assert!(create_composite_key("", &[]).is_err());
+        assert!(split_composite_key("asset").is_err());
+    }
+}
@@ -1,4 +1,5 @@
 pub mod base64;
+pub mod composite_key;
 pub mod hash;
 pub mod hashable;
 pub mod proto;
@@ -34,7 +34,7 @@ mod tests {
     fn test() {
         let reg = ContractRegister {
             name: "kv".to_string(),
-            decorations: Default::default(),
+            ..Default::default()
         };
         let ret = marshal(&reg);
         assert!(ret.is_ok());
@@ -52,6 +52,7 @@ mod tests {
         let reg = ContractRegister {
             name: "contract name".to_string(),
             decorations: dec,
+            ..Default::default()
         };
         let ret = marshal(&reg).unwrap();
 
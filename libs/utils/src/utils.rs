@@ -1,9 +1,44 @@
 use crate::proto;
 use error::*;
-use silk_proto::{Block, Header, Proposal, Transaction};
+use silk_proto::{
+    tx_validation_code_from, Block, BlockMetadata, ChannelConfig, Header, HeaderType, Proposal,
+    Transaction, TxValidationCode,
+};
 
-pub fn get_chain_id_from_block(_block: &Block) -> Result<String> {
-    unimplemented!()
+// TRANSACTIONS_FILTER is the index of the block metadata entry holding the validation code of
+// each transaction of the block, one byte per transaction
+pub const TRANSACTIONS_FILTER: usize = 0;
+
+// get_chain_id_from_block returns the channel id named by the first transaction of a block
+pub fn get_chain_id_from_block(block: &Block) -> Result<String> {
+    let data = block
+        .data
+        .as_ref()
+        .and_then(|data| data.data.first())
+        .ok_or_else(|| from_str("block data is empty"))?;
+    let (_, header) = get_tx_header_from_data(data)?;
+    if header.channel_id.is_empty() {
+        return Err(from_str("block channel id is empty"));
+    }
+    Ok(header.channel_id)
+}
+
+// get_channel_config_from_block returns the channel config carried by the CREATE_CHANNEL
+// transaction opening a genesis block, None if the block does not start with one
+pub fn get_channel_config_from_block(block: &Block) -> Result<Option<ChannelConfig>> {
+    let data = match block.data.as_ref().and_then(|data| data.data.first()) {
+        Some(data) => data,
+        None => return Ok(None),
+    };
+    let (tx, header) = get_tx_header_from_data(data)?;
+    if header.header_type != HeaderType::CreateChannel as i32 {
+        return Ok(None);
+    }
+    let signed_proposal = tx
+        .signed_proposal
+        .ok_or_else(|| from_str("transaction signed proposal is null"))?;
+    let proposal = proto::unmarshal::<Proposal>(&signed_proposal.proposal_bytes)?;
+    Ok(Some(proto::unmarshal(&proposal.payload)?))
 }
 
 pub fn get_tx_header_from_data(data: &[u8]) -> Result<(Transaction, Header)> {
@@ -18,3 +53,37 @@ pub fn get_tx_header_from_data(data: &[u8]) -> Result<(Transaction, Header)> {
         .ok_or_else(|| from_str("transaction header is null"))?;
     Ok((tx, tx_header))
 }
+
+// compute_tx_id returns the id of a transaction, the hex encoded hash of the nonce and the
+// creator of its proposal
+pub fn compute_tx_id(nonce: &[u8], creator: &[u8]) -> String {
+    crate::hash::hex_to_string(&crate::hash::compute_vec_sha256(&[
+        nonce.to_vec(),
+        creator.to_vec(),
+    ]))
+}
+
+// set_tx_validation_flags records the validation code of each transaction in the block metadata
+pub fn set_tx_validation_flags(block: &mut Block, codes: &[TxValidationCode]) {
+    let metadata = block.metadata.get_or_insert_with(BlockMetadata::default);
+    if metadata.metadata.len() < TRANSACTIONS_FILTER + 1 {
+        metadata.metadata.resize(TRANSACTIONS_FILTER + 1, vec![]);
+    }
+    metadata.metadata[TRANSACTIONS_FILTER] = codes.iter().map(|c| *c as u8).collect();
+}
+
+// get_tx_validation_flags returns the validation codes recorded in the block metadata, an empty
+// list is returned for a block that has not been validated yet
+pub fn get_tx_validation_flags(block: &Block) -> Vec<TxValidationCode> {
+    block
+        .metadata
+        .as_ref()
+        .and_then(|m| m.metadata.get(TRANSACTIONS_FILTER))
+        .map(|flags| {
+            flags
+                .iter()
+                .map(|f| tx_validation_code_from(*f as i32))
+                .collect()
+        })
+        .unwrap_or_default()
+}
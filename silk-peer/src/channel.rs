@@ -1,16 +1,47 @@
-use std::collections::HashMap;
+use error::*;
+use identity::msp::Msp;
+use identity::policy::SignaturePolicy;
+use silk_proto::ChannelConfig;
+use std::sync::Arc;
 
+// Channel is a channel the peer has joined, with the msp of its member organizations
 #[derive(Clone)]
 pub struct Channel {
     name: String,
-    storage: HashMap<String, Vec<u8>>,
+    config: ChannelConfig,
+    msp: Arc<Msp>,
 }
 
 impl Channel {
-    pub fn new(name: String) -> Self {
-        Channel {
-            name,
-            storage: HashMap::new(),
+    pub fn new(config: ChannelConfig) -> Result<Self> {
+        if config.channel_id.is_empty() {
+            return Err(from_str("channel id is empty"));
         }
+        let msp = Msp::from_config(&config.organizations)?;
+        for (contract, policy) in &config.endorsement_policies {
+            SignaturePolicy::parse(policy).map_err(|e| {
+                from_str(&format!(
+                    "invalid endorsement policy of contract {:}: {:}",
+                    contract, e
+                ))
+            })?;
+        }
+        Ok(Channel {
+            name: config.channel_id.clone(),
+            config,
+            msp: Arc::new(msp),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn config(&self) -> &ChannelConfig {
+        &self.config
+    }
+
+    pub fn msp(&self) -> Arc<Msp> {
+        self.msp.clone()
     }
 }
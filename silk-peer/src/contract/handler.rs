@@ -5,23 +5,41 @@ use std::sync::Arc;
 
 use dashmap::DashMap;
 use silk_proto::message::MessageType;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::time::Duration;
 
 // https://github.com/libp2p/rust-libp2p/blob/master/core/src/identity.rs
 
+static NEXT_INSTANCE_ID: AtomicU64 = AtomicU64::new(1);
+
+// Contract is a registered instance of a contract, the instances registered under the same name
+// share the load of the contract
 #[derive(Clone)]
 pub struct Contract {
     name: String,
+    version: String,
+    // the package hash the instance registered with, it runs a contract definition once verified
+    package_hash: Vec<u8>,
+    verified: bool,
+    launch_id: String,
+    instance_id: u64,
     sender: Sender<Message>,
     transaction_context_registry: Arc<DashMap<String, TransactionContext>>, // key: channel_id + tx_id
+    inflight: Arc<AtomicUsize>,
 }
 
 impl Contract {
     pub fn new(register: ContractRegister, sender: Sender<Message>) -> Self {
         Contract {
             name: register.name,
+            version: register.version,
+            package_hash: register.package_hash,
+            verified: false,
+            launch_id: register.launch_id,
+            instance_id: NEXT_INSTANCE_ID.fetch_add(1, Ordering::Relaxed),
             sender,
             transaction_context_registry: Arc::new(DashMap::new()),
+            inflight: Arc::new(AtomicUsize::new(0)),
         }
     }
 
@@ -29,6 +47,52 @@ impl Contract {
         self.name.clone()
     }
 
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn package_hash(&self) -> &[u8] {
+        &self.package_hash
+    }
+
+    // launch_id returns the id the peer launched the instance with, empty when it was started
+    // apart from the peer
+    pub fn launch_id(&self) -> &str {
+        &self.launch_id
+    }
+
+    // verified returns the instance running the package of the given hash, as known by the peer
+    pub fn verified(mut self, package_hash: Vec<u8>) -> Self {
+        self.package_hash = package_hash;
+        self.verified = true;
+        self
+    }
+
+    // matches tells whether the instance runs the package of a contract definition
+    pub fn matches(&self, definition: &ContractDefinition) -> bool {
+        self.verified
+            && self.name == definition.name
+            && self.version == definition.version
+            && self.package_hash == definition.package_hash
+    }
+
+    pub fn instance_id(&self) -> u64 {
+        self.instance_id
+    }
+
+    // inflight returns the number of the transactions the instance is executing
+    pub fn inflight(&self) -> usize {
+        self.inflight.load(Ordering::Relaxed)
+    }
+
+    fn closed(&self, retryable: bool) -> Error {
+        Box::new(InstanceClosed {
+            contract: self.name.clone(),
+            instance_id: self.instance_id,
+            retryable,
+        })
+    }
+
     fn notify(&self, msg: &Message) -> Result<()> {
         let TransactionCompleted {
             proposal,
@@ -37,57 +101,239 @@ impl Contract {
         } = utils::proto::unmarshal(&msg.content)?;
         let prop = proposal.ok_or_else(|| from_str("proposal is null"))?;
 
-        if let Some(ctx) = self
+        // the context of a transaction that timed out is gone, its completion is discarded
+        match self
             .transaction_context_registry
             .remove(&msg.correlation_id)
         {
-            let ctx = ctx.1;
-
-            ctx.response_notifier
-                .send(TransactionCompleted {
+            Some((_, ctx)) => {
+                let completed = TransactionCompleted {
                     proposal: Some(prop),
                     response,
                     event,
-                })
-                .map_err(|_| from_str("send error"))?;
+                };
+                if ctx.response_notifier.send(Ok(completed)).is_err() {
+                    warn!("discard late completion of {:?}", msg.correlation_id);
+                }
+            }
+            None => warn!("discard late completion of {:?}", msg.correlation_id),
         }
         Ok(())
     }
 
-    fn handle_get_state(
+    fn handle_get_state(&self, ctx: &TransactionContext, msg: &Message) -> Result<Message> {
+        let req: GetStateRequest = utils::proto::unmarshal(&msg.content)?;
+        check_request(ctx, &req.channel_id, &req.tx_id)?;
+        let value = ctx
+            .simulator
+            .with(|s| s.get_state(&ctx.namespace, &req.key))?;
+        Ok(Message {
+            message_type: MessageType::ContractGetStateResponse as i32,
+            correlation_id: msg.correlation_id.clone(),
+            content: utils::proto::marshal(&GetStateResponse { value })?,
+        })
+    }
+
+    fn handle_put_state(&self, ctx: &TransactionContext, msg: &Message) -> Result<Message> {
+        let req: PutStateRequest = utils::proto::unmarshal(&msg.content)?;
+        check_request(ctx, &req.channel_id, &req.tx_id)?;
+        if req.key.is_empty() {
+            return Err(from_str("key is empty"));
+        }
+        ctx.simulator
+            .with(|s| s.set_state(&ctx.namespace, &req.key, req.value))?;
+        Ok(Message {
+            message_type: MessageType::ContractPutStateResponse as i32,
+            correlation_id: msg.correlation_id.clone(),
+            content: vec![],
+        })
+    }
+
+    fn handle_del_state(&self, ctx: &TransactionContext, msg: &Message) -> Result<Message> {
+        let req: DelStateRequest = utils::proto::unmarshal(&msg.content)?;
+        check_request(ctx, &req.channel_id, &req.tx_id)?;
+        if req.key.is_empty() {
+            return Err(from_str("key is empty"));
+        }
+        ctx.simulator
+            .with(|s| s.delete_state(&ctx.namespace, &req.key))?;
+        Ok(Message {
+            message_type: MessageType::ContractDelStateResponse as i32,
+            correlation_id: msg.correlation_id.clone(),
+            content: vec![],
+        })
+    }
+
+    fn handle_get_state_metadata(
+        &self,
+        ctx: &TransactionContext,
+        msg: &Message,
+    ) -> Result<Message> {
+        let req: GetStateMetadataRequest = utils::proto::unmarshal(&msg.content)?;
+        check_request(ctx, &req.channel_id, &req.tx_id)?;
+        let entries = ctx
+            .simulator
+            .with(|s| s.get_state_metadata(&ctx.namespace, &req.key))?;
+        Ok(Message {
+            message_type: MessageType::ContractGetStateMetadataResponse as i32,
+            correlation_id: msg.correlation_id.clone(),
+            content: utils::proto::marshal(&GetStateMetadataResponse { entries })?,
+        })
+    }
+
+    fn handle_put_state_metadata(
         &self,
-        _ctx: &TransactionContext,
-        _msg: &Message,
-    ) -> Result<Option<Message>> {
-        Ok(None)
+        ctx: &TransactionContext,
+        msg: &Message,
+    ) -> Result<Message> {
+        let req: PutStateMetadataRequest = utils::proto::unmarshal(&msg.content)?;
+        check_request(ctx, &req.channel_id, &req.tx_id)?;
+        if req.key.is_empty() {
+            return Err(from_str("key is empty"));
+        }
+        ctx.simulator
+            .with(|s| s.set_state_metadata(&ctx.namespace, &req.key, req.entries))?;
+        Ok(Message {
+            message_type: MessageType::ContractPutStateMetadataResponse as i32,
+            correlation_id: msg.correlation_id.clone(),
+            content: vec![],
+        })
     }
 
-    fn handle_put_state(
+    fn handle_get_state_by_range(
         &self,
-        _ctx: &TransactionContext,
-        _msg: &Message,
-    ) -> Result<Option<Message>> {
-        Ok(None)
+        ctx: &TransactionContext,
+        msg: &Message,
+    ) -> Result<Message> {
+        let req: GetStateByRangeRequest = utils::proto::unmarshal(&msg.content)?;
+        check_request(ctx, &req.channel_id, &req.tx_id)?;
+        let resp = ctx.simulator.with(|s| {
+            if req.page_size == 0 {
+                let kvs = s
+                    .get_state_range_scan_iterator(&ctx.namespace, &req.start_key, &req.end_key)?
+                    .collect();
+                return Ok(QueryStateResponse {
+                    kvs,
+                    bookmark: String::new(),
+                });
+            }
+            let page = s.get_state_range_scan_iterator_with_pagination(
+                &ctx.namespace,
+                &req.start_key,
+                &req.end_key,
+                &req.bookmark,
+                req.page_size as usize,
+            )?;
+            Ok(QueryStateResponse {
+                kvs: page.results,
+                bookmark: page.bookmark,
+            })
+        })?;
+        Ok(Message {
+            message_type: MessageType::ContractQueryStateResponse as i32,
+            correlation_id: msg.correlation_id.clone(),
+            content: utils::proto::marshal(&resp)?,
+        })
+    }
+
+    fn handle_get_query_result(&self, ctx: &TransactionContext, msg: &Message) -> Result<Message> {
+        let req: GetQueryResultRequest = utils::proto::unmarshal(&msg.content)?;
+        check_request(ctx, &req.channel_id, &req.tx_id)?;
+        let resp = ctx.simulator.with(|s| {
+            if req.page_size == 0 {
+                let kvs = s.execute_query(&ctx.namespace, &req.query)?.collect();
+                return Ok(QueryStateResponse {
+                    kvs,
+                    bookmark: String::new(),
+                });
+            }
+            let page = s.execute_query_with_pagination(
+                &ctx.namespace,
+                &req.query,
+                &req.bookmark,
+                req.page_size as usize,
+            )?;
+            Ok(QueryStateResponse {
+                kvs: page.results,
+                bookmark: page.bookmark,
+            })
+        })?;
+        Ok(Message {
+            message_type: MessageType::ContractQueryStateResponse as i32,
+            correlation_id: msg.correlation_id.clone(),
+            content: utils::proto::marshal(&resp)?,
+        })
     }
 
+    fn handle_get_state_by_partial_composite_key(
+        &self,
+        ctx: &TransactionContext,
+        msg: &Message,
+    ) -> Result<Message> {
+        let req: GetStateByPartialCompositeKeyRequest = utils::proto::unmarshal(&msg.content)?;
+        check_request(ctx, &req.channel_id, &req.tx_id)?;
+        let attributes: Vec<&str> = req.attributes.iter().map(|a| a.as_str()).collect();
+        let kvs =
+            ctx.simulator.with(|s| {
+                Ok(s.get_state_by_partial_composite_key(
+                    &ctx.namespace,
+                    &req.object_type,
+                    &attributes,
+                )?
+                .collect())
+            })?;
+        Ok(Message {
+            message_type: MessageType::ContractQueryStateResponse as i32,
+            correlation_id: msg.correlation_id.clone(),
+            content: utils::proto::marshal(&QueryStateResponse {
+                kvs,
+                bookmark: String::new(),
+            })?,
+        })
+    }
+
+    // handle_transaction answers a state request of a running transaction. A request that fails
+    // is answered with the error, the contract stream stays open
     async fn handle_transaction<F>(&self, msg: &Message, delegate: F) -> Result<()>
     where
-        F: Fn(&TransactionContext, &Message) -> Result<Option<Message>>,
+        F: Fn(&TransactionContext, &Message) -> Result<Message>,
     {
-        let ctx = self
+        // the registry entry is released before waiting on the stream
+        let reply = match self
             .transaction_context_registry
-            .get(&msg.correlation_id)
-            .ok_or_else(|| from_str("transaction not found"))?;
-        let ctx = &*ctx;
-        if let Some(m) = delegate(ctx, msg)? {
-            let sender = self.sender.clone();
-            sender.send(Ok(m)).await?;
-        }
-
+            .get_mut(&msg.correlation_id)
+        {
+            Some(mut ctx) => {
+                ctx.accessed = true;
+                delegate(&ctx, msg)
+            }
+            None => Err(from_str("transaction not found")),
+        };
+        let reply = reply.unwrap_or_else(|e| {
+            debug!("state request {:?} failed: {:?}", msg.correlation_id, e);
+            Message {
+                message_type: MessageType::ContractStateError as i32,
+                correlation_id: msg.correlation_id.clone(),
+                content: e.to_string().into_bytes(),
+            }
+        });
+        let sender = self.sender.clone();
+        sender.send(Ok(reply)).await?;
         Ok(())
     }
 }
 
+// check_request checks a state request belongs to the transaction of its context
+fn check_request(ctx: &TransactionContext, channel_id: &str, tx_id: &str) -> Result<()> {
+    if channel_id != ctx.channel_id || tx_id != ctx.tx_id {
+        return Err(from_str(&format!(
+            "request of transaction {:}/{:} does not match {:}/{:}",
+            channel_id, tx_id, ctx.channel_id, ctx.tx_id
+        )));
+    }
+    Ok(())
+}
+
 #[async_trait::async_trait]
 impl Handler for Contract {
     async fn handler(&self, msg: Message) -> Result<()> {
@@ -99,6 +345,46 @@ impl Handler for Contract {
                     |ctx: &TransactionContext, msg: &Message| self.handle_get_state(ctx, msg);
                 self.handle_transaction(&msg, delegate).await
             }
+            t if t == MessageType::ContractPutStateRequest as i32 => {
+                let delegate =
+                    |ctx: &TransactionContext, msg: &Message| self.handle_put_state(ctx, msg);
+                self.handle_transaction(&msg, delegate).await
+            }
+            t if t == MessageType::ContractDelStateRequest as i32 => {
+                let delegate =
+                    |ctx: &TransactionContext, msg: &Message| self.handle_del_state(ctx, msg);
+                self.handle_transaction(&msg, delegate).await
+            }
+            t if t == MessageType::ContractGetStateMetadataRequest as i32 => {
+                let delegate = |ctx: &TransactionContext, msg: &Message| {
+                    self.handle_get_state_metadata(ctx, msg)
+                };
+                self.handle_transaction(&msg, delegate).await
+            }
+            t if t == MessageType::ContractPutStateMetadataRequest as i32 => {
+                let delegate = |ctx: &TransactionContext, msg: &Message| {
+                    self.handle_put_state_metadata(ctx, msg)
+                };
+                self.handle_transaction(&msg, delegate).await
+            }
+            t if t == MessageType::ContractGetStateByRangeRequest as i32 => {
+                let delegate = |ctx: &TransactionContext, msg: &Message| {
+                    self.handle_get_state_by_range(ctx, msg)
+                };
+                self.handle_transaction(&msg, delegate).await
+            }
+            t if t == MessageType::ContractGetQueryResultRequest as i32 => {
+                let delegate = |ctx: &TransactionContext, msg: &Message| {
+                    self.handle_get_query_result(ctx, msg)
+                };
+                self.handle_transaction(&msg, delegate).await
+            }
+            t if t == MessageType::ContractGetStateByPartialCompositeKeyRequest as i32 => {
+                let delegate = |ctx: &TransactionContext, msg: &Message| {
+                    self.handle_get_state_by_partial_composite_key(ctx, msg)
+                };
+                self.handle_transaction(&msg, delegate).await
+            }
             _ => {
                 let describe = format!("unhandled massage type {:?}", msg.message_type);
                 Err(from_str(&describe))
@@ -110,30 +396,166 @@ impl Handler for Contract {
         &self,
         tx_params: &TransactionParams,
         msg: Message,
-        _timeout: Duration,
+        timeout: Duration,
     ) -> Result<TransactionCompleted> {
         let sender = self.sender.clone();
-        let _registry = self.transaction_context_registry.clone();
 
         let key = msg.correlation_id.clone();
-        sender.send(Ok(msg)).await?;
-
         let (tx, rx) = tokio::sync::oneshot::channel();
 
         let tx_ctx = TransactionContext {
             channel_id: tx_params.channel_id.clone(),
+            tx_id: tx_params.tx_id.clone(),
             namespace: tx_params.namespace.clone(),
             proposal: tx_params.proposal.clone(),
             response_notifier: tx,
             simulator: tx_params.tx_simulator.clone(),
+            accessed: false,
         };
 
-        self.transaction_context_registry.insert(key, tx_ctx);
+        self.inflight.fetch_add(1, Ordering::Relaxed);
+        let _inflight = InflightGuard(self.inflight.clone());
+
+        // the context is registered first, the contract may query the state right away
+        self.transaction_context_registry
+            .insert(key.clone(), tx_ctx);
+        if sender.send(Ok(msg)).await.is_err() {
+            self.transaction_context_registry.remove(&key);
+            return Err(self.closed(true));
+        }
         debug!("wait transaction completed message from contract");
-        Ok(rx.await?)
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(completed)) => completed,
+            // the context was dropped without an answer
+            Ok(Err(_)) => Err(self.closed(false)),
+            Err(_) => {
+                self.transaction_context_registry.remove(&key);
+                Err(Box::new(ExecuteTimeout {
+                    contract: self.name.clone(),
+                    tx_id: tx_params.tx_id.clone(),
+                    timeout,
+                }))
+            }
+        }
     }
 
+    // close fails the transactions in flight on the instance, those the contract did not access
+    // the state of may be retried
     fn close(&mut self) {
-        unimplemented!()
+        let keys: Vec<String> = self
+            .transaction_context_registry
+            .iter()
+            .map(|e| e.key().clone())
+            .collect();
+        for key in keys {
+            if let Some((_, ctx)) = self.transaction_context_registry.remove(&key) {
+                let _ = ctx.response_notifier.send(Err(self.closed(!ctx.accessed)));
+            }
+        }
+    }
+}
+
+// InflightGuard counts a transaction in flight until it is dropped
+struct InflightGuard(Arc<AtomicUsize>);
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use ledger::simulator::sim::BasedTxSimulator;
+    use ledger::statedb::{VersionedDBMemProvider, VersionedDBProvider};
+    use tokio::sync::mpsc;
+
+    // instance registers an instance of the contract "cc", the messages sent to the contract
+    // come out of the receiver
+    pub(in crate::contract) fn instance() -> (Contract, Receiver<Message>) {
+        let (tx, rx) = mpsc::channel(100);
+        let register = ContractRegister {
+            name: "cc".to_string(),
+            version: "1".to_string(),
+            package_hash: vec![1],
+            ..Default::default()
+        };
+        (Contract::new(register, tx), rx)
+    }
+
+    pub(in crate::contract) fn tx_params(
+        tx_id: &str,
+        timeout: Option<Duration>,
+    ) -> TransactionParams {
+        let vdb = VersionedDBMemProvider::new().get_db_handle("ch1").unwrap();
+        let simulator = BasedTxSimulator::new(tx_id.to_string(), vdb);
+        TransactionParams {
+            tx_id: tx_id.to_string(),
+            channel_id: "ch1".to_string(),
+            namespace: "cc".to_string(),
+            proposal: Proposal::default(),
+            tx_simulator: TxSimulator::new(Box::new(simulator)),
+            timeout,
+            definition: None,
+        }
+    }
+
+    // complete answers a transaction sent to the contract, the response payload is the instance id
+    pub(in crate::contract) async fn complete(contract: &Contract, msg: &Message) -> Result<()> {
+        let ct: ContractTransaction = utils::proto::unmarshal(&msg.content)?;
+        let completed = TransactionCompleted {
+            proposal: ct.proposal,
+            response: Some(Response {
+                status: 200,
+                message: String::new(),
+                payload: contract.instance_id().to_string().into_bytes(),
+            }),
+            event: None,
+        };
+        contract
+            .handler(Message {
+                message_type: MessageType::ContractTransactionCompletedRequest as i32,
+                correlation_id: msg.correlation_id.clone(),
+                content: utils::proto::marshal(&completed)?,
+            })
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_execute_timeout() {
+        let support = ContractSupport::new(Duration::from_millis(50)).with_unverified_packages();
+        // the contract receives the transaction but never answers
+        let (contract, mut rx) = instance();
+        support.register(contract.clone()).unwrap();
+
+        let err = support
+            .invoke(&tx_params("tx1", None), "cc")
+            .await
+            .unwrap_err();
+        let timeout = err.downcast_ref::<ExecuteTimeout>().unwrap();
+        assert_eq!(timeout.tx_id, "tx1");
+        assert_eq!(timeout.timeout, Duration::from_millis(50));
+        assert_eq!(support.timed_out_executions(), 1);
+        assert!(contract.transaction_context_registry.is_empty());
+        assert_eq!(contract.inflight(), 0);
+        // the instance stays registered
+        assert!(support.launch("cc").is_some());
+
+        // the timeout of the proposal prevails, a late completion is discarded
+        let err = support
+            .invoke(&tx_params("tx2", Some(Duration::from_millis(10))), "cc")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ExecuteTimeout>().unwrap().timeout,
+            Duration::from_millis(10)
+        );
+        assert_eq!(support.timed_out_executions(), 2);
+        for _ in 0..2 {
+            let msg = rx.recv().await.unwrap().unwrap();
+            complete(&contract, &msg).await.unwrap();
+        }
+        assert!(contract.transaction_context_registry.is_empty());
     }
 }
@@ -0,0 +1,352 @@
+use super::*;
+use dashmap::{DashMap, DashSet};
+use futures::future;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+// TlsFiles are the TLS material handed to the launched contracts to reach the peer
+#[derive(Clone, Debug)]
+pub struct TlsFiles {
+    pub root_cert: PathBuf,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+// LauncherConfig configures the launch of the installed contracts. A version of a contract is
+// installed in the package directory as the executable `<name>/<version>`
+#[derive(Clone, Debug)]
+pub struct LauncherConfig {
+    pub package_dir: PathBuf,
+    // the address the launched contracts register at
+    pub peer_address: String,
+    pub tls: Option<TlsFiles>,
+    // how long a launched contract has to register
+    pub register_timeout: Duration,
+    // the bounds of the delay before restarting a contract that exited, the delay doubles at
+    // every restart and is reset once the contract ran for max_backoff
+    pub min_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl LauncherConfig {
+    pub fn new(package_dir: impl Into<PathBuf>, peer_address: &str) -> Self {
+        LauncherConfig {
+            package_dir: package_dir.into(),
+            peer_address: peer_address.to_string(),
+            tls: None,
+            register_timeout: Duration::from_secs(10),
+            min_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+// Launcher starts the installed contracts on demand and restarts them when they exit, as long as
+// they run the committed definition. The output of the contracts goes to the log of the peer
+#[derive(Clone)]
+pub struct Launcher {
+    config: Arc<LauncherConfig>,
+    // the supervised contracts, as name and version
+    supervised: Arc<DashSet<(String, String)>>,
+    // the last committed definition of the contracts, by name
+    definitions: Arc<DashMap<String, ContractDefinition>>,
+    // bumped whenever a committed definition changes, the supervisors then check theirs
+    redefined: Arc<watch::Sender<u64>>,
+    redefinitions: watch::Receiver<u64>,
+    // the definitions the running processes were started for, by launch id
+    launched: Arc<DashMap<String, ContractDefinition>>,
+}
+
+impl Launcher {
+    pub fn new(config: LauncherConfig) -> Self {
+        let (redefined, redefinitions) = watch::channel(0);
+        Launcher {
+            config: Arc::new(config),
+            supervised: Arc::new(DashSet::new()),
+            definitions: Arc::new(DashMap::new()),
+            redefined: Arc::new(redefined),
+            redefinitions,
+            launched: Arc::new(DashMap::new()),
+        }
+    }
+
+    pub fn register_timeout(&self) -> Duration {
+        self.config.register_timeout
+    }
+
+    // launched returns the definition a running process was started for, its package hash is the
+    // one the peer computed before starting it
+    pub fn launched(&self, launch_id: &str) -> Option<ContractDefinition> {
+        self.launched.get(launch_id).map(|d| d.value().clone())
+    }
+
+    // define records the committed definition of a contract, the supervisors of the packages of
+    // its former definitions stop their contract
+    pub fn define(&self, definition: &ContractDefinition) {
+        let changed = match self
+            .definitions
+            .insert(definition.name.clone(), definition.clone())
+        {
+            Some(former) => {
+                former.version != definition.version
+                    || former.package_hash != definition.package_hash
+            }
+            None => false,
+        };
+        if changed {
+            info!(
+                "contract {:} is defined at version {:}",
+                definition.name, definition.version
+            );
+            let generation = *self.redefinitions.borrow() + 1;
+            let _ = self.redefined.send(generation);
+        }
+    }
+
+    // launch starts the package of a contract definition unless it is supervised already. The
+    // package must be installed and its hash must be the one of the definition
+    pub fn launch(&self, definition: &ContractDefinition) -> Result<()> {
+        self.define(definition);
+        let key = (definition.name.clone(), definition.version.clone());
+        if self.supervised.contains(&key) {
+            return Ok(());
+        }
+        let path = self
+            .config
+            .package_dir
+            .join(&definition.name)
+            .join(&definition.version);
+        check_package(&path, definition)?;
+        if !self.supervised.insert(key.clone()) {
+            return Ok(());
+        }
+
+        let supervisor = Supervisor {
+            config: self.config.clone(),
+            path,
+            definition: definition.clone(),
+            definitions: self.definitions.clone(),
+            redefinitions: self.redefinitions.clone(),
+            launched: self.launched.clone(),
+        };
+        let supervised = self.supervised.clone();
+        tokio::spawn(async move {
+            supervisor.run().await;
+            supervised.remove(&key);
+        });
+        Ok(())
+    }
+}
+
+// Supervisor runs a contract process, restarting it with a backoff whenever it exits, until the
+// definition of the contract is no longer the committed one
+struct Supervisor {
+    config: Arc<LauncherConfig>,
+    path: PathBuf,
+    definition: ContractDefinition,
+    definitions: Arc<DashMap<String, ContractDefinition>>,
+    redefinitions: watch::Receiver<u64>,
+    launched: Arc<DashMap<String, ContractDefinition>>,
+}
+
+// check_package checks that the package installed at path is the package of a definition
+fn check_package(path: &Path, definition: &ContractDefinition) -> Result<()> {
+    let package = std::fs::read(path).map_err(|e| {
+        from_str(&format!(
+            "version {:} of contract {:} is not installed: {:}",
+            definition.version, definition.name, e
+        ))
+    })?;
+    if *utils::hash::compute_sha256(&package) != definition.package_hash[..] {
+        return Err(from_str(&format!(
+            "the package installed at {:?} is not the package of the contract definition",
+            path
+        )));
+    }
+    Ok(())
+}
+
+impl Supervisor {
+    // is_current tells whether the supervised definition is the committed one
+    fn is_current(&self) -> bool {
+        match self.definitions.get(&self.definition.name) {
+            Some(current) => {
+                current.version == self.definition.version
+                    && current.package_hash == self.definition.package_hash
+            }
+            None => false,
+        }
+    }
+
+    // retired waits until the supervised definition is no longer the committed one
+    async fn retired(&self) {
+        let mut redefinitions = self.redefinitions.clone();
+        while self.is_current() {
+            if redefinitions.changed().await.is_err() {
+                // the launcher is gone, the definition can not change anymore
+                future::pending::<()>().await;
+            }
+        }
+    }
+
+    fn command(&self, launch_id: &str) -> Command {
+        let mut cmd = Command::new(&self.path);
+        cmd.env("SILK_PEER_ADDRESS", &self.config.peer_address)
+            .env("SILK_CONTRACT_LAUNCH_ID", launch_id)
+            .env("SILK_CONTRACT_NAME", &self.definition.name)
+            .env("SILK_CONTRACT_VERSION", &self.definition.version)
+            .env(
+                "SILK_CONTRACT_PACKAGE_HASH",
+                utils::hash::hex_to_string(&self.definition.package_hash),
+            )
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if let Some(tls) = &self.config.tls {
+            cmd.env("SILK_CONTRACT_TLS_ROOT_CERT_FILE", &tls.root_cert)
+                .env("SILK_CONTRACT_TLS_CERT_FILE", &tls.cert)
+                .env("SILK_CONTRACT_TLS_KEY_FILE", &tls.key);
+        }
+        cmd
+    }
+
+    // run restarts the contract until it can not be spawned anymore or it is retired, a retired
+    // contract is killed. The package is checked before every start, it may have been replaced
+    // meanwhile
+    async fn run(&self) {
+        let name = format!("{:}:{:}", self.definition.name, self.definition.version);
+        let mut backoff = self.config.min_backoff;
+        loop {
+            if !self.is_current() {
+                info!("contract {:} is no longer defined, stop it", name);
+                return;
+            }
+            if let Err(e) = check_package(&self.path, &self.definition) {
+                error!("contract {:} can not be started: {:}", name, e);
+                return;
+            }
+            // the instances registering with the launch id run the checked package
+            let launch_id = utils::hash::hex_to_string(&utils::random::get_random_nonce());
+            self.launched
+                .insert(launch_id.clone(), self.definition.clone());
+            let child = match self.command(&launch_id).spawn() {
+                Ok(child) => child,
+                Err(e) => {
+                    self.launched.remove(&launch_id);
+                    error!("contract {:} can not be started: {:?}", name, e);
+                    return;
+                }
+            };
+            info!("contract {:} started, pid {:?}", name, child.id());
+            let started = Instant::now();
+            // the process is killed when its wait is dropped
+            let retired = tokio::select! {
+                exited = wait(&name, child) => {
+                    match exited {
+                        Ok(status) => warn!("contract {:} exited with {:}", name, status),
+                        Err(e) => error!("contract {:} failed: {:?}", name, e),
+                    }
+                    false
+                }
+                _ = self.retired() => true,
+            };
+            self.launched.remove(&launch_id);
+            if retired {
+                info!("contract {:} is no longer defined, stop it", name);
+                return;
+            }
+
+            if started.elapsed() >= self.config.max_backoff {
+                backoff = self.config.min_backoff;
+            }
+            info!("restart contract {:} in {:?}", name, backoff);
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {}
+                _ = self.retired() => {}
+            }
+            backoff = std::cmp::min(backoff * 2, self.config.max_backoff);
+        }
+    }
+}
+
+// wait captures the output of a contract process until it exits
+async fn wait(name: &str, mut child: Child) -> Result<ExitStatus> {
+    if let Some(stdout) = child.stdout.take() {
+        tokio::spawn(capture(name.to_string(), stdout));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(capture(name.to_string(), stderr));
+    }
+    Ok(child.wait().await?)
+}
+
+// capture forwards the output of a contract to the log, line by line
+async fn capture(name: String, output: impl AsyncRead + Unpin) {
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        info!("[{:}] {:}", name, line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    // install installs a package running forever as a version of the contract "cc"
+    fn install(package_dir: &std::path::Path, version: &str) -> ContractDefinition {
+        let package = b"#!/bin/sh\nexec sleep 60\n";
+        let dir = package_dir.join("cc");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(version);
+        std::fs::write(&path, package).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        ContractDefinition {
+            name: "cc".to_string(),
+            version: version.to_string(),
+            package_hash: utils::hash::compute_sha256(package).to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_redefine() {
+        let package_dir =
+            std::env::temp_dir().join(format!("silk-launcher-{:}", std::process::id()));
+        let (v1, v2) = (install(&package_dir, "1"), install(&package_dir, "2"));
+        let launcher = Launcher::new(LauncherConfig::new(&package_dir, "http://127.0.0.1:8081"));
+        let supervised = |version: &str| {
+            launcher
+                .supervised
+                .contains(&("cc".to_string(), version.to_string()))
+        };
+
+        launcher.launch(&v1).unwrap();
+        assert!(supervised("1"));
+        // a former definition is not launched again
+        launcher.define(&v2);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while supervised("1") && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!supervised("1"));
+        launcher.launch(&v2).unwrap();
+        assert!(supervised("2"));
+
+        // the launch id of the running process gives the definition it was started for
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while launcher.launched.len() != 1 && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let launch_id = launcher.launched.iter().next().unwrap().key().clone();
+        assert_eq!(launcher.launched(&launch_id), Some(v2));
+        assert!(launcher.launched("unknown").is_none());
+        std::fs::remove_dir_all(&package_dir).unwrap();
+    }
+}
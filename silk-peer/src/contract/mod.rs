@@ -2,12 +2,17 @@ mod server;
 pub use server::*;
 
 mod handler;
+mod launcher;
 mod support;
-use dashmap::mapref::one::Ref;
+mod wasm;
 use error::*;
 pub use handler::*;
+pub use launcher::*;
+use ledger::rwset::builder::TxSimulationResults;
 use silk_proto::*;
+use std::sync::{Arc, Mutex};
 pub use support::*;
+pub use wasm::*;
 
 // https://github.com/libp2p/rust-libp2p/blob/master/core/src/identity.rs
 #[async_trait::async_trait]
@@ -27,9 +32,9 @@ pub trait IContractSupport: Send + Sync + 'static {
     // PIN
     fn register(&self, contract: Contract) -> Result<()>;
     // PIN
-    fn deregister(&self, name: &str) -> Result<()>;
+    fn deregister(&self, contract: &Contract) -> Result<()>;
     // PIN
-    fn launch(&self, name: &str) -> Option<Ref<String, Contract>>;
+    fn launch(&self, name: &str) -> Option<Contract>;
     // PIN
     async fn execute(
         &self,
@@ -51,21 +56,95 @@ pub struct TransactionParams {
     //  pub signed_proposal: &'a SignedProposal,
     pub proposal: Proposal,
     pub tx_simulator: TxSimulator,
+    // the execution timeout asked by the proposal, the default of the contract support otherwise
+    pub timeout: Option<std::time::Duration>,
+    // the committed definition of the contract, only the instances matching it run the proposal
+    pub definition: Option<ContractDefinition>,
 }
 
+// ExecuteTimeout is the error of a contract execution that did not complete in time
+#[derive(Debug)]
+pub struct ExecuteTimeout {
+    pub contract: String,
+    pub tx_id: String,
+    pub timeout: std::time::Duration,
+}
+
+impl std::fmt::Display for ExecuteTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "contract {:} did not complete transaction {:} in {:?}",
+            self.contract, self.tx_id, self.timeout
+        )
+    }
+}
+
+impl std::error::Error for ExecuteTimeout {}
+
+// InstanceClosed is the error of a transaction whose contract instance went away before completing
+// it. The transaction may be retried on another instance unless the contract accessed the state
+#[derive(Debug)]
+pub struct InstanceClosed {
+    pub contract: String,
+    pub instance_id: u64,
+    pub retryable: bool,
+}
+
+impl std::fmt::Display for InstanceClosed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "instance {:} of contract {:} is closed",
+            self.instance_id, self.contract
+        )
+    }
+}
+
+impl std::error::Error for InstanceClosed {}
+
+// TxSimulator is the ledger simulator of a proposal, shared with the handler answering the state
+// requests of the contract
 #[derive(Clone)]
-pub struct TxSimulator {}
+pub struct TxSimulator {
+    inner: Arc<Mutex<Box<dyn ledger::simulator::TxSimulator + Send>>>,
+}
 
 impl TxSimulator {
-    pub fn get_tx_simulation_results(&self) -> Result<TxReadWriteSet> {
-        Ok(TxReadWriteSet::default())
+    pub fn new(simulator: Box<dyn ledger::simulator::TxSimulator + Send>) -> Self {
+        TxSimulator {
+            inner: Arc::new(Mutex::new(simulator)),
+        }
+    }
+
+    // with runs a function on the ledger simulator
+    pub fn with<R>(
+        &self,
+        f: impl FnOnce(&mut dyn ledger::simulator::TxSimulator) -> Result<R>,
+    ) -> Result<R> {
+        let mut simulator = self.inner.lock().map_err(|e| from_str(&e.to_string()))?;
+        f(simulator.as_mut())
+    }
+
+    pub fn get_tx_simulation_results(&self) -> Result<TxSimulationResults> {
+        self.with(|s| s.get_tx_simulation_results())
+    }
+
+    // done releases the committed state the simulation reads
+    pub fn done(&self) {
+        if let Ok(mut simulator) = self.inner.lock() {
+            simulator.done();
+        }
     }
 }
 
 pub struct TransactionContext {
     channel_id: String,
+    tx_id: String,
     namespace: String,
     proposal: Proposal,
-    response_notifier: tokio::sync::oneshot::Sender<TransactionCompleted>,
+    response_notifier: tokio::sync::oneshot::Sender<Result<TransactionCompleted>>,
     simulator: TxSimulator,
+    // whether the contract sent a state request for the transaction
+    accessed: bool,
 }
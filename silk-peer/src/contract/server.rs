@@ -18,7 +18,7 @@ impl<S: IContractSupport> Server<S> {
 }
 
 #[async_trait::async_trait]
-impl<S: IContractSupport> contract_server::Contract for Server<S> {
+impl<S: IContractSupport + Clone> contract_server::Contract for Server<S> {
     type RegisterStream = Receiver<Message>; //mpsc::Receiver<Result<Message, Status>>;
                                              // Pin<Box<dyn Stream<Item = Result<Message, Status>> + Send + Sync>>;
 
@@ -30,7 +30,7 @@ impl<S: IContractSupport> contract_server::Contract for Server<S> {
 
         let mut stream = request.into_inner();
 
-        let handler = match stream.next().await.unwrap() {
+        let mut handler = match stream.next().await.unwrap() {
             Ok(msg) => match msg.message_type {
                 t if t == MessageType::ContractRegister as i32 => {
                     let register: ContractRegister = utils::proto::unmarshal(&msg.content)
@@ -50,6 +50,7 @@ impl<S: IContractSupport> contract_server::Contract for Server<S> {
             Err(s) => return Err(s),
         };
 
+        let support = self.support.clone();
         tokio::spawn(async move {
             while let Some(res) = stream.next().await {
                 match res {
@@ -70,6 +71,11 @@ impl<S: IContractSupport> contract_server::Contract for Server<S> {
                     }
                 }
             }
+            // the instance goes away with its stream
+            if let Err(e) = support.deregister(&handler) {
+                error!("deregister contract {:?}: {:?}", handler.contract_id(), e);
+            }
+            handler.close();
         });
 
         Ok(Response::new(rx))
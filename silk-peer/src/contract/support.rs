@@ -1,33 +1,244 @@
 use super::*;
 use std::sync::Arc;
 
-use dashmap::mapref::one::Ref;
 use dashmap::DashMap;
-use failure::_core::time::Duration;
 use silk_proto::message::MessageType;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
 
-#[derive(Clone, Default)]
+// DEFAULT_EXECUTE_TIMEOUT is the execution timeout of the proposals not asking for one
+pub const DEFAULT_EXECUTE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Balancing is how a transaction is dispatched among the instances of a contract
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Balancing {
+    RoundRobin,
+    LeastInflight,
+}
+
+impl std::str::FromStr for Balancing {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "round-robin" => Ok(Balancing::RoundRobin),
+            "least-inflight" => Ok(Balancing::LeastInflight),
+            _ => Err(from_str(&format!("unknown balancing '{:}'", s))),
+        }
+    }
+}
+
+// Instances are the registered instances of a contract
+#[derive(Default)]
+struct Instances {
+    contracts: Vec<Contract>,
+    // the round-robin position
+    next: usize,
+}
+
+#[derive(Clone)]
 pub struct ContractSupport {
-    handler_registry: Arc<DashMap<String, Contract>>,
+    handler_registry: Arc<DashMap<String, Instances>>,
+    execute_timeout: Duration,
+    balancing: Balancing,
+    timeouts: Arc<AtomicU64>,
+    launcher: Option<Launcher>,
+    wasm: Option<WasmRuntime>,
+    // whether the instances the peer did not launch are trusted with their package hash
+    unverified_packages: bool,
+    // notified at every registration
+    registered: Arc<Notify>,
+}
+
+impl Default for ContractSupport {
+    fn default() -> Self {
+        ContractSupport::new(DEFAULT_EXECUTE_TIMEOUT)
+    }
+}
+
+impl ContractSupport {
+    pub fn new(execute_timeout: Duration) -> Self {
+        ContractSupport {
+            handler_registry: Arc::new(DashMap::new()),
+            execute_timeout,
+            balancing: Balancing::RoundRobin,
+            timeouts: Arc::new(AtomicU64::new(0)),
+            launcher: None,
+            wasm: None,
+            unverified_packages: false,
+            registered: Arc::new(Notify::new()),
+        }
+    }
+
+    // with_launcher makes the support start the installed contracts having no instance
+    pub fn with_launcher(mut self, launcher: Launcher) -> Self {
+        self.launcher = Some(launcher);
+        self
+    }
+
+    // with_wasm makes the support run the contracts installed as WASM in the peer, the other
+    // contracts still run in their own processes
+    pub fn with_wasm(mut self, wasm: WasmRuntime) -> Self {
+        self.wasm = Some(wasm);
+        self
+    }
+
+    // with_unverified_packages makes the support take the package hash the instances it did not
+    // launch register with, for the contracts run apart from the peer e.g. while developing them
+    pub fn with_unverified_packages(mut self) -> Self {
+        self.unverified_packages = true;
+        self
+    }
+
+    pub fn with_balancing(mut self, balancing: Balancing) -> Self {
+        self.balancing = balancing;
+        self
+    }
+
+    // timed_out_executions returns the number of the executions that did not complete in time
+    pub fn timed_out_executions(&self) -> u64 {
+        self.timeouts.load(Ordering::Relaxed)
+    }
+
+    // verify gives an instance the package hash computed by the peer, the hash of the package it
+    // launched the instance from. The instances started apart from the peer are refused, unless
+    // their package hash is trusted
+    fn verify(&self, contract: Contract) -> Result<Contract> {
+        let launched = match &self.launcher {
+            Some(launcher) if !contract.launch_id().is_empty() => {
+                launcher.launched(contract.launch_id())
+            }
+            _ => None,
+        };
+        match launched {
+            Some(d) if d.name == contract.contract_id() && d.version == contract.version() => {
+                Ok(contract.verified(d.package_hash))
+            }
+            Some(d) => Err(from_str(&format!(
+                "the instance registered as contract {:} was launched for version {:} of contract {:}",
+                contract.contract_id(),
+                d.version,
+                d.name
+            ))),
+            None if self.unverified_packages => {
+                let package_hash = contract.package_hash().to_vec();
+                Ok(contract.verified(package_hash))
+            }
+            None => Err(from_str(&format!(
+                "contract {:} was not launched by the peer, its package can not be verified",
+                contract.contract_id()
+            ))),
+        }
+    }
+
+    // select picks an instance of a contract matching its definition, if any, skipping the
+    // instances already tried
+    fn select(
+        &self,
+        name: &str,
+        definition: Option<&ContractDefinition>,
+        tried: &[u64],
+    ) -> Option<Contract> {
+        let mut instances = self.handler_registry.get_mut(name)?;
+        let candidates: Vec<usize> = (0..instances.contracts.len())
+            .filter(|i| {
+                let contract = &instances.contracts[*i];
+                !tried.contains(&contract.instance_id())
+                    && definition.iter().all(|d| contract.matches(d))
+            })
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        let index = match self.balancing {
+            Balancing::RoundRobin => {
+                let index = candidates[instances.next % candidates.len()];
+                instances.next = instances.next.wrapping_add(1);
+                index
+            }
+            Balancing::LeastInflight => *candidates
+                .iter()
+                .min_by_key(|i| instances.contracts[**i].inflight())?,
+        };
+        Some(instances.contracts[index].clone())
+    }
+}
+
+impl ContractSupport {
+    // launch starts the package of a defined contract having no instance and waits for the
+    // registration of an instance
+    async fn launch_instance(
+        &self,
+        contract: &str,
+        tx_params: &TransactionParams,
+    ) -> Result<Contract> {
+        let (launcher, definition) = match (&self.launcher, &tx_params.definition) {
+            (Some(launcher), Some(definition)) => (launcher, definition),
+            (_, Some(d)) => {
+                return Err(from_str(&format!(
+                    "no instance of contract {:} runs version {:} of package {:}",
+                    contract,
+                    d.version,
+                    utils::hash::hex_to_string(&d.package_hash)
+                )))
+            }
+            (_, None) => return Err(from_str("contract not found")),
+        };
+        launcher.launch(definition)?;
+
+        let deadline = Instant::now() + launcher.register_timeout();
+        loop {
+            if let Some(h) = self.select(contract, Some(definition), &[]) {
+                return Ok(h);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(from_str(&format!(
+                    "version {:} of contract {:} did not register in {:?}",
+                    definition.version,
+                    contract,
+                    launcher.register_timeout()
+                )));
+            }
+            // a registration may come before the wait, the wait is bounded
+            let wait = std::cmp::min(deadline - now, Duration::from_millis(100));
+            let _ = tokio::time::timeout(wait, self.registered.notified()).await;
+        }
+    }
 }
 
 #[async_trait::async_trait]
 impl IContractSupport for ContractSupport {
     // PIN
     fn register(&self, contract: Contract) -> Result<()> {
+        let contract = self.verify(contract)?;
         self.handler_registry
-            .insert(contract.contract_id(), contract);
+            .entry(contract.contract_id())
+            .or_default()
+            .contracts
+            .push(contract);
+        self.registered.notify_waiters();
         Ok(())
     }
 
     // PIN
-    fn deregister(&self, _name: &str) -> Result<()> {
-        unimplemented!()
+    fn deregister(&self, contract: &Contract) -> Result<()> {
+        let name = contract.contract_id();
+        if let Some(mut instances) = self.handler_registry.get_mut(&name) {
+            instances
+                .contracts
+                .retain(|c| c.instance_id() != contract.instance_id());
+        }
+        self.handler_registry
+            .remove_if(&name, |_, instances| instances.contracts.is_empty());
+        Ok(())
     }
 
     // PIN
-    fn launch(&self, name: &str) -> Option<Ref<String, Contract>> {
-        self.handler_registry.get(name)
+    fn launch(&self, name: &str) -> Option<Contract> {
+        self.select(name, None, &[])
     }
 
     // PIN
@@ -49,6 +260,16 @@ impl IContractSupport for ContractSupport {
         tx_params: &TransactionParams,
         contract: &str,
     ) -> Result<TransactionCompleted> {
+        // the launched packages of the former definitions are stopped
+        if let (Some(launcher), Some(definition)) = (&self.launcher, &tx_params.definition) {
+            launcher.define(definition);
+        }
+        if let (Some(wasm), Some(definition)) = (&self.wasm, &tx_params.definition) {
+            if let Some(module) = wasm.module(definition)? {
+                return wasm.execute(module, tx_params, contract).await;
+            }
+        }
+
         let ct = ContractTransaction {
             proposal: Some(tx_params.proposal.clone()),
         };
@@ -58,10 +279,174 @@ impl IContractSupport for ContractSupport {
             correlation_id: tx_params.channel_id.clone() + &tx_params.tx_id,
             content: payload,
         };
-        let r = self
-            .launch(contract)
-            .ok_or_else(|| from_str("contract not found"))?;
-        let h = &*r;
-        h.execute(tx_params, msg, Duration::from_secs(5)).await
+        let timeout = tx_params.timeout.unwrap_or(self.execute_timeout);
+
+        // a transaction whose instance closed before it touched the state is retried on another
+        let mut tried = vec![];
+        loop {
+            let h = match self.select(contract, tx_params.definition.as_ref(), &tried) {
+                Some(h) => h,
+                None if tried.is_empty() => self.launch_instance(contract, tx_params).await?,
+                None => return Err(from_str("no instance of the contract is left")),
+            };
+            tried.push(h.instance_id());
+
+            let result = h.execute(tx_params, msg.clone(), timeout).await;
+            if let Err(e) = &result {
+                if e.downcast_ref::<ExecuteTimeout>().is_some() {
+                    let count = self.timeouts.fetch_add(1, Ordering::Relaxed) + 1;
+                    warn!("{:}, {:} executions timed out", e, count);
+                }
+                if let Some(closed) = e.downcast_ref::<InstanceClosed>() {
+                    self.deregister(&h)?;
+                    if closed.retryable {
+                        warn!("{:}, retry transaction {:}", e, tx_params.tx_id);
+                        continue;
+                    }
+                }
+            }
+            return result;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contract::handler::tests::{complete, instance, tx_params};
+    use std::sync::atomic::AtomicUsize;
+
+    // answer completes every transaction the instance receives, counting them
+    fn answer(contract: Contract, mut rx: Receiver<Message>) -> Arc<AtomicUsize> {
+        let count = Arc::new(AtomicUsize::new(0));
+        let received = count.clone();
+        tokio::spawn(async move {
+            while let Some(Ok(msg)) = rx.recv().await {
+                if msg.message_type == MessageType::ContractTransaction as i32 {
+                    received.fetch_add(1, Ordering::Relaxed);
+                    complete(&contract, &msg).await.unwrap();
+                }
+            }
+        });
+        count
+    }
+
+    // invoke runs a transaction of the contract "cc" and returns the id of the instance that
+    // completed it
+    async fn invoke(support: &ContractSupport, tx_id: &str) -> Result<u64> {
+        let params = tx_params(tx_id, Some(Duration::from_secs(5)));
+        let completed = support.invoke(&params, "cc").await?;
+        let payload = completed.response.unwrap().payload;
+        Ok(String::from_utf8(payload)?.parse()?)
+    }
+
+    #[test]
+    fn test_verify() {
+        let definition = ContractDefinition {
+            name: "cc".to_string(),
+            version: "1".to_string(),
+            package_hash: vec![1],
+            ..Default::default()
+        };
+        let (a, _rx) = instance();
+        assert!(!a.matches(&definition));
+
+        // the package of an instance the peer did not launch is unknown
+        let support = ContractSupport::default();
+        assert!(support.register(a.clone()).is_err());
+        assert!(support.launch("cc").is_none());
+
+        let support = ContractSupport::default().with_unverified_packages();
+        support.register(a).unwrap();
+        let registered = support.select("cc", Some(&definition), &[]).unwrap();
+        assert!(registered.matches(&definition));
+    }
+
+    #[tokio::test]
+    async fn test_round_robin() {
+        let support = ContractSupport::default().with_unverified_packages();
+        let ((a, rx_a), (b, rx_b)) = (instance(), instance());
+        support.register(a.clone()).unwrap();
+        support.register(b.clone()).unwrap();
+        answer(a.clone(), rx_a);
+        answer(b.clone(), rx_b);
+
+        let mut ids = vec![];
+        for tx_id in &["tx1", "tx2", "tx3", "tx4"] {
+            ids.push(invoke(&support, tx_id).await.unwrap());
+        }
+        let (a, b) = (a.instance_id(), b.instance_id());
+        assert_eq!(ids, vec![a, b, a, b]);
+    }
+
+    #[tokio::test]
+    async fn test_least_inflight() {
+        let support = ContractSupport::default()
+            .with_unverified_packages()
+            .with_balancing(Balancing::LeastInflight);
+        // the first instance keeps its transactions
+        let ((a, mut rx_a), (b, rx_b)) = (instance(), instance());
+        support.register(a.clone()).unwrap();
+        support.register(b.clone()).unwrap();
+        let answered = answer(b.clone(), rx_b);
+
+        let stuck = support.clone();
+        tokio::spawn(async move { invoke(&stuck, "tx1").await });
+        rx_a.recv().await.unwrap().unwrap();
+        assert_eq!(a.inflight(), 1);
+
+        for tx_id in &["tx2", "tx3"] {
+            assert_eq!(invoke(&support, tx_id).await.unwrap(), b.instance_id());
+        }
+        assert_eq!(answered.load(Ordering::Relaxed), 2);
+        assert_eq!(b.inflight(), 0);
+        assert_eq!(a.inflight(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_close() {
+        for accessed in &[false, true] {
+            let support = ContractSupport::default().with_unverified_packages();
+            let ((a, mut rx_a), (b, rx_b)) = (instance(), instance());
+            support.register(a.clone()).unwrap();
+            support.register(b.clone()).unwrap();
+            let answered = answer(b.clone(), rx_b);
+
+            let running = support.clone();
+            let tx = tokio::spawn(async move { invoke(&running, "tx1").await });
+            let msg = rx_a.recv().await.unwrap().unwrap();
+            if *accessed {
+                let req = GetStateRequest {
+                    key: "k".to_string(),
+                    channel_id: "ch1".to_string(),
+                    tx_id: "tx1".to_string(),
+                };
+                a.handler(Message {
+                    message_type: MessageType::ContractGetStateRequest as i32,
+                    correlation_id: msg.correlation_id.clone(),
+                    content: utils::proto::marshal(&req).unwrap(),
+                })
+                .await
+                .unwrap();
+            }
+            // the instance goes away in the middle of the transaction
+            a.clone().close();
+
+            let result = tx.await.unwrap();
+            if *accessed {
+                // the contract may have acted on the state it read, the transaction fails
+                let err = result.unwrap_err();
+                let closed = err.downcast_ref::<InstanceClosed>().unwrap();
+                assert_eq!(closed.instance_id, a.instance_id());
+                assert!(!closed.retryable);
+                assert_eq!(answered.load(Ordering::Relaxed), 0);
+            } else {
+                assert_eq!(result.unwrap(), b.instance_id());
+                assert_eq!(answered.load(Ordering::Relaxed), 1);
+            }
+            // the closed instance is deregistered
+            assert_eq!(support.launch("cc").unwrap().instance_id(), b.instance_id());
+            assert_eq!(support.launch("cc").unwrap().instance_id(), b.instance_id());
+        }
     }
 }
@@ -0,0 +1,618 @@
+use super::*;
+use dashmap::DashMap;
+use std::path::PathBuf;
+use wasmi::core::TrapCode;
+use wasmi::{Caller, Config, Engine, Extern, Linker, Memory, Module, Store, StoreLimits};
+
+// the module of the host functions imported by the WASM contracts
+const HOST_MODULE: &str = "silk";
+// the fuel charged for a host call, on top of one unit per byte it moves
+const HOST_CALL_FUEL: u64 = 1_000;
+// the fuel charged for every entry get_range reads, on top of one unit per byte of the entry
+const RANGE_ENTRY_FUEL: u64 = 100;
+
+type HostResult<T> = std::result::Result<T, wasmi::Error>;
+
+// WasmConfig configures the runtime of the WASM contracts. A version of a contract compiled to WASM
+// is installed in the package directory as `<name>/<version>.wasm`
+#[derive(Clone, Debug)]
+pub struct WasmConfig {
+    pub package_dir: PathBuf,
+    // the fuel an execution may burn, roughly one unit per instruction
+    pub fuel: u64,
+    // the bytes of linear memory a contract may use
+    pub max_memory: usize,
+}
+
+impl WasmConfig {
+    pub fn new(package_dir: impl Into<PathBuf>) -> Self {
+        WasmConfig {
+            package_dir: package_dir.into(),
+            fuel: 1_000_000_000,
+            max_memory: 16 << 20,
+        }
+    }
+}
+
+// WasmRuntime runs the contracts compiled to WASM in the peer, on the simulator of the proposal.
+// A contract exports its linear memory as `memory` and the functions `init` and `invoke`, taking
+// no argument and returning nothing. It reaches the peer through the functions of the `silk`
+// module, the lengths and pointers being i32 into its memory:
+//
+//   tx_id() -> len                          the transaction id
+//   channel_id() -> len                     the channel id
+//   arg_count() -> count                    the number of arguments of the proposal
+//   arg(index) -> len                       an argument, -1 when out of range
+//   get_state(key, key_len) -> len          the value of a key, empty when missing
+//   put_state(key, key_len, value, value_len)
+//   del_state(key, key_len)
+//   get_range(start, start_len, end, end_len) -> len
+//                                           the keys of [start, end), each as the little endian
+//                                           u32 length of the key, the key, then the same for
+//                                           the value. Every entry is charged as it
+//                                           is read
+//   set_event(name, name_len, payload, payload_len)
+//   set_response(status, message, message_len, payload, payload_len)
+//   read_result(ptr)                        copies the output of the last call returning a
+//                                           length to ptr
+//   log(message, message_len)
+//
+// The executions are bounded by fuel rather than by the execution timeout, and a contract
+// burning all its fuel or growing its memory over the limit fails the proposal
+#[derive(Clone)]
+pub struct WasmRuntime {
+    config: Arc<WasmConfig>,
+    engine: Engine,
+    linker: Arc<Linker<HostState>>,
+    // the compiled packages, by package hash
+    modules: Arc<DashMap<Vec<u8>, Arc<Module>>>,
+}
+
+// HostState is the state of an execution the host functions work on
+struct HostState {
+    contract: String,
+    tx_id: String,
+    channel_id: String,
+    args: Vec<Vec<u8>>,
+    simulator: TxSimulator,
+    // the output of the last host call returning a length
+    result: Vec<u8>,
+    response: Option<Response>,
+    event: Option<ContractEvent>,
+    limits: StoreLimits,
+}
+
+impl WasmRuntime {
+    pub fn new(config: WasmConfig) -> Result<Self> {
+        let mut wasm_config = Config::default();
+        wasm_config.consume_fuel(true);
+        let engine = Engine::new(&wasm_config);
+        let linker = host_functions(&engine)?;
+        Ok(WasmRuntime {
+            config: Arc::new(config),
+            engine,
+            linker: Arc::new(linker),
+            modules: Arc::new(DashMap::new()),
+        })
+    }
+
+    // module returns the compiled package of a contract definition, None when the contract is not
+    // installed as WASM. The package must be the one of the definition
+    pub fn module(&self, definition: &ContractDefinition) -> Result<Option<Arc<Module>>> {
+        if let Some(module) = self.modules.get(&definition.package_hash) {
+            return Ok(Some(module.clone()));
+        }
+        let path = self
+            .config
+            .package_dir
+            .join(&definition.name)
+            .join(format!("{:}.wasm", definition.version));
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let package = std::fs::read(&path)?;
+        if *utils::hash::compute_sha256(&package) != definition.package_hash[..] {
+            return Err(from_str(&format!(
+                "the package installed at {:?} is not the package of the contract definition",
+                path
+            )));
+        }
+        let module = Module::new(&self.engine, &package[..])
+            .map_err(|e| from_str(&format!("invalid WASM package {:?}: {:}", path, e)))?;
+        let module = Arc::new(module);
+        self.modules
+            .insert(definition.package_hash.clone(), module.clone());
+        Ok(Some(module))
+    }
+
+    // execute runs a proposal on a WASM contract, apart from the async tasks of the peer
+    pub async fn execute(
+        &self,
+        module: Arc<Module>,
+        tx_params: &TransactionParams,
+        contract: &str,
+    ) -> Result<TransactionCompleted> {
+        let input =
+            utils::proto::unmarshal::<ContractProposalPayload>(&tx_params.proposal.payload)?
+                .input
+                .ok_or_else(|| from_str("input is null"))?;
+        let state = HostState {
+            contract: contract.to_string(),
+            tx_id: tx_params.tx_id.clone(),
+            channel_id: tx_params.channel_id.clone(),
+            args: input.args,
+            simulator: tx_params.tx_simulator.clone(),
+            result: vec![],
+            response: None,
+            event: None,
+            limits: wasmi::StoreLimitsBuilder::new()
+                .memory_size(self.config.max_memory)
+                .build(),
+        };
+        let entry = if input.is_init { "init" } else { "invoke" };
+
+        let runtime = self.clone();
+        let (response, event) =
+            tokio::task::spawn_blocking(move || runtime.run(module, state, entry)).await??;
+        Ok(TransactionCompleted {
+            proposal: Some(tx_params.proposal.clone()),
+            response: Some(response),
+            event,
+        })
+    }
+
+    fn run(
+        &self,
+        module: Arc<Module>,
+        state: HostState,
+        entry: &str,
+    ) -> Result<(Response, Option<ContractEvent>)> {
+        let contract = state.contract.clone();
+        let failed = |e: wasmi::Error| match e.as_trap_code() {
+            Some(TrapCode::OutOfFuel) => from_str(&format!(
+                "contract {:} burned all its fuel of {:}",
+                contract, self.config.fuel
+            )),
+            _ => from_str(&format!("contract {:} failed: {:}", contract, e)),
+        };
+
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store
+            .set_fuel(self.config.fuel)
+            .map_err(|e| failed(e.into()))?;
+        let instance = self
+            .linker
+            .instantiate(&mut store, &module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(failed)?;
+        let func = instance
+            .get_typed_func::<(), ()>(&store, entry)
+            .map_err(|_| from_str(&format!("contract {:} exports no {:}", contract, entry)))?;
+        func.call(&mut store, ()).map_err(failed)?;
+        debug!(
+            "contract {:} burned {:} fuel",
+            contract,
+            self.config.fuel - store.get_fuel().unwrap_or(0)
+        );
+
+        let state = store.into_data();
+        let response = state
+            .response
+            .ok_or_else(|| from_str(&format!("contract {:} set no response", contract)))?;
+        Ok((response, state.event))
+    }
+}
+
+// charge burns the fuel of a host call moving the given bytes
+fn charge(caller: &mut Caller<'_, HostState>, bytes: usize) -> HostResult<()> {
+    let fuel = caller.get_fuel()?;
+    let cost = HOST_CALL_FUEL.saturating_add(bytes as u64);
+    if fuel < cost {
+        caller.set_fuel(0)?;
+        return Err(TrapCode::OutOfFuel.into());
+    }
+    caller.set_fuel(fuel - cost)?;
+    Ok(())
+}
+
+fn memory(caller: &Caller<'_, HostState>) -> HostResult<Memory> {
+    match caller.get_export("memory") {
+        Some(Extern::Memory(memory)) => Ok(memory),
+        _ => Err(wasmi::Error::new("the contract exports no memory")),
+    }
+}
+
+// read copies a slice of the contract memory
+fn read(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> HostResult<Vec<u8>> {
+    let memory = memory(caller)?;
+    if ptr < 0 || len < 0 || (ptr as usize + len as usize) > memory.data(&*caller).len() {
+        return Err(TrapCode::MemoryOutOfBounds.into());
+    }
+    charge(caller, len as usize)?;
+    let mut buf = vec![0; len as usize];
+    memory.read(&*caller, ptr as usize, &mut buf)?;
+    Ok(buf)
+}
+
+fn read_string(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> HostResult<String> {
+    String::from_utf8(read(caller, ptr, len)?).map_err(|e| wasmi::Error::new(e.to_string()))
+}
+
+// read_key reads a state key, which can not be empty
+fn read_key(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> HostResult<String> {
+    let key = read_string(caller, ptr, len)?;
+    if key.is_empty() {
+        return Err(wasmi::Error::new("empty state key"));
+    }
+    Ok(key)
+}
+
+// set_result keeps the output of a host call for read_result, returning its length
+fn set_result(caller: &mut Caller<'_, HostState>, result: Vec<u8>) -> HostResult<i32> {
+    charge(caller, result.len())?;
+    let len = result.len() as i32;
+    caller.data_mut().result = result;
+    Ok(len)
+}
+
+// with_simulator runs a function on the simulator of the execution
+fn with_simulator<R>(
+    caller: &Caller<'_, HostState>,
+    f: impl FnOnce(&mut dyn ledger::simulator::TxSimulator) -> Result<R>,
+) -> HostResult<R> {
+    caller
+        .data()
+        .simulator
+        .with(f)
+        .map_err(|e| wasmi::Error::new(e.to_string()))
+}
+
+// encode_range encodes the key-values of a range for get_range
+fn encode_range(kvs: impl Iterator<Item = (String, Vec<u8>)>) -> Vec<u8> {
+    let mut buf = vec![];
+    for (key, value) in kvs {
+        buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        buf.extend_from_slice(key.as_bytes());
+        buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&value);
+    }
+    buf
+}
+
+fn host_functions(engine: &Engine) -> Result<Linker<HostState>> {
+    let mut linker = Linker::new(engine);
+    let wrap_err = |e: wasmi::errors::LinkerError| from_str(&e.to_string());
+
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "tx_id",
+            |mut caller: Caller<'_, HostState>| -> HostResult<i32> {
+                let tx_id = caller.data().tx_id.clone().into_bytes();
+                set_result(&mut caller, tx_id)
+            },
+        )
+        .map_err(wrap_err)?;
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "channel_id",
+            |mut caller: Caller<'_, HostState>| -> HostResult<i32> {
+                let channel_id = caller.data().channel_id.clone().into_bytes();
+                set_result(&mut caller, channel_id)
+            },
+        )
+        .map_err(wrap_err)?;
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "arg_count",
+            |mut caller: Caller<'_, HostState>| -> HostResult<i32> {
+                charge(&mut caller, 0)?;
+                Ok(caller.data().args.len() as i32)
+            },
+        )
+        .map_err(wrap_err)?;
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "arg",
+            |mut caller: Caller<'_, HostState>, index: i32| -> HostResult<i32> {
+                let arg = match caller.data().args.get(index as usize) {
+                    Some(arg) if index >= 0 => arg.clone(),
+                    _ => return Ok(-1),
+                };
+                set_result(&mut caller, arg)
+            },
+        )
+        .map_err(wrap_err)?;
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "get_state",
+            |mut caller: Caller<'_, HostState>, key: i32, key_len: i32| -> HostResult<i32> {
+                let key = read_key(&mut caller, key, key_len)?;
+                let namespace = caller.data().contract.clone();
+                let value = with_simulator(&caller, |s| s.get_state(&namespace, &key))?;
+                set_result(&mut caller, value)
+            },
+        )
+        .map_err(wrap_err)?;
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "put_state",
+            |mut caller: Caller<'_, HostState>,
+             key: i32,
+             key_len: i32,
+             value: i32,
+             value_len: i32|
+             -> HostResult<()> {
+                let key = read_key(&mut caller, key, key_len)?;
+                let value = read(&mut caller, value, value_len)?;
+                let namespace = caller.data().contract.clone();
+                with_simulator(&caller, |s| s.set_state(&namespace, &key, value))
+            },
+        )
+        .map_err(wrap_err)?;
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "del_state",
+            |mut caller: Caller<'_, HostState>, key: i32, key_len: i32| -> HostResult<()> {
+                let key = read_key(&mut caller, key, key_len)?;
+                let namespace = caller.data().contract.clone();
+                with_simulator(&caller, |s| s.delete_state(&namespace, &key))
+            },
+        )
+        .map_err(wrap_err)?;
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "get_range",
+            |mut caller: Caller<'_, HostState>,
+             start: i32,
+             start_len: i32,
+             end: i32,
+             end_len: i32|
+             -> HostResult<i32> {
+                let start = read_string(&mut caller, start, start_len)?;
+                let end = read_string(&mut caller, end, end_len)?;
+                charge(&mut caller, 0)?;
+                let namespace = caller.data().contract.clone();
+                // the scan stops as soon as the entries read burn all the fuel left
+                let mut fuel = caller.get_fuel()?;
+                let range = with_simulator(&caller, |s| {
+                    let mut kvs = vec![];
+                    for kv in s.get_state_range_scan_iterator(&namespace, &start, &end)? {
+                        let cost = RANGE_ENTRY_FUEL
+                            .saturating_add(kv.key.len() as u64 + kv.value.len() as u64);
+                        if fuel < cost {
+                            return Ok(None);
+                        }
+                        fuel -= cost;
+                        kvs.push((kv.key, kv.value));
+                    }
+                    Ok(Some(encode_range(kvs.into_iter())))
+                })?;
+                match range {
+                    Some(range) => {
+                        caller.set_fuel(fuel)?;
+                        let len = range.len() as i32;
+                        caller.data_mut().result = range;
+                        Ok(len)
+                    }
+                    None => {
+                        caller.set_fuel(0)?;
+                        Err(TrapCode::OutOfFuel.into())
+                    }
+                }
+            },
+        )
+        .map_err(wrap_err)?;
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "set_event",
+            |mut caller: Caller<'_, HostState>,
+             name: i32,
+             name_len: i32,
+             payload: i32,
+             payload_len: i32|
+             -> HostResult<()> {
+                let event_name = read_string(&mut caller, name, name_len)?;
+                let payload = read(&mut caller, payload, payload_len)?;
+                let state = caller.data_mut();
+                state.event = Some(ContractEvent {
+                    contract: state.contract.clone(),
+                    tx_id: state.tx_id.clone(),
+                    event_name,
+                    payload,
+                });
+                Ok(())
+            },
+        )
+        .map_err(wrap_err)?;
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "set_response",
+            |mut caller: Caller<'_, HostState>,
+             status: i32,
+             message: i32,
+             message_len: i32,
+             payload: i32,
+             payload_len: i32|
+             -> HostResult<()> {
+                let message = read_string(&mut caller, message, message_len)?;
+                let payload = read(&mut caller, payload, payload_len)?;
+                caller.data_mut().response = Some(Response {
+                    status,
+                    message,
+                    payload,
+                });
+                Ok(())
+            },
+        )
+        .map_err(wrap_err)?;
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "read_result",
+            |mut caller: Caller<'_, HostState>, ptr: i32| -> HostResult<()> {
+                let result = std::mem::take(&mut caller.data_mut().result);
+                let memory = memory(&caller)?;
+                if ptr < 0 {
+                    return Err(TrapCode::MemoryOutOfBounds.into());
+                }
+                memory.write(&mut caller, ptr as usize, &result)?;
+                Ok(())
+            },
+        )
+        .map_err(wrap_err)?;
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "log",
+            |mut caller: Caller<'_, HostState>, message: i32, message_len: i32| -> HostResult<()> {
+                let message = read(&mut caller, message, message_len)?;
+                info!(
+                    "[{:}] {:}",
+                    caller.data().contract,
+                    String::from_utf8_lossy(&message)
+                );
+                Ok(())
+            },
+        )
+        .map_err(wrap_err)?;
+    Ok(linker)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ledger::simulator::sim::BasedTxSimulator;
+    use ledger::statedb::{
+        Height, MemDBVersion, UpdateBatch, VersionedDB, VersionedDBMemProvider, VersionedDBProvider,
+    };
+
+    const IMPORTS: &str = r#"
+        (import "silk" "put_state" (func $put_state (param i32 i32 i32 i32)))
+        (import "silk" "get_range" (func $get_range (param i32 i32 i32 i32) (result i32)))
+        (import "silk" "set_response" (func $set_response (param i32 i32 i32 i32 i32)))
+    "#;
+
+    fn vdb() -> MemDBVersion {
+        VersionedDBMemProvider::new().get_db_handle("ch1").unwrap()
+    }
+
+    // run invokes the contract "cc" written in WAT with the fuel, on the state of the database
+    async fn run(wat: &str, fuel: u64, vdb: MemDBVersion) -> (Result<Response>, TxSimulator) {
+        let mut config = WasmConfig::new(std::env::temp_dir());
+        config.fuel = fuel;
+        let runtime = WasmRuntime::new(config).unwrap();
+        let wasm = wat::parse_str(format!("(module {:} {:})", IMPORTS, wat)).unwrap();
+        let module = Arc::new(Module::new(&runtime.engine, &wasm[..]).unwrap());
+        let payload = ContractProposalPayload {
+            input: Some(ContractInput::default()),
+            ..Default::default()
+        };
+        let params = TransactionParams {
+            tx_id: "tx1".to_string(),
+            channel_id: "ch1".to_string(),
+            namespace: "cc".to_string(),
+            proposal: Proposal {
+                header: None,
+                payload: utils::proto::marshal(&payload).unwrap(),
+            },
+            tx_simulator: TxSimulator::new(Box::new(BasedTxSimulator::new("tx1".to_string(), vdb))),
+            timeout: None,
+            definition: None,
+        };
+        let completed = runtime.execute(module, &params, "cc").await;
+        let response = completed.map(|completed| completed.response.unwrap());
+        (response, params.tx_simulator)
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let wat = r#"
+            (memory (export "memory") 1)
+            (data (i32.const 0) "kvok")
+            (func (export "invoke")
+                (call $put_state (i32.const 0) (i32.const 1) (i32.const 1) (i32.const 1))
+                (call $set_response (i32.const 200) (i32.const 2) (i32.const 2) (i32.const 0) (i32.const 0)))
+        "#;
+        let (response, simulator) = run(wat, 1_000_000, vdb()).await;
+        let response = response.unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.message, "ok");
+        let results = simulator.get_tx_simulation_results().unwrap();
+        let ns_rwset = &results.simulation_results.ns_rwset[0];
+        assert_eq!(ns_rwset.namespace, "cc");
+        let rwset: KvrwSet = utils::proto::unmarshal(&ns_rwset.rwset).unwrap();
+        assert_eq!(rwset.writes[0].key, "k");
+        assert_eq!(rwset.writes[0].value, b"v".to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_out_of_fuel() {
+        let wat = r#"
+            (memory (export "memory") 1)
+            (func (export "invoke") (loop $forever (br $forever)))
+        "#;
+        let err = run(wat, 100_000, vdb()).await.0.unwrap_err();
+        assert!(err.to_string().contains("burned all its fuel"), "{:}", err);
+    }
+
+    #[tokio::test]
+    async fn test_max_memory() {
+        // the status is the result of memory.grow, the former size in pages or -1
+        let grow = |pages: u32| {
+            format!(
+                r#"
+                (memory (export "memory") 1)
+                (func (export "invoke")
+                    (call $set_response (memory.grow (i32.const {:}))
+                        (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0)))
+                "#,
+                pages
+            )
+        };
+        // the memory is bounded to 16 MiB, 256 pages
+        let (response, _) = run(&grow(255), 1_000_000, vdb()).await;
+        assert_eq!(response.unwrap().status, 1);
+        let (response, _) = run(&grow(256), 1_000_000, vdb()).await;
+        assert_eq!(response.unwrap().status, -1);
+    }
+
+    #[tokio::test]
+    async fn test_get_range_fuel() {
+        let vdb = vdb();
+        let mut batch = UpdateBatch::new();
+        for i in 0..100 {
+            batch.put(
+                "cc",
+                &format!("k{:03}", i),
+                vec![0; 1000],
+                Height::new(0, 0),
+            );
+        }
+        vdb.apply_updates(batch, Some(Height::new(0, 0))).unwrap();
+        // the status is the length of the range of [k, l)
+        let wat = r#"
+            (memory (export "memory") 1)
+            (data (i32.const 0) "kl")
+            (func (export "invoke")
+                (call $set_response
+                    (call $get_range (i32.const 0) (i32.const 1) (i32.const 1) (i32.const 1))
+                    (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0)))
+        "#;
+        let (response, _) = run(wat, 1_000_000, vdb.clone()).await;
+        assert_eq!(response.unwrap().status, 100 * (4 + 4 + 4 + 1000));
+        // the entries are charged as they are read, the range does not fit the fuel
+        let err = run(wat, 50_000, vdb).await.0.unwrap_err();
+        assert!(err.to_string().contains("burned all its fuel"), "{:}", err);
+    }
+}
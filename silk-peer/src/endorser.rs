@@ -1,23 +1,43 @@
 use crate::contract::*;
+use crate::lifecycle;
 use crate::support::ISupport;
 use silk_proto::endorser_server::Endorser;
 use silk_proto::*;
 use tonic::Request;
 
 use error::*;
+use identity::signer::SigningIdentity;
+use ledger::lifecycle::LIFECYCLE_NS;
+use ledger::rwset::builder::TxSimulationResults;
 
 #[derive(Clone)]
 pub struct Server<S: ISupport> {
     support: S,
+    // the identity the proposal responses are endorsed with
+    signer: SigningIdentity,
 }
 
 impl<S: ISupport> Server<S> {
-    pub fn new(support: S) -> Self {
-        Server { support }
+    pub fn new(support: S, signer: SigningIdentity) -> Self {
+        Server { support, signer }
     }
 
-    // 验证签名，参数等
-    fn verify(&self, _sp: &SignedProposal, _creator: &[u8]) -> Result<()> {
+    // verify checks the creator of a proposal is a member of the channel, the proposal is signed
+    // by the creator and the tx id is derived from the nonce and the creator
+    fn verify(&self, sp: &SignedProposal, header: &Header) -> Result<()> {
+        let channel = self
+            .support
+            .get_channel(&header.channel_id)
+            .ok_or_else(|| from_str(&format!("channel {:} not found", header.channel_id)))?;
+        let creator = channel.msp().deserialize_identity(&header.creator)?;
+        creator.verify(&sp.proposal_bytes, &sp.signature)?;
+
+        if header.nonce.is_empty() {
+            return Err(from_str("nonce is empty"));
+        }
+        if header.tx_id != utils::utils::compute_tx_id(&header.nonce, &header.creator) {
+            return Err(from_str(&format!("invalid tx id {:}", header.tx_id)));
+        }
         Ok(())
     }
 
@@ -28,50 +48,88 @@ impl<S: ISupport> Server<S> {
             .clone()
             .ok_or_else(|| from_str("header is null"))?;
 
-        self.verify(&signed_proposal, &header.creator)?;
+        if let Err(e) = self.verify(&signed_proposal, &header) {
+            return Ok(error_response(Status::Forbidden, &e.to_string()));
+        }
 
         let payload: ContractProposalPayload = utils::proto::unmarshal(&proposal.payload)?;
         let contract = payload
             .contract_id
             .ok_or_else(|| from_str("contract id is null"))?;
-        if payload.input.is_none() {
-            return Err(from_str("input is null"));
-        }
+        let input = payload.input.ok_or_else(|| from_str("input is null"))?;
 
         let tx_simulator = self
             .support
-            .get_transaction_simulator(&header.channel_id, &header.tx_id)?
+            .get_transaction_simulator(&header.channel_id, &header.tx_id)
+            .await?
             .ok_or_else(|| from_str("simulator not found"))?;
 
-        let tx_params = TransactionParams {
+        let mut tx_params = TransactionParams {
             tx_id: header.tx_id.clone(),
             channel_id: header.channel_id.clone(),
             namespace: contract.name.clone(),
             // signed_proposal,
             proposal,
             tx_simulator,
+            timeout: if payload.timeout > 0 {
+                Some(std::time::Duration::from_millis(payload.timeout as u64))
+            } else {
+                None
+            },
+            definition: None,
         };
 
-        let (rw_set, response, event) = self.simulate_proposal(tx_params, &contract.name).await?;
+        // the lifecycle contract runs in the peer, the other contracts must be defined
+        if contract.name != LIFECYCLE_NS {
+            match lifecycle::check_invocation(&tx_params.tx_simulator, &contract, &input) {
+                Ok(Ok(definition)) => tx_params.definition = Some(definition),
+                Ok(Err((status, message))) => {
+                    tx_params.tx_simulator.done();
+                    return Ok(error_response(status, &message));
+                }
+                Err(e) => {
+                    tx_params.tx_simulator.done();
+                    return Err(e);
+                }
+            }
+        }
+
+        let (results, response, event) = match self
+            .simulate_proposal(tx_params, &contract.name, &input)
+            .await
+        {
+            Ok(r) => r,
+            Err(e) if e.downcast_ref::<ExecuteTimeout>().is_some() => {
+                return Ok(error_response(Status::RequestTimeout, &e.to_string()));
+            }
+            Err(e) => return Err(e),
+        };
 
         if response.status != 200 {
             return Err(from_str(&response.message));
         }
+        // the private writes are kept by the peer until the transaction is committed, only their
+        // hashes go in the endorsed results
+        if let Some(pvt_rwset) = &results.pvt_simulation_results {
+            self.support
+                .persist_pvt_rwset(&header.channel_id, &header.tx_id, pvt_rwset)?;
+        }
 
         let event_bytes = match event {
             Some(e) => utils::proto::marshal(&e).map_err(|e| into_status(Box::new(e)))?,
             None => vec![],
         };
-        let payload = ProposalResponsePayload {
-            results: utils::proto::marshal(&rw_set)?,
+        let payload = utils::proto::marshal(&ProposalResponsePayload {
+            results: utils::proto::marshal(&results.simulation_results)?,
             events: event_bytes,
-        };
+        })?;
+        let endorsement = self.signer.endorse(&payload)?;
         let proposal_response = ProposalResponse {
             version: 0,
             timestamp: None,
             response: Some(response),
-            payload: utils::proto::marshal(&payload)?,
-            endorsement: None,
+            payload,
+            endorsement: Some(endorsement),
         };
 
         if header.header_type == HeaderType::Invoke as i32 {
@@ -90,12 +148,33 @@ impl<S: ISupport> Server<S> {
         &self,
         tx_params: TransactionParams,
         contract: &str,
-    ) -> Result<(TxReadWriteSet, Response, Option<ContractEvent>)> {
-        let (resp, event) = self.support.execute(&tx_params, &contract).await?;
+        input: &ContractInput,
+    ) -> Result<(TxSimulationResults, Response, Option<ContractEvent>)> {
+        // the simulator releases the committed state once the contract is done, whatever the outcome
+        let result = if contract == LIFECYCLE_NS {
+            lifecycle::execute(&tx_params.tx_simulator, input).map(|resp| (resp, None))
+        } else {
+            self.support.execute(&tx_params, contract).await
+        };
+        let results = tx_params.tx_simulator.get_tx_simulation_results();
+        tx_params.tx_simulator.done();
 
-        let rw_set = tx_params.tx_simulator.get_tx_simulation_results()?;
+        let (resp, event) = result?;
+        Ok((results?, resp, event))
+    }
+}
 
-        Ok((rw_set, resp, event))
+fn error_response(status: Status, message: &str) -> ProposalResponse {
+    ProposalResponse {
+        version: 0,
+        timestamp: None,
+        response: Some(Response {
+            status: status as i32,
+            message: message.to_string(),
+            payload: vec![],
+        }),
+        payload: vec![],
+        endorsement: None,
     }
 }
 
@@ -113,17 +192,7 @@ impl<S: ISupport> Endorser for Server<S> {
             let signed_prop = batch.signed_proposal_list.remove(0);
             let proposal_response = match self.process(signed_prop).await {
                 Ok(r) => r,
-                Err(e) => ProposalResponse {
-                    version: 0,
-                    timestamp: None,
-                    response: Some(Response {
-                        status: 500,
-                        message: e.to_string(),
-                        payload: vec![],
-                    }),
-                    payload: vec![],
-                    endorsement: None,
-                },
+                Err(e) => error_response(Status::InternalServerError, &e.to_string()),
             };
             bret.proposal_response_list.insert(index, proposal_response)
         }
@@ -135,3 +204,251 @@ impl<S: ISupport> Endorser for Server<S> {
         into_rpc_response(Empty {})
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Server;
+    use crate::channel::Channel;
+    use crate::contract::{
+        Contract, ContractSupport, IContractSupport, TransactionParams, TxSimulator,
+    };
+    use crate::support::ISupport;
+    use error::*;
+    use identity::cert::{cert_params, create_ca_cert, create_cert, Register};
+    use identity::signer::{verify_endorsement, SigningIdentity};
+    use ledger::lifecycle::{definition_key, LIFECYCLE_NS};
+    use ledger::simulator::sim::BasedTxSimulator;
+    use ledger::statedb::{
+        Height, MemDBVersion, UpdateBatch, VersionedDB, VersionedDBMemProvider, VersionedDBProvider,
+    };
+    use silk_proto::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    // FakeSupport simulates the proposals of a channel on a memory state, its contracts write the
+    // key "k" unless they run on a contract support, and the transactions broadcast are kept
+    #[derive(Clone)]
+    struct FakeSupport {
+        channel: Channel,
+        vdb: MemDBVersion,
+        broadcast: Arc<Mutex<Vec<Transaction>>>,
+        contracts: Option<ContractSupport>,
+    }
+
+    #[async_trait::async_trait]
+    impl ISupport for FakeSupport {
+        fn get_channel(&self, channel_id: &str) -> Option<Channel> {
+            if channel_id == self.channel.name() {
+                Some(self.channel.clone())
+            } else {
+                None
+            }
+        }
+
+        async fn get_transaction_simulator(
+            &self,
+            _ledger: &str,
+            tx_id: &str,
+        ) -> Result<Option<TxSimulator>> {
+            let simulator = BasedTxSimulator::new(tx_id.to_string(), self.vdb.clone());
+            Ok(Some(TxSimulator::new(Box::new(simulator))))
+        }
+
+        fn get_ledger_height(&self, _channel_id: String) -> Result<u64> {
+            Ok(0)
+        }
+
+        fn get_transaction_by_id(&self, _channel_id: String, _tx_id: String) -> Result<()> {
+            Ok(())
+        }
+
+        fn persist_pvt_rwset(
+            &self,
+            _channel_id: &str,
+            _tx_id: &str,
+            _pvt_rwset: &TxPvtReadWriteSet,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn execute<'a>(
+            &self,
+            tx_params: &'a TransactionParams,
+            name: &'a str,
+        ) -> Result<(Response, Option<ContractEvent>)> {
+            if let Some(contracts) = &self.contracts {
+                return contracts.execute(tx_params, name).await;
+            }
+            tx_params
+                .tx_simulator
+                .with(|s| s.set_state(&tx_params.namespace, "k", b"v".to_vec()))?;
+            Ok((
+                Response {
+                    status: Status::Success as i32,
+                    message: String::new(),
+                    payload: b"ok".to_vec(),
+                },
+                None,
+            ))
+        }
+
+        async fn broadcast(&self, tx: &Transaction) -> Result<()> {
+            self.broadcast.lock().unwrap().push(tx.clone());
+            Ok(())
+        }
+    }
+
+    // sign_proposal builds a proposal of the client invoking the contract "cc" on a channel
+    fn sign_proposal(client: &SigningIdentity, channel_id: &str) -> SignedProposal {
+        let creator = client.identity().to_bytes().unwrap();
+        let nonce = b"nonce".to_vec();
+        let proposal = Proposal {
+            header: Some(Header {
+                header_type: HeaderType::Invoke as i32,
+                channel_id: channel_id.to_string(),
+                tx_id: utils::utils::compute_tx_id(&nonce, &creator),
+                creator,
+                nonce,
+                ..Default::default()
+            }),
+            payload: utils::proto::marshal(&ContractProposalPayload {
+                contract_id: Some(ContractId {
+                    name: "cc".to_string(),
+                    ..Default::default()
+                }),
+                input: Some(ContractInput::default()),
+                ..Default::default()
+            })
+            .unwrap(),
+        };
+        let proposal_bytes = utils::proto::marshal(&proposal).unwrap();
+        SignedProposal {
+            signature: client.sign(&proposal_bytes).unwrap(),
+            proposal_bytes,
+        }
+    }
+
+    // fake_support returns the support of the channel "ch1" of org1, where the contract "cc" is
+    // defined, with a client and a peer of org1
+    fn fake_support(
+        contracts: Option<ContractSupport>,
+    ) -> (
+        FakeSupport,
+        SigningIdentity,
+        SigningIdentity,
+        SigningIdentity,
+    ) {
+        let signer = |ca| {
+            let cert = create_cert(cert_params(None, Register::default())).unwrap();
+            SigningIdentity::new(
+                "org1",
+                cert.serialize_der_with_signer(ca).unwrap(),
+                &cert.serialize_private_key_der(),
+            )
+            .unwrap()
+        };
+        let (ca, other_ca) = (create_ca_cert(), create_ca_cert());
+        let (client, peer) = (signer(&ca), signer(&ca));
+        // the stranger is not issued by the CA of org1
+        let stranger = signer(&other_ca);
+        let channel = Channel::new(ChannelConfig {
+            channel_id: "ch1".to_string(),
+            organizations: vec![OrganizationConfig {
+                org_id: "org1".to_string(),
+                root_certs: vec![ca.serialize_der().unwrap()],
+            }],
+            ..Default::default()
+        })
+        .unwrap();
+        let vdb = VersionedDBMemProvider::new().get_db_handle("ch1").unwrap();
+        let mut batch = UpdateBatch::new();
+        batch.put(
+            LIFECYCLE_NS,
+            &definition_key("cc"),
+            utils::proto::marshal(&ContractDefinition {
+                name: "cc".to_string(),
+                version: "1".to_string(),
+                package_hash: vec![1],
+                ..Default::default()
+            })
+            .unwrap(),
+            Height::new(0, 0),
+        );
+        vdb.apply_updates(batch, Some(Height::new(0, 0))).unwrap();
+        let support = FakeSupport {
+            channel,
+            vdb,
+            broadcast: Arc::new(Mutex::new(vec![])),
+            contracts,
+        };
+        (support, client, peer, stranger)
+    }
+
+    #[tokio::test]
+    async fn test_process() {
+        let (support, client, peer, stranger) = fake_support(None);
+        let channel = support.channel.clone();
+        let server = Server::new(support.clone(), peer);
+
+        let resp = server.process(sign_proposal(&client, "ch1")).await.unwrap();
+        assert_eq!(
+            resp.response.as_ref().unwrap().status,
+            Status::Success as i32
+        );
+        assert_eq!(resp.response.as_ref().unwrap().payload, b"ok".to_vec());
+        verify_endorsement(
+            &channel.msp(),
+            &resp.payload,
+            resp.endorsement.as_ref().unwrap(),
+        )
+        .unwrap();
+        let payload: ProposalResponsePayload = utils::proto::unmarshal(&resp.payload).unwrap();
+        let results: TxReadWriteSet = utils::proto::unmarshal(&payload.results).unwrap();
+        let ns_rwset = results
+            .ns_rwset
+            .iter()
+            .find(|ns_rwset| ns_rwset.namespace == "cc")
+            .unwrap();
+        let rwset: KvrwSet = utils::proto::unmarshal(&ns_rwset.rwset).unwrap();
+        assert_eq!(rwset.writes[0].key, "k");
+        assert_eq!(support.broadcast.lock().unwrap().len(), 1);
+
+        // the signature must be the creator's, the creator issued by the CA of its org and the
+        // channel joined
+        let mut forged = sign_proposal(&client, "ch1");
+        forged.signature = stranger.sign(&forged.proposal_bytes).unwrap();
+        let rejected = [
+            forged,
+            sign_proposal(&stranger, "ch1"),
+            sign_proposal(&client, "ch2"),
+        ];
+        for sp in rejected.iter() {
+            let resp = server.process(sp.clone()).await.unwrap();
+            assert_eq!(resp.response.unwrap().status, Status::Forbidden as i32);
+            assert!(resp.endorsement.is_none());
+        }
+        assert_eq!(support.broadcast.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_process_timeout() {
+        // the instance of the contract never answers
+        let contracts = ContractSupport::new(Duration::from_millis(50)).with_unverified_packages();
+        let (tx, _rx) = tokio::sync::mpsc::channel(10);
+        let register = ContractRegister {
+            name: "cc".to_string(),
+            version: "1".to_string(),
+            package_hash: vec![1],
+            ..Default::default()
+        };
+        contracts.register(Contract::new(register, tx)).unwrap();
+        let (support, client, peer, _) = fake_support(Some(contracts.clone()));
+        let server = Server::new(support.clone(), peer);
+
+        let resp = server.process(sign_proposal(&client, "ch1")).await.unwrap();
+        assert_eq!(resp.response.unwrap().status, Status::RequestTimeout as i32);
+        assert!(resp.endorsement.is_none());
+        assert_eq!(contracts.timed_out_executions(), 1);
+        assert!(support.broadcast.lock().unwrap().is_empty());
+    }
+}
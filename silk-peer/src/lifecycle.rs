@@ -0,0 +1,155 @@
+use crate::contract::TxSimulator;
+use error::*;
+use ledger::lifecycle::{self, LIFECYCLE_NS};
+use ledger::pvtdata::collection::{encode_collection_configs, COLLECTION_CONFIG_NS};
+use silk_proto::*;
+
+// the functions of the lifecycle contract, named by the first argument of its proposals
+const DEFINE: &[u8] = b"define";
+const QUERY: &[u8] = b"query";
+
+fn response(status: Status, message: &str, payload: Vec<u8>) -> Response {
+    Response {
+        status: status as i32,
+        message: message.to_string(),
+        payload,
+    }
+}
+
+// get_definition reads the definition of a contract through the simulator, so that the
+// transaction conflicts with a redefinition committed before it
+fn get_definition(simulator: &TxSimulator, name: &str) -> Result<Option<ContractDefinition>> {
+    let value = simulator.with(|s| s.get_state(LIFECYCLE_NS, &lifecycle::definition_key(name)))?;
+    lifecycle::decode_definition(&value)
+}
+
+// execute runs a proposal of the lifecycle contract, the peer runs it in process:
+//
+//   define <ContractDefinition>   defines a new version of a contract, with its collections
+//   query <name>                  returns the definition of a contract
+pub fn execute(simulator: &TxSimulator, input: &ContractInput) -> Result<Response> {
+    match input.args.first().map(|f| f.as_slice()) {
+        Some(DEFINE) if input.args.len() == 2 => {
+            let definition: ContractDefinition = match utils::proto::unmarshal(&input.args[1]) {
+                Ok(definition) => definition,
+                Err(e) => return Ok(response(Status::BadRequest, &e.to_string(), vec![])),
+            };
+            if let Err(e) = lifecycle::validate_definition(&definition) {
+                return Ok(response(Status::BadRequest, &e.to_string(), vec![]));
+            }
+            if let Some(current) = get_definition(simulator, &definition.name)? {
+                if current.version == definition.version {
+                    let message = format!(
+                        "version {:} of contract {:} is already defined",
+                        definition.version, definition.name
+                    );
+                    return Ok(response(Status::BadRequest, &message, vec![]));
+                }
+            }
+            let value = utils::proto::marshal(&definition)?;
+            simulator.with(|s| {
+                s.set_state(
+                    LIFECYCLE_NS,
+                    &lifecycle::definition_key(&definition.name),
+                    value,
+                )
+            })?;
+            // the collections are replaced by the ones of the new version
+            let collections =
+                encode_collection_configs(&lifecycle::collection_configs(&definition))?;
+            simulator.with(|s| s.set_state(COLLECTION_CONFIG_NS, &definition.name, collections))?;
+            info!(
+                "define version {:} of contract {:}",
+                definition.version, definition.name
+            );
+            Ok(response(Status::Success, "ok", vec![]))
+        }
+        Some(QUERY) if input.args.len() == 2 => {
+            let name = String::from_utf8_lossy(&input.args[1]);
+            match get_definition(simulator, &name)? {
+                Some(definition) => Ok(response(
+                    Status::Success,
+                    "ok",
+                    utils::proto::marshal(&definition)?,
+                )),
+                None => Ok(response(
+                    Status::NotFound,
+                    &format!("contract {:} is not defined", name),
+                    vec![],
+                )),
+            }
+        }
+        _ => Ok(response(
+            Status::BadRequest,
+            "expected 'define <definition>' or 'query <name>'",
+            vec![],
+        )),
+    }
+}
+
+// check_invocation checks a proposal invoking a contract against the definition of the contract,
+// which is returned. The proposal is rejected with a status when the contract is not defined, its
+// version is not the one asked for, or it is not initialized as its definition requires
+pub fn check_invocation(
+    simulator: &TxSimulator,
+    contract_id: &ContractId,
+    input: &ContractInput,
+) -> Result<std::result::Result<ContractDefinition, (Status, String)>> {
+    let definition = match get_definition(simulator, &contract_id.name)? {
+        Some(definition) => definition,
+        None => {
+            return Ok(Err((
+                Status::NotFound,
+                format!("contract {:} is not defined", contract_id.name),
+            )))
+        }
+    };
+    if !contract_id.version.is_empty() && contract_id.version != definition.version {
+        return Ok(Err((
+            Status::BadRequest,
+            format!(
+                "contract {:} is at version {:}, not {:}",
+                definition.name, definition.version, contract_id.version
+            ),
+        )));
+    }
+
+    if !definition.init_required {
+        if input.is_init {
+            return Ok(Err((
+                Status::BadRequest,
+                format!(
+                    "contract {:} does not require initialization",
+                    definition.name
+                ),
+            )));
+        }
+        return Ok(Ok(definition));
+    }
+    let key = lifecycle::initialized_key(&definition.name);
+    let initialized = simulator.with(|s| s.get_state(LIFECYCLE_NS, &key))?;
+    let initialized = initialized == definition.version.as_bytes();
+    match (input.is_init, initialized) {
+        (true, true) => Ok(Err((
+            Status::BadRequest,
+            format!(
+                "version {:} of contract {:} is already initialized",
+                definition.version, definition.name
+            ),
+        ))),
+        (true, false) => {
+            // the mark is committed with the initialization
+            let version = definition.version.as_bytes().to_vec();
+            simulator.with(|s| s.set_state(LIFECYCLE_NS, &key, version))?;
+            Ok(Ok(definition))
+        }
+        (false, true) => Ok(Ok(definition)),
+        (false, false) => Ok(Err((
+            Status::BadRequest,
+            format!(
+                "version {:} of contract {:} must be initialized first",
+                definition.version, definition.name
+            ),
+        ))),
+    }
+}
@@ -6,11 +6,19 @@ extern crate log;
 use silk_proto::consensus_server::ConsensusServer;
 use silk_proto::contract_server::ContractServer;
 use silk_proto::endorser_server::EndorserServer;
+use silk_proto::query_server::QueryServer;
 
-use tonic::transport::Server;
+use identity::signer::SigningIdentity;
+use ledger::ledger_mgmt::LedgerMgr;
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
 
 use crate::consensus::ConsensusSupport;
-use crate::contract::ContractSupport;
+use crate::contract::{
+    ContractSupport, Launcher, LauncherConfig, TlsFiles, WasmConfig, WasmRuntime,
+    DEFAULT_EXECUTE_TIMEOUT,
+};
 use crate::event::EventHandler;
 
 pub mod channel;
@@ -18,7 +26,9 @@ pub mod consensus;
 pub mod contract;
 pub mod endorser;
 pub mod event;
+pub mod lifecycle;
 pub mod p2p;
+pub mod query;
 pub mod support;
 
 #[tokio::main]
@@ -26,30 +36,197 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
 
     let access_addr = "127.0.0.1:8080".parse().unwrap();
-    let inner_addr = "127.0.0.1:8081".parse().unwrap();
+    let inner_addr: std::net::SocketAddr = "127.0.0.1:8081".parse().unwrap();
+
+    let signer = load_signing_identity()?;
+    info!(
+        "endorsing as a member of org {}",
+        signer.identity().org_id()
+    );
 
     let handler = Box::new(EventHandler::new());
 
-    let contract_support = ContractSupport::default();
+    let mut contract_support = ContractSupport::new(load_execute_timeout()?);
+    if let Ok(balancing) = std::env::var("SILK_PEER_CONTRACT_BALANCING") {
+        contract_support = contract_support.with_balancing(
+            balancing
+                .parse()
+                .map_err(|e| e as Box<dyn std::error::Error>)?,
+        );
+    }
+    // the contracts reach the component server over TLS when the TLS material is set, at
+    // localhost, which the server certificate must name
+    let contract_tls = load_contract_tls()?;
+    let component_tls = load_component_tls(contract_tls.as_ref())?;
+    let peer_address = match &contract_tls {
+        Some(_) => format!("https://localhost:{}", inner_addr.port()),
+        None => format!("http://{}", inner_addr),
+    };
+    // the contracts started apart from the peer are only run when their package hash is trusted
+    if std::env::var("SILK_PEER_CONTRACT_UNVERIFIED_PACKAGES").as_deref() == Ok("true") {
+        warn!("trusting the package hash of the contracts not launched by the peer");
+        contract_support = contract_support.with_unverified_packages();
+    }
+    if let Some(mut config) = load_launcher_config(&peer_address)? {
+        config.tls = contract_tls;
+        info!(
+            "launching the contracts installed in {:?}",
+            config.package_dir
+        );
+        contract_support = contract_support.with_launcher(Launcher::new(config));
+    }
+    if let Some(config) = load_wasm_config()? {
+        contract_support = contract_support
+            .with_wasm(WasmRuntime::new(config).map_err(|e| e as Box<dyn std::error::Error>)?);
+    }
     let consensus_support = ConsensusSupport::new(handler);
-    let support = support::Support::new(contract_support.clone(), consensus_support.clone());
+    let ledger_mgr = Arc::new(LedgerMgr::new().map_err(|e| e as Box<dyn std::error::Error>)?);
+    let support = support::Support::new(
+        contract_support.clone(),
+        consensus_support.clone(),
+        ledger_mgr.clone(),
+    );
+    support
+        .join_ledgers()
+        .map_err(|e| e as Box<dyn std::error::Error>)?;
 
     let consensus_svr = consensus::Server::new(consensus_support);
     let contract_svr = contract::Server::new(contract_support);
-    let endorser_svr = endorser::Server::new(support);
+    let query_svr = query::Server::new(ledger_mgr, support.clone());
+    let endorser_svr = endorser::Server::new(support, signer);
 
     info!("component server listening on {}", inner_addr);
     info!("sdk server listening on {}", access_addr);
 
-    let c_svr = Server::builder()
+    let mut c_builder = Server::builder();
+    if let Some(tls) = component_tls {
+        info!("component server requires TLS");
+        c_builder = c_builder.tls_config(tls)?;
+    }
+    let c_svr = c_builder
         .add_service(ConsensusServer::new(consensus_svr))
         .add_service(ContractServer::new(contract_svr))
         .serve(inner_addr);
 
     let s_svr = Server::builder()
         .add_service(EndorserServer::new(endorser_svr))
+        .add_service(QueryServer::new(query_svr))
         .serve(access_addr);
 
     future::try_join(c_svr, s_svr).await?;
     Ok(())
 }
+
+// load_signing_identity loads the identity the peer endorses with, named by the SILK_PEER_ORG_ID,
+// SILK_PEER_CERT_FILE and SILK_PEER_KEY_FILE environment variables
+fn load_signing_identity() -> Result<SigningIdentity, Box<dyn std::error::Error>> {
+    let var = |name: &str| {
+        std::env::var(name).map_err(|_| format!("environment variable {} is not set", name))
+    };
+    let org_id = var("SILK_PEER_ORG_ID")?;
+    let cert_file = var("SILK_PEER_CERT_FILE")?;
+    let key_file = var("SILK_PEER_KEY_FILE")?;
+    SigningIdentity::from_files(&org_id, cert_file, key_file)
+        .map_err(|e| e as Box<dyn std::error::Error>)
+}
+
+// load_execute_timeout reads the default contract execution timeout, in milliseconds, from the
+// SILK_PEER_CONTRACT_EXECUTE_TIMEOUT environment variable
+fn load_execute_timeout() -> Result<Duration, Box<dyn std::error::Error>> {
+    match std::env::var("SILK_PEER_CONTRACT_EXECUTE_TIMEOUT") {
+        Ok(v) => {
+            let millis = v
+                .parse::<u64>()
+                .map_err(|e| format!("invalid SILK_PEER_CONTRACT_EXECUTE_TIMEOUT {}: {}", v, e))?;
+            Ok(Duration::from_millis(millis))
+        }
+        Err(_) => Ok(DEFAULT_EXECUTE_TIMEOUT),
+    }
+}
+
+// load_launcher_config reads the configuration of the launch of the installed contracts. The
+// peer launches the contracts when the SILK_PEER_CONTRACT_PACKAGE_DIR environment variable names
+// the package directory
+fn load_launcher_config(
+    peer_address: &str,
+) -> Result<Option<LauncherConfig>, Box<dyn std::error::Error>> {
+    match std::env::var("SILK_PEER_CONTRACT_PACKAGE_DIR") {
+        Ok(dir) => Ok(Some(LauncherConfig::new(dir, peer_address))),
+        Err(_) => Ok(None),
+    }
+}
+
+// load_contract_tls reads the TLS material of the contracts, named by the
+// SILK_PEER_CONTRACT_TLS_ROOT_CERT_FILE, SILK_PEER_CONTRACT_TLS_CERT_FILE and
+// SILK_PEER_CONTRACT_TLS_KEY_FILE environment variables. The root certificate issues the
+// certificates of the contracts and the one of the component server
+fn load_contract_tls() -> Result<Option<TlsFiles>, Box<dyn std::error::Error>> {
+    use std::env::var;
+
+    let tls = (
+        var("SILK_PEER_CONTRACT_TLS_ROOT_CERT_FILE"),
+        var("SILK_PEER_CONTRACT_TLS_CERT_FILE"),
+        var("SILK_PEER_CONTRACT_TLS_KEY_FILE"),
+    );
+    match tls {
+        (Ok(root_cert), Ok(cert), Ok(key)) => Ok(Some(TlsFiles {
+            root_cert: root_cert.into(),
+            cert: cert.into(),
+            key: key.into(),
+        })),
+        (Err(_), Err(_), Err(_)) => Ok(None),
+        _ => Err("the contract TLS root certificate, certificate and key go together".into()),
+    }
+}
+
+// load_component_tls builds the TLS configuration of the component server when the contracts
+// have TLS material. The server certificate and key are named by the SILK_PEER_TLS_CERT_FILE and
+// SILK_PEER_TLS_KEY_FILE environment variables, the contracts must present a certificate issued
+// by their root certificate
+fn load_component_tls(
+    contract_tls: Option<&TlsFiles>,
+) -> Result<Option<ServerTlsConfig>, Box<dyn std::error::Error>> {
+    let contract_tls = match contract_tls {
+        Some(tls) => tls,
+        None => return Ok(None),
+    };
+    let read = |name: &str| -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let file =
+            std::env::var(name).map_err(|_| format!("environment variable {} is not set", name))?;
+        Ok(std::fs::read(&file).map_err(|e| format!("can not read {}: {}", file, e))?)
+    };
+    let identity = Identity::from_pem(
+        read("SILK_PEER_TLS_CERT_FILE")?,
+        read("SILK_PEER_TLS_KEY_FILE")?,
+    );
+    let root_cert = std::fs::read(&contract_tls.root_cert)?;
+    Ok(Some(
+        ServerTlsConfig::new()
+            .identity(identity)
+            .client_ca_root(Certificate::from_pem(root_cert)),
+    ))
+}
+
+// load_wasm_config reads the configuration of the WASM contracts, installed in the package
+// directory too. SILK_PEER_CONTRACT_WASM_FUEL bounds the fuel of an execution and
+// SILK_PEER_CONTRACT_WASM_MAX_MEMORY the bytes of memory of a contract
+fn load_wasm_config() -> Result<Option<WasmConfig>, Box<dyn std::error::Error>> {
+    use std::env::var;
+
+    let package_dir = match var("SILK_PEER_CONTRACT_PACKAGE_DIR") {
+        Ok(dir) => dir,
+        Err(_) => return Ok(None),
+    };
+    let mut config = WasmConfig::new(package_dir);
+    if let Ok(v) = var("SILK_PEER_CONTRACT_WASM_FUEL") {
+        config.fuel = v
+            .parse()
+            .map_err(|e| format!("invalid SILK_PEER_CONTRACT_WASM_FUEL {}: {}", v, e))?;
+    }
+    if let Ok(v) = var("SILK_PEER_CONTRACT_WASM_MAX_MEMORY") {
+        config.max_memory = v
+            .parse()
+            .map_err(|e| format!("invalid SILK_PEER_CONTRACT_WASM_MAX_MEMORY {}: {}", v, e))?;
+    }
+    Ok(Some(config))
+}
@@ -0,0 +1,232 @@
+use crate::support::ISupport;
+use error::*;
+use ledger::ledger_mgmt::LedgerMgr;
+use ledger::rwset::key;
+use ledger::{Ledger, LedgerProvider};
+use silk_proto::query_server::Query;
+use silk_proto::*;
+use std::sync::Arc;
+use tonic::{Request, Status};
+
+// SignedQuery is a query request signed by its creator, a member of the channel queried
+trait SignedQuery: prost::Message + Clone {
+    fn channel_id(&self) -> &str;
+    fn namespace(&self) -> &str;
+    fn creator(&self) -> &[u8];
+    fn signature(&self) -> &[u8];
+    fn clear_signature(&mut self);
+}
+
+macro_rules! impl_signed_query {
+    ($($request:ty),*) => {
+        $(impl SignedQuery for $request {
+            fn channel_id(&self) -> &str {
+                &self.channel_id
+            }
+
+            fn namespace(&self) -> &str {
+                &self.namespace
+            }
+
+            fn creator(&self) -> &[u8] {
+                &self.creator
+            }
+
+            fn signature(&self) -> &[u8] {
+                &self.signature
+            }
+
+            fn clear_signature(&mut self) {
+                self.signature.clear();
+            }
+        })*
+    };
+}
+
+impl_signed_query!(
+    StateAtRequest,
+    StateRangeAtRequest,
+    StateRangePageRequest,
+    QueryPageRequest
+);
+
+// Server answers the queries of the members of a channel on the state of its ledger
+pub struct Server<P: LedgerProvider, S: ISupport> {
+    ledger_mgr: Arc<LedgerMgr<P>>,
+    support: S,
+}
+
+impl<P: LedgerProvider, S: ISupport> Server<P, S> {
+    pub fn new(ledger_mgr: Arc<LedgerMgr<P>>, support: S) -> Self {
+        Server {
+            ledger_mgr,
+            support,
+        }
+    }
+
+    // check_access verifies the creator of a query is a member of the channel and signed it. The
+    // namespaces derived from a contract, holding the private data of its collections and their
+    // hashes, are not served
+    fn check_access<Q: SignedQuery>(&self, req: &Q) -> Result<()> {
+        key::validate_name(req.namespace())?;
+        let channel = self
+            .support
+            .get_channel(req.channel_id())
+            .ok_or_else(|| from_str(&format!("channel {:} not found", req.channel_id())))?;
+        let creator = channel.msp().deserialize_identity(req.creator())?;
+        let mut unsigned = req.clone();
+        unsigned.clear_signature();
+        creator.verify(&utils::proto::marshal(&unsigned)?, req.signature())
+    }
+
+    // blocking runs a query off the async workers, the query executor waits for the commit lock
+    // and reads the state database
+    async fn blocking<T, F>(&self, query: F) -> Result<T>
+    where
+        P: Send + Sync + 'static,
+        P::L: Send + Sync,
+        T: Send + 'static,
+        F: FnOnce(&LedgerMgr<P>) -> Result<T> + Send + 'static,
+    {
+        let ledger_mgr = self.ledger_mgr.clone();
+        tokio::task::spawn_blocking(move || query(&ledger_mgr)).await?
+    }
+
+    fn state_at(ledger_mgr: &LedgerMgr<P>, req: &StateAtRequest) -> Result<StateAtResponse> {
+        let qe = ledger_mgr
+            .get_ledger(&req.channel_id)?
+            .new_query_executor()?;
+        let resp = match qe.get_state_at(&req.namespace, &req.key, req.block_num)? {
+            Some(vv) => StateAtResponse {
+                exists: true,
+                value: vv.value,
+                block_num: vv.version.block_num,
+                tx_num: vv.version.tx_num,
+            },
+            None => StateAtResponse::default(),
+        };
+        Ok(resp)
+    }
+
+    fn state_range_at(
+        ledger_mgr: &LedgerMgr<P>,
+        req: &StateRangeAtRequest,
+    ) -> Result<StateRangeAtResponse> {
+        let qe = ledger_mgr
+            .get_ledger(&req.channel_id)?
+            .new_query_executor()?;
+        let kvs = qe
+            .get_state_range_scan_iterator_at(
+                &req.namespace,
+                &req.start_key,
+                &req.end_key,
+                req.block_num,
+            )?
+            .collect();
+        Ok(StateRangeAtResponse { kvs })
+    }
+
+    fn state_range_page(
+        ledger_mgr: &LedgerMgr<P>,
+        req: &StateRangePageRequest,
+    ) -> Result<QueryPageResponse> {
+        let qe = ledger_mgr
+            .get_ledger(&req.channel_id)?
+            .new_query_executor()?;
+        let page = qe.get_state_range_scan_iterator_with_pagination(
+            &req.namespace,
+            &req.start_key,
+            &req.end_key,
+            &req.bookmark,
+            page_size(req.page_size)?,
+        )?;
+        Ok(QueryPageResponse {
+            kvs: page.results,
+            bookmark: page.bookmark,
+        })
+    }
+
+    fn query_page(ledger_mgr: &LedgerMgr<P>, req: &QueryPageRequest) -> Result<QueryPageResponse> {
+        let qe = ledger_mgr
+            .get_ledger(&req.channel_id)?
+            .new_query_executor()?;
+        let page = qe.execute_query_with_pagination(
+            &req.namespace,
+            &req.query,
+            &req.bookmark,
+            page_size(req.page_size)?,
+        )?;
+        Ok(QueryPageResponse {
+            kvs: page.results,
+            bookmark: page.bookmark,
+        })
+    }
+}
+
+fn permission_denied(err: Error) -> Status {
+    Status::permission_denied(err.to_string())
+}
+
+fn page_size(page_size: u32) -> Result<usize> {
+    if page_size == 0 {
+        return Err(from_str("page size must be positive"));
+    }
+    Ok(page_size as usize)
+}
+
+#[async_trait::async_trait]
+impl<P, S> Query for Server<P, S>
+where
+    P: LedgerProvider + Send + Sync + 'static,
+    P::L: Send + Sync,
+    S: ISupport,
+{
+    async fn get_state_at(&self, request: Request<StateAtRequest>) -> RpcResult<StateAtResponse> {
+        let req = request.into_inner();
+        self.check_access(&req).map_err(permission_denied)?;
+        let resp = self
+            .blocking(move |ledger_mgr| Self::state_at(ledger_mgr, &req))
+            .await
+            .map_err(into_status)?;
+        into_rpc_response(resp)
+    }
+
+    async fn get_state_range_at(
+        &self,
+        request: Request<StateRangeAtRequest>,
+    ) -> RpcResult<StateRangeAtResponse> {
+        let req = request.into_inner();
+        self.check_access(&req).map_err(permission_denied)?;
+        let resp = self
+            .blocking(move |ledger_mgr| Self::state_range_at(ledger_mgr, &req))
+            .await
+            .map_err(into_status)?;
+        into_rpc_response(resp)
+    }
+
+    async fn get_state_range_page(
+        &self,
+        request: Request<StateRangePageRequest>,
+    ) -> RpcResult<QueryPageResponse> {
+        let req = request.into_inner();
+        self.check_access(&req).map_err(permission_denied)?;
+        let resp = self
+            .blocking(move |ledger_mgr| Self::state_range_page(ledger_mgr, &req))
+            .await
+            .map_err(into_status)?;
+        into_rpc_response(resp)
+    }
+
+    async fn execute_query_page(
+        &self,
+        request: Request<QueryPageRequest>,
+    ) -> RpcResult<QueryPageResponse> {
+        let req = request.into_inner();
+        self.check_access(&req).map_err(permission_denied)?;
+        let resp = self
+            .blocking(move |ledger_mgr| Self::query_page(ledger_mgr, &req))
+            .await
+            .map_err(into_status)?;
+        into_rpc_response(resp)
+    }
+}
@@ -1,14 +1,33 @@
+use crate::channel::Channel;
 use crate::contract::*;
+use dashmap::DashMap;
 use error::*;
+use ledger::ledger_mgmt::LedgerMgr;
+use ledger::{Ledger, LedgerProvider};
+use std::sync::Arc;
 
 use crate::consensus::*;
 use silk_proto::*;
 
 #[async_trait::async_trait]
 pub trait ISupport: Send + Sync + 'static {
-    fn get_transaction_simulator(&self, ledger: &str, tx_id: &str) -> Result<Option<TxSimulator>>;
+    fn get_channel(&self, channel_id: &str) -> Option<Channel>;
+    // get_transaction_simulator waits for the commit lock of the ledger, it must not block the
+    // async workers meanwhile
+    async fn get_transaction_simulator(
+        &self,
+        ledger: &str,
+        tx_id: &str,
+    ) -> Result<Option<TxSimulator>>;
     fn get_ledger_height(&self, channel_id: String) -> Result<u64>;
     fn get_transaction_by_id(&self, channel_id: String, _tx_id: String) -> Result<()>;
+    // persist_pvt_rwset keeps the private rwset of an endorsed transaction until it is committed
+    fn persist_pvt_rwset(
+        &self,
+        channel_id: &str,
+        tx_id: &str,
+        pvt_rwset: &TxPvtReadWriteSet,
+    ) -> Result<()>;
     async fn execute<'a>(
         &self,
         tx_params: &'a TransactionParams,
@@ -17,32 +36,105 @@ pub trait ISupport: Send + Sync + 'static {
     async fn broadcast(&self, tx: &Transaction) -> Result<()>;
 }
 
-#[derive(Clone)]
-pub struct Support<H: IContractSupport, T: IConsensusSupport> {
+pub struct Support<H: IContractSupport, T: IConsensusSupport, P: LedgerProvider> {
     contract_support: H,
     consensus_support: T,
     node_support: i32,
+    channels: Arc<DashMap<String, Channel>>,
+    ledger_mgr: Arc<LedgerMgr<P>>,
+}
+
+impl<H: IContractSupport + Clone, T: IConsensusSupport + Clone, P: LedgerProvider> Clone
+    for Support<H, T, P>
+{
+    fn clone(&self) -> Self {
+        Support {
+            contract_support: self.contract_support.clone(),
+            consensus_support: self.consensus_support.clone(),
+            node_support: self.node_support,
+            channels: self.channels.clone(),
+            ledger_mgr: self.ledger_mgr.clone(),
+        }
+    }
 }
 
-impl<H: IContractSupport, T: IConsensusSupport> Support<H, T> {
-    pub fn new(contract_support: H, consensus_support: T) -> Self {
+impl<H: IContractSupport, T: IConsensusSupport, P: LedgerProvider> Support<H, T, P> {
+    pub fn new(contract_support: H, consensus_support: T, ledger_mgr: Arc<LedgerMgr<P>>) -> Self {
         Support {
             contract_support,
             consensus_support,
             node_support: 0,
+            channels: Arc::new(DashMap::new()),
+            ledger_mgr,
+        }
+    }
+
+    // join_channel makes the peer endorse the proposals of a channel
+    pub fn join_channel(&self, config: ChannelConfig) -> Result<()> {
+        let channel = Channel::new(config)?;
+        self.channels.insert(channel.name().to_string(), channel);
+        Ok(())
+    }
+
+    // join_ledgers joins the channels of the ledgers created on the peer
+    pub fn join_ledgers(&self) -> Result<()> {
+        for id in self.ledger_mgr.list_ledgers()? {
+            if self.join_ledger(&id)?.is_none() {
+                warn!("ledger {:?} has no channel config", id);
+            }
         }
+        Ok(())
+    }
+
+    // join_ledger joins the channel of a ledger from the config of its genesis block, a ledger
+    // without config has no channel
+    fn join_ledger(&self, id: &str) -> Result<Option<Channel>> {
+        let config = match self.ledger_mgr.get_ledger(id)?.get_channel_config()? {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+        let channel = Channel::new(config)?;
+        info!("joined channel {:?}", channel.name());
+        self.channels
+            .insert(channel.name().to_string(), channel.clone());
+        Ok(Some(channel))
     }
 }
 
 #[async_trait::async_trait]
-impl<H: IContractSupport, T: IConsensusSupport> ISupport for Support<H, T> {
-    // PIN
-    fn get_transaction_simulator(
+impl<H, T, P> ISupport for Support<H, T, P>
+where
+    H: IContractSupport,
+    T: IConsensusSupport,
+    P: LedgerProvider + Send + Sync + 'static,
+    P::L: Send + Sync,
+{
+    fn get_channel(&self, channel_id: &str) -> Option<Channel> {
+        if let Some(channel) = self.channels.get(channel_id) {
+            return Some(channel.value().clone());
+        }
+        // the ledgers created after the start of the peer are joined on their first use
+        match self.ledger_mgr.ledger_exists(channel_id) {
+            Ok(true) => self.join_ledger(channel_id).unwrap_or_else(|e| {
+                warn!("failed to join channel {:?}: {:?}", channel_id, e);
+                None
+            }),
+            _ => None,
+        }
+    }
+
+    async fn get_transaction_simulator(
         &self,
-        _ledger: &str,
-        _tx_id: &str,
+        ledger: &str,
+        tx_id: &str,
     ) -> Result<Option<TxSimulator>> {
-        Ok(Some(TxSimulator {}))
+        let ledger_mgr = self.ledger_mgr.clone();
+        let (ledger, tx_id) = (ledger.to_string(), tx_id.to_string());
+        let simulator = tokio::task::spawn_blocking(move || {
+            ledger_mgr.get_ledger(&ledger)?.new_tx_simulator(tx_id)
+        })
+        .await??;
+        Ok(Some(TxSimulator::new(simulator)))
     }
 
     // PIN
@@ -55,6 +147,17 @@ impl<H: IContractSupport, T: IConsensusSupport> ISupport for Support<H, T> {
         Ok(())
     }
 
+    fn persist_pvt_rwset(
+        &self,
+        channel_id: &str,
+        tx_id: &str,
+        pvt_rwset: &TxPvtReadWriteSet,
+    ) -> Result<()> {
+        self.ledger_mgr
+            .get_ledger(channel_id)?
+            .persist_pvt_rwset(tx_id, pvt_rwset)
+    }
+
     // PIN
     async fn execute<'a>(
         &self,
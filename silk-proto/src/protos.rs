@@ -17,13 +17,45 @@ pub struct Header {
     /// the hash of the client's TLS certificate
     #[prost(bytes, tag = "6")]
     pub tls_cert_hash: std::vec::Vec<u8>,
-    /// Creator of the message, a marshaled msp.SerializedIdentity
+    /// Creator of the message, a marshaled SerializedIdentity
     #[prost(bytes, tag = "7")]
     pub creator: std::vec::Vec<u8>,
     /// Arbitrary number that may only be used once. Can be used to detect replay attacks.
     #[prost(bytes, tag = "8")]
     pub nonce: std::vec::Vec<u8>,
 }
+/// SerializedIdentity identifies the creator of a message or an endorser
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SerializedIdentity {
+    /// the organization the identity belongs to
+    #[prost(string, tag = "1")]
+    pub org_id: std::string::String,
+    /// the certificate of the identity
+    #[prost(bytes, tag = "2")]
+    pub id_bytes: std::vec::Vec<u8>,
+}
+/// OrganizationConfig is the configuration of an organization member of a channel
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct OrganizationConfig {
+    #[prost(string, tag = "1")]
+    pub org_id: std::string::String,
+    /// the DER encoded root certificates of the certificate authorities of the organization
+    #[prost(bytes, repeated, tag = "2")]
+    pub root_certs: ::std::vec::Vec<std::vec::Vec<u8>>,
+}
+/// ChannelConfig is the configuration of a channel, carried as the proposal payload of the
+/// CREATE_CHANNEL transaction of its genesis block
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ChannelConfig {
+    #[prost(string, tag = "1")]
+    pub channel_id: std::string::String,
+    #[prost(message, repeated, tag = "2")]
+    pub organizations: ::std::vec::Vec<OrganizationConfig>,
+    /// the endorsement policies of the contracts by contract name, such as
+    /// AND('Org1.peer', OR('Org2.peer', 'Org3.peer'))
+    #[prost(map = "string, string", tag = "3")]
+    pub endorsement_policies: ::std::collections::HashMap<std::string::String, std::string::String>,
+}
 /// Payload is the message contents (and header to allow for signing)
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Payload {
@@ -52,6 +84,7 @@ pub enum Status {
     BadRequest = 400,
     Forbidden = 403,
     NotFound = 404,
+    RequestTimeout = 408,
     RequestEntityTooLarge = 413,
     InternalServerError = 500,
     NotImplemented = 501,
@@ -98,6 +131,7 @@ pub struct ContractProposalPayload {
     pub input: ::std::option::Option<ContractInput>,
     #[prost(map = "string, bytes", tag = "3")]
     pub transient_map: ::std::collections::HashMap<std::string::String, std::vec::Vec<u8>>,
+    /// the execution timeout of the contract in milliseconds, the peer default when not positive
     #[prost(int32, tag = "4")]
     pub timeout: i32,
 }
@@ -107,6 +141,9 @@ pub struct ContractId {
     ///the deploy transaction
     #[prost(string, tag = "1")]
     pub name: std::string::String,
+    /// the version of the contract the proposal is meant for, any version when empty
+    #[prost(string, tag = "2")]
+    pub version: std::string::String,
 }
 /// Carries the contract function and its arguments.
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -278,8 +315,22 @@ pub mod message {
         ContractGetStateResponse = 113,
         /// contract request put state
         ContractPutStateRequest = 114,
+        ContractPutStateResponse = 117,
         /// contract request delete state
         ContractDelStateRequest = 115,
+        ContractDelStateResponse = 118,
+        /// peer -> contract, a state request failed, the content is the error message
+        ContractStateError = 119,
+        /// contract request range queries, answered with a query state response
+        ContractGetStateByRangeRequest = 120,
+        ContractGetStateByPartialCompositeKeyRequest = 121,
+        ContractQueryStateResponse = 122,
+        ContractGetQueryResultRequest = 123,
+        /// contract request get and put the metadata of a key
+        ContractGetStateMetadataRequest = 124,
+        ContractGetStateMetadataResponse = 125,
+        ContractPutStateMetadataRequest = 126,
+        ContractPutStateMetadataResponse = 127,
         /// contract -> peer
         ContractTransactionCompletedRequest = 116,
         /// Peer message
@@ -489,12 +540,77 @@ pub mod consensus_server {
         const NAME: &'static str = "protos.Consensus";
     }
 }
+/// KV -- QueryResult for range/execute query. Holds a key and corresponding value.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Kv {
+    #[prost(string, tag = "1")]
+    pub namespace: std::string::String,
+    #[prost(string, tag = "2")]
+    pub key: std::string::String,
+    #[prost(bytes, tag = "3")]
+    pub value: std::vec::Vec<u8>,
+}
+/// KeyModification -- QueryResult for history query. Holds a transaction ID, value,
+/// timestamp, and delete marker which resulted from a history query.
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct KeyModification {
+    #[prost(string, tag = "1")]
+    pub tx_id: std::string::String,
+    #[prost(bytes, tag = "2")]
+    pub value: std::vec::Vec<u8>,
+    #[prost(message, optional, tag = "3")]
+    pub timestamp: ::std::option::Option<::prost_types::Timestamp>,
+    #[prost(bool, tag = "4")]
+    pub is_delete: bool,
+}
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ContractRegister {
     #[prost(string, tag = "1")]
     pub name: std::string::String,
     #[prost(map = "string, bytes", tag = "2")]
     pub decorations: ::std::collections::HashMap<std::string::String, std::vec::Vec<u8>>,
+    /// the version and the package hash of the instance, matched against the contract definition
+    #[prost(string, tag = "3")]
+    pub version: std::string::String,
+    #[prost(bytes, tag = "4")]
+    pub package_hash: std::vec::Vec<u8>,
+    /// the id the peer handed to the instance it launched, the peer then knows the package hash of
+    /// the instance instead of trusting the one above
+    #[prost(string, tag = "5")]
+    pub launch_id: std::string::String,
+}
+/// ContractDefinition is the definition of a contract on a channel, committed in the lifecycle
+/// namespace. Only the instances registered with its version and package hash run the contract
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ContractDefinition {
+    #[prost(string, tag = "1")]
+    pub name: std::string::String,
+    #[prost(string, tag = "2")]
+    pub version: std::string::String,
+    #[prost(bytes, tag = "3")]
+    pub package_hash: std::vec::Vec<u8>,
+    /// the endorsement policy of the contract, the policy of the channel when empty
+    #[prost(string, tag = "4")]
+    pub endorsement_policy: std::string::String,
+    /// whether the contract must be initialized before it is invoked
+    #[prost(bool, tag = "5")]
+    pub init_required: bool,
+    /// the json index definitions built over the values of the contract namespace once the
+    /// definition is committed, e.g. {"name":"owner","fields":["owner"]}
+    #[prost(bytes, repeated, tag = "6")]
+    pub indexes: ::std::vec::Vec<std::vec::Vec<u8>>,
+    /// the private data collections of the contract
+    #[prost(message, repeated, tag = "7")]
+    pub collections: ::std::vec::Vec<CollectionDefinition>,
+}
+/// CollectionDefinition defines a private data collection of a contract
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CollectionDefinition {
+    #[prost(string, tag = "1")]
+    pub name: std::string::String,
+    /// the organizations whose peers receive and store the private data
+    #[prost(string, repeated, tag = "2")]
+    pub member_orgs: ::std::vec::Vec<std::string::String>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ContractTransaction {
@@ -536,6 +652,89 @@ pub struct DelStateRequest {
     pub tx_id: std::string::String,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetStateMetadataRequest {
+    #[prost(string, tag = "1")]
+    pub key: std::string::String,
+    #[prost(string, tag = "2")]
+    pub channel_id: std::string::String,
+    #[prost(string, tag = "3")]
+    pub tx_id: std::string::String,
+}
+/// GetStateMetadataResponse holds the entries of the metadata of a key
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetStateMetadataResponse {
+    #[prost(map = "string, bytes", tag = "1")]
+    pub entries: ::std::collections::HashMap<std::string::String, std::vec::Vec<u8>>,
+}
+/// PutStateMetadataRequest replaces the entries of the metadata of a key, its value is unchanged
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct PutStateMetadataRequest {
+    #[prost(string, tag = "1")]
+    pub key: std::string::String,
+    #[prost(map = "string, bytes", tag = "2")]
+    pub entries: ::std::collections::HashMap<std::string::String, std::vec::Vec<u8>>,
+    #[prost(string, tag = "3")]
+    pub channel_id: std::string::String,
+    #[prost(string, tag = "4")]
+    pub tx_id: std::string::String,
+}
+/// GetStateByRangeRequest reads the keys of the contract between start_key (included) and end_key
+/// (excluded, empty for no end). With a page size, a page of the range is read from the bookmark
+/// of the previous page
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetStateByRangeRequest {
+    #[prost(string, tag = "1")]
+    pub start_key: std::string::String,
+    #[prost(string, tag = "2")]
+    pub end_key: std::string::String,
+    #[prost(string, tag = "3")]
+    pub channel_id: std::string::String,
+    #[prost(string, tag = "4")]
+    pub tx_id: std::string::String,
+    #[prost(string, tag = "5")]
+    pub bookmark: std::string::String,
+    #[prost(uint32, tag = "6")]
+    pub page_size: u32,
+}
+/// GetStateByPartialCompositeKeyRequest reads the keys of the contract whose composite key starts
+/// with the object type and the attributes
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetStateByPartialCompositeKeyRequest {
+    #[prost(string, tag = "1")]
+    pub object_type: std::string::String,
+    #[prost(string, repeated, tag = "2")]
+    pub attributes: ::std::vec::Vec<std::string::String>,
+    #[prost(string, tag = "3")]
+    pub channel_id: std::string::String,
+    #[prost(string, tag = "4")]
+    pub tx_id: std::string::String,
+}
+/// GetQueryResultRequest runs a rich query on the values of the contract, see the state db for
+/// the query syntax. With a page size, a page of the results is read from the bookmark of the
+/// previous page
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetQueryResultRequest {
+    #[prost(string, tag = "1")]
+    pub query: std::string::String,
+    #[prost(string, tag = "2")]
+    pub channel_id: std::string::String,
+    #[prost(string, tag = "3")]
+    pub tx_id: std::string::String,
+    #[prost(string, tag = "4")]
+    pub bookmark: std::string::String,
+    #[prost(uint32, tag = "5")]
+    pub page_size: u32,
+}
+/// QueryStateResponse holds the results of a query, the bookmark resumes a paginated query after
+/// them and is empty on the last page
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct QueryStateResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub kvs: ::std::vec::Vec<Kv>,
+    #[prost(string, tag = "2")]
+    pub bookmark: std::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct TransactionCompleted {
     #[prost(message, optional, tag = "1")]
     pub proposal: ::std::option::Option<Proposal>,
@@ -806,6 +1005,13 @@ pub struct KvMetadataEntry {
     #[prost(bytes, tag = "2")]
     pub value: std::vec::Vec<u8>,
 }
+/// KeyEndorsementPolicy is the endorsement policy of a key, kept in the VALIDATION_PARAMETER entry
+/// of its metadata. A transaction writing the key must be endorsed by all the listed organizations
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct KeyEndorsementPolicy {
+    #[prost(string, repeated, tag = "1")]
+    pub orgs: ::std::vec::Vec<std::string::String>,
+}
 /// Version encapsulates the version of a Key
 /// A version of a committed key is maintained as the height of the transaction that committed the key.
 /// The height is represenetd as a tuple <blockNum, txNum> where the txNum is the position of the transaction
@@ -863,6 +1069,106 @@ pub struct QueryReadsMerkleSummary {
     #[prost(bytes, repeated, tag = "3")]
     pub max_level_hashes: ::std::vec::Vec<std::vec::Vec<u8>>,
 }
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StateAtRequest {
+    #[prost(string, tag = "1")]
+    pub channel_id: std::string::String,
+    #[prost(string, tag = "2")]
+    pub namespace: std::string::String,
+    #[prost(string, tag = "3")]
+    pub key: std::string::String,
+    #[prost(uint64, tag = "4")]
+    pub block_num: u64,
+    #[prost(bytes, tag = "5")]
+    pub creator: std::vec::Vec<u8>,
+    #[prost(bytes, tag = "6")]
+    pub signature: std::vec::Vec<u8>,
+}
+/// StateAtResponse holds the value of a key and the height of the transaction that wrote it,
+/// exists is false when the key did not exist at the block
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StateAtResponse {
+    #[prost(bool, tag = "1")]
+    pub exists: bool,
+    #[prost(bytes, tag = "2")]
+    pub value: std::vec::Vec<u8>,
+    #[prost(uint64, tag = "3")]
+    pub block_num: u64,
+    #[prost(uint64, tag = "4")]
+    pub tx_num: u64,
+}
+/// StateRangeAtRequest reads the keys between start_key (included) and end_key (excluded, empty
+/// for no end)
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StateRangeAtRequest {
+    #[prost(string, tag = "1")]
+    pub channel_id: std::string::String,
+    #[prost(string, tag = "2")]
+    pub namespace: std::string::String,
+    #[prost(string, tag = "3")]
+    pub start_key: std::string::String,
+    #[prost(string, tag = "4")]
+    pub end_key: std::string::String,
+    #[prost(uint64, tag = "5")]
+    pub block_num: u64,
+    #[prost(bytes, tag = "6")]
+    pub creator: std::vec::Vec<u8>,
+    #[prost(bytes, tag = "7")]
+    pub signature: std::vec::Vec<u8>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StateRangeAtResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub kvs: ::std::vec::Vec<Kv>,
+}
+/// StateRangePageRequest reads a page of at most page_size keys between start_key (included) and
+/// end_key (excluded, empty for no end), from the bookmark of the previous page if not empty
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct StateRangePageRequest {
+    #[prost(string, tag = "1")]
+    pub channel_id: std::string::String,
+    #[prost(string, tag = "2")]
+    pub namespace: std::string::String,
+    #[prost(string, tag = "3")]
+    pub start_key: std::string::String,
+    #[prost(string, tag = "4")]
+    pub end_key: std::string::String,
+    #[prost(string, tag = "5")]
+    pub bookmark: std::string::String,
+    #[prost(uint32, tag = "6")]
+    pub page_size: u32,
+    #[prost(bytes, tag = "7")]
+    pub creator: std::vec::Vec<u8>,
+    #[prost(bytes, tag = "8")]
+    pub signature: std::vec::Vec<u8>,
+}
+/// QueryPageRequest reads a page of at most page_size results of a rich query, from the bookmark
+/// of the previous page if not empty
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct QueryPageRequest {
+    #[prost(string, tag = "1")]
+    pub channel_id: std::string::String,
+    #[prost(string, tag = "2")]
+    pub namespace: std::string::String,
+    #[prost(string, tag = "3")]
+    pub query: std::string::String,
+    #[prost(string, tag = "4")]
+    pub bookmark: std::string::String,
+    #[prost(uint32, tag = "5")]
+    pub page_size: u32,
+    #[prost(bytes, tag = "6")]
+    pub creator: std::vec::Vec<u8>,
+    #[prost(bytes, tag = "7")]
+    pub signature: std::vec::Vec<u8>,
+}
+/// QueryPageResponse holds a page of results, the bookmark is empty on the last page
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct QueryPageResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub kvs: ::std::vec::Vec<Kv>,
+    #[prost(string, tag = "2")]
+    pub bookmark: std::string::String,
+}
 #[doc = r" Generated client implementations."]
 pub mod endorser_client {
     #![allow(unused_variables, dead_code, missing_docs)]
@@ -1004,6 +1310,112 @@ pub mod p2p_client {
         }
     }
 }
+#[doc = r" Generated client implementations."]
+pub mod query_client {
+    #![allow(unused_variables, dead_code, missing_docs)]
+    use tonic::codegen::*;
+    #[doc = " read the state of the ledgers as of a past block, or page through the last state. A query is"]
+    #[doc = " answered to a member of the channel: creator is its serialized identity and signature signs"]
+    #[doc = " the request marshaled with an empty signature"]
+    pub struct QueryClient<T> {
+        inner: tonic::client::Grpc<T>,
+    }
+    impl QueryClient<tonic::transport::Channel> {
+        #[doc = r" Attempt to create a new client by connecting to a given endpoint."]
+        pub async fn connect<D>(dst: D) -> Result<Self, tonic::transport::Error>
+        where
+            D: std::convert::TryInto<tonic::transport::Endpoint>,
+            D::Error: Into<StdError>,
+        {
+            let conn = tonic::transport::Endpoint::new(dst)?.connect().await?;
+            Ok(Self::new(conn))
+        }
+    }
+    impl<T> QueryClient<T>
+    where
+        T: tonic::client::GrpcService<tonic::body::BoxBody>,
+        T::ResponseBody: Body + HttpBody + Send + 'static,
+        T::Error: Into<StdError>,
+        <T::ResponseBody as HttpBody>::Error: Into<StdError> + Send,
+    {
+        pub fn new(inner: T) -> Self {
+            let inner = tonic::client::Grpc::new(inner);
+            Self { inner }
+        }
+        pub fn with_interceptor(inner: T, interceptor: impl Into<tonic::Interceptor>) -> Self {
+            let inner = tonic::client::Grpc::with_interceptor(inner, interceptor);
+            Self { inner }
+        }
+        pub async fn get_state_at(
+            &mut self,
+            request: impl tonic::IntoRequest<super::StateAtRequest>,
+        ) -> Result<tonic::Response<super::StateAtResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/protos.Query/GetStateAt");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn get_state_range_at(
+            &mut self,
+            request: impl tonic::IntoRequest<super::StateRangeAtRequest>,
+        ) -> Result<tonic::Response<super::StateRangeAtResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/protos.Query/GetStateRangeAt");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn get_state_range_page(
+            &mut self,
+            request: impl tonic::IntoRequest<super::StateRangePageRequest>,
+        ) -> Result<tonic::Response<super::QueryPageResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/protos.Query/GetStateRangePage");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn execute_query_page(
+            &mut self,
+            request: impl tonic::IntoRequest<super::QueryPageRequest>,
+        ) -> Result<tonic::Response<super::QueryPageResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/protos.Query/ExecuteQueryPage");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+    }
+    impl<T: Clone> Clone for QueryClient<T> {
+        fn clone(&self) -> Self {
+            Self {
+                inner: self.inner.clone(),
+            }
+        }
+    }
+    impl<T> std::fmt::Debug for QueryClient<T> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "QueryClient {{ ... }}")
+        }
+    }
+}
 #[doc = r" Generated server implementations."]
 pub mod endorser_server {
     #![allow(unused_variables, dead_code, missing_docs)]
@@ -1255,6 +1667,221 @@ pub mod p2p_server {
         const NAME: &'static str = "protos.P2P";
     }
 }
+#[doc = r" Generated server implementations."]
+pub mod query_server {
+    #![allow(unused_variables, dead_code, missing_docs)]
+    use tonic::codegen::*;
+    #[doc = "Generated trait containing gRPC methods that should be implemented for use with QueryServer."]
+    #[async_trait]
+    pub trait Query: Send + Sync + 'static {
+        async fn get_state_at(
+            &self,
+            request: tonic::Request<super::StateAtRequest>,
+        ) -> Result<tonic::Response<super::StateAtResponse>, tonic::Status>;
+        async fn get_state_range_at(
+            &self,
+            request: tonic::Request<super::StateRangeAtRequest>,
+        ) -> Result<tonic::Response<super::StateRangeAtResponse>, tonic::Status>;
+        async fn get_state_range_page(
+            &self,
+            request: tonic::Request<super::StateRangePageRequest>,
+        ) -> Result<tonic::Response<super::QueryPageResponse>, tonic::Status>;
+        async fn execute_query_page(
+            &self,
+            request: tonic::Request<super::QueryPageRequest>,
+        ) -> Result<tonic::Response<super::QueryPageResponse>, tonic::Status>;
+    }
+    #[doc = " read the state of the ledgers as of a past block, or page through the last state. A query is"]
+    #[doc = " answered to a member of the channel: creator is its serialized identity and signature signs"]
+    #[doc = " the request marshaled with an empty signature"]
+    #[derive(Debug)]
+    pub struct QueryServer<T: Query> {
+        inner: _Inner<T>,
+    }
+    struct _Inner<T>(Arc<T>, Option<tonic::Interceptor>);
+    impl<T: Query> QueryServer<T> {
+        pub fn new(inner: T) -> Self {
+            let inner = Arc::new(inner);
+            let inner = _Inner(inner, None);
+            Self { inner }
+        }
+        pub fn with_interceptor(inner: T, interceptor: impl Into<tonic::Interceptor>) -> Self {
+            let inner = Arc::new(inner);
+            let inner = _Inner(inner, Some(interceptor.into()));
+            Self { inner }
+        }
+    }
+    impl<T, B> Service<http::Request<B>> for QueryServer<T>
+    where
+        T: Query,
+        B: HttpBody + Send + Sync + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+    {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = Never;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+        fn call(&mut self, req: http::Request<B>) -> Self::Future {
+            let inner = self.inner.clone();
+            match req.uri().path() {
+                "/protos.Query/GetStateAt" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetStateAtSvc<T: Query>(pub Arc<T>);
+                    impl<T: Query> tonic::server::UnaryService<super::StateAtRequest> for GetStateAtSvc<T> {
+                        type Response = super::StateAtResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::StateAtRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).get_state_at(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let interceptor = inner.1.clone();
+                        let inner = inner.0;
+                        let method = GetStateAtSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = if let Some(interceptor) = interceptor {
+                            tonic::server::Grpc::with_interceptor(codec, interceptor)
+                        } else {
+                            tonic::server::Grpc::new(codec)
+                        };
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/protos.Query/GetStateRangeAt" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetStateRangeAtSvc<T: Query>(pub Arc<T>);
+                    impl<T: Query> tonic::server::UnaryService<super::StateRangeAtRequest> for GetStateRangeAtSvc<T> {
+                        type Response = super::StateRangeAtResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::StateRangeAtRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).get_state_range_at(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let interceptor = inner.1.clone();
+                        let inner = inner.0;
+                        let method = GetStateRangeAtSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = if let Some(interceptor) = interceptor {
+                            tonic::server::Grpc::with_interceptor(codec, interceptor)
+                        } else {
+                            tonic::server::Grpc::new(codec)
+                        };
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/protos.Query/GetStateRangePage" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetStateRangePageSvc<T: Query>(pub Arc<T>);
+                    impl<T: Query> tonic::server::UnaryService<super::StateRangePageRequest>
+                        for GetStateRangePageSvc<T>
+                    {
+                        type Response = super::QueryPageResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::StateRangePageRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).get_state_range_page(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let interceptor = inner.1.clone();
+                        let inner = inner.0;
+                        let method = GetStateRangePageSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = if let Some(interceptor) = interceptor {
+                            tonic::server::Grpc::with_interceptor(codec, interceptor)
+                        } else {
+                            tonic::server::Grpc::new(codec)
+                        };
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/protos.Query/ExecuteQueryPage" => {
+                    #[allow(non_camel_case_types)]
+                    struct ExecuteQueryPageSvc<T: Query>(pub Arc<T>);
+                    impl<T: Query> tonic::server::UnaryService<super::QueryPageRequest> for ExecuteQueryPageSvc<T> {
+                        type Response = super::QueryPageResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::QueryPageRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).execute_query_page(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let interceptor = inner.1.clone();
+                        let inner = inner.0;
+                        let method = ExecuteQueryPageSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = if let Some(interceptor) = interceptor {
+                            tonic::server::Grpc::with_interceptor(codec, interceptor)
+                        } else {
+                            tonic::server::Grpc::new(codec)
+                        };
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                _ => Box::pin(async move {
+                    Ok(http::Response::builder()
+                        .status(200)
+                        .header("grpc-status", "12")
+                        .body(tonic::body::BoxBody::empty())
+                        .unwrap())
+                }),
+            }
+        }
+    }
+    impl<T: Query> Clone for QueryServer<T> {
+        fn clone(&self) -> Self {
+            let inner = self.inner.clone();
+            Self { inner }
+        }
+    }
+    impl<T: Query> Clone for _Inner<T> {
+        fn clone(&self) -> Self {
+            Self(self.0.clone(), self.1.clone())
+        }
+    }
+    impl<T: std::fmt::Debug> std::fmt::Debug for _Inner<T> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{:?}", self.0)
+        }
+    }
+    impl<T: Query> tonic::transport::NamedService for QueryServer<T> {
+        const NAME: &'static str = "protos.Query";
+    }
+}
 /// TxReadWriteSet encapsulates a read-write set for a transaction
 /// DataModel specifies the enum value of the data model
 /// ns_rwset field specifies a list of chaincode specific read-write set (one for each chaincode)
@@ -1320,29 +1947,6 @@ pub struct CollectionPvtReadWriteSet {
     #[prost(bytes, tag = "2")]
     pub rwset: std::vec::Vec<u8>,
 }
-/// KV -- QueryResult for range/execute query. Holds a key and corresponding value.
-#[derive(Clone, PartialEq, ::prost::Message)]
-pub struct Kv {
-    #[prost(string, tag = "1")]
-    pub namespace: std::string::String,
-    #[prost(string, tag = "2")]
-    pub key: std::string::String,
-    #[prost(bytes, tag = "3")]
-    pub value: std::vec::Vec<u8>,
-}
-/// KeyModification -- QueryResult for history query. Holds a transaction ID, value,
-/// timestamp, and delete marker which resulted from a history query.
-#[derive(Clone, PartialEq, ::prost::Message)]
-pub struct KeyModification {
-    #[prost(string, tag = "1")]
-    pub tx_id: std::string::String,
-    #[prost(bytes, tag = "2")]
-    pub value: std::vec::Vec<u8>,
-    #[prost(message, optional, tag = "3")]
-    pub timestamp: ::std::option::Option<::prost_types::Timestamp>,
-    #[prost(bool, tag = "4")]
-    pub is_delete: bool,
-}
 /// ProcessedTransaction wraps an Envelope that includes a transaction along with an indication
 /// of whether the transaction was validated or invalidated by committing peer.
 /// The use case is that get_transaction_by_id API needs to retrieve the transaction Envelope
@@ -1432,6 +2036,19 @@ pub struct BlockchainInfo {
     pub previous_block_hash: std::vec::Vec<u8>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
+pub struct LedgerMetadata {
+    #[prost(enumeration = "LedgerStatus", tag = "1")]
+    pub status: i32,
+}
+/// LedgerStatus is the status of a ledger in the registry of the ledgers of a peer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum LedgerStatus {
+    UnderConstruction = 0,
+    Active = 1,
+    Inactive = 2,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct PublicKey {
     #[prost(enumeration = "KeyType", tag = "1")]
     pub r#type: i32,